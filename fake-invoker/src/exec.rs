@@ -0,0 +1,490 @@
+//! Execution of invoke requests with plain subprocesses.
+//!
+//! Every request gets its own directory. Volumes, extra files and created
+//! files are stored there. Sandbox is only a set of mounts: paths of
+//! exposed directories in arguments, environment and working directory of
+//! commands are replaced with corresponding host paths (e.g.
+//! `/compile-input/main.cpp` becomes
+//! `<work-dir>/<request>/extra-files/main.cpp`). Paths which are computed
+//! by commands themselves are not translated.
+//!
+//! Steps are executed stage by stage. Commands of one stage run
+//! concurrently (e.g. solution and interactor connected with pipes),
+//! and the next stage starts when all of them have finished.
+//!
+//! Time limit is enforced on wall-clock time, which is also reported as
+//! CPU time. Memory usage is not measured.
+
+use anyhow::Context;
+use invoker_api::{
+    invoke::{
+        Action, ActionResult, Command, CommandResult, EnvVarValue, InputSource, InvokeRequest,
+        InvokeResponse, Output, OutputData, OutputRequestTarget, PathPrefix, PrefixedPath,
+        SandboxSettings,
+    },
+    shim::{RequestExtensions, SharedDirExtensionSource, EXTRA_FILES_DIR_NAME},
+};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs::{File, OpenOptions},
+    os::unix::{
+        fs::PermissionsExt,
+        io::{AsRawFd, RawFd},
+        process::ExitStatusExt,
+    },
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+/// Commands are killed after `WALL_TIME_FACTOR` times their time limit,
+/// so that a busy host does not produce false time limit verdicts
+const WALL_TIME_FACTOR: u32 = 3;
+
+/// Extra time given to every command before it is killed
+const WALL_TIME_GRACE: Duration = Duration::from_secs(1);
+
+/// File which can be used as stdio of commands
+enum FileEntry {
+    Null,
+    Disk(PathBuf),
+    PipeRead(os_pipe::PipeReader),
+    PipeWrite(os_pipe::PipeWriter),
+}
+
+struct Sandbox {
+    /// Used as working directory when it is not in any mount
+    root: PathBuf,
+    /// Pairs of sandbox path and host path, longest sandbox paths first
+    mounts: Vec<(PathBuf, PathBuf)>,
+    wall_time_limit: Duration,
+}
+
+impl Sandbox {
+    /// Replaces sandbox path at the beginning of `value` with host path
+    fn translate(&self, value: &str) -> String {
+        for (sandbox_path, host_path) in &self.mounts {
+            let rest = match value.strip_prefix(&*sandbox_path.to_string_lossy()) {
+                Some(rest) => rest,
+                None => continue,
+            };
+            if rest.is_empty() || rest.starts_with('/') {
+                return format!("{}{}", host_path.display(), rest);
+            }
+        }
+        value.to_string()
+    }
+
+    fn working_dir(&self, cwd: &str) -> anyhow::Result<PathBuf> {
+        let translated = self.translate(cwd);
+        let dir = if translated != cwd {
+            PathBuf::from(translated)
+        } else {
+            self.root.join(cwd.trim_start_matches('/'))
+        };
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        Ok(dir)
+    }
+}
+
+/// Command which was started
+struct Running {
+    step: usize,
+    child: tokio::process::Child,
+    started: Instant,
+    wall_time_limit: Duration,
+}
+
+pub(crate) struct Executor {
+    work_dir: PathBuf,
+    keep_work_dirs: bool,
+}
+
+impl Executor {
+    pub(crate) fn new(work_dir: PathBuf, keep_work_dirs: bool) -> anyhow::Result<Executor> {
+        std::fs::create_dir_all(&work_dir)
+            .with_context(|| format!("failed to create {}", work_dir.display()))?;
+        Ok(Executor {
+            work_dir,
+            keep_work_dirs,
+        })
+    }
+
+    pub(crate) async fn exec(&self, req: InvokeRequest) -> anyhow::Result<InvokeResponse> {
+        let dir = self.work_dir.join(req.id.to_string());
+        tracing::info!(request_id = %req.id, steps = req.steps.len(), "executing request");
+        let res = match RequestState::new(dir.clone(), &req) {
+            Ok(state) => state.run(&req).await,
+            Err(err) => Err(err),
+        };
+        if !self.keep_work_dirs {
+            if let Err(err) = std::fs::remove_dir_all(&dir) {
+                tracing::warn!("failed to remove {}: {}", dir.display(), err);
+            }
+        }
+        res
+    }
+}
+
+struct RequestState {
+    dir: PathBuf,
+    substitutions: HashMap<String, String>,
+    files: HashMap<String, FileEntry>,
+    volumes: HashSet<String>,
+    sandboxes: HashMap<String, Sandbox>,
+}
+
+fn decode_source(source: &InputSource) -> anyhow::Result<Vec<u8>> {
+    match source {
+        InputSource::InlineBase64 { data } => base64::decode(data).context("invalid base64"),
+        InputSource::LocalFile { path } => {
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))
+        }
+    }
+}
+
+fn write_file(path: &Path, data: &[u8], executable: bool) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    std::fs::write(path, data).with_context(|| format!("failed to write {}", path.display()))?;
+    if executable {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("failed to make {} executable", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Converts exit status to the invoker convention: negated signal number
+/// for killed processes
+fn exit_code(status: std::process::ExitStatus) -> i64 {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => i64::from(code),
+        (None, Some(signal)) => -i64::from(signal),
+        (None, None) => -1,
+    }
+}
+
+fn spawn_failed(err: &anyhow::Error) -> CommandResult {
+    CommandResult {
+        exit_code: -1,
+        spawn_error: Some(format!("{:#}", err)),
+        cpu_time: None,
+        memory: None,
+    }
+}
+
+async fn wait(mut running: Running) -> (usize, CommandResult) {
+    let started = running.started;
+    let status = match tokio::time::timeout(running.wall_time_limit, running.child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            // kill() also reaps the child
+            if let Err(err) = running.child.kill().await {
+                tracing::warn!("failed to kill command: {}", err);
+            }
+            return (
+                running.step,
+                CommandResult {
+                    exit_code: -9,
+                    spawn_error: None,
+                    cpu_time: Some(started.elapsed().as_nanos() as u64),
+                    memory: None,
+                },
+            );
+        }
+    };
+    let result = match status {
+        Ok(status) => CommandResult {
+            exit_code: exit_code(status),
+            spawn_error: None,
+            cpu_time: Some(started.elapsed().as_nanos() as u64),
+            memory: None,
+        },
+        Err(err) => spawn_failed(&anyhow::Error::new(err).context("failed to wait for command")),
+    };
+    (running.step, result)
+}
+
+impl RequestState {
+    fn new(dir: PathBuf, req: &InvokeRequest) -> anyhow::Result<RequestState> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let shim_ext: Option<RequestExtensions> =
+            req.ext.get().context("invalid request extensions")?;
+        let mut substitutions = HashMap::new();
+        if let Some(shim_ext) = shim_ext {
+            for (name, file) in &shim_ext.extra_files {
+                let data = decode_source(&file.contents)
+                    .with_context(|| format!("invalid extra file {}", name))?;
+                write_file(
+                    &dir.join(EXTRA_FILES_DIR_NAME).join(name),
+                    &data,
+                    file.executable,
+                )?;
+            }
+            substitutions = shim_ext.substitutions;
+        }
+        std::fs::create_dir_all(dir.join(EXTRA_FILES_DIR_NAME))
+            .context("failed to create extra files directory")?;
+        let mut state = RequestState {
+            dir,
+            substitutions,
+            files: HashMap::new(),
+            volumes: HashSet::new(),
+            sandboxes: HashMap::new(),
+        };
+        for input in &req.inputs {
+            let data = decode_source(&input.source)
+                .with_context(|| format!("invalid input {}", input.file_id.0))?;
+            let path = state.new_file_path();
+            write_file(&path, &data, false)?;
+            state
+                .files
+                .insert(input.file_id.0.clone(), FileEntry::Disk(path));
+        }
+        Ok(state)
+    }
+
+    fn new_file_path(&self) -> PathBuf {
+        self.dir.join("files").join(self.files.len().to_string())
+    }
+
+    /// Expands `$(Name)` placeholders
+    fn substitute(&self, value: &str) -> String {
+        let mut value = value.to_string();
+        for (name, replacement) in &self.substitutions {
+            value = value.replace(&format!("$({})", name), replacement);
+        }
+        value
+    }
+
+    fn resolve(&self, path: &PrefixedPath) -> anyhow::Result<PathBuf> {
+        let base = match &path.prefix {
+            PathPrefix::Host => return Ok(path.path.clone()),
+            PathPrefix::Volume(name) => {
+                if !self.volumes.contains(name) {
+                    anyhow::bail!("volume {} does not exist", name);
+                }
+                self.dir.join("volumes").join(name)
+            }
+            PathPrefix::Extension(ext) => {
+                let source: SharedDirExtensionSource = ext
+                    .get()
+                    .context("invalid shared directory source")?
+                    .context("unsupported path prefix")?;
+                if source.name != EXTRA_FILES_DIR_NAME {
+                    anyhow::bail!("unknown shared directory {}", source.name);
+                }
+                self.dir.join(EXTRA_FILES_DIR_NAME)
+            }
+        };
+        Ok(base.join(&path.path))
+    }
+
+    fn file(&self, id: &str) -> anyhow::Result<&FileEntry> {
+        self.files
+            .get(id)
+            .with_context(|| format!("file {} does not exist", id))
+    }
+
+    fn stdio(&self, id: &str, write: bool) -> anyhow::Result<Stdio> {
+        Ok(match self.file(id)? {
+            FileEntry::Null => Stdio::null(),
+            FileEntry::Disk(path) => {
+                let file = OpenOptions::new()
+                    .read(!write)
+                    .append(write)
+                    .open(path)
+                    .with_context(|| format!("failed to open {}", path.display()))?;
+                file.into()
+            }
+            FileEntry::PipeRead(reader) if !write => reader.try_clone()?.into(),
+            FileEntry::PipeWrite(writer) if write => writer.try_clone()?.into(),
+            _ => anyhow::bail!("pipe end {} is used in a wrong direction", id),
+        })
+    }
+
+    fn create_sandbox(&mut self, settings: &SandboxSettings) -> anyhow::Result<()> {
+        let root = self.dir.join("sandboxes").join(&settings.name);
+        std::fs::create_dir_all(&root)
+            .with_context(|| format!("failed to create {}", root.display()))?;
+        let mut mounts = Vec::new();
+        for shared in &settings.expose {
+            let host_path = self.resolve(&shared.host_path)?;
+            if shared.create {
+                std::fs::create_dir_all(&host_path)
+                    .with_context(|| format!("failed to create {}", host_path.display()))?;
+            }
+            mounts.push((shared.sandbox_path.clone(), host_path));
+        }
+        mounts.sort_by_key(|(sandbox_path, _)| std::cmp::Reverse(sandbox_path.as_os_str().len()));
+        let time_limit = Duration::from_millis(settings.limits.time);
+        self.sandboxes.insert(
+            settings.name.clone(),
+            Sandbox {
+                root,
+                mounts,
+                wall_time_limit: time_limit * WALL_TIME_FACTOR + WALL_TIME_GRACE,
+            },
+        );
+        Ok(())
+    }
+
+    fn spawn(&self, step: usize, cmd: &Command) -> anyhow::Result<Running> {
+        let sandbox = self
+            .sandboxes
+            .get(&cmd.sandbox_name)
+            .with_context(|| format!("sandbox {} does not exist", cmd.sandbox_name))?;
+        let argv: Vec<String> = cmd
+            .argv
+            .iter()
+            .map(|arg| sandbox.translate(&self.substitute(arg)))
+            .collect();
+        let program = argv.first().context("argv is empty")?;
+        let mut command = tokio::process::Command::new(program);
+        command
+            .args(&argv[1..])
+            .current_dir(sandbox.working_dir(&self.substitute(&cmd.cwd))?)
+            .stdin(self.stdio(&cmd.stdio.stdin.0, false)?)
+            .stdout(self.stdio(&cmd.stdio.stdout.0, true)?)
+            .stderr(self.stdio(&cmd.stdio.stderr.0, true)?)
+            .kill_on_drop(true);
+        // files passed in environment are inherited as descriptors
+        let mut inherited = Vec::new();
+        for var in &cmd.env {
+            let value = match &var.value {
+                EnvVarValue::Plain(value) => sandbox.translate(&self.substitute(value)),
+                EnvVarValue::File(id) => {
+                    let file = match self.file(&id.0)? {
+                        FileEntry::Disk(path) => OpenOptions::new()
+                            .read(true)
+                            .write(true)
+                            .open(path)
+                            .with_context(|| format!("failed to open {}", path.display()))?,
+                        _ => anyhow::bail!("file {} can not be passed in environment", id.0),
+                    };
+                    let fd = file.as_raw_fd();
+                    inherited.push(file);
+                    fd.to_string()
+                }
+            };
+            command.env(&var.name, value);
+        }
+        let fds: Vec<RawFd> = inherited.iter().map(File::as_raw_fd).collect();
+        // SAFETY: only async-signal-safe fcntl is called in the child
+        unsafe {
+            command.pre_exec(move || {
+                for &fd in &fds {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        let child = command
+            .spawn()
+            .with_context(|| format!("failed to spawn {}", program))?;
+        // child has its own copies of the descriptors now
+        drop(inherited);
+        Ok(Running {
+            step,
+            child,
+            started: Instant::now(),
+            wall_time_limit: sandbox.wall_time_limit,
+        })
+    }
+
+    /// Performs non-command action
+    fn perform(&mut self, action: &Action) -> anyhow::Result<()> {
+        match action {
+            Action::OpenNullFile { id } => {
+                self.files.insert(id.0.clone(), FileEntry::Null);
+            }
+            Action::CreateFile { id, .. } => {
+                let path = self.new_file_path();
+                write_file(&path, &[], false)?;
+                self.files.insert(id.0.clone(), FileEntry::Disk(path));
+            }
+            Action::OpenFile { path, id } => {
+                let path = self.resolve(path)?;
+                self.files.insert(id.0.clone(), FileEntry::Disk(path));
+            }
+            Action::CreatePipe { read, write } => {
+                let (reader, writer) = os_pipe::pipe().context("failed to create pipe")?;
+                self.files
+                    .insert(read.0.clone(), FileEntry::PipeRead(reader));
+                self.files
+                    .insert(write.0.clone(), FileEntry::PipeWrite(writer));
+            }
+            Action::CreateVolume(settings) => {
+                let path = self.dir.join("volumes").join(&settings.name);
+                std::fs::create_dir_all(&path)
+                    .with_context(|| format!("failed to create {}", path.display()))?;
+                self.volumes.insert(settings.name.clone());
+            }
+            Action::CreateSandbox(settings) => self.create_sandbox(settings)?,
+            Action::ExecuteCommand(_) => unreachable!(),
+        }
+        Ok(())
+    }
+
+    async fn run(mut self, req: &InvokeRequest) -> anyhow::Result<InvokeResponse> {
+        let mut results: Vec<ActionResult> = vec![ActionResult::Other; req.steps.len()];
+        let stages: BTreeSet<u32> = req.steps.iter().map(|step| step.stage).collect();
+        for stage in stages {
+            let mut running = Vec::new();
+            for (i, step) in req.steps.iter().enumerate() {
+                if step.stage != stage {
+                    continue;
+                }
+                match &step.action {
+                    Action::ExecuteCommand(cmd) => match self.spawn(i, cmd) {
+                        Ok(r) => running.push(r),
+                        Err(err) => {
+                            tracing::info!("command was not started: {:#}", err);
+                            results[i] = ActionResult::ExecuteCommand(spawn_failed(&err));
+                        }
+                    },
+                    action => self
+                        .perform(action)
+                        .with_context(|| format!("step {} failed", i))?,
+                }
+            }
+            // otherwise readers never see end of file
+            self.files
+                .retain(|_, f| !matches!(f, FileEntry::PipeRead(_) | FileEntry::PipeWrite(_)));
+            for (step, result) in futures::future::join_all(running.into_iter().map(wait)).await {
+                results[step] = ActionResult::ExecuteCommand(result);
+            }
+        }
+        let mut outputs = Vec::new();
+        for out in &req.outputs {
+            let path = match &out.target {
+                OutputRequestTarget::File(id) => match self.file(&id.0)? {
+                    FileEntry::Disk(path) => Some(path.clone()),
+                    FileEntry::Null => None,
+                    _ => anyhow::bail!("output {} refers to a pipe", out.name),
+                },
+                OutputRequestTarget::Path(path) => Some(self.resolve(path)?),
+            };
+            let data = match path {
+                Some(path) => std::fs::read(&path)
+                    .with_context(|| format!("failed to read output {}", out.name))?,
+                None => Vec::new(),
+            };
+            outputs.push(Output {
+                name: out.name.clone(),
+                data: OutputData::InlineBase64(base64::encode(&data)),
+            });
+        }
+        Ok(InvokeResponse {
+            id: req.id,
+            actions: results,
+            outputs,
+        })
+    }
+}
@@ -0,0 +1,83 @@
+//! Fake invoker for local development and CI.
+//!
+//! Implements invoker HTTP API (`GET /capabilities` and `POST /exec`), but
+//! runs commands as plain subprocesses of this server. There is NO
+//! isolation: commands see the whole host filesystem, can use network,
+//! and are not limited in memory or process count. Never run it on
+//! machines which judge untrusted code.
+//!
+//! Sandbox images are ignored, so compilers and interpreters used by
+//! toolchains must be installed on the host.
+
+mod exec;
+
+use clap::Clap;
+use std::{convert::Infallible, net::IpAddr, path::PathBuf, sync::Arc};
+use warp::{http::StatusCode, Filter};
+
+/// Invoker which executes commands without any sandboxing.
+/// UNSAFE FOR PRODUCTION.
+#[derive(Clap)]
+struct Args {
+    /// Address that fake invoker should listen. Anyone who can connect
+    /// can run arbitrary commands on the host, so only change it if the
+    /// network is trusted.
+    #[clap(long, default_value = "127.0.0.1")]
+    listen: IpAddr,
+    /// Port that fake invoker should listen
+    #[clap(long, default_value = "8000")]
+    port: u16,
+    /// Directory for files of requests being executed
+    #[clap(long, default_value = "/tmp/jjs-fake-invoker")]
+    work_dir: PathBuf,
+    /// Do not remove files of finished requests, for debugging
+    #[clap(long)]
+    keep_work_dirs: bool,
+}
+
+async fn route_exec(
+    executor: Arc<exec::Executor>,
+    req: invoker_api::invoke::InvokeRequest,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let id = req.id;
+    match executor.exec(req).await {
+        Ok(resp) => Ok(Box::new(warp::reply::json(&resp))),
+        Err(err) => {
+            tracing::warn!(request_id = %id, "request failed: {:#}", err);
+            Ok(Box::new(warp::reply::with_status(
+                format!("{:#}", err),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )))
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+    let args: Args = Clap::parse();
+    tracing::warn!("fake invoker runs commands WITHOUT SANDBOXING; do not use it in production");
+
+    let executor = Arc::new(exec::Executor::new(args.work_dir, args.keep_work_dirs)?);
+
+    let route_capabilities = warp::get()
+        .and(warp::path("capabilities"))
+        .and(warp::path::end())
+        // none of the optional features are implemented
+        .map(|| warp::reply::json(&invoker_client::Capabilities::default()));
+
+    let route_exec = warp::post()
+        .and(warp::path("exec"))
+        .and(warp::path::end())
+        .and(warp::filters::body::json())
+        .and_then(move |req| route_exec(executor.clone(), req));
+
+    let routes = route_capabilities
+        .or(route_exec)
+        .with(warp::filters::trace::request());
+    tracing::info!(address = %args.listen, port = args.port, "serving");
+    warp::serve(routes).run((args.listen, args.port)).await;
+    Ok(())
+}
@@ -1,41 +1,350 @@
 //! Allows you to send InvokeRequest's to one or several invokers.
+//!
+//! If several pools are configured, requests are distributed between them
+//! according to the [`Policy`]. Pool which fails to accept a request is
+//! skipped for a while, and the request is sent to the next pool.
+//!
+//! Pool which responds with 503 or 429 is saturated: its queue is full,
+//! but it is healthy. If all pools are unhealthy or saturated and at least
+//! one of them is saturated, the request is resent after a growing delay
+//! instead of failing (see [`ClientBuilder::saturation_timeout`]).
+//!
+//! Pools can have labels (e.g. `jjs.io/arch: arm64`). Client restricted
+//! with [`Client::with_labels`] only uses pools which have all given labels.
+//!
+//! Once invoker keeps some state for a client (see [`Client::pin`]), the
+//! client is pinned to that pool and requests are not sent to other pools.
+//!
+//! Traffic can be recorded and later replayed without invokers, see
+//! [`Client::recording`] and [`Client::replay`].
 
-use std::sync::Arc;
+mod replay;
+mod scheduler;
+
+pub use replay::{RecordedCall, Recorder, Recording};
+pub use scheduler::Policy;
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
 use invoker_api::invoke::{InvokeRequest, InvokeResponse};
+use replay::Replayer;
+use reqwest::StatusCode;
+use scheduler::{PoolState, Scheduler};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// Labels of a pool, or labels which selected pools must have
+pub type Labels = BTreeMap<String, String>;
+
+/// Called with `true` each time a request is delayed because all pools
+/// are saturated, and with `false` when the delayed request is accepted
+/// or fails. Request waits until the returned future completes.
+pub type CapacityListener =
+    Arc<dyn Fn(bool) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// Called after each invoke request sent to invokers, with the request
+/// and the response or the error message. Response is returned to the
+/// caller after the returned future completes.
+pub type CallListener = Arc<
+    dyn Fn(
+            InvokeRequest,
+            Result<InvokeResponse, String>,
+        ) -> Pin<Box<dyn Future<Output = ()> + Send>>
+        + Send
+        + Sync,
+>;
+
 /// Like a database connection pool, but for invokers.
 #[derive(Clone)]
 pub struct Client {
-    pools: Arc<[PoolInner]>,
+    scheduler: Arc<Scheduler>,
     transport: reqwest::Client,
+    /// Pool which is used while it is healthy
+    affinity: Option<usize>,
+    /// Pool which keeps state of the client, shared by clones of the
+    /// client. Only set for clients returned by `with_affinity`.
+    pinned: Option<Arc<Mutex<Option<usize>>>>,
+    /// Only pools with all these labels are used
+    selector: Arc<Labels>,
+    /// If set, successful calls are recorded
+    recorder: Option<Recorder>,
+    /// If set, calls are answered from a recording instead of invokers
+    replayer: Option<Arc<Replayer>>,
+    capacity_listener: Option<CapacityListener>,
+    call_listener: Option<CallListener>,
+    /// See `ClientBuilder::max_response_size`
+    max_response_size: Option<u64>,
 }
 
 impl Client {
     /// Creates a new builder.
     pub fn builder() -> ClientBuilder {
-        ClientBuilder { pools: Vec::new() }
+        ClientBuilder {
+            pools: Vec::new(),
+            connect_timeout: None,
+            request_timeout: None,
+            policy: Policy::default(),
+            unhealthy_cooldown: scheduler::DEFAULT_UNHEALTHY_COOLDOWN,
+            saturation_timeout: scheduler::DEFAULT_SATURATION_TIMEOUT,
+            max_response_size: None,
+        }
     }
 
     /// Attempts to connect to a invoker instance according to the
     /// configured pools.
     pub fn instance(&self) -> anyhow::Result<Instance> {
-        let pool = self.pools.first().context("no pools configured")?;
-        let inst = match pool {
-            PoolInner::Http { addr } => Instance {
-                address: addr.clone(),
-                transport: self.transport.clone(),
-            },
+        let pool = match self.pinned_pool() {
+            Some(pool) => pool.0,
+            None => self.select_pool()?,
         };
-        Ok(inst)
+        Ok(Instance {
+            client: self.clone(),
+            pool,
+        })
+    }
+
+    /// Returns instance which sends requests to `pool`, e.g. to release
+    /// resources created there.
+    pub fn instance_in(&self, pool: PoolId) -> Instance {
+        Instance {
+            client: self.clone(),
+            pool: pool.0,
+        }
+    }
+
+    fn select_pool(&self) -> anyhow::Result<usize> {
+        if self.replayer.is_some() {
+            return Ok(0);
+        }
+        self.scheduler
+            .select(self.affinity, &[], &self.selector)
+            .with_context(|| {
+                if self.selector.is_empty() {
+                    "no pools configured".to_string()
+                } else {
+                    format!("no pools have labels {:?}", self.selector)
+                }
+            })
+    }
+
+    /// Returns client which sends all requests to the same pool while it
+    /// is healthy. Invoker-side state (persisted files, reused sandboxes)
+    /// is not shared between pools, so requests of one job should use
+    /// such a client.
+    pub fn with_affinity(&self) -> Client {
+        Client {
+            affinity: self.scheduler.select(self.affinity, &[], &self.selector),
+            pinned: Some(Arc::new(Mutex::new(None))),
+            ..self.clone()
+        }
+    }
+
+    /// Pins the client (and all its clones) to one pool. Must be called
+    /// before sending a request which makes invoker keep some state, e.g.
+    /// persist an output or keep a sandbox for reuse. Requests of pinned
+    /// client are only sent to that pool: if it fails, requests fail
+    /// instead of going to pools which do not have the state. Until the
+    /// client is pinned, failed requests are sent to other pools.
+    ///
+    /// Only clients returned by `with_affinity` can be pinned.
+    pub fn pin(&self) -> anyhow::Result<PoolId> {
+        if self.replayer.is_some() {
+            return Ok(PoolId(0));
+        }
+        let pinned = self
+            .pinned
+            .as_ref()
+            .context("client without affinity can not be pinned")?;
+        let mut pinned = pinned.lock().unwrap();
+        if let Some(pool) = *pinned {
+            return Ok(PoolId(pool));
+        }
+        let pool = self.select_pool()?;
+        *pinned = Some(pool);
+        Ok(PoolId(pool))
+    }
+
+    /// Pins the client to `pool`, which keeps state created by another
+    /// client (e.g. by a previous job). Fails if the client is pinned to
+    /// another pool, or if `pool` can not be used by the client now.
+    pub fn pin_to(&self, pool: PoolId) -> anyhow::Result<()> {
+        if self.replayer.is_some() {
+            return Ok(());
+        }
+        let pinned = self
+            .pinned
+            .as_ref()
+            .context("client without affinity can not be pinned")?;
+        let state = self
+            .scheduler
+            .pools
+            .get(pool.0)
+            .context("pool does not exist")?;
+        if !state.matches(&self.selector) {
+            anyhow::bail!(
+                "pool {} does not have labels {:?}",
+                state.addr,
+                self.selector
+            );
+        }
+        if !state.is_healthy(Instant::now()) {
+            anyhow::bail!("pool {} failed recently", state.addr);
+        }
+        let mut pinned = pinned.lock().unwrap();
+        match *pinned {
+            Some(other) if other != pool.0 => anyhow::bail!(
+                "client is already pinned to pool {}",
+                self.scheduler.pools[other].addr
+            ),
+            _ => *pinned = Some(pool.0),
+        }
+        Ok(())
+    }
+
+    /// Returns pool the client is pinned to, if it is
+    pub fn pinned_pool(&self) -> Option<PoolId> {
+        let pinned = self.pinned.as_ref()?;
+        let pool = *pinned.lock().unwrap();
+        pool.map(PoolId)
+    }
+
+    /// Returns client which only uses pools that have all labels from
+    /// `selector`. Labels of the client are replaced, not extended. Should
+    /// be called before `with_affinity`.
+    pub fn with_labels(&self, selector: Labels) -> Client {
+        Client {
+            selector: Arc::new(selector),
+            affinity: None,
+            pinned: None,
+            ..self.clone()
+        }
+    }
+
+    /// Checks if some pool has label `key`
+    pub fn is_label(&self, key: &str) -> bool {
+        self.scheduler
+            .pools
+            .iter()
+            .any(|pool| pool.labels.contains_key(key))
+    }
+
+    /// Checks if requests of this client can be sent to some pool
+    pub fn has_matching_pool(&self) -> bool {
+        self.replayer.is_some()
+            || self
+                .scheduler
+                .pools
+                .iter()
+                .any(|pool| pool.matches(&self.selector))
+    }
+
+    /// Returns client which records all successful calls to `recorder`.
+    /// Persisted outputs stay on invoker and can not be replayed, so this
+    /// client reports that invoker can not persist outputs.
+    pub fn recording(&self, recorder: Recorder) -> Client {
+        Client {
+            recorder: Some(recorder),
+            ..self.clone()
+        }
+    }
+
+    /// Returns client which reports waiting for invoker capacity to
+    /// `listener`.
+    pub fn on_capacity_wait(&self, listener: CapacityListener) -> Client {
+        Client {
+            capacity_listener: Some(listener),
+            ..self.clone()
+        }
+    }
+
+    /// Returns client which reports every call to `listener`, e.g. to
+    /// dump invoker traffic for troubleshooting.
+    pub fn on_call(&self, listener: CallListener) -> Client {
+        Client {
+            call_listener: Some(listener),
+            ..self.clone()
+        }
+    }
+
+    /// Returns client without listeners set by `on_capacity_wait` and
+    /// `on_call`. Listeners usually belong to a job, so clients which
+    /// outlive the job must not keep them.
+    pub fn without_listeners(&self) -> Client {
+        Client {
+            capacity_listener: None,
+            call_listener: None,
+            ..self.clone()
+        }
+    }
+
+    async fn notify_capacity_wait(&self, waiting: bool) {
+        if let Some(listener) = &self.capacity_listener {
+            listener(waiting).await;
+        }
+    }
+
+    /// Creates client which does not connect to invokers. Calls are
+    /// answered with responses from `recording`, in the recorded order.
+    /// Call which does not match the recorded one fails.
+    pub fn replay(recording: Recording) -> Client {
+        let mut client = Client::builder().build();
+        client.replayer = Some(Arc::new(Replayer::new(recording)));
+        client
+    }
+
+    /// Checks all configured pools and returns number of healthy ones.
+    /// Each pool is counted as one invoker.
+    pub async fn healthy_pools(&self) -> usize {
+        if self.replayer.is_some() {
+            return 1;
+        }
+        let mut healthy = 0;
+        for pool in 0..self.scheduler.pools.len() {
+            let inst = Instance {
+                client: self.clone(),
+                pool,
+            };
+            if inst.check_health().await.is_ok() {
+                healthy += 1;
+            }
+        }
+        healthy
+    }
+
+    /// Returns number of invokers in the pools this client may use,
+    /// skipping pools which failed recently. Unlike `healthy_pools`, does
+    /// not send requests.
+    pub fn available_invokers(&self) -> usize {
+        if self.replayer.is_some() {
+            return 1;
+        }
+        let now = Instant::now();
+        self.scheduler
+            .pools
+            .iter()
+            .filter(|pool| pool.matches(&self.selector) && pool.is_healthy(now))
+            .map(|pool| pool.invokers)
+            .sum()
     }
 }
 
 /// The builder for `Client`.
 pub struct ClientBuilder {
     pools: Vec<PoolInner>,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    policy: Policy,
+    unhealthy_cooldown: Duration,
+    saturation_timeout: Duration,
+    max_response_size: Option<u64>,
 }
 
 impl ClientBuilder {
@@ -44,17 +353,150 @@ impl ClientBuilder {
         self.pools.push(pool.0);
     }
 
+    /// Sets timeout for establishing connection to an invoker.
+    pub fn connect_timeout(&mut self, timeout: Duration) {
+        self.connect_timeout = Some(timeout);
+    }
+
+    /// Sets timeout for the whole invoker call, including
+    /// receiving response.
+    pub fn request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = Some(timeout);
+    }
+
+    /// Sets how requests are distributed between pools.
+    pub fn policy(&mut self, policy: Policy) {
+        self.policy = policy;
+    }
+
+    /// Sets for how long a pool which failed is skipped.
+    pub fn unhealthy_cooldown(&mut self, cooldown: Duration) {
+        self.unhealthy_cooldown = cooldown;
+    }
+
+    /// Sets for how long a request waits while all pools are saturated.
+    /// After that the request fails with `SaturatedError`.
+    pub fn saturation_timeout(&mut self, timeout: Duration) {
+        self.saturation_timeout = timeout;
+    }
+
+    /// Sets maximal size of an invoker response in bytes. Larger
+    /// responses are not read to the end, and the call fails with
+    /// `ResponseTooLarge`.
+    pub fn max_response_size(&mut self, size: u64) {
+        self.max_response_size = Some(size);
+    }
+
     /// Builds a client
     pub fn build(self) -> Client {
+        let mut transport = reqwest::Client::builder();
+        if let Some(t) = self.connect_timeout {
+            transport = transport.connect_timeout(t);
+        }
+        if let Some(t) = self.request_timeout {
+            transport = transport.timeout(t);
+        }
+        let pools = self
+            .pools
+            .into_iter()
+            .map(|pool| match pool {
+                PoolInner::Http {
+                    addr,
+                    labels,
+                    invokers,
+                } => PoolState::new(addr, labels, invokers),
+            })
+            .collect();
         Client {
-            pools: self.pools.into(),
-            transport: reqwest::Client::new(),
+            scheduler: Arc::new(Scheduler::new(
+                pools,
+                self.policy,
+                self.unhealthy_cooldown,
+                self.saturation_timeout,
+            )),
+            // same as `reqwest::Client::new`, which panics too
+            transport: transport.build().expect("failed to initialize HTTP client"),
+            affinity: None,
+            pinned: None,
+            selector: Arc::new(Labels::new()),
+            recorder: None,
+            replayer: None,
+            capacity_listener: None,
+            call_listener: None,
+            max_response_size: self.max_response_size,
         }
     }
 }
 
+/// Returned (wrapped in `anyhow::Error`) when invoker response is larger
+/// than `ClientBuilder::max_response_size`.
+#[derive(Debug)]
+pub struct ResponseTooLarge {
+    pub limit: u64,
+}
+
+impl std::fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invoker response is larger than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for ResponseTooLarge {}
+
+impl ResponseTooLarge {
+    /// Checks if the error was caused by too large response
+    pub fn is_cause_of(err: &anyhow::Error) -> bool {
+        err.chain().any(|e| e.is::<ResponseTooLarge>())
+    }
+}
+
+/// Returned (wrapped in `anyhow::Error`) when invoker did not respond
+/// in time.
+#[derive(Debug)]
+pub struct TimeoutError;
+
+impl std::fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invoker request timed out")
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+impl TimeoutError {
+    /// Checks if the error was caused by invoker timeout
+    pub fn is_cause_of(err: &anyhow::Error) -> bool {
+        err.chain().any(|e| e.is::<TimeoutError>())
+    }
+}
+
+/// Returned (wrapped in `anyhow::Error`) when all pools stayed saturated
+/// for longer than the saturation timeout.
+#[derive(Debug)]
+pub struct SaturatedError;
+
+impl std::fmt::Display for SaturatedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("all invoker pools are saturated")
+    }
+}
+
+impl std::error::Error for SaturatedError {}
+
+fn map_transport_error(err: reqwest::Error, action: &'static str) -> anyhow::Error {
+    if err.is_timeout() {
+        anyhow::Error::new(TimeoutError)
+    } else {
+        anyhow::Error::new(err).context(action)
+    }
+}
+
 enum PoolInner {
-    Http { addr: String },
+    Http {
+        addr: String,
+        labels: Labels,
+        invokers: usize,
+    },
 }
 
 /// A set of invokers
@@ -67,34 +509,458 @@ impl Pool {
     pub fn new_from_address(address: &str) -> Pool {
         Pool(PoolInner::Http {
             addr: address.to_string(),
+            labels: Labels::new(),
+            invokers: 1,
         })
     }
+
+    /// Sets number of invokers behind the address (e.g. behind a
+    /// load-balancer), which is 1 by default. Only used to decide how
+    /// many requests are sent concurrently.
+    pub fn invokers(&mut self, count: usize) {
+        match &mut self.0 {
+            PoolInner::Http { invokers, .. } => *invokers = count.max(1),
+        }
+    }
+
+    /// Adds a label to the pool, replacing previous value of the label.
+    pub fn label(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        match &mut self.0 {
+            PoolInner::Http { labels, .. } => labels.insert(key.into(), value.into()),
+        };
+    }
+}
+
+/// Optional features supported by invoker
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Capabilities {
+    /// If set, invoker can keep outputs between requests (see
+    /// `PersistOutputExtension`). Persisted outputs can be referenced in
+    /// subsequent requests as local files in this directory.
+    #[serde(default)]
+    pub persistent_files_dir: Option<PathBuf>,
+    /// Names of optional extensions invoker understands
+    /// (e.g. `sandbox-network`)
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+/// `OutputRequest` extension asking invoker to keep the output
+/// instead of returning it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PersistOutputExtension {
+    /// Name of the file in `Capabilities::persistent_files_dir`
+    pub persist_as: String,
+}
+
+/// `OutputRequest` extensions. Superset of `PersistOutputExtension`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OutputExtensions {
+    #[serde(flatten)]
+    pub persist: Option<PersistOutputExtension>,
+    /// If set, invoker returns (or persists) at most this many first
+    /// bytes of the output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+}
+
+/// `SandboxSettings` extension. Superset of
+/// `invoker_api::shim::SandboxSettingsExtensions`.
+#[derive(Serialize)]
+pub struct SandboxExtensions {
+    #[serde(flatten)]
+    pub shim: invoker_api::shim::SandboxSettingsExtensions,
+    /// If true, sandbox is given network access
+    pub network: bool,
+    /// If set, invoker keeps the sandbox after the request, and later
+    /// requests with the same key reuse it instead of creating a new one.
+    /// Sandbox state is reset before each reuse. Unused sandboxes are
+    /// destroyed by invoker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reuse_key: Option<String>,
+    /// If set, sandbox file system is read-only, except for these paths
+    /// and exposed directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writable_paths: Option<Vec<PathBuf>>,
+    /// If set, core dumps of sandboxed commands are enabled or disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core_dumps: Option<bool>,
+}
+
+/// `Command` extension asking invoker to periodically sample memory
+/// usage of the command. Samples are written to the file `output` as
+/// a JSON array of `{"time": <ms since start>, "memory": <bytes>}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MemorySamplingExtension {
+    pub interval_ms: u64,
+    /// Id of the file created by a previous step
+    pub output: invoker_api::invoke::FileId,
+}
+
+/// `Command` extension asking invoker to report attempts of the command
+/// to escape its sandbox (e.g. writes outside of allowed directories or
+/// blocked syscalls). Violations are written to the file
+/// `security_report` as a JSON array of
+/// `{"kind": <kind>, "detail": <details>}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SecurityReportExtension {
+    /// Id of the file created by a previous step
+    pub security_report: invoker_api::invoke::FileId,
+}
+
+/// `Command` extension asking invoker to report why the command
+/// terminated. The report is written to the file `termination_report`
+/// as `TerminationReport` JSON.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TerminationReportExtension {
+    /// Id of the file created by a previous step
+    pub termination_report: invoker_api::invoke::FileId,
+}
+
+/// Why a command terminated, as reported by invoker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TerminationCause {
+    /// Command exited by itself
+    Exited,
+    /// Command was killed by a signal it did not get from the sandbox
+    /// (e.g. it aborted or killed itself)
+    Signaled,
+    /// Sandbox killed the command for exceeding CPU time limit
+    TimeLimit,
+    /// Sandbox killed the command for exceeding wall-clock time limit
+    WallTimeLimit,
+    /// Sandbox killed the command for exceeding memory limit
+    MemoryLimit,
+    /// Sandbox killed the command for exceeding process count limit
+    ProcessLimit,
+}
+
+/// Contents of the file requested with `TerminationReportExtension`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TerminationReport {
+    pub cause: TerminationCause,
+    /// Signal which killed the command, if it was killed
+    #[serde(default)]
+    pub signal: Option<i64>,
+}
+
+/// `Command` extensions. Superset of `MemorySamplingExtension`,
+/// `SecurityReportExtension` and `TerminationReportExtension`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CommandExtensions {
+    #[serde(flatten)]
+    pub memory_sampling: Option<MemorySamplingExtension>,
+    #[serde(flatten)]
+    pub security_report: Option<SecurityReportExtension>,
+    #[serde(flatten)]
+    pub termination_report: Option<TerminationReportExtension>,
+}
+
+/// Invoker-side resource which outlives the request that created it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Resource {
+    /// Sandbox kept for reuse (see `SandboxExtensions::reuse_key`)
+    Sandbox { reuse_key: String },
+    /// Output kept in the persistent files directory (see
+    /// `PersistOutputExtension`)
+    PersistedFile { name: String },
+}
+
+/// Identifies a pool of the client, see `Client::pin`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PoolId(usize);
+
+/// Failure of a request sent to one pool
+enum CallError {
+    /// Pool could not process the request, another one can be tried
+    Unavailable(anyhow::Error),
+    /// Pool is healthy, but does not accept requests now
+    Saturated(anyhow::Error),
+    Other(anyhow::Error),
 }
 
 /// One invoker or several indistinguishable invokers
 pub struct Instance {
-    address: String,
-    transport: reqwest::Client,
+    client: Client,
+    /// Index of the pool
+    pool: usize,
 }
 
 impl Instance {
-    /// Sends an invokerequest
-    pub async fn call(&self, mut req: InvokeRequest) -> anyhow::Result<InvokeResponse> {
-        if !req.id.is_nil() {
-            anyhow::bail!("request id is not nil")
+    fn state(&self) -> &PoolState {
+        &self.client.scheduler.pools[self.pool]
+    }
+
+    /// Queries optional invoker features. Invokers which do not support
+    /// capabilities discovery are assumed to have no optional features.
+    pub async fn capabilities(&self) -> anyhow::Result<Capabilities> {
+        if let Some(replayer) = &self.client.replayer {
+            return Ok(replayer.capabilities());
         }
-        req.id = Uuid::new_v4();
-        let url = format!("{}/exec", self.address);
+        let mut caps = self.query_capabilities().await?;
+        if let Some(recorder) = &self.client.recorder {
+            caps.persistent_files_dir = None;
+            recorder.capabilities(&caps);
+        }
+        Ok(caps)
+    }
+
+    async fn query_capabilities(&self) -> anyhow::Result<Capabilities> {
+        let url = format!("{}/capabilities", self.state().addr);
+        let resp = self
+            .client
+            .transport
+            .get(url)
+            .send()
+            .await
+            .map_err(|err| map_transport_error(err, "failed to send request"))?;
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(Capabilities::default());
+        }
+        let resp = resp
+            .error_for_status()
+            .context("response is not successful")?
+            .json()
+            .await
+            .map_err(|err| map_transport_error(err, "failed to receive response"))?;
+        Ok(resp)
+    }
+
+    /// Checks that invoker responds to requests. Result is taken into
+    /// account when pools are selected.
+    pub async fn check_health(&self) -> anyhow::Result<()> {
+        if self.client.replayer.is_some() {
+            return Ok(());
+        }
+        let res = self.probe().await;
+        match &res {
+            Ok(()) => self.state().mark_healthy(),
+            Err(_) => self
+                .state()
+                .mark_unhealthy(self.client.scheduler.unhealthy_cooldown),
+        }
+        res
+    }
+
+    async fn probe(&self) -> anyhow::Result<()> {
+        let url = format!("{}/capabilities", self.state().addr);
         let resp = self
+            .client
+            .transport
+            .get(url)
+            .send()
+            .await
+            .map_err(|err| map_transport_error(err, "failed to send request"))?;
+        // invokers without capabilities discovery respond with 404
+        if resp.status().is_server_error() {
+            anyhow::bail!("invoker responded with {}", resp.status());
+        }
+        Ok(())
+    }
+
+    /// Asks invoker to destroy resources created by previous requests.
+    /// Resources which do not exist anymore are ignored by invoker.
+    /// Requires `resource-release` extension.
+    pub async fn release(&self, resources: &[Resource]) -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct ReleaseRequest<'a> {
+            resources: &'a [Resource],
+        }
+        if self.client.replayer.is_some() {
+            return Ok(());
+        }
+        let url = format!("{}/resources/release", self.state().addr);
+        self.client
             .transport
             .post(url)
-            .json(&req)
+            .json(&ReleaseRequest { resources })
             .send()
             .await
-            .context("failed to send request")?
+            .map_err(|err| map_transport_error(err, "failed to send request"))?
             .error_for_status()
             .context("response is not successful")?;
-        let resp = resp.json().await.context("failed to receive response")?;
+        Ok(())
+    }
+
+    /// Sends an invokerequest. If the pool is unreachable or fails with
+    /// a server error, it is marked as unhealthy and the request is sent
+    /// to other pools, unless the client is pinned. If all pools fail and
+    /// some of them are saturated, the request is resent later. Timed out
+    /// requests are not resent, since they may still be running.
+    pub async fn call(&self, mut req: InvokeRequest) -> anyhow::Result<InvokeResponse> {
+        if !req.id.is_nil() {
+            anyhow::bail!("request id is not nil")
+        }
+        req.id = Uuid::new_v4();
+        if let Some(replayer) = &self.client.replayer {
+            return replayer.call(&req);
+        }
+        let res = self.send(&req).await;
+        if let Some(listener) = &self.client.call_listener {
+            let outcome = match &res {
+                Ok(resp) => Ok(resp.clone()),
+                Err(err) => Err(format!("{:#}", err)),
+            };
+            listener(req.clone(), outcome).await;
+        }
+        let resp = res?;
+        if let Some(recorder) = &self.client.recorder {
+            recorder.call(&req, &resp);
+        }
         Ok(resp)
     }
+
+    async fn send(&self, req: &InvokeRequest) -> anyhow::Result<InvokeResponse> {
+        // set when the request is delayed for the first time
+        let mut waiting_since: Option<Instant> = None;
+        let res = self.send_with_backoff(req, &mut waiting_since).await;
+        if waiting_since.is_some() {
+            self.client.notify_capacity_wait(false).await;
+        }
+        res
+    }
+
+    async fn send_with_backoff(
+        &self,
+        req: &InvokeRequest,
+        waiting_since: &mut Option<Instant>,
+    ) -> anyhow::Result<InvokeResponse> {
+        let scheduler = &self.client.scheduler;
+        let selector = &self.client.selector;
+        let mut backoff = scheduler::INITIAL_SATURATION_BACKOFF;
+        let mut tried = Vec::new();
+        // pinned pool may have been chosen after this instance was created
+        let pinned = self.client.pinned_pool().map(|pool| pool.0);
+        let mut pool = pinned.unwrap_or(self.pool);
+        // true if some pool tried since the last delay was saturated
+        let mut saturated = false;
+        loop {
+            tried.push(pool);
+            let state = &scheduler.pools[pool];
+            let err = match self.call_pool(state, req).await {
+                Ok(resp) => {
+                    state.mark_healthy();
+                    state.mark_accepting();
+                    return Ok(resp);
+                }
+                Err(CallError::Unavailable(err)) => {
+                    state.mark_unhealthy(scheduler.unhealthy_cooldown);
+                    err
+                }
+                Err(CallError::Saturated(err)) => {
+                    state.mark_saturated(backoff);
+                    saturated = true;
+                    err
+                }
+                Err(CallError::Other(err)) => return Err(err),
+            };
+            if pinned.is_none() {
+                if let Some(next) = scheduler.select(None, &tried, selector) {
+                    pool = next;
+                    continue;
+                }
+            }
+            if !saturated {
+                if pinned.is_some() {
+                    return Err(err.context("invoker pool which keeps state of the job failed"));
+                }
+                return Err(err.context(format!("all {} invoker pools failed", tried.len())));
+            }
+            let since = *waiting_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= scheduler.saturation_timeout {
+                return Err(anyhow::Error::new(SaturatedError).context(format!(
+                    "waited for invoker capacity for {} seconds",
+                    since.elapsed().as_secs()
+                )));
+            }
+            tracing::warn!(
+                "all {} invoker pools are saturated or unhealthy, retrying in {:?}",
+                tried.len(),
+                backoff
+            );
+            self.client.notify_capacity_wait(true).await;
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(scheduler::MAX_SATURATION_BACKOFF);
+            tried.clear();
+            saturated = false;
+            pool = match pinned {
+                Some(pool) => pool,
+                None => scheduler
+                    .select(None, &[], selector)
+                    .context("no pools configured")?,
+            };
+        }
+    }
+
+    async fn call_pool(
+        &self,
+        state: &PoolState,
+        req: &InvokeRequest,
+    ) -> Result<InvokeResponse, CallError> {
+        let _in_flight = state.start_request();
+        let url = format!("{}/exec", state.addr);
+        let resp = match self.client.transport.post(url).json(req).send().await {
+            Ok(resp) => resp,
+            Err(err) => {
+                let unavailable = err.is_connect();
+                let err = map_transport_error(err, "failed to send request");
+                return Err(if unavailable {
+                    CallError::Unavailable(err)
+                } else {
+                    CallError::Other(err)
+                });
+            }
+        };
+        let resp = match resp.error_for_status() {
+            Ok(resp) => resp,
+            Err(err) => {
+                let status = err.status();
+                let err = anyhow::Error::new(err).context("response is not successful");
+                return Err(match status {
+                    Some(StatusCode::SERVICE_UNAVAILABLE) | Some(StatusCode::TOO_MANY_REQUESTS) => {
+                        CallError::Saturated(err)
+                    }
+                    Some(s) if s.is_server_error() => CallError::Unavailable(err),
+                    _ => CallError::Other(err),
+                });
+            }
+        };
+        let body = self.read_body(resp).await.map_err(CallError::Other)?;
+        serde_json::from_slice(&body)
+            .context("failed to parse response")
+            .map_err(CallError::Other)
+    }
+
+    /// Reads response body, failing if it exceeds the size limit
+    async fn read_body(&self, mut resp: reqwest::Response) -> anyhow::Result<Vec<u8>> {
+        let limit = self.client.max_response_size;
+        if let (Some(limit), Some(len)) = (limit, resp.content_length()) {
+            if len > limit {
+                return Err(ResponseTooLarge { limit }.into());
+            }
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = resp
+            .chunk()
+            .await
+            .map_err(|err| map_transport_error(err, "failed to receive response"))?
+        {
+            body.extend_from_slice(&chunk);
+            if let Some(limit) = limit {
+                if body.len() as u64 > limit {
+                    return Err(ResponseTooLarge { limit }.into());
+                }
+            }
+        }
+        Ok(body)
+    }
 }
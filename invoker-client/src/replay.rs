@@ -0,0 +1,110 @@
+//! Recording of invoker traffic and replaying it without invokers.
+//!
+//! Client created with [`Client::recording`] remembers every successful
+//! call and the reported capabilities. Later [`Client::replay`] answers
+//! the same calls from the recording in the same order, so that the
+//! processor can be run again without sandboxes, e.g. to reproduce a bug
+//! in transformation of logs or in scoring. Calls are matched by order,
+//! so both recorded and replayed jobs must send them sequentially.
+
+use crate::Capabilities;
+use anyhow::Context;
+use invoker_api::invoke::{InvokeRequest, InvokeResponse};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+/// One request which was sent to invoker and its response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedCall {
+    pub request: InvokeRequest,
+    pub response: InvokeResponse,
+}
+
+/// Invoker traffic of one job
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    /// Capabilities reported to the client, if they were queried
+    pub capabilities: Option<Capabilities>,
+    /// Calls in the order they were sent
+    pub calls: Vec<RecordedCall>,
+}
+
+/// Handle to a recording which is being made. Cloned handles append to
+/// the same recording.
+#[derive(Clone, Default)]
+pub struct Recorder(Arc<Mutex<Recording>>);
+
+impl Recorder {
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    /// Returns everything recorded so far
+    pub fn snapshot(&self) -> Recording {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub(crate) fn capabilities(&self, caps: &Capabilities) {
+        self.0.lock().unwrap().capabilities = Some(caps.clone());
+    }
+
+    pub(crate) fn call(&self, request: &InvokeRequest, response: &InvokeResponse) {
+        self.0.lock().unwrap().calls.push(RecordedCall {
+            request: request.clone(),
+            response: response.clone(),
+        });
+    }
+}
+
+/// Source of responses for a replaying client
+pub(crate) struct Replayer {
+    capabilities: Capabilities,
+    calls: Mutex<VecDeque<RecordedCall>>,
+    /// Number of recorded calls, for diagnostics
+    total: usize,
+}
+
+impl Replayer {
+    pub(crate) fn new(recording: Recording) -> Replayer {
+        Replayer {
+            capabilities: recording.capabilities.unwrap_or_default(),
+            total: recording.calls.len(),
+            calls: Mutex::new(recording.calls.into()),
+        }
+    }
+
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        self.capabilities.clone()
+    }
+
+    /// Returns recorded response to the next call. Request contents are
+    /// not compared exactly, because they contain random names; only its
+    /// shape is checked to detect that processor took another path.
+    pub(crate) fn call(&self, req: &InvokeRequest) -> anyhow::Result<InvokeResponse> {
+        let mut calls = self.calls.lock().unwrap();
+        let recorded = calls
+            .pop_front()
+            .with_context(|| format!("replay diverged: only {} calls were recorded", self.total))?;
+        let number = self.total - calls.len();
+        let same_shape = recorded.request.steps.len() == req.steps.len()
+            && recorded.request.inputs.len() == req.inputs.len()
+            && recorded
+                .request
+                .outputs
+                .iter()
+                .map(|o| &o.name)
+                .eq(req.outputs.iter().map(|o| &o.name));
+        if !same_shape {
+            anyhow::bail!(
+                "replay diverged: call #{} does not match the recorded request",
+                number
+            );
+        }
+        let mut response = recorded.response;
+        response.id = req.id;
+        Ok(response)
+    }
+}
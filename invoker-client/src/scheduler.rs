@@ -0,0 +1,195 @@
+//! Distribution of requests between invoker pools
+
+use crate::Labels;
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// For how long a pool is skipped after it failed
+pub(crate) const DEFAULT_UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// For how long requests wait when all pools are saturated, before failing
+pub(crate) const DEFAULT_SATURATION_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// First delay before requests are resent to saturated pools. Each next
+/// delay is twice as long, up to `MAX_SATURATION_BACKOFF`.
+pub(crate) const INITIAL_SATURATION_BACKOFF: Duration = Duration::from_millis(500);
+
+pub(crate) const MAX_SATURATION_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How requests are distributed between healthy pools
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Policy {
+    /// Pools are used in turn
+    #[default]
+    RoundRobin,
+    /// Pool with the fewest requests in flight is used
+    LeastLoaded,
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "round-robin" => Ok(Policy::RoundRobin),
+            "least-loaded" => Ok(Policy::LeastLoaded),
+            _ => anyhow::bail!(
+                "unknown scheduling policy {:?}, expected one of: round-robin, least-loaded",
+                s
+            ),
+        }
+    }
+}
+
+pub(crate) struct PoolState {
+    pub(crate) addr: String,
+    pub(crate) labels: Labels,
+    /// Number of invokers behind the address
+    pub(crate) invokers: usize,
+    in_flight: AtomicUsize,
+    /// Pool is skipped until this moment
+    unhealthy_until: Mutex<Option<Instant>>,
+    /// Pool rejected a request because its queue was full. It is skipped
+    /// until this moment, unless all pools are skipped.
+    saturated_until: Mutex<Option<Instant>>,
+}
+
+impl PoolState {
+    pub(crate) fn new(addr: String, labels: Labels, invokers: usize) -> PoolState {
+        PoolState {
+            addr,
+            labels,
+            invokers,
+            in_flight: AtomicUsize::new(0),
+            unhealthy_until: Mutex::new(None),
+            saturated_until: Mutex::new(None),
+        }
+    }
+
+    /// Checks that pool has all labels of `selector`
+    pub(crate) fn matches(&self, selector: &Labels) -> bool {
+        selector
+            .iter()
+            .all(|(key, value)| self.labels.get(key) == Some(value))
+    }
+
+    pub(crate) fn is_healthy(&self, now: Instant) -> bool {
+        match *self.unhealthy_until.lock().unwrap() {
+            Some(until) => until <= now,
+            None => true,
+        }
+    }
+
+    fn is_saturated(&self, now: Instant) -> bool {
+        match *self.saturated_until.lock().unwrap() {
+            Some(until) => until > now,
+            None => false,
+        }
+    }
+
+    pub(crate) fn mark_healthy(&self) {
+        *self.unhealthy_until.lock().unwrap() = None;
+    }
+
+    pub(crate) fn mark_unhealthy(&self, cooldown: Duration) {
+        *self.unhealthy_until.lock().unwrap() = Some(Instant::now() + cooldown);
+    }
+
+    /// Called when pool accepted a request
+    pub(crate) fn mark_accepting(&self) {
+        *self.saturated_until.lock().unwrap() = None;
+    }
+
+    pub(crate) fn mark_saturated(&self, backoff: Duration) {
+        *self.saturated_until.lock().unwrap() = Some(Instant::now() + backoff);
+    }
+
+    /// Counts request as in flight until the guard is dropped
+    pub(crate) fn start_request(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self)
+    }
+}
+
+pub(crate) struct InFlight<'a>(&'a PoolState);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub(crate) struct Scheduler {
+    pub(crate) pools: Vec<PoolState>,
+    policy: Policy,
+    /// Rotates starting point of the selection
+    next: AtomicUsize,
+    pub(crate) unhealthy_cooldown: Duration,
+    pub(crate) saturation_timeout: Duration,
+}
+
+impl Scheduler {
+    pub(crate) fn new(
+        pools: Vec<PoolState>,
+        policy: Policy,
+        unhealthy_cooldown: Duration,
+        saturation_timeout: Duration,
+    ) -> Self {
+        Scheduler {
+            pools,
+            policy,
+            next: AtomicUsize::new(0),
+            unhealthy_cooldown,
+            saturation_timeout,
+        }
+    }
+
+    /// Chooses pool for a request among pools matching `selector`,
+    /// skipping pools listed in `exclude`. `preferred` pool is chosen
+    /// while it is healthy and not saturated. If all pools are unhealthy or
+    /// saturated, one of them is still returned, since it may have
+    /// recovered.
+    pub(crate) fn select(
+        &self,
+        preferred: Option<usize>,
+        exclude: &[usize],
+        selector: &Labels,
+    ) -> Option<usize> {
+        let now = Instant::now();
+        let candidates: Vec<usize> = (0..self.pools.len())
+            .filter(|idx| !exclude.contains(idx) && self.pools[*idx].matches(selector))
+            .collect();
+        let healthy: Vec<usize> = candidates
+            .iter()
+            .copied()
+            .filter(|&idx| self.pools[idx].is_healthy(now) && !self.pools[idx].is_saturated(now))
+            .collect();
+        if let Some(p) = preferred {
+            if healthy.contains(&p) {
+                return Some(p);
+            }
+        }
+        let set = if healthy.is_empty() {
+            candidates
+        } else {
+            healthy
+        };
+        if set.is_empty() {
+            return None;
+        }
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % set.len();
+        let rotated = set[start..].iter().chain(set[..start].iter()).copied();
+        match self.policy {
+            Policy::RoundRobin => Some(set[start]),
+            Policy::LeastLoaded => {
+                rotated.min_by_key(|&idx| self.pools[idx].in_flight.load(Ordering::SeqCst))
+            }
+        }
+    }
+}
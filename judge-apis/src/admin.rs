@@ -0,0 +1,185 @@
+//! Types used by administrative API
+use crate::{
+    judge_log::Status,
+    rest::{ByteString, JobPriority},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Job queue state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueueStatus {
+    /// If true, new jobs are accepted, but not started
+    pub paused: bool,
+    /// Number of jobs waiting to be started
+    pub pending: usize,
+    /// If true, too few invokers are healthy: new jobs are rejected and
+    /// accepted ones are not started
+    #[serde(default)]
+    pub degraded: bool,
+    /// Number of jobs being judged
+    #[serde(default)]
+    pub running: usize,
+    /// Limit of concurrently judged jobs, if configured
+    #[serde(default)]
+    pub max_running: Option<usize>,
+    /// Number of jobs waiting to be started, by priority
+    #[serde(default)]
+    pub pending_by_priority: BTreeMap<JobPriority, usize>,
+}
+
+/// Verdict freeze state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FreezeStatus {
+    /// If true, contestant logs of new jobs are withheld
+    pub frozen: bool,
+    /// Number of jobs whose contestant logs are currently withheld
+    pub frozen_jobs: usize,
+}
+
+/// Overall judge state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JudgeStatus {
+    pub queue: QueueStatus,
+    pub freeze: FreezeStatus,
+    /// Number of jobs which are not completed yet (including pending)
+    pub active_jobs: usize,
+    /// Number of jobs known to the judge
+    pub total_jobs: usize,
+    /// Kinds of judge logs which are produced
+    #[serde(default)]
+    pub log_kinds: Vec<String>,
+}
+
+/// Manual change of a test verdict
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VerdictOverride {
+    pub test_id: pom::TestId,
+    /// New status of the test
+    pub status: Status,
+    /// Human-readable explanation
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Identifier of the judge instance which applied the override.
+    /// Ignored in requests.
+    #[serde(default)]
+    pub judge_id: Option<String>,
+}
+
+/// Recurring warning. All occurrences with the same fingerprint are
+/// aggregated into one summary.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WarningSummary {
+    pub fingerprint: String,
+    /// Message of the latest occurrence
+    pub message: String,
+    /// Number of occurrences
+    pub count: u64,
+    /// Unix timestamp (in milliseconds) of the first occurrence
+    pub first_seen_ms: u64,
+    /// Unix timestamp (in milliseconds) of the latest occurrence
+    pub last_seen_ms: u64,
+}
+
+/// Request to generate correct answers of a problem with a trusted
+/// solution
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswersJobRequest {
+    pub problem_id: String,
+    pub toolchain_name: String,
+    pub run_source: ByteString,
+    /// If true, problem package with the answers replaces the problem in
+    /// the writable registry. Otherwise it can only be downloaded.
+    #[serde(default)]
+    pub store: bool,
+}
+
+/// Answer generation job
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnswersJob {
+    pub id: Uuid,
+    pub problem_id: String,
+    pub completed: bool,
+    /// Error message, if the job has failed
+    pub error: Option<String>,
+    pub compile_log: Option<String>,
+    /// Statuses of the solution on tests which were run. Job stops at the
+    /// first test where solution did not succeed.
+    pub tests: Vec<(pom::TestId, Status)>,
+    /// If true, package with the answers can be downloaded
+    pub package_available: bool,
+    /// If true, package was stored in the registry
+    pub stored: bool,
+}
+
+/// Tests where solutions leave less than this share of the time limit
+/// unused (in percent) are flagged in `TimingReport`
+pub const MIN_TIME_HEADROOM_PERCENT: u64 = 20;
+
+/// Request to drop cached problems, so that their packages are fetched
+/// again by the next jobs
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct InvalidateProblemsRequest {
+    /// Problems to drop. If not set, all cached problems are dropped.
+    #[serde(default)]
+    pub problem_ids: Option<Vec<String>>,
+}
+
+/// Result of problem cache invalidation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InvalidatedProblems {
+    /// Problems which were cached. Jobs which already use them keep
+    /// their revisions.
+    pub invalidated: Vec<String>,
+    /// Subset of `invalidated` whose revisions are still used by jobs.
+    /// Their files are removed once the jobs release them.
+    #[serde(default)]
+    pub in_use: Vec<InUseRevision>,
+}
+
+/// Problem revision which was dropped from the cache, but is still used
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InUseRevision {
+    pub problem_id: String,
+    pub revision: u64,
+}
+
+/// Request to build a time limit report of a problem from completed jobs,
+/// which judged its reference solutions
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimingReportRequest {
+    pub job_ids: Vec<Uuid>,
+    /// If true, report is added to the problem package in the writable
+    /// registry, so that it can be retrieved later
+    #[serde(default)]
+    pub archive: bool,
+}
+
+/// Time usage of reference solutions on one test
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestTiming {
+    pub test_id: pom::TestId,
+    /// In milliseconds
+    pub time_limit_ms: u64,
+    /// Number of runs of the test with known time usage
+    pub runs: u32,
+    /// In milliseconds. None if the test was not run.
+    pub max_time_ms: Option<u64>,
+    /// In milliseconds. None if the test was not run.
+    pub avg_time_ms: Option<u64>,
+    /// True if maximum time leaves less than `MIN_TIME_HEADROOM_PERCENT`
+    /// of the limit unused
+    pub low_headroom: bool,
+}
+
+/// Time usage of reference solutions compared to the time limits
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TimingReport {
+    pub problem_id: String,
+    /// Jobs the report was built from
+    pub job_ids: Vec<Uuid>,
+    pub tests: Vec<TestTiming>,
+    /// If true, report was stored with the problem
+    pub archived: bool,
+}
@@ -0,0 +1,87 @@
+//! Comparison of judge logs, e.g. produced by original judging and rejudge.
+use crate::judge_log::{JudgeLog, JudgeLogTestRow, Status};
+use serde::{Deserialize, Serialize};
+
+/// Difference between two judge logs of the same kind.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JudgeLogDiff {
+    pub old_status: Status,
+    pub new_status: Status,
+    /// `new.score - old.score`
+    pub score_delta: i64,
+    /// Tests which have different status or resource usage.
+    pub tests: Vec<TestDiff>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TestDiff {
+    pub test_id: pom::TestId,
+    /// None if test is absent from old log or its status is hidden.
+    pub old_status: Option<Status>,
+    /// None if test is absent from new log or its status is hidden.
+    pub new_status: Option<Status>,
+    /// Change of CPU time usage, if both logs contain it.
+    pub time_delta: Option<i64>,
+    /// Change of memory usage, if both logs contain it.
+    pub memory_delta: Option<i64>,
+}
+
+impl TestDiff {
+    /// Returns true if the test verdict has changed
+    pub fn status_changed(&self) -> bool {
+        self.old_status.as_ref().map(|s| &s.code) != self.new_status.as_ref().map(|s| &s.code)
+    }
+}
+
+fn delta(old: Option<u64>, new: Option<u64>) -> Option<i64> {
+    Some(new? as i64 - old? as i64)
+}
+
+fn find_test(log: &JudgeLog, test_id: pom::TestId) -> Option<&JudgeLogTestRow> {
+    log.tests.iter().find(|row| row.test_id == test_id)
+}
+
+fn diff_test(
+    test_id: pom::TestId,
+    old: Option<&JudgeLogTestRow>,
+    new: Option<&JudgeLogTestRow>,
+) -> TestDiff {
+    TestDiff {
+        test_id,
+        old_status: old.and_then(|r| r.status.clone()),
+        new_status: new.and_then(|r| r.status.clone()),
+        time_delta: delta(
+            old.and_then(|r| r.time_usage),
+            new.and_then(|r| r.time_usage),
+        ),
+        memory_delta: delta(
+            old.and_then(|r| r.memory_usage),
+            new.and_then(|r| r.memory_usage),
+        ),
+    }
+}
+
+/// Computes difference between `old` and `new` logs.
+pub fn diff(old: &JudgeLog, new: &JudgeLog) -> JudgeLogDiff {
+    let mut test_ids: Vec<_> = old
+        .tests
+        .iter()
+        .chain(new.tests.iter())
+        .map(|row| row.test_id)
+        .collect();
+    test_ids.sort();
+    test_ids.dedup();
+    let tests = test_ids
+        .into_iter()
+        .map(|test_id| diff_test(test_id, find_test(old, test_id), find_test(new, test_id)))
+        .filter(|d| {
+            d.status_changed() || d.time_delta.unwrap_or(0) != 0 || d.memory_delta.unwrap_or(0) != 0
+        })
+        .collect();
+    JudgeLogDiff {
+        old_status: old.status.clone(),
+        new_status: new.status.clone(),
+        score_delta: i64::from(new.score) - i64::from(old.score),
+        tests,
+    }
+}
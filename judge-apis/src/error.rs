@@ -0,0 +1,116 @@
+//! Error responses of the REST API.
+//!
+//! Every unsuccessful response has an [`ErrorResponse`] body, e.g.
+//!
+//! ```json
+//! {
+//!   "code": "JudgeJobNotFound",
+//!   "message": "judge job not found",
+//!   "details": {"job_id": "4b8e8f1c-..."},
+//!   "retryable": false
+//! }
+//! ```
+//!
+//! Clients should dispatch on `code` (see [`codes`]) rather than on HTTP
+//! status or message.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Body of an unsuccessful response
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorResponse {
+    /// Machine-readable error code
+    pub code: String,
+    /// Human-readable description. For `InternalError` it only contains
+    /// the outermost error, unless the judge runs with `--debug-errors`.
+    pub message: String,
+    /// Code-specific information, e.g. id of the missing job
+    #[serde(default)]
+    pub details: HashMap<String, String>,
+    /// If true, the same request can succeed if it is retried later
+    #[serde(default)]
+    pub retryable: bool,
+}
+
+/// Error codes. HTTP status used with each code is given in brackets.
+pub mod codes {
+    /// (404) Job with given id does not exist
+    pub const JUDGE_JOB_NOT_FOUND: &str = "JudgeJobNotFound";
+    /// (404) Job exists, but has no log of given kind (yet)
+    pub const JUDGE_LOG_NOT_FOUND: &str = "JudgeLogNotFound";
+    /// (404) Problem has no test with given id
+    pub const TEST_NOT_FOUND: &str = "TestNotFound";
+    /// (404) No problem registry knows about the problem
+    pub const PROBLEM_NOT_FOUND: &str = "ProblemNotFound";
+    /// (404) Toolchain `auto` was requested, but no toolchain matches
+    pub const TOOLCHAIN_NOT_DETECTED: &str = "ToolchainNotDetected";
+    /// (409) Toolchain `auto` was requested, but several toolchains match.
+    /// Details contain comma-separated `candidates`.
+    pub const TOOLCHAIN_AMBIGUOUS: &str = "ToolchainAmbiguous";
+    /// (403) Run source URL does not match allowed prefixes, or fetching
+    /// run sources is disabled
+    pub const RUN_SOURCE_URL_NOT_ALLOWED: &str = "RunSourceUrlNotAllowed";
+    /// (502) Run source could not be downloaded
+    pub const RUN_SOURCE_FETCH_FAILED: &str = "RunSourceFetchFailed";
+    /// (400) Downloaded run source does not match expected digest
+    pub const RUN_SOURCE_DIGEST_MISMATCH: &str = "RunSourceDigestMismatch";
+    /// (404) Toolchain with given name does not exist
+    pub const TOOLCHAIN_NOT_FOUND: &str = "ToolchainNotFound";
+    /// (404) Toolchain does not declare syntax check command
+    pub const SYNTAX_CHECK_NOT_SUPPORTED: &str = "SyntaxCheckNotSupported";
+    /// (404) No endpoint matches request path
+    pub const ROUTE_NOT_FOUND: &str = "RouteNotFound";
+    /// (405) Endpoint does not support request method
+    pub const METHOD_NOT_ALLOWED: &str = "MethodNotAllowed";
+    /// (401) Admin token is missing or invalid
+    pub const UNAUTHORIZED: &str = "Unauthorized";
+    /// (403) Request uses a feature which requires admin token
+    pub const FORBIDDEN: &str = "Forbidden";
+    /// (400) Request body, query or headers are malformed
+    pub const INVALID_REQUEST: &str = "InvalidRequest";
+    /// (413) Request body is too large
+    pub const PAYLOAD_TOO_LARGE: &str = "PayloadTooLarge";
+    /// (400) Uploaded problem package is invalid
+    pub const INVALID_PROBLEM_PACKAGE: &str = "InvalidProblemPackage";
+    /// (400) Too many items are requested at once
+    pub const TOO_MANY_ITEMS: &str = "TooManyItems";
+    /// (409) Operation requires job to be successfully completed
+    pub const JOB_NOT_COMPLETED: &str = "JobNotCompleted";
+    /// (404) Judge log does not contain output required for the operation
+    pub const OUTPUT_NOT_AVAILABLE: &str = "OutputNotAvailable";
+    /// (404) Operation is only available in shadow mode
+    pub const SHADOW_MODE_DISABLED: &str = "ShadowModeDisabled";
+    /// (409) Job has no compiled run which could be reused
+    pub const ARTIFACT_NOT_AVAILABLE: &str = "ArtifactNotAvailable";
+    /// (409) Job was imported from an archive and cannot be changed
+    pub const JOB_READ_ONLY: &str = "JobReadOnly";
+    /// (409) Job with the same id already exists
+    pub const JOB_ALREADY_EXISTS: &str = "JobAlreadyExists";
+    /// (409) Judging phase with this name was already started
+    pub const PHASE_ALREADY_EXISTS: &str = "PhaseAlreadyExists";
+    /// (409) Job judged a local problem, which was removed when the job
+    /// finished
+    pub const LOCAL_PROBLEM_RELEASED: &str = "LocalProblemReleased";
+    /// (409) Problem revision and compiled run of the job were released,
+    /// because the job finished long ago
+    pub const RUN_RELEASED: &str = "RunReleased";
+    /// (404) Answer generation job with given id does not exist
+    pub const ANSWERS_JOB_NOT_FOUND: &str = "AnswersJobNotFound";
+    /// (404) Answer generation job has not produced a package
+    pub const PACKAGE_NOT_AVAILABLE: &str = "PackageNotAvailable";
+    /// (404) Problem has no archived timing report
+    pub const TIMING_REPORT_NOT_FOUND: &str = "TimingReportNotFound";
+    /// (400) No invoker pool has labels selected by job annotations.
+    /// `labels` detail contains the selected labels as comma-separated
+    /// `key=value` pairs.
+    pub const NO_MATCHING_INVOKER_POOL: &str = "NoMatchingInvokerPool";
+    /// (400) Request asks for a debug dump, but judge is not configured
+    /// to store them
+    pub const DEBUG_DUMPS_DISABLED: &str = "DebugDumpsDisabled";
+    /// (503) Too few invokers are healthy to accept jobs
+    pub const NOT_ENOUGH_INVOKERS: &str = "NotEnoughInvokers";
+    /// (503) Judge is shutting down and does not accept jobs
+    pub const SHUTTING_DOWN: &str = "ShuttingDown";
+    /// (500) Unexpected failure. Its causes are written to the judge log.
+    pub const INTERNAL_ERROR: &str = "InternalError";
+}
@@ -0,0 +1,59 @@
+//! Judging events exported to analytics pipelines (e.g. Kafka).
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JudgeEvent {
+    pub job_id: Uuid,
+    /// Identifier of the judge instance which produced the event
+    pub judge_id: String,
+    /// Milliseconds since Unix epoch
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: JudgeEventKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum JudgeEventKind {
+    /// Job was accepted
+    #[serde(rename_all = "kebab-case")]
+    JobCreated {
+        problem_id: String,
+        toolchain_name: String,
+        phase: Option<String>,
+        annotations: HashMap<String, String>,
+    },
+    /// Solution was judged on a test
+    #[serde(rename_all = "kebab-case")]
+    TestFinished { test_id: u32, status_code: String },
+    /// Judge log was produced
+    #[serde(rename_all = "kebab-case")]
+    Verdict {
+        /// Name of the log, e.g. `pretests.full`
+        log: String,
+        status_code: String,
+        score: u32,
+    },
+    /// Job was completed
+    #[serde(rename_all = "kebab-case")]
+    JobFinished {
+        /// Error message, if the job has failed
+        error: Option<String>,
+        /// Fault category, if the job has failed
+        fault_category: Option<String>,
+    },
+}
+
+impl JudgeEventKind {
+    /// Value of the `type` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            JudgeEventKind::JobCreated { .. } => "job-created",
+            JudgeEventKind::TestFinished { .. } => "test-finished",
+            JudgeEventKind::Verdict { .. } => "verdict",
+            JudgeEventKind::JobFinished { .. } => "job-finished",
+        }
+    }
+}
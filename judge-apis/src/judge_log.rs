@@ -4,6 +4,10 @@ pub use valuer_api::{JudgeLogKind, Status, StatusKind, SubtaskId};
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct JudgeLogTestRow {
     pub test_id: pom::TestId,
+    /// Display name of the test, if the problem names its tests. Rows are
+    /// still ordered by `test_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_name: Option<String>,
     pub status: Option<Status>,
     pub test_stdin: Option<String>,
     pub test_stdout: Option<String>,
@@ -11,6 +15,143 @@ pub struct JudgeLogTestRow {
     pub test_answer: Option<String>,
     pub time_usage: Option<u64>,
     pub memory_usage: Option<u64>,
+    /// If solution stdout was put to the output storage instead of
+    /// `test_stdout`, id of the gzip-compressed output.
+    #[serde(default)]
+    pub test_stdout_ref: Option<String>,
+    /// Same as `test_stdout_ref`, but for stderr
+    #[serde(default)]
+    pub test_stderr_ref: Option<String>,
+    /// True if solution stdout exceeded the output size limit of the
+    /// judge, so only its beginning is available
+    #[serde(default)]
+    pub test_stdout_truncated: bool,
+    /// Same as `test_stdout_truncated`, but for stderr
+    #[serde(default)]
+    pub test_stderr_truncated: bool,
+    /// Memory usage of the solution over time, if sampling was enabled.
+    /// Only present in full logs. Contains at most `MAX_MEMORY_SAMPLES`
+    /// items.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_samples: Option<Vec<MemorySample>>,
+    /// True if the test was not run because it could not change the
+    /// score. Tests which were neither run nor skipped have no row.
+    #[serde(default)]
+    pub skipped: bool,
+    /// Message of the checker (or interactor) about the solution output,
+    /// as UTF-8 text. Truncated to `MAX_CHECKER_COMMENT_SIZE` bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checker_comment: Option<String>,
+    /// Results of problem-specific pipeline steps (e.g. input
+    /// preprocessors), in execution order. Only present in full logs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pipeline: Vec<PipelineStepLog>,
+    /// Sandbox violations of the solution reported by invoker. Only
+    /// present in full logs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub security_violations: Vec<SecurityViolation>,
+    /// Steps of the invoker request which create or use sandboxes. Only
+    /// present in full logs, and only if judging the test failed with
+    /// judge fault.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invoker_steps: Vec<InvokerStepRow>,
+    /// Exit code of the solution, if it was started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i64>,
+    /// Why the solution did not finish successfully, e.g. `killed by
+    /// signal 9`. None if the solution exited with code 0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_reason: Option<String>,
+    /// All runs of the test, if it was run several times because its
+    /// time usage was close to the time limit. Only present in full logs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<TestAttempt>,
+}
+
+/// Single run of a test which was run several times
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TestAttempt {
+    pub status: Status,
+    pub time_usage: Option<u64>,
+    pub memory_usage: Option<u64>,
+    /// True if outcome of the test was taken from this run
+    pub selected: bool,
+}
+
+/// Step of the invoker request of a test
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InvokerStepRow {
+    /// Index of the step in the request
+    pub index: usize,
+    pub stage: u32,
+    /// Kind of the action, e.g. `execute-command`
+    pub action: String,
+    /// Sandbox which was created or used by the step
+    pub sandbox: String,
+    /// Exit code, if the step executed a command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i64>,
+    /// Reason why the command could not be started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawn_error: Option<String>,
+}
+
+/// Attempt of the solution to do something its sandbox forbids
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SecurityViolation {
+    /// Kind of the violation, e.g. `forbidden-write` or `blocked-syscall`
+    pub kind: String,
+    /// Details, e.g. path or syscall name
+    #[serde(default)]
+    pub detail: String,
+}
+
+/// Maximum number of security violations kept for one test
+pub const MAX_SECURITY_VIOLATIONS: usize = 16;
+
+/// Result of a problem-specific pipeline step on one test
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PipelineStepLog {
+    /// Name of the step in the problem manifest
+    pub name: String,
+    pub exit_code: i64,
+    pub time_usage: Option<u64>,
+    pub memory_usage: Option<u64>,
+    /// Stderr of the step as UTF-8 text. Truncated to
+    /// `MAX_CHECKER_COMMENT_SIZE` bytes.
+    pub log: String,
+}
+
+/// Maximum size of `JudgeLogTestRow::checker_comment`
+pub const MAX_CHECKER_COMMENT_SIZE: usize = 64 * 1024;
+
+/// Memory usage of the solution at some point of time
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct MemorySample {
+    /// Milliseconds since solution start
+    pub time: u64,
+    /// Memory usage in bytes
+    pub memory: u64,
+}
+
+/// Maximum number of memory samples kept for one test
+pub const MAX_MEMORY_SAMPLES: usize = 64;
+
+/// Reduces number of samples to at most `limit` by splitting them into
+/// equal buckets and keeping the sample with peak memory of each bucket.
+/// Samples must be ordered by time.
+pub fn downsample_memory(samples: &[MemorySample], limit: usize) -> Vec<MemorySample> {
+    if samples.len() <= limit {
+        return samples.to_vec();
+    }
+    if limit == 0 {
+        return Vec::new();
+    }
+    let bucket_size = samples.len().div_ceil(limit);
+    samples
+        .chunks(bucket_size)
+        .filter_map(|bucket| bucket.iter().max_by_key(|s| s.memory).copied())
+        .collect()
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -28,6 +169,123 @@ pub struct JudgeLog {
     pub score: u32,
     pub is_full: bool,
     pub status: Status,
+    /// True if some test verdicts were changed by an administrator
+    /// after judging.
+    #[serde(default)]
+    pub manually_adjusted: bool,
+    /// Identifier of the judge instance which produced this log
+    #[serde(default)]
+    pub judge_id: Option<String>,
+    /// Judging phase (e.g. pretests) this log belongs to
+    #[serde(default)]
+    pub phase: Option<String>,
+    /// Sandbox image which was used instead of the toolchain image.
+    /// Set only in full logs of experimental runs requested by an
+    /// administrator; such verdicts are not comparable with regular ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_override: Option<String>,
+    /// Set by the server if `tests` contains only the first rows of the
+    /// log. All rows can be retrieved page by page from
+    /// `GET /jobs/{id}/logs/{kind}/tests`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_tests: Option<usize>,
+}
+
+/// Status code of the placeholder returned instead of a withheld log
+pub const PENDING_STATUS_CODE: &str = "PENDING";
+
+/// Status code of a test which was skipped
+pub const SKIPPED_STATUS_CODE: &str = "SKIPPED";
+
+/// Status code of a test on which solution was killed for spawning
+/// too many processes (e.g. a fork bomb)
+pub const PROCESS_LIMIT_STATUS_CODE: &str = "PROCESS_LIMIT_EXCEEDED";
+
+/// Status code of a test on which solution printed more than judge is
+/// able to receive from invoker
+pub const OUTPUT_LIMIT_STATUS_CODE: &str = "OUTPUT_LIMIT_EXCEEDED";
+
+/// Status code of a test on which solution tried to escape the sandbox
+/// (e.g. wrote outside of allowed directories or made a blocked syscall)
+pub const SECURITY_VIOLATION_STATUS_CODE: &str = "SECURITY_VIOLATION";
+
+/// Maximum size of `test_stdout` and `test_stderr` in anonymized logs
+pub const ANONYMIZED_OUTPUT_SIZE: usize = 256;
+
+/// Returns name under which log of given kind and phase is available
+pub fn log_name(kind: JudgeLogKind, phase: Option<&str>) -> String {
+    match phase {
+        Some(phase) => format!("{}.{}", phase, kind.as_str()),
+        None => kind.as_str().to_string(),
+    }
+}
+
+/// Checks that `phase` can be used as a part of log name
+pub fn is_valid_phase(phase: &str) -> bool {
+    !phase.is_empty()
+        && phase
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl JudgeLog {
+    /// Name under which this log is available
+    pub fn name(&self) -> String {
+        log_name(self.kind, self.phase.as_deref())
+    }
+
+    /// Variant of the log which can be published even if the problem is
+    /// reused: test inputs, answers, checker comments, pipeline step logs,
+    /// security violations and invoker steps are removed, solution outputs
+    /// are truncated to `ANONYMIZED_OUTPUT_SIZE` bytes.
+    /// Verdicts, scores and resource usage are kept.
+    pub fn anonymized(&self) -> JudgeLog {
+        let truncate = |output: &Option<String>| {
+            output.as_ref().map(|text| {
+                let mut end = text.len().min(ANONYMIZED_OUTPUT_SIZE);
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                text[..end].to_string()
+            })
+        };
+        let tests = self
+            .tests
+            .iter()
+            .map(|row| JudgeLogTestRow {
+                test_stdin: None,
+                test_answer: None,
+                test_stdout: truncate(&row.test_stdout),
+                test_stderr: truncate(&row.test_stderr),
+                // full outputs can be downloaded by reference
+                test_stdout_ref: None,
+                test_stderr_ref: None,
+                checker_comment: None,
+                pipeline: Vec::new(),
+                security_violations: Vec::new(),
+                invoker_steps: Vec::new(),
+                ..row.clone()
+            })
+            .collect();
+        JudgeLog {
+            tests,
+            image_override: None,
+            ..self.clone()
+        }
+    }
+
+    /// Placeholder which is returned instead of a log which exists, but
+    /// is withheld (e.g. during scoreboard freeze).
+    pub fn pending(kind: JudgeLogKind) -> JudgeLog {
+        JudgeLog {
+            kind,
+            status: Status {
+                code: PENDING_STATUS_CODE.to_string(),
+                kind: StatusKind::Queue,
+            },
+            ..Default::default()
+        }
+    }
 }
 
 impl Default for JudgeLog {
@@ -43,6 +301,11 @@ impl Default for JudgeLog {
                 code: "".to_string(),
                 kind: StatusKind::NotSet,
             },
+            manually_adjusted: false,
+            judge_id: None,
+            phase: None,
+            image_override: None,
+            total_tests: None,
         }
     }
 }
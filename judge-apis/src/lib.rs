@@ -1,3 +1,11 @@
+pub mod admin;
+pub mod diff;
+pub mod error;
+pub mod events;
 pub mod judge_log;
 pub mod live;
+pub mod output_diff;
 pub mod rest;
+pub mod shadow;
+pub mod usage;
+pub mod verdict;
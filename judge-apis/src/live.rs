@@ -1,13 +1,164 @@
 use serde::{Deserialize, Serialize};
 
+/// Version of `LiveJudgeStatus` produced by this crate. Fields added in
+/// later versions are optional, so statuses of any version can be parsed;
+/// status without `version` has version 1.
+pub const LIVE_STATUS_VERSION: u32 = 4;
+
+fn legacy_version() -> u32 {
+    1
+}
+
+/// Score estimate, in the same units as the score of judge logs.
+/// Serialized as a number.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct Score(pub u32);
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Coarse-grained state of the job
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LiveStage {
+    /// Waiting for a free slot
+    Queued,
+    /// Problem and toolchain are being loaded and run is being compiled
+    Compiling,
+    /// Run is being tested
+    Testing,
+    /// All invokers are saturated, judging continues when one of them
+    /// accepts requests
+    WaitingForInvoker,
+    /// Job is completed
+    Finished,
+    /// Stage added in a newer version of the API
+    #[serde(other)]
+    Unknown,
+}
+
 /// Describes current judging status of particular job.
 /// This information can be imprecise or stale, so it should
 /// not be relied upon.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LiveJudgeStatus {
+    /// Version of the structure, see `LIVE_STATUS_VERSION`
+    #[serde(default = "legacy_version")]
+    pub version: u32,
     /// Current test. If run is being tested on multiple tests,
     /// it is unspecified which is returned
-    pub test: Option<u32>,
+    pub test: Option<pom::TestId>,
+    /// Display name of `test`, if the problem names its tests. Since
+    /// version 3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test_name: Option<String>,
     /// Current score. None if no estimates were provided yet.
-    pub score: Option<u32>,
+    pub score: Option<Score>,
+    /// Since version 2. None if the status was produced by an older judge.
+    #[serde(default)]
+    pub stage: Option<LiveStage>,
+    /// Number of tests judged in the current phase. Always 0 while the
+    /// job is frozen. Since version 2.
+    #[serde(default)]
+    pub finished_tests: u32,
+    /// Number of tests of the problem, if it is known. Valuer may judge
+    /// only some of them. Since version 2.
+    #[serde(default)]
+    pub total_tests: Option<u32>,
+    /// Compilation output received so far. Empty if it is not available
+    /// yet.
+    #[serde(default)]
+    pub compile_log: String,
+    /// Resources consumed by the solution on finished tests. None if no
+    /// test is finished yet.
+    #[serde(default)]
+    pub resources: Option<LiveResources>,
+    /// Scores of subtasks from the latest contestant log of the current
+    /// phase. Empty while the job is frozen. Since version 4.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subtasks: Vec<LiveSubtaskScore>,
+}
+
+/// Score of one subtask
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveSubtaskScore {
+    pub subtask_id: crate::judge_log::SubtaskId,
+    pub score: Score,
+}
+
+impl LiveSubtaskScore {
+    /// Extracts scores of subtasks from the judge log. Subtasks without
+    /// a score are skipped.
+    pub fn from_log(log: &crate::judge_log::JudgeLog) -> Vec<LiveSubtaskScore> {
+        log.subtasks
+            .iter()
+            .filter_map(|row| {
+                Some(LiveSubtaskScore {
+                    subtask_id: row.subtask_id,
+                    score: Score(row.score?),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Cumulative resource usage of the solution
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LiveResources {
+    /// Maximal memory usage (in bytes) on a single test
+    pub max_memory: u64,
+    /// Total CPU time (in nanoseconds) on all tests
+    pub cpu_time: u64,
+}
+
+impl LiveResources {
+    /// Accounts a finished test
+    pub fn add_test(&mut self, cpu_time: Option<u64>, memory: Option<u64>) {
+        self.cpu_time += cpu_time.unwrap_or(0);
+        self.max_memory = self.max_memory.max(memory.unwrap_or(0));
+    }
+}
+
+/// Event streamed by `GET /jobs/{id}/events`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum LiveEvent {
+    /// Current state of the job. Always sent first.
+    Snapshot { job: Box<crate::rest::JudgeJob> },
+    /// Solution is being tested on this test
+    LiveTest {
+        test: pom::TestId,
+        /// Display name of the test, if the problem names its tests
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        test_name: Option<String>,
+    },
+    /// Current score estimate. Not sent while the job is frozen.
+    LiveScore { score: Score },
+    /// Resources consumed by the solution so far, sent after each test
+    LiveResources { resources: LiveResources },
+    /// Next chunk of compilation output, to be appended to the previous ones
+    LiveCompileLog { chunk: String },
+    /// Judge log with this name was created
+    LogCreated { log: String },
+    /// Job was completed. This is the last event of the stream.
+    Completed,
+}
+
+impl LiveEvent {
+    /// Value of the `type` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            LiveEvent::Snapshot { .. } => "snapshot",
+            LiveEvent::LiveTest { .. } => "live-test",
+            LiveEvent::LiveScore { .. } => "live-score",
+            LiveEvent::LiveResources { .. } => "live-resources",
+            LiveEvent::LiveCompileLog { .. } => "live-compile-log",
+            LiveEvent::LogCreated { .. } => "log-created",
+            LiveEvent::Completed => "completed",
+        }
+    }
 }
@@ -0,0 +1,122 @@
+//! Comparison of expected and actual outputs of a single test.
+//!
+//! Outputs are compared token by token (tokens are separated by any
+//! whitespace), which matches behavior of typical checkers.
+use serde::{Deserialize, Serialize};
+
+/// Lines longer than this are truncated in context windows
+pub const MAX_CONTEXT_LINE_LEN: usize = 256;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputDiff {
+    pub test_id: pom::TestId,
+    /// Number of lines in the expected output (test answer)
+    pub expected_lines: usize,
+    /// Number of lines in the actual output (solution stdout)
+    pub actual_lines: usize,
+    /// None if outputs contain the same tokens
+    pub mismatch: Option<OutputMismatch>,
+}
+
+/// First mismatching token
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputMismatch {
+    /// 1-based line of the expected output containing mismatching token
+    pub expected_line: usize,
+    /// 1-based line of the actual output containing mismatching token
+    pub actual_line: usize,
+    /// 0-based index of the mismatching token in the whole output
+    pub token_index: usize,
+    /// None if expected output ended before. Long tokens are truncated
+    /// to `MAX_CONTEXT_LINE_LEN` bytes.
+    pub expected_token: Option<String>,
+    /// None if actual output ended before
+    pub actual_token: Option<String>,
+    /// Lines of the expected output surrounding the mismatch
+    pub expected_context: Vec<ContextLine>,
+    /// Lines of the actual output surrounding the mismatch
+    pub actual_context: Vec<ContextLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContextLine {
+    /// 1-based line number
+    pub line: usize,
+    pub text: String,
+    /// True if `text` was cut to `MAX_CONTEXT_LINE_LEN` bytes
+    pub truncated: bool,
+}
+
+/// Yields (1-based line number, token) pairs
+fn tokens(data: &str) -> impl Iterator<Item = (usize, &str)> {
+    data.lines()
+        .enumerate()
+        .flat_map(|(idx, line)| line.split_whitespace().map(move |tok| (idx + 1, tok)))
+}
+
+fn truncate(text: &str) -> &str {
+    let mut end = text.len().min(MAX_CONTEXT_LINE_LEN);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+fn context(data: &str, line: usize, radius: usize) -> Vec<ContextLine> {
+    let first = line.saturating_sub(radius).max(1);
+    data.lines()
+        .enumerate()
+        .skip(first - 1)
+        .take(line + radius + 1 - first)
+        .map(|(idx, text)| ContextLine {
+            line: idx + 1,
+            text: truncate(text).to_string(),
+            truncated: truncate(text).len() < text.len(),
+        })
+        .collect()
+}
+
+/// Finds first mismatching token of `expected` and `actual`.
+/// `radius` is number of lines shown before and after the mismatch.
+pub fn diff_outputs(
+    test_id: pom::TestId,
+    expected: &[u8],
+    actual: &[u8],
+    radius: usize,
+) -> OutputDiff {
+    let expected = String::from_utf8_lossy(expected);
+    let actual = String::from_utf8_lossy(actual);
+    let mut expected_tokens = tokens(&expected);
+    let mut actual_tokens = tokens(&actual);
+    let mut token_index = 0;
+    // line numbers of the last seen tokens, used when one of outputs ends
+    let mut last_lines = (1, 1);
+    let mismatch = loop {
+        match (expected_tokens.next(), actual_tokens.next()) {
+            (None, None) => break None,
+            (Some((e_line, e_tok)), Some((a_line, a_tok))) if e_tok == a_tok => {
+                last_lines = (e_line, a_line);
+                token_index += 1;
+            }
+            (e, a) => {
+                let expected_line = e.map_or(last_lines.0, |(line, _)| line);
+                let actual_line = a.map_or(last_lines.1, |(line, _)| line);
+                break Some(OutputMismatch {
+                    expected_line,
+                    actual_line,
+                    token_index,
+                    expected_token: e.map(|(_, tok)| truncate(tok).to_string()),
+                    actual_token: a.map(|(_, tok)| truncate(tok).to_string()),
+                    expected_context: context(&expected, expected_line, radius),
+                    actual_context: context(&actual, actual_line, radius),
+                });
+            }
+        }
+    };
+    OutputDiff {
+        test_id,
+        expected_lines: expected.lines().count(),
+        actual_lines: actual.lines().count(),
+        mismatch,
+    }
+}
@@ -1,9 +1,16 @@
-use crate::live::LiveJudgeStatus;
+use crate::{
+    admin::VerdictOverride,
+    judge_log::{JudgeLog, JudgeLogTestRow},
+    live::LiveJudgeStatus,
+    usage::{JobCost, Usage},
+    verdict::LogSummary,
+};
 use serde::{de::Error, Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf};
 use uuid::Uuid;
 
 /// Base64 encoding for binary data
+#[derive(Debug, Clone)]
 pub struct ByteString(pub Vec<u8>);
 
 impl Serialize for ByteString {
@@ -31,22 +38,219 @@ impl<'de> Deserialize<'de> for ByteString {
     }
 }
 
+/// Run source: either the source itself, or its location
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum RunSource {
+    /// Base64-encoded string
+    Inline(ByteString),
+    /// Judge downloads the source before accepting the job
+    Fetch(SourceRef),
+}
+
+/// Problem which is judged instead of a problem from registries
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum LocalProblemSource {
+    /// Absolute path of the problem directory on the judge host. It is
+    /// laid out as problems of the filesystem registry.
+    Path(PathBuf),
+    /// Base64-encoded problem package, as accepted by `PUT /problems/{id}`
+    Package(ByteString),
+}
+
+/// Location of a run source
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SourceRef {
+    /// URL which is fetched with GET. Judge only fetches URLs with
+    /// configured prefixes.
+    pub url: String,
+    /// Value of the `Authorization` header, if storage requires it
+    #[serde(default)]
+    pub authorization: Option<String>,
+    /// Expected SHA-256 of the source, hex-encoded. Job is rejected if
+    /// the downloaded source does not match.
+    pub sha256: String,
+}
+
+/// Which tests are judged. Valuer implements the policy, so modes other
+/// than `default` require valuer support.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum JudgingMode {
+    /// As configured in the problem
+    #[default]
+    Default,
+    /// Judging stops at the first failed test (ICPC style)
+    StopOnFirstFailure,
+    /// All tests are judged, regardless of failures
+    AllTests,
+}
+
+impl JudgingMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JudgingMode::Default => "default",
+            JudgingMode::StopOnFirstFailure => "stop-on-first-failure",
+            JudgingMode::AllTests => "all-tests",
+        }
+    }
+}
+
+/// Determines order in which waiting jobs are started. Jobs of higher
+/// priority are always started first, e.g. rejudges can be submitted with
+/// `low` priority so that they do not delay live contest submissions.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobPriority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl JobPriority {
+    /// All priorities, from the highest to the lowest
+    pub const ALL: [JobPriority; 3] = [JobPriority::High, JobPriority::Normal, JobPriority::Low];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobPriority::High => "high",
+            JobPriority::Normal => "normal",
+            JobPriority::Low => "low",
+        }
+    }
+}
+
 /// Judge request
 #[derive(Serialize, Deserialize)]
 pub struct JudgeRequest {
-    /// Toolchain name (will be passed to toolchain loader)
+    /// Toolchain name (will be passed to toolchain loader).
+    /// If set to `auto` or empty, judge will choose toolchain based on
+    /// `filename` and run source.
+    #[serde(default)]
     pub toolchain_name: String,
     /// Problem name (will be passed to problem loader)
     pub problem_id: String,
-    /// Run source, as a base64-encoded string
-    pub run_source: ByteString,
+    /// Run source, as a base64-encoded string or a `SourceRef` object
+    pub run_source: RunSource,
+    /// Original name of the source file. Used as a hint for toolchain
+    /// detection.
+    #[serde(default)]
+    pub filename: Option<String>,
     /// Additional metadata. Judge will simply preserve it.
     #[serde(default)]
     pub annotations: HashMap<String, String>,
+    /// Judging phase, e.g. `pretests`. If set, names of created logs are
+    /// prefixed with it (`pretests.full`), and later phases can be
+    /// started without compiling the run again.
+    #[serde(default)]
+    pub phase: Option<String>,
+    /// Preferred locale of human-readable messages, e.g. `ru-RU`
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// Timezone used to render timestamps: `UTC` or a fixed offset
+    /// such as `+03:00`
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Sandbox image used for compilation and solution runs instead of
+    /// the toolchain image. Requires admin token, and is rejected if
+    /// judge has no admin token configured.
+    #[serde(default)]
+    pub image_override: Option<String>,
+    /// Forwarded to the valuer in `judging_mode` extension of
+    /// `ProblemInfo`. Later phases of the job use the same mode.
+    #[serde(default)]
+    pub judging_mode: JudgingMode,
+    /// If set, this problem is judged instead of looking `problem_id` up
+    /// in problem registries, which is useful for problem development.
+    /// `problem_id` is only used as its name. Files of the problem are
+    /// removed when the job finishes. Requires admin token, and is
+    /// rejected if judge has no admin token configured.
+    #[serde(default)]
+    pub local_problem: Option<LocalProblemSource>,
+    /// Queue priority of the job. Later phases of the job use the same
+    /// priority.
+    #[serde(default)]
+    pub priority: JobPriority,
+    /// If true, raw valuer messages, invoke requests with their responses
+    /// and judge logs of the job are dumped on the judge host for
+    /// troubleshooting. Later phases are dumped too. Requires admin token.
+    #[serde(default)]
+    pub debug: bool,
 }
 
-/// Information about previously created judge job
+/// Request to check run source without judging it
+#[derive(Serialize, Deserialize)]
+pub struct SyntaxCheckRequest {
+    /// Toolchain name, or `auto` (or empty) to detect it as in
+    /// `JudgeRequest`
+    #[serde(default)]
+    pub toolchain_name: String,
+    /// Run source, as a base64-encoded string
+    pub run_source: ByteString,
+    /// Original name of the source file. Used as a hint for toolchain
+    /// detection.
+    #[serde(default)]
+    pub filename: Option<String>,
+}
+
+/// Result of the syntax check
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyntaxCheckResult {
+    /// Toolchain which was used
+    pub toolchain_name: String,
+    /// True if no errors were found
+    pub passed: bool,
+    /// True if the check was killed because it took too long
+    pub timed_out: bool,
+    /// Compiler messages
+    pub diagnostics: String,
+    /// True if `diagnostics` were truncated
+    #[serde(default)]
+    pub diagnostics_truncated: bool,
+}
+
+/// Toolchain as listed by `GET /toolchains`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolchainInfo {
+    pub name: String,
+    /// Human-readable name, if the manifest is valid
+    pub title: Option<String>,
+    /// Version of the manifest schema, if the manifest is valid
+    pub schema_version: Option<u32>,
+    /// Source file extensions used for toolchain detection
+    pub extensions: Vec<String>,
+    /// False if the toolchain can not be used
+    pub valid: bool,
+    /// Problems which make the toolchain unusable
+    pub errors: Vec<ToolchainIssue>,
+    /// Problems which do not prevent using the toolchain, e.g. unknown
+    /// fields of manifests without `schema-version`
+    pub warnings: Vec<ToolchainIssue>,
+}
+
+/// Problem found in a toolchain manifest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ToolchainIssue {
+    /// Path of the offending value, e.g. `build[0].argv`. Empty if the
+    /// issue concerns the whole manifest.
+    pub path: String,
+    /// Line of the manifest, if known
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Request to start another judging phase of a completed job
 #[derive(Serialize, Deserialize)]
+pub struct StartPhaseRequest {
+    pub phase: String,
+}
+
+/// Information about previously created judge job
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JudgeJob {
     /// Identifier of the job
     pub id: Uuid,
@@ -56,8 +260,149 @@ pub struct JudgeJob {
     pub annotations: HashMap<String, String>,
     /// Whether the job has completed
     pub completed: bool,
+    /// Whether the job (or its current phase) waits in the queue and
+    /// was not started yet
+    #[serde(default)]
+    pub queued: bool,
     /// Live status
     pub live: LiveJudgeStatus,
     /// Error message, if the job has failed
     pub error: Option<String>,
+    /// Verdict changes made by administrators, oldest first
+    #[serde(default)]
+    pub overrides: Vec<VerdictOverride>,
+    /// Identifier of the judge instance which runs the job
+    #[serde(default)]
+    pub judge_id: String,
+    /// If true, contestant log is withheld until the job is thawed, and
+    /// live score is not reported
+    #[serde(default)]
+    pub frozen: bool,
+    /// Resources consumed so far
+    #[serde(default)]
+    pub usage: Usage,
+    /// Current judging phase
+    #[serde(default)]
+    pub phase: Option<String>,
+    /// Classification of the error, if the job has failed
+    #[serde(default)]
+    pub fault: Option<FaultInfo>,
+    /// Locale hint as specified in request
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// Timezone hint as specified in request
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Sandbox image override as specified in request
+    #[serde(default)]
+    pub image_override: Option<String>,
+    /// Queue priority as specified in request
+    #[serde(default)]
+    pub priority: JobPriority,
+    /// Whether debug dump was requested
+    #[serde(default)]
+    pub debug: bool,
+    /// If true, the job was imported from an archive and cannot be
+    /// changed
+    #[serde(default)]
+    pub imported: bool,
+    /// Estimated cost of the current phase and CPU time actually used
+    #[serde(default)]
+    pub cost: JobCost,
+    /// Sequence number of the last judging event applied to this
+    /// response, 0 if there were none. Numbering restarts with each phase.
+    #[serde(default)]
+    pub last_event_seq: u64,
+}
+
+/// Response of the readiness probe
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Readiness {
+    /// True if judge accepts jobs
+    pub ready: bool,
+    /// Number of healthy invokers, if invoker health is checked
+    pub healthy_invokers: Option<usize>,
+    pub min_healthy_invokers: Option<usize>,
+    /// True if judge is finishing running jobs before exit
+    #[serde(default)]
+    pub shutting_down: bool,
+}
+
+/// Human-readable job summary. Messages and timestamps are rendered
+/// according to locale and timezone hints of the job.
+#[derive(Serialize, Deserialize)]
+pub struct JobSummary {
+    pub id: Uuid,
+    /// Status code of the contestant log, if it exists
+    pub status_code: Option<String>,
+    /// Localized description of `status_code`
+    pub status_message: Option<String>,
+    pub score: Option<u32>,
+    /// RFC 3339 timestamp
+    pub created_at: String,
+    /// RFC 3339 timestamp, if the job is finished
+    pub finished_at: Option<String>,
+    /// Locale which was actually used
+    pub locale: String,
+    /// Timezone which was actually used
+    pub timezone: String,
+}
+
+/// Category of a judge fault with a remediation hint for operators
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FaultInfo {
+    /// E.g. `invoker-timeout` or `problem-asset-missing`
+    pub category: String,
+    pub hint: String,
+}
+
+/// Reference to a judge log
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogRef {
+    pub job_id: Uuid,
+    pub kind: String,
+}
+
+/// Request for `POST /jobs/logs:batchGet`
+#[derive(Serialize, Deserialize)]
+pub struct BatchGetLogsRequest {
+    pub logs: Vec<LogRef>,
+}
+
+/// Single entry of `BatchGetLogsResponse`.
+/// Exactly one of `log` and `error` is set.
+#[derive(Serialize, Deserialize)]
+pub struct BatchGetLogsItem {
+    pub job_id: Uuid,
+    pub kind: String,
+    pub log: Option<JudgeLog>,
+    /// Error message, if log could not be retrieved
+    pub error: Option<String>,
+}
+
+/// Response for `POST /jobs/logs:batchGet`. Items are in the same order
+/// as in request.
+#[derive(Serialize, Deserialize)]
+pub struct BatchGetLogsResponse {
+    pub items: Vec<BatchGetLogsItem>,
+}
+
+/// Response for `GET /jobs/{id}/logs/{kind}?view=summary`: the log with
+/// failed tests only, and counters of all tests
+#[derive(Serialize, Deserialize)]
+pub struct SummarizedJudgeLog {
+    #[serde(flatten)]
+    pub log: JudgeLog,
+    pub summary: LogSummary,
+}
+
+/// Response for `GET /jobs/{id}/logs/{kind}/tests?page=&page_size=`
+#[derive(Serialize, Deserialize)]
+pub struct JudgeLogTestsPage {
+    /// Zero-based
+    pub page: usize,
+    pub page_size: usize,
+    /// Number of rows in the whole log
+    pub total_tests: usize,
+    pub tests: Vec<JudgeLogTestRow>,
 }
@@ -0,0 +1,94 @@
+//! Types used by shadow judging mode.
+//!
+//! A shadow judge receives copies of real runs, judges them without
+//! publishing anything and compares verdicts with the ones produced by the
+//! active judge.
+use crate::judge_log::JudgeLog;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Annotation containing status code assigned by the active judge
+pub const REFERENCE_STATUS_ANNOTATION: &str = "judge.shadow.reference-status";
+/// Annotation containing score assigned by the active judge
+pub const REFERENCE_SCORE_ANNOTATION: &str = "judge.shadow.reference-score";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShadowVerdict {
+    /// Status code of the full judge log
+    pub status: String,
+    /// None if the active judge did not report a score
+    #[serde(default)]
+    pub score: Option<u32>,
+}
+
+impl ShadowVerdict {
+    /// Scores are only compared if both verdicts contain them
+    pub fn matches(&self, other: &ShadowVerdict) -> bool {
+        let scores_match = match (self.score, other.score) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        };
+        self.status == other.status && scores_match
+    }
+}
+
+/// Result of shadow-judging a single run, as kept in the comparison store
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShadowRecord {
+    pub job_id: Uuid,
+    pub problem_id: String,
+    /// Verdict of the active judge, if it was provided
+    pub reference: Option<ShadowVerdict>,
+    /// Verdict of this judge. None if full log was not produced.
+    pub verdict: Option<ShadowVerdict>,
+    /// Error message, if the job has failed
+    pub error: Option<String>,
+    /// All logs produced by this judge
+    pub logs: Vec<JudgeLog>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShadowMismatch {
+    pub job_id: Uuid,
+    pub problem_id: String,
+    pub reference: ShadowVerdict,
+    pub verdict: Option<ShadowVerdict>,
+    pub error: Option<String>,
+}
+
+/// Summary of all shadow-judged runs
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShadowReport {
+    /// Number of completed runs
+    pub total: usize,
+    /// Number of runs for which no reference verdict was provided
+    pub without_reference: usize,
+    /// Number of runs whose verdict matches the reference
+    pub matched: usize,
+    /// Runs whose verdict differs from the reference (including failed ones)
+    pub mismatches: Vec<ShadowMismatch>,
+}
+
+impl ShadowReport {
+    pub fn add(&mut self, record: &ShadowRecord) {
+        self.total += 1;
+        let reference = match &record.reference {
+            Some(r) => r,
+            None => {
+                self.without_reference += 1;
+                return;
+            }
+        };
+        if matches!(&record.verdict, Some(v) if v.matches(reference)) {
+            self.matched += 1;
+            return;
+        }
+        self.mismatches.push(ShadowMismatch {
+            job_id: record.job_id,
+            problem_id: record.problem_id.clone(),
+            reference: reference.clone(),
+            verdict: record.verdict.clone(),
+            error: record.error.clone(),
+        });
+    }
+}
@@ -0,0 +1,88 @@
+//! Resource usage accounting
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Resources consumed by a job (or by several jobs)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Usage {
+    /// Total CPU time of all commands (compilers, solution and checkers),
+    /// in seconds
+    pub cpu_seconds: f64,
+    /// Maximal memory usage (in bytes) of a single command
+    pub peak_memory: u64,
+    /// Number of requests sent to invokers
+    pub invoker_calls: u64,
+}
+
+impl Usage {
+    /// Accounts a single command. `cpu_time` is in nanoseconds.
+    pub fn add_command(&mut self, cpu_time: Option<u64>, memory: Option<u64>) {
+        if let Some(cpu_time) = cpu_time {
+            self.cpu_seconds += cpu_time as f64 / 1e9;
+        }
+        if let Some(memory) = memory {
+            self.peak_memory = self.peak_memory.max(memory);
+        }
+    }
+
+    /// Adds usage of `other` to this usage
+    pub fn merge(&mut self, other: &Usage) {
+        self.cpu_seconds += other.cpu_seconds;
+        self.peak_memory = self.peak_memory.max(other.peak_memory);
+        self.invoker_calls += other.invoker_calls;
+    }
+}
+
+/// Up-front estimate of the work needed to judge a run
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CostEstimate {
+    /// Number of tests which may be run
+    pub tests: u32,
+    /// Upper bound of CPU time used by the build and the solution: sum
+    /// of time limits of all build commands and all tests
+    pub max_cpu_seconds: f64,
+    pub class: CostClass,
+}
+
+/// Coarse size of a job
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CostClass {
+    Light,
+    Medium,
+    Heavy,
+}
+
+impl CostClass {
+    /// Classifies job by the upper bound of its CPU time
+    pub fn of(max_cpu_seconds: f64) -> CostClass {
+        if max_cpu_seconds < 10.0 {
+            CostClass::Light
+        } else if max_cpu_seconds < 100.0 {
+            CostClass::Medium
+        } else {
+            CostClass::Heavy
+        }
+    }
+}
+
+/// Estimated and actual cost of a job
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct JobCost {
+    /// None if the problem was not found when the job was created
+    pub estimate: Option<CostEstimate>,
+    /// CPU time actually used, set when the job is completed
+    pub actual_cpu_seconds: Option<f64>,
+}
+
+/// Aggregated usage of all jobs known to the judge
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UsageReport {
+    /// Number of accounted jobs
+    pub jobs: usize,
+    pub total: Usage,
+    /// If report was grouped by an annotation, usage per annotation value.
+    /// Jobs without this annotation are grouped under empty string.
+    #[serde(default)]
+    pub groups: HashMap<String, Usage>,
+}
@@ -0,0 +1,366 @@
+//! Summary views of judge logs, so that all clients compute final
+//! verdicts and scores the same way. Functions here do no I/O.
+use crate::judge_log::{JudgeLog, JudgeLogTestRow, Status, StatusKind};
+use serde::{Deserialize, Serialize};
+use valuer_api::status_codes;
+
+/// Aggregated information about a judge log
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct LogSummary {
+    /// See [`final_status`]
+    pub status: Status,
+    /// See [`total_score`]
+    pub score: u32,
+    /// Number of tests in the log
+    pub tests_total: usize,
+    /// Number of tests with `Accepted` status
+    pub tests_passed: usize,
+    /// Number of tests with `Rejected` status
+    pub tests_failed: usize,
+    /// Number of skipped tests
+    pub tests_skipped: usize,
+    /// Number of tests whose status is hidden
+    pub tests_hidden: usize,
+    /// First failed test, if any
+    pub first_failed_test: Option<pom::TestId>,
+}
+
+/// Orders status kinds from the best to the worst. Statuses which do not
+/// describe an outcome (not set, queued) rank lowest.
+pub fn severity(kind: StatusKind) -> u8 {
+    match kind {
+        StatusKind::NotSet | StatusKind::Queue => 0,
+        StatusKind::Skipped => 1,
+        StatusKind::Accepted => 2,
+        StatusKind::Rejected => 3,
+        StatusKind::CompilationError => 4,
+        StatusKind::InternalError => 5,
+    }
+}
+
+/// Returns the worst visible test status. Among equally bad statuses,
+/// the one of the test with the smallest id is returned.
+pub fn worst_test_status(log: &JudgeLog) -> Option<&Status> {
+    let mut worst: Option<(&JudgeLogTestRow, &Status)> = None;
+    for row in &log.tests {
+        let status = match &row.status {
+            Some(s) => s,
+            None => continue,
+        };
+        let replace = match worst {
+            None => true,
+            Some((prev_row, prev)) => {
+                let ord = severity(status.kind).cmp(&severity(prev.kind));
+                ord == std::cmp::Ordering::Greater
+                    || (ord == std::cmp::Ordering::Equal && row.test_id < prev_row.test_id)
+            }
+        };
+        if replace {
+            worst = Some((row, status));
+        }
+    }
+    worst.map(|(_, status)| status)
+}
+
+/// Returns id of the first test (by id) with `Rejected` status
+pub fn first_failed_test(log: &JudgeLog) -> Option<pom::TestId> {
+    log.tests
+        .iter()
+        .filter(|row| matches!(&row.status, Some(s) if s.kind == StatusKind::Rejected))
+        .map(|row| row.test_id)
+        .min()
+}
+
+/// Status of the whole run. If the log has overall status, it is used
+/// as is. Otherwise (e.g. for a log which is still being built), status
+/// is derived from tests: the worst test status if some test was not
+/// passed, and `ACCEPTED` otherwise.
+pub fn final_status(log: &JudgeLog) -> Status {
+    if log.status.kind != StatusKind::NotSet {
+        return log.status.clone();
+    }
+    match worst_test_status(log) {
+        Some(s) if severity(s.kind) > severity(StatusKind::Accepted) => s.clone(),
+        Some(_) => Status {
+            kind: StatusKind::Accepted,
+            code: status_codes::ACCEPTED.to_string(),
+        },
+        None => log.status.clone(),
+    }
+}
+
+/// Score of the run. If the log has subtasks, it is the sum of known
+/// subtask scores (subtasks with hidden score count as zero), otherwise
+/// `log.score`.
+pub fn total_score(log: &JudgeLog) -> u32 {
+    if log.subtasks.is_empty() {
+        return log.score;
+    }
+    log.subtasks
+        .iter()
+        .filter_map(|subtask| subtask.score)
+        .fold(0u32, |acc, score| acc.saturating_add(score))
+}
+
+pub fn summarize(log: &JudgeLog) -> LogSummary {
+    let mut summary = LogSummary {
+        status: final_status(log),
+        score: total_score(log),
+        tests_total: log.tests.len(),
+        tests_passed: 0,
+        tests_failed: 0,
+        tests_skipped: 0,
+        tests_hidden: 0,
+        first_failed_test: first_failed_test(log),
+    };
+    for row in &log.tests {
+        match &row.status {
+            _ if row.skipped => summary.tests_skipped += 1,
+            None => summary.tests_hidden += 1,
+            Some(s) => match s.kind {
+                StatusKind::Accepted => summary.tests_passed += 1,
+                StatusKind::Rejected => summary.tests_failed += 1,
+                StatusKind::Skipped => summary.tests_skipped += 1,
+                _ => {}
+            },
+        }
+    }
+    summary
+}
+
+/// Returns copy of the log which keeps only tests with a status worse
+/// than `Accepted`, for logs too large to be shown in full
+pub fn retain_failed_tests(log: &JudgeLog) -> JudgeLog {
+    let tests = log
+        .tests
+        .iter()
+        .filter(|row| {
+            matches!(&row.status, Some(s) if severity(s.kind) > severity(StatusKind::Accepted))
+        })
+        .cloned()
+        .collect();
+    JudgeLog {
+        tests,
+        subtasks: log.subtasks.clone(),
+        compile_log: log.compile_log.clone(),
+        status: log.status.clone(),
+        judge_id: log.judge_id.clone(),
+        phase: log.phase.clone(),
+        image_override: log.image_override.clone(),
+        ..*log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::judge_log::{JudgeLogSubtaskRow, SubtaskId};
+
+    fn status(kind: StatusKind, code: &str) -> Status {
+        Status {
+            kind,
+            code: code.to_string(),
+        }
+    }
+
+    fn passed() -> Status {
+        status(StatusKind::Accepted, status_codes::TEST_PASSED)
+    }
+
+    fn wrong_answer() -> Status {
+        status(StatusKind::Rejected, status_codes::WRONG_ANSWER)
+    }
+
+    fn row(id: u32, status: Option<Status>) -> JudgeLogTestRow {
+        JudgeLogTestRow {
+            test_id: pom::TestId::make(id),
+            status,
+            test_stdin: None,
+            test_stdout: None,
+            test_stderr: None,
+            test_answer: None,
+            time_usage: None,
+            memory_usage: None,
+            test_stdout_ref: None,
+            test_stderr_ref: None,
+            memory_samples: None,
+            skipped: false,
+            checker_comment: None,
+            pipeline: Vec::new(),
+            security_violations: Vec::new(),
+            invoker_steps: Vec::new(),
+            exit_code: None,
+            failure_reason: None,
+            attempts: Vec::new(),
+            test_name: None,
+            test_stdout_truncated: false,
+            test_stderr_truncated: false,
+        }
+    }
+
+    fn log(tests: Vec<JudgeLogTestRow>) -> JudgeLog {
+        JudgeLog {
+            tests,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn severity_ordering() {
+        assert!(severity(StatusKind::Accepted) < severity(StatusKind::Rejected));
+        assert!(severity(StatusKind::Rejected) < severity(StatusKind::InternalError));
+        assert!(severity(StatusKind::Skipped) < severity(StatusKind::Accepted));
+        assert_eq!(severity(StatusKind::NotSet), severity(StatusKind::Queue));
+    }
+
+    #[test]
+    fn worst_status_of_empty_log() {
+        assert_eq!(worst_test_status(&log(vec![])), None);
+    }
+
+    #[test]
+    fn worst_status_prefers_failures() {
+        let l = log(vec![
+            row(1, Some(passed())),
+            row(2, Some(wrong_answer())),
+            row(3, Some(passed())),
+        ]);
+        assert_eq!(worst_test_status(&l), Some(&wrong_answer()));
+    }
+
+    #[test]
+    fn worst_status_ties_are_broken_by_test_id() {
+        let tle = status(StatusKind::Rejected, status_codes::TIME_LIMIT_EXCEEDED);
+        let l = log(vec![
+            row(3, Some(wrong_answer())),
+            row(2, Some(tle.clone())),
+        ]);
+        assert_eq!(worst_test_status(&l), Some(&tle));
+    }
+
+    #[test]
+    fn worst_status_ignores_hidden_tests() {
+        let l = log(vec![row(1, None), row(2, Some(passed()))]);
+        assert_eq!(worst_test_status(&l), Some(&passed()));
+    }
+
+    #[test]
+    fn first_failed_test_is_smallest_id() {
+        let l = log(vec![
+            row(4, Some(wrong_answer())),
+            row(1, Some(passed())),
+            row(2, Some(wrong_answer())),
+        ]);
+        assert_eq!(first_failed_test(&l), Some(pom::TestId::make(2)));
+        assert_eq!(first_failed_test(&log(vec![row(1, Some(passed()))])), None);
+    }
+
+    #[test]
+    fn final_status_uses_overall_status() {
+        let mut l = log(vec![row(1, Some(wrong_answer()))]);
+        l.status = status(StatusKind::Rejected, status_codes::PARTIAL_SOLUTION);
+        assert_eq!(final_status(&l), l.status);
+    }
+
+    #[test]
+    fn final_status_is_derived_from_tests() {
+        let l = log(vec![row(1, Some(passed())), row(2, Some(wrong_answer()))]);
+        assert_eq!(final_status(&l), wrong_answer());
+        let l = log(vec![row(1, Some(passed()))]);
+        assert_eq!(
+            final_status(&l),
+            status(StatusKind::Accepted, status_codes::ACCEPTED)
+        );
+    }
+
+    #[test]
+    fn final_status_of_empty_log_is_not_set() {
+        assert_eq!(final_status(&log(vec![])).kind, StatusKind::NotSet);
+    }
+
+    #[test]
+    fn total_score_without_subtasks() {
+        let mut l = log(vec![]);
+        l.score = 42;
+        assert_eq!(total_score(&l), 42);
+    }
+
+    #[test]
+    fn total_score_sums_known_subtasks() {
+        let mut l = log(vec![]);
+        l.score = 100;
+        l.subtasks = vec![
+            JudgeLogSubtaskRow {
+                subtask_id: SubtaskId(1),
+                score: Some(30),
+            },
+            JudgeLogSubtaskRow {
+                subtask_id: SubtaskId(2),
+                score: None,
+            },
+            JudgeLogSubtaskRow {
+                subtask_id: SubtaskId(3),
+                score: Some(20),
+            },
+        ];
+        assert_eq!(total_score(&l), 50);
+    }
+
+    #[test]
+    fn total_score_saturates() {
+        let mut l = log(vec![]);
+        l.subtasks = vec![
+            JudgeLogSubtaskRow {
+                subtask_id: SubtaskId(1),
+                score: Some(u32::MAX),
+            },
+            JudgeLogSubtaskRow {
+                subtask_id: SubtaskId(2),
+                score: Some(1),
+            },
+        ];
+        assert_eq!(total_score(&l), u32::MAX);
+    }
+
+    #[test]
+    fn summary_counts_tests() {
+        let mut skipped = row(
+            4,
+            Some(status(
+                StatusKind::Skipped,
+                crate::judge_log::SKIPPED_STATUS_CODE,
+            )),
+        );
+        skipped.skipped = true;
+        let l = log(vec![
+            row(1, Some(passed())),
+            row(2, Some(wrong_answer())),
+            row(3, None),
+            skipped,
+        ]);
+        let summary = summarize(&l);
+        assert_eq!(summary.tests_total, 4);
+        assert_eq!(summary.tests_passed, 1);
+        assert_eq!(summary.tests_failed, 1);
+        assert_eq!(summary.tests_hidden, 1);
+        assert_eq!(summary.tests_skipped, 1);
+        assert_eq!(summary.first_failed_test, Some(pom::TestId::make(2)));
+        assert_eq!(summary.status, wrong_answer());
+    }
+
+    #[test]
+    fn failed_tests_are_retained() {
+        let l = log(vec![
+            row(1, Some(passed())),
+            row(2, Some(wrong_answer())),
+            row(3, None),
+            row(4, Some(status(StatusKind::InternalError, "JUDGE_FAULT"))),
+        ]);
+        let ids: Vec<u32> = retain_failed_tests(&l)
+            .tests
+            .iter()
+            .map(|row| row.test_id.get())
+            .collect();
+        assert_eq!(ids, vec![2, 4]);
+    }
+}
@@ -7,15 +7,16 @@ use std::{
 use anyhow::Context;
 use clap::Clap;
 use judge_apis::{
-    live::LiveJudgeStatus,
-    rest::{ByteString, JudgeJob, JudgeRequest},
+    judge_log::{JudgeLog, MemorySample},
+    live::{LiveJudgeStatus, Score},
+    rest::{ByteString, JudgeJob, JudgeRequest, LocalProblemSource, RunSource},
 };
 
 /// Command-line JJS judge client
 #[derive(Clap)]
 struct Args {
-    /// Name of the toolchain to use
-    #[clap(long, short = 't')]
+    /// Name of the toolchain to use. By default, judge will detect it
+    #[clap(long, short = 't', default_value = "auto")]
     toolchain: String,
     /// Name of the problem to use
     #[clap(long, short = 'p')]
@@ -26,6 +27,22 @@ struct Args {
     /// Judge API endpoing, e.g. http://localhost:1789
     #[clap(long, short = 'j')]
     judge_api: String,
+    /// Judging phase, e.g. `pretests`
+    #[clap(long)]
+    phase: Option<String>,
+    /// Judge problem from this directory on the judge host instead of
+    /// looking it up in registries. `--problem` is used as its name.
+    /// Requires `--admin-token`.
+    #[clap(long)]
+    problem_dir: Option<PathBuf>,
+    /// Judge problem from this local package (`.tar.gz` with
+    /// `manifest.json` and `assets`) instead of looking it up in
+    /// registries. Requires `--admin-token`.
+    #[clap(long)]
+    problem_package: Option<PathBuf>,
+    /// Judge admin token
+    #[clap(long)]
+    admin_token: Option<String>,
 }
 
 #[tokio::main]
@@ -39,21 +56,44 @@ async fn main() -> anyhow::Result<()> {
     let source = tokio::fs::read(&args.source)
         .await
         .context("failed to read run source")?;
+    let local_problem = match (&args.problem_dir, &args.problem_package) {
+        (Some(_), Some(_)) => {
+            anyhow::bail!("--problem-dir and --problem-package can not be used together")
+        }
+        (Some(dir), None) => Some(LocalProblemSource::Path(dir.clone())),
+        (None, Some(package)) => {
+            let package = tokio::fs::read(package)
+                .await
+                .context("failed to read problem package")?;
+            Some(LocalProblemSource::Package(ByteString(package)))
+        }
+        (None, None) => None,
+    };
     let req = JudgeRequest {
         annotations,
         toolchain_name: args.toolchain.clone(),
         problem_id: args.problem.clone(),
-        run_source: ByteString(source),
+        run_source: RunSource::Inline(ByteString(source)),
+        filename: args
+            .source
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(ToString::to_string),
+        phase: args.phase.clone(),
+        locale: None,
+        timezone: None,
+        image_override: None,
+        judging_mode: Default::default(),
+        local_problem,
+        priority: Default::default(),
+        debug: false,
     };
     let client = reqwest::Client::new();
-    let result: JudgeJob = client
-        .post(format!("{}/jobs", args.judge_api))
-        .json(&req)
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
+    let mut submit = client.post(format!("{}/jobs", args.judge_api)).json(&req);
+    if let Some(token) = &args.admin_token {
+        submit = submit.bearer_auth(token);
+    }
+    let result: JudgeJob = submit.send().await?.error_for_status()?.json().await?;
     println!("Submitted, judge job id: {}", result.id.to_hyphenated());
     let mut received_logs = HashSet::<String>::new();
     let mut printer = ProgressPrinter::new();
@@ -86,6 +126,9 @@ async fn main() -> anyhow::Result<()> {
                     .error_for_status()?
                     .text()
                     .await?;
+                if let Ok(parsed) = serde_json::from_str::<JudgeLog>(&log_data) {
+                    print_memory_samples(&parsed);
+                }
                 let path = format!("log-{}.json", log);
                 let path = Path::new(&path);
                 tokio::fs::write(path, log_data)
@@ -104,9 +147,40 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Prints memory usage charts of tests which have samples
+fn print_memory_samples(log: &JudgeLog) {
+    for test in &log.tests {
+        if let Some(samples) = &test.memory_samples {
+            if samples.is_empty() {
+                continue;
+            }
+            let peak = samples.iter().map(|s| s.memory).max().unwrap_or(0);
+            println!(
+                "Test {} memory: {} (peak {} KiB)",
+                test.test_id.get(),
+                sparkline(samples, peak),
+                peak / 1024
+            );
+        }
+    }
+}
+
+fn sparkline(samples: &[MemorySample], peak: u64) -> String {
+    const BARS: &[char] = &['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    samples
+        .iter()
+        .map(|s| {
+            let idx = (s.memory * (BARS.len() as u64 - 1))
+                .checked_div(peak)
+                .unwrap_or(0);
+            BARS[idx as usize]
+        })
+        .collect()
+}
+
 struct ProgressPrinter {
     last_test: Option<u32>,
-    last_score: Option<u32>,
+    last_score: Option<Score>,
 }
 
 impl ProgressPrinter {
@@ -118,7 +192,7 @@ impl ProgressPrinter {
     }
 
     fn add(&mut self, live_status: &LiveJudgeStatus) {
-        if let Some(t) = live_status.test {
+        if let Some(t) = live_status.test.map(|t| t.get()) {
             if Some(t) != self.last_test {
                 self.last_test = Some(t);
                 println!("Running on test {}", t);
@@ -1,16 +1,25 @@
 //! This library is responsible for fetching problem packages
 
 mod registry;
+pub mod template;
+mod validate;
 
 use anyhow::Context;
 use registry::Registry;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 // TODO: cache expiration, checksum, etc
 /// Stores cached problem information
 struct ProblemCache {
-    /// Maps problem name to problem cache.
-    items: HashMap<String, ProblemCacheItem>,
+    /// Maps problem name to the latest revision of the problem.
+    items: HashMap<String, LoadedProblem>,
 }
 
 impl ProblemCache {
@@ -21,16 +30,401 @@ impl ProblemCache {
     }
 }
 
-struct ProblemCacheItem {
-    assets: PathBuf,
-    manifest: pom::Problem,
+/// Directory with files of a single problem revision. It is removed when
+/// neither the cache nor any job refers to the revision.
+#[derive(Debug)]
+struct RevisionDir(PathBuf);
+
+impl RevisionDir {
+    fn remove(path: &Path) {
+        if let Err(err) = std::fs::remove_dir_all(path) {
+            tracing::warn!(path = %path.display(), "failed to remove problem revision: {}", err);
+        }
+    }
+}
+
+impl Drop for RevisionDir {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.0);
+        // already removed by `Loader::release`
+        if path.as_os_str().is_empty() {
+            return;
+        }
+        // revision is usually released by an async task, which must not
+        // be blocked while large directory is removed
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(move || Self::remove(&path));
+            }
+            Err(_) => Self::remove(&path),
+        }
+    }
+}
+
+/// Judge-specific problem settings, which are not part of `pom::Problem`.
+/// They are read from the `judge` section of the problem manifest.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct ProblemExtensions {
+    /// If enabled, test id, test name and test group are passed to the
+    /// solution in `JJS_TEST_ID`, `JJS_TEST_NAME` and `JJS_TEST_GROUP`
+    /// environment variables. Test name is the test number if the
+    /// problem does not name tests.
+    #[serde(default)]
+    pub expose_test_metadata: bool,
+    /// Files which are available to the solution at runtime (e.g.
+    /// dictionaries or precomputed tables). They are mounted read-only.
+    #[serde(default)]
+    pub runtime_files: Vec<RuntimeFile>,
+    /// Maximal score of the problem. Used to normalize live scores.
+    #[serde(default)]
+    pub max_score: Option<u32>,
+    /// If enabled, one solution sandbox is reused by all tests of a run
+    /// (if invoker supports it). This reduces judging latency for problems
+    /// with many tests.
+    #[serde(default)]
+    pub reuse_sandbox: bool,
+    /// If set, the problem is interactive: interactor runs concurrently
+    /// with the solution and decides the verdict instead of the checker.
+    #[serde(default)]
+    pub interactor: Option<Interactor>,
+    /// Limits of the checker (or interactor) sandbox. Limits which are not
+    /// set here are the same as the limits of the solution on the test.
+    #[serde(default)]
+    pub checker_limits: Option<pom::Limits>,
+    /// Additional commands which are run on each test before the solution
+    /// (e.g. input preprocessors) or before the checker (e.g. output
+    /// normalizers). Steps of the same phase run in declaration order.
+    #[serde(default)]
+    pub pipeline: Vec<PipelineStep>,
+    /// Additional environment variables of the checker (or interactor).
+    /// Their values, as well as checker and interactor arguments, may
+    /// contain placeholders such as `$(Test.Id)` (see [`template`]).
+    #[serde(default)]
+    pub checker_env: BTreeMap<String, String>,
+    /// Parameters of test groups (e.g. scoring weight), keyed by group
+    /// name. Each parameter is available to the checker as
+    /// `$(Group.${name})` placeholder.
+    #[serde(default)]
+    pub group_params: HashMap<String, BTreeMap<String, String>>,
+    /// Re-execution of tests whose time usage is close to the time limit.
+    /// Overrides the judge-wide policy.
+    #[serde(default)]
+    pub rerun: Option<RerunPolicy>,
+    /// Display names of tests (e.g. `sample-2`), in the order of tests in
+    /// the manifest. Either empty or contains a name for each test. Tests
+    /// are still identified by their numbers, names are only shown to
+    /// users.
+    #[serde(default)]
+    pub test_names: Vec<String>,
+    /// Hardening of solution, checker and pipeline sandboxes. It is
+    /// combined with the judge-wide policy, and can only make it stricter.
+    #[serde(default)]
+    pub security: SecurityPolicy,
+    /// Built-in comparison of solution output with the correct answer.
+    /// If set, checker is not run, so `checker_exe` may be absent from
+    /// the package. Not supported for interactive problems.
+    #[serde(default)]
+    pub comparator: Option<Comparator>,
+}
+
+impl ProblemExtensions {
+    /// Returns display name of the test, if the problem names its tests
+    pub fn test_name(&self, test_id: pom::TestId) -> Option<&str> {
+        self.test_names.get(test_id.to_idx()).map(String::as_str)
+    }
+}
+
+/// Problem-specific command which is run on each test in its own sandbox.
+/// Pre-test steps read test data from stdin, and their stdout is passed to
+/// the solution instead. Post-test steps read solution output from stdin,
+/// and their stdout is passed to the checker instead. Both get original
+/// test data in the file from `JJS_TEST` environment variable.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct PipelineStep {
+    /// Name of the step in judge logs
+    pub name: String,
+    pub phase: PipelinePhase,
+    pub exe: pom::FileRef,
+    /// Additional arguments
+    #[serde(default)]
+    pub argv: Vec<String>,
+    /// Limits of the step sandbox. Limits which are not set here are the
+    /// same as the limits of the solution on the test.
+    #[serde(default)]
+    pub limits: Option<pom::Limits>,
+    /// Sandbox image. By default, the checker image is used.
+    #[serde(default)]
+    pub image: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PipelinePhase {
+    /// Step runs before the solution. If it fails, the test fails with
+    /// judge fault.
+    PreTest,
+    /// Step runs after the solution, before the checker. If it fails,
+    /// solution output is considered malformed (presentation error).
+    PostTest,
+}
+
+/// Hardening of sandboxes. If several policies apply to a sandbox, they
+/// are combined (see [`combine`](SecurityPolicy::combine)). Unknown
+/// fields are rejected, so that a misspelled restriction is not ignored.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SecurityPolicy {
+    /// Maximal number of processes in a sandbox. Lower limits of
+    /// toolchains and tests are kept.
+    #[serde(default)]
+    pub max_processes: Option<u64>,
+    /// If true, sandboxes never get network access, even if toolchain
+    /// requests it for builds
+    #[serde(default)]
+    pub deny_network: bool,
+    /// If set, file system of a sandbox is read-only, except for these
+    /// absolute paths and directories exposed by judge. Requires invoker
+    /// support.
+    #[serde(default)]
+    pub writable_paths: Option<Vec<PathBuf>>,
+    /// If set, core dumps of sandboxed commands are enabled or disabled.
+    /// Requires invoker support.
+    #[serde(default)]
+    pub core_dumps: Option<bool>,
+}
+
+impl SecurityPolicy {
+    /// Returns policy which is at least as strict as both `self` and
+    /// `other`
+    pub fn combine(&self, other: &SecurityPolicy) -> SecurityPolicy {
+        let max_processes = match (self.max_processes, other.max_processes) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let writable_paths = match (&self.writable_paths, &other.writable_paths) {
+            (Some(a), Some(b)) => {
+                // path is writable if it lies under writable paths of both
+                let mut paths: Vec<PathBuf> = a
+                    .iter()
+                    .filter(|p| b.iter().any(|q| p.starts_with(q)))
+                    .chain(b.iter().filter(|p| a.iter().any(|q| p.starts_with(q))))
+                    .cloned()
+                    .collect();
+                paths.sort();
+                paths.dedup();
+                Some(paths)
+            }
+            (a, b) => a.clone().or_else(|| b.clone()),
+        };
+        let core_dumps = match (self.core_dumps, other.core_dumps) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (a, b) => a.or(b),
+        };
+        SecurityPolicy {
+            max_processes,
+            deny_network: self.deny_network || other.deny_network,
+            writable_paths,
+            core_dumps,
+        }
+    }
+
+    /// Checks that policy is meaningful
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_processes == Some(0) {
+            anyhow::bail!("max-processes must be positive");
+        }
+        for path in self.writable_paths.iter().flatten() {
+            if !path.is_absolute() {
+                anyhow::bail!("writable path {} must be absolute", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describes when tests are run again to make timing more stable on noisy
+/// hardware
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct RerunPolicy {
+    /// Maximal number of additional runs of a test. Zero disables
+    /// re-execution.
+    pub max_reruns: u32,
+    /// Test is run again if CPU time of its first run differs from the
+    /// time limit by at most this percentage of the limit
+    pub window_percent: u32,
+    /// Which run determines outcome of the test
+    #[serde(default)]
+    pub selection: RunSelection,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunSelection {
+    /// Run with the smallest CPU time
+    #[default]
+    Best,
+    /// Run with the median CPU time
+    Median,
+}
+
+impl std::str::FromStr for RunSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "best" => Ok(RunSelection::Best),
+            "median" => Ok(RunSelection::Median),
+            _ => anyhow::bail!(
+                "unknown run selection {:?}, expected one of: best, median",
+                s
+            ),
+        }
+    }
+}
+
+/// Comparison of solution output with the correct answer, performed by
+/// judge itself instead of a checker
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Comparator {
+    /// Outputs must consist of the same whitespace-separated tokens
+    Tokens,
+    /// Outputs must consist of the same lines. Trailing whitespace of each
+    /// line and trailing empty lines are ignored.
+    Lines,
+    /// Like `Tokens`, but tokens which are both numbers are equal if they
+    /// differ by at most `epsilon`, absolutely or relatively
+    Float { epsilon: f64 },
+}
+
+/// Program which communicates with the solution of an interactive
+/// problem. Its stdout is connected to the solution stdin, and the
+/// solution stdout is connected to its stdin. It reports verdict in the
+/// same format as the checker.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Interactor {
+    pub exe: pom::FileRef,
+    /// Additional arguments
+    #[serde(default)]
+    pub argv: Vec<String>,
+}
+
+/// Problem file exposed to the solution
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct RuntimeFile {
+    pub source: pom::FileRef,
+    /// Absolute path of the file in the solution sandbox.
+    /// Its parent directory is replaced with a read-only directory
+    /// containing all runtime files with the same parent. It must not be
+    /// placed in the directory of the solution binary, `/compile-out`.
+    pub sandbox_path: PathBuf,
+}
+
+impl RuntimeFile {
+    /// Splits sandbox path into parent directory and file name, checking
+    /// that the path can be used
+    pub fn split_sandbox_path(&self) -> anyhow::Result<(&Path, &str)> {
+        let path = &self.sandbox_path;
+        let has_parent_dir = path
+            .components()
+            .any(|c| c == std::path::Component::ParentDir);
+        let parent = path
+            .parent()
+            .filter(|p| p.is_absolute() && *p != Path::new("/"));
+        let file_name = path.file_name().and_then(|n| n.to_str());
+        match (parent, file_name) {
+            (Some(parent), _) if parent.starts_with("/compile-out") => anyhow::bail!(
+                "runtime file {} conflicts with solution binary directory",
+                path.display()
+            ),
+            (Some(parent), Some(file_name)) if !has_parent_dir => Ok((parent, file_name)),
+            _ => anyhow::bail!(
+                "runtime file path {} must be absolute, must not contain `..` and must not be placed in /",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Problem which is loaded bypassing registries, e.g. while it is being
+/// developed
+#[derive(Debug)]
+pub enum LocalProblem {
+    /// Absolute path of a directory on this host, laid out as problems
+    /// of the filesystem registry
+    Dir(PathBuf),
+    /// Package in the format accepted by [`Loader::upload`]
+    Package(Vec<u8>),
+}
+
+/// Returned by [`Loader::upload`] if problem package is rejected
+#[derive(Debug)]
+pub struct InvalidProblem(pub String);
+
+impl std::fmt::Display for InvalidProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "problem package is invalid: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidProblem {}
+
+/// Problem, resolved by the [`Loader`].
+///
+/// Each value is a handle to a particular revision of the problem: its
+/// assets are kept on disk while the handle is alive, even if the problem
+/// is updated in the meantime. Jobs keep the handle they started with to
+/// finish against the same revision.
+#[derive(Clone)]
+pub struct LoadedProblem {
+    pub manifest: pom::Problem,
+    pub extensions: ProblemExtensions,
+    /// Directory containing problem assets
+    pub assets: PathBuf,
+    /// Identifies fetched revision. Unique within the loader lifetime.
+    pub revision: u64,
+    _dir: Arc<RevisionDir>,
+}
+
+/// Problem revision dropped from the cache, see
+/// [`Loader::invalidate`]
+#[derive(Debug, Clone)]
+pub struct Invalidated {
+    pub problem_name: String,
+    pub revision: u64,
+    /// True if jobs still use the revision, so its files are kept until
+    /// they release it
+    pub in_use: bool,
+}
+
+/// Parses problem manifest together with judge-specific extensions.
+pub(crate) fn parse_manifest(data: &[u8]) -> anyhow::Result<(pom::Problem, ProblemExtensions)> {
+    #[derive(serde::Deserialize)]
+    struct ManifestExtensions {
+        #[serde(default)]
+        judge: ProblemExtensions,
+    }
+    let manifest = serde_json::from_slice(data).context("invalid problem manifest")?;
+    let extensions: ManifestExtensions =
+        serde_json::from_slice(data).context("invalid judge section of problem manifest")?;
+    Ok((manifest, extensions.judge))
 }
 
 pub struct Loader {
     registries: Vec<Box<dyn Registry>>,
     cache: tokio::sync::Mutex<ProblemCache>,
-    /// Each problem will be represented by ${cache_dir}/${problem_name}
+    /// Each problem revision will be represented by
+    /// ${cache_dir}/${problem_name}/${revision}
     cache_dir: PathBuf,
+    /// Used to name revision directories
+    revision_counter: AtomicU64,
+    /// Used to name scratch directories of [`validate`](Loader::validate)
+    validation_counter: AtomicU64,
 }
 
 impl Loader {
@@ -42,9 +436,12 @@ impl Loader {
             registries: vec![],
             cache_dir,
             cache: tokio::sync::Mutex::new(ProblemCache::new()),
+            revision_counter: AtomicU64::new(0),
+            validation_counter: AtomicU64::new(0),
         };
+        loader.clean_cache_dir().await?;
         if let Some(fs) = &conf.fs {
-            let fs_reg = registry::FsRegistry::new(fs.clone());
+            let fs_reg = registry::FsRegistry::new(fs.clone(), loader.cache_dir.join(".packages"));
             loader.registries.push(Box::new(fs_reg));
         }
         if let Some(mongodb) = &conf.mongodb {
@@ -57,25 +454,25 @@ impl Loader {
     }
 
     /// Tries to resolve problem named `problem_name` in all configured
-    /// registries. On success, returns problem manifest and path to assets dir.
+    /// registries. Problem is checked the same way uploaded packages are.
+    /// Its defects, as well as names which can not be used as file names,
+    /// are reported as [`InvalidProblem`].
     #[tracing::instrument(skip(self))]
-    pub async fn find(
-        &self,
-        problem_name: &str,
-    ) -> anyhow::Result<Option<(pom::Problem, PathBuf)>> {
+    pub async fn find(&self, problem_name: &str) -> anyhow::Result<Option<LoadedProblem>> {
+        // registries and the cache build paths from the name
+        check_problem_name(problem_name)?;
         let mut cache = self.cache.lock().await;
-        if let Some(cached_info) = cache.items.get(problem_name) {
-            tracing::info!("Found problem in cache");
-            return Ok(Some((
-                cached_info.manifest.clone(),
-                cached_info.assets.clone(),
-            )));
+        if let Some(cached) = cache.items.get(problem_name) {
+            tracing::info!(revision = cached.revision, "Found problem in cache");
+            return Ok(Some(cached.clone()));
         }
         tracing::info!("cache miss");
-        // cache for this problem not found, let's load it.
-        let problem_path = self.cache_dir.join(problem_name);
+        // cache for this problem not found, let's load it into a new
+        // revision directory. Older revisions may still be in use.
+        let revision = self.revision_counter.fetch_add(1, Ordering::Relaxed);
+        let problem_path = self.cache_dir.join(problem_name).join(revision.to_string());
         tokio::fs::remove_dir_all(&problem_path).await.ok();
-        tokio::fs::create_dir(&problem_path)
+        tokio::fs::create_dir_all(&problem_path)
             .await
             .with_context(|| {
                 format!(
@@ -83,9 +480,130 @@ impl Loader {
                     problem_path.display()
                 )
             })?;
+        // from now on the directory is removed once it is not needed
+        let dir = Arc::new(RevisionDir(problem_path.clone()));
+        let (manifest, extensions) = match self.fetch(problem_name, &problem_path).await? {
+            Some(res) => res,
+            None => {
+                // no registry knows about this problem
+                tracing::warn!("problem not found");
+                return Ok(None);
+            }
+        };
+        let assets = problem_path.join("assets");
+        validate::validate(&manifest, &extensions, &assets).await?;
+        let problem = LoadedProblem {
+            manifest,
+            extensions,
+            assets,
+            revision,
+            _dir: dir,
+        };
+        tracing::info!(revision, "loaded problem revision");
+        cache
+            .items
+            .insert(problem_name.to_string(), problem.clone());
+        Ok(Some(problem))
+    }
+
+    /// Drops cached revision of the problem, so that the next
+    /// [`find`](Loader::find) fetches it from registries again. If no job
+    /// uses the revision, its files are removed before returning;
+    /// otherwise they are removed once jobs release it. Returns None if
+    /// the problem was not cached.
+    #[tracing::instrument(skip(self))]
+    pub async fn invalidate(&self, problem_name: &str) -> Option<Invalidated> {
+        let removed = self.cache.lock().await.items.remove(problem_name)?;
+        tracing::info!(revision = removed.revision, "dropped cached problem");
+        Some(Self::release(problem_name.to_string(), removed).await)
+    }
+
+    /// Drops all cached revisions, see [`invalidate`](Loader::invalidate).
+    /// Results are sorted by problem name.
+    pub async fn invalidate_all(&self) -> Vec<Invalidated> {
+        let removed = std::mem::take(&mut self.cache.lock().await.items);
+        tracing::info!(count = removed.len(), "dropping all cached problems");
+        let mut invalidated = Vec::new();
+        for (name, problem) in removed {
+            invalidated.push(Self::release(name, problem).await);
+        }
+        invalidated.sort_by(|a, b| a.problem_name.cmp(&b.problem_name));
+        invalidated
+    }
+
+    /// Removes files of the revision which is no longer cached, unless
+    /// some job still uses it
+    async fn release(problem_name: String, problem: LoadedProblem) -> Invalidated {
+        let revision = problem.revision;
+        let in_use = match Arc::try_unwrap(problem._dir) {
+            Ok(mut dir) => {
+                let path = std::mem::take(&mut dir.0);
+                tokio::task::spawn_blocking(move || RevisionDir::remove(&path))
+                    .await
+                    .ok();
+                false
+            }
+            Err(_) => {
+                tracing::info!(revision, "revision is still used by jobs");
+                true
+            }
+        };
+        Invalidated {
+            problem_name,
+            revision,
+            in_use,
+        }
+    }
+
+    /// Removes problem revisions left by previous runs. Only directories
+    /// named like revisions (`${problem_name}/${revision}` and
+    /// `${problem_name}/local-${revision}`) are removed, so that files
+    /// which were put to the cache directory by mistake survive. Problem
+    /// directories are removed if they become empty. Directories starting
+    /// with `.` are scratch directories and removed when used, except for
+    /// `.packages`, which holds unpacked packages of the filesystem
+    /// registry and is kept between runs.
+    async fn clean_cache_dir(&self) -> anyhow::Result<()> {
+        let mut entries = tokio::fs::read_dir(&self.cache_dir)
+            .await
+            .with_context(|| format!("failed to list {}", self.cache_dir.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_name().to_string_lossy().starts_with('.')
+                || !entry.file_type().await?.is_dir()
+            {
+                continue;
+            }
+            let problem_dir = entry.path();
+            let mut revisions = tokio::fs::read_dir(&problem_dir)
+                .await
+                .with_context(|| format!("failed to list {}", problem_dir.display()))?;
+            while let Some(revision) = revisions.next_entry().await? {
+                if !is_revision_name(&revision.file_name().to_string_lossy())
+                    || !revision.file_type().await?.is_dir()
+                {
+                    continue;
+                }
+                let path = revision.path();
+                tokio::fs::remove_dir_all(&path).await.with_context(|| {
+                    format!("failed to remove stale problem revision {}", path.display())
+                })?;
+            }
+            // fails if something else is left there
+            tokio::fs::remove_dir(&problem_dir).await.ok();
+        }
+        Ok(())
+    }
+
+    /// Downloads problem from the first registry which knows about it.
+    /// Assets are placed to `${problem_path}/assets`.
+    async fn fetch(
+        &self,
+        problem_name: &str,
+        problem_path: &Path,
+    ) -> anyhow::Result<Option<(pom::Problem, ProblemExtensions)>> {
         for registry in &self.registries {
             let res = registry
-                .get_problem(problem_name, &problem_path)
+                .get_problem(problem_name, problem_path)
                 .await
                 .with_context(|| {
                     format!(
@@ -95,26 +613,254 @@ impl Loader {
                     )
                 })?;
 
-            if let Some(manifest) = res {
+            if let Some(problem) = res {
                 tracing::info!(
                     registry_name = registry.name(),
                     "successfully resolved problem"
                 );
-                let assets_path = problem_path.join("assets");
-                cache.items.insert(
-                    problem_name.to_string(),
-                    ProblemCacheItem {
-                        manifest: manifest.clone(),
-                        assets: assets_path.clone(),
-                    },
-                );
-                return Ok(Some((manifest, assets_path)));
+                return Ok(Some(problem));
             }
         }
-        // no registry knows about this problem
-        tracing::warn!("problem not found");
         Ok(None)
     }
+
+    /// Fetches problem named `problem_name` and checks it the same way
+    /// uploaded packages are checked, without touching the cache.
+    /// Returns false if no registry knows about the problem. Defects of
+    /// the problem are reported as [`InvalidProblem`].
+    #[tracing::instrument(skip(self))]
+    pub async fn validate(&self, problem_name: &str) -> anyhow::Result<bool> {
+        check_problem_name(problem_name)?;
+        let scratch_dir = self.cache_dir.join(format!(
+            ".validate-{}",
+            self.validation_counter.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::remove_dir_all(&scratch_dir).await.ok();
+        tokio::fs::create_dir(&scratch_dir)
+            .await
+            .with_context(|| format!("failed to create {}", scratch_dir.display()))?;
+        let res = self.validate_in(problem_name, &scratch_dir).await;
+        tokio::fs::remove_dir_all(&scratch_dir).await.ok();
+        res
+    }
+
+    async fn validate_in(&self, problem_name: &str, scratch_dir: &Path) -> anyhow::Result<bool> {
+        let fetched = self.fetch(problem_name, scratch_dir).await.map_err(|err| {
+            // registries fail with a JSON error if the manifest is malformed
+            let malformed = err
+                .chain()
+                .any(|e| e.downcast_ref::<serde_json::Error>().is_some());
+            if malformed {
+                InvalidProblem(format!("{:#}", err)).into()
+            } else {
+                err
+            }
+        })?;
+        let (manifest, extensions) = match fetched {
+            Some(res) => res,
+            None => return Ok(false),
+        };
+        validate::validate(&manifest, &extensions, &scratch_dir.join("assets")).await?;
+        tracing::info!("problem is valid");
+        Ok(true)
+    }
+}
+
+impl Loader {
+    /// Validates problem package and stores it in the first writable
+    /// registry. Package is a gzipped tarball containing `manifest.json`
+    /// and `assets` directory.
+    /// Cached version of the problem, if any, is dropped. Jobs which
+    /// already use it are not affected.
+    #[tracing::instrument(skip(self, package))]
+    pub async fn upload(&self, problem_name: &str, package: Vec<u8>) -> anyhow::Result<()> {
+        check_problem_name(problem_name)?;
+        let registry = self
+            .registries
+            .iter()
+            .find(|r| r.is_writable())
+            .context("no writable problem registry is configured")?;
+        // holding the lock prevents `find` from caching previous version
+        let mut cache = self.cache.lock().await;
+        let upload_dir = self.cache_dir.join(format!(".upload-{}", problem_name));
+        tokio::fs::remove_dir_all(&upload_dir).await.ok();
+        let res = Self::unpack_and_store(&**registry, problem_name, package, &upload_dir).await;
+        tokio::fs::remove_dir_all(&upload_dir).await.ok();
+        res?;
+        tracing::info!(
+            registry_name = registry.name(),
+            "successfully uploaded problem"
+        );
+        cache.items.remove(problem_name);
+        Ok(())
+    }
+
+    async fn unpack_and_store(
+        registry: &dyn Registry,
+        problem_name: &str,
+        package: Vec<u8>,
+        upload_dir: &Path,
+    ) -> anyhow::Result<()> {
+        let (manifest_data, manifest, extensions) = unpack(package, upload_dir).await?;
+        let assets_path = upload_dir.join("assets");
+        validate::validate(&manifest, &extensions, &assets_path).await?;
+        registry
+            .put_problem(problem_name, &manifest_data, &assets_path)
+            .await
+            .with_context(|| format!("failed to store problem in registry {}", registry.name()))
+    }
+
+    /// Loads problem which is not stored in any registry. The revision
+    /// is not cached, and its files are removed once the returned handle
+    /// is dropped. Problem is checked the same way uploaded packages are,
+    /// and its defects are reported as [`InvalidProblem`].
+    #[tracing::instrument(skip(self, problem))]
+    pub async fn load_local(
+        &self,
+        problem_name: &str,
+        problem: LocalProblem,
+    ) -> anyhow::Result<LoadedProblem> {
+        check_problem_name(problem_name)?;
+        let revision = self.revision_counter.fetch_add(1, Ordering::Relaxed);
+        // registry revisions are named by numbers only, so they never clash
+        let problem_path = self
+            .cache_dir
+            .join(problem_name)
+            .join(format!("local-{}", revision));
+        tokio::fs::remove_dir_all(&problem_path).await.ok();
+        tokio::fs::create_dir_all(&problem_path)
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to prepare problem assets directory at {}",
+                    problem_path.display()
+                )
+            })?;
+        let dir = Arc::new(RevisionDir(problem_path.clone()));
+        let (manifest, extensions) = match problem {
+            LocalProblem::Dir(src) => {
+                if !src.is_absolute() {
+                    return Err(InvalidProblem(format!(
+                        "problem directory {} is not absolute",
+                        src.display()
+                    ))
+                    .into());
+                }
+                registry::read_problem_dir(&src, &problem_path)
+                    .await
+                    .map_err(|err| InvalidProblem(format!("{:#}", err)))?
+                    .ok_or_else(|| {
+                        InvalidProblem(format!("{} does not contain manifest.json", src.display()))
+                    })?
+            }
+            LocalProblem::Package(package) => {
+                let (_, manifest, extensions) = unpack(package, &problem_path).await?;
+                (manifest, extensions)
+            }
+        };
+        let assets = problem_path.join("assets");
+        validate::validate(&manifest, &extensions, &assets).await?;
+        tracing::info!(revision, "loaded local problem");
+        Ok(LoadedProblem {
+            manifest,
+            extensions,
+            assets,
+            revision,
+            _dir: dir,
+        })
+    }
+}
+
+/// Checks if directory name is produced by [`Loader::find`] or
+/// [`Loader::load_local`]
+fn is_revision_name(name: &str) -> bool {
+    let number = name.strip_prefix("local-").unwrap_or(name);
+    !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn check_problem_name(problem_name: &str) -> Result<(), InvalidProblem> {
+    if problem_name.is_empty()
+        || problem_name.starts_with('.')
+        || problem_name.contains(['/', '\\'])
+    {
+        return Err(InvalidProblem(format!(
+            "invalid problem name: {:?}",
+            problem_name
+        )));
+    }
+    Ok(())
+}
+
+/// Unpacks package in the format accepted by [`Loader::upload`] to `dir`.
+/// Returns raw manifest together with the parsed one.
+async fn unpack(
+    package: Vec<u8>,
+    dir: &Path,
+) -> anyhow::Result<(Vec<u8>, pom::Problem, ProblemExtensions)> {
+    let dest = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let decoder = flate2::bufread::GzDecoder::new(package.as_slice());
+        tar::Archive::new(decoder).unpack(dest)
+    })
+    .await
+    .unwrap()
+    .map_err(|err| InvalidProblem(format!("failed to unpack: {}", err)))?;
+    let manifest_path = dir.join("manifest.json");
+    let manifest_data = tokio::fs::read(&manifest_path)
+        .await
+        .map_err(|_| InvalidProblem("manifest.json is missing".to_string()))?;
+    let (manifest, extensions) =
+        parse_manifest(&manifest_data).map_err(|err| InvalidProblem(format!("{:#}", err)))?;
+    Ok((manifest_data, manifest, extensions))
+}
+
+/// Builds package in the format accepted by [`Loader::upload`].
+/// `extra_assets` are added to the files of `assets`; their paths are
+/// relative to the assets directory. If such file already exists, the
+/// added one takes precedence on unpacking.
+pub async fn pack(
+    manifest: &pom::Problem,
+    extensions: &ProblemExtensions,
+    assets: &Path,
+    extra_assets: Vec<(PathBuf, Vec<u8>)>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut manifest_data =
+        serde_json::to_value(manifest).context("failed to serialize manifest")?;
+    manifest_data
+        .as_object_mut()
+        .context("manifest is not an object")?
+        .insert(
+            "judge".to_string(),
+            serde_json::to_value(extensions).context("failed to serialize extensions")?,
+        );
+    let manifest_data = serde_json::to_vec_pretty(&manifest_data)?;
+    let assets = assets.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        append_file(&mut builder, Path::new("manifest.json"), &manifest_data)?;
+        builder.append_dir_all("assets", &assets)?;
+        // later entries overwrite earlier ones on unpacking
+        for (path, data) in &extra_assets {
+            append_file(&mut builder, &Path::new("assets").join(path), data)?;
+        }
+        builder.into_inner()?.finish()
+    })
+    .await
+    .unwrap()
+    .context("failed to build problem package")
+}
+
+fn append_file<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)
 }
 
 /// Used in [`from_config`](Loader::from_config) constructor
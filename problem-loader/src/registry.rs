@@ -1,8 +1,13 @@
 //! defines Registry trait and several registries
 
+use crate::ProblemExtensions;
 use anyhow::Context as _;
 use async_trait::async_trait;
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
 use tracing::instrument;
 
 /// Single problem source.
@@ -19,20 +24,142 @@ pub trait Registry: Send + Sync {
         &self,
         problem_name: &str,
         assets_path: &Path,
-    ) -> anyhow::Result<Option<pom::Problem>>;
+    ) -> anyhow::Result<Option<(pom::Problem, ProblemExtensions)>>;
+
+    /// Returns true if registry supports `put_problem`.
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    /// Stores problem manifest and assets, replacing previous version
+    /// of the problem if it exists.
+    async fn put_problem(
+        &self,
+        _problem_name: &str,
+        _manifest: &[u8],
+        _assets_path: &Path,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!("registry {} is read-only", self.name())
+    }
 }
 
-/// Resolves problems from filesystem
+/// Format of a problem package
+#[derive(Debug, Clone, Copy)]
+enum PackageFormat {
+    TarGz,
+    Zip,
+}
+
+/// Extensions of problem packages, in order of lookup
+const PACKAGE_EXTENSIONS: &[(&str, PackageFormat)] = &[
+    ("tar.gz", PackageFormat::TarGz),
+    ("tgz", PackageFormat::TarGz),
+    ("zip", PackageFormat::Zip),
+];
+
+/// Name of the file in the unpacked package which identifies the version
+/// of the package it was unpacked from
+const PACKAGE_STAMP: &str = ".package-stamp";
+
+/// Resolves problems from filesystem. Each problem is either a directory
+/// containing `manifest.json` and `assets`, or a package
+/// (`${name}.tar.gz`, `${name}.tgz` or `${name}.zip`) with the same
+/// layout. Directory takes precedence over packages.
 #[derive(Debug)]
 pub struct FsRegistry {
     /// Directory containing all problems
     problems_dir: PathBuf,
+    /// Packages are unpacked to `${unpack_dir}/${problem_name}` on demand
+    /// and reused while the package is not changed
+    unpack_dir: PathBuf,
+    /// Prevents concurrent unpacking of the same package
+    unpack_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
 }
 
 impl FsRegistry {
-    pub fn new(problems_dir: PathBuf) -> FsRegistry {
-        FsRegistry { problems_dir }
+    pub fn new(problems_dir: PathBuf, unpack_dir: PathBuf) -> FsRegistry {
+        FsRegistry {
+            problems_dir,
+            unpack_dir,
+            unpack_locks: Mutex::new(HashMap::new()),
+        }
     }
+
+    /// Returns path and format of the problem package, if it exists
+    async fn find_package(&self, problem_name: &str) -> Option<(PathBuf, PackageFormat)> {
+        for (extension, format) in PACKAGE_EXTENSIONS {
+            let path = self
+                .problems_dir
+                .join(format!("{}.{}", problem_name, extension));
+            if tokio::fs::metadata(&path).await.is_ok() {
+                return Some((path, *format));
+            }
+        }
+        None
+    }
+
+    /// Unpacks the package unless it was unpacked before and did not
+    /// change since then. Returns directory of the unpacked package.
+    async fn unpack_package(
+        &self,
+        problem_name: &str,
+        package: &Path,
+        format: PackageFormat,
+    ) -> anyhow::Result<PathBuf> {
+        let lock = self
+            .unpack_locks
+            .lock()
+            .unwrap()
+            .entry(problem_name.to_string())
+            .or_default()
+            .clone();
+        let _guard = lock.lock().await;
+        let metadata = tokio::fs::metadata(package)
+            .await
+            .with_context(|| format!("failed to stat {}", package.display()))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos());
+        let stamp = format!("{} {} {}", package.display(), metadata.len(), modified);
+        let unpacked = self.unpack_dir.join(problem_name);
+        let stamp_path = unpacked.join(PACKAGE_STAMP);
+        if tokio::fs::read_to_string(&stamp_path).await.ok().as_deref() == Some(stamp.as_str()) {
+            tracing::debug!("reusing unpacked package");
+            return Ok(unpacked);
+        }
+        tracing::info!(package = %package.display(), "unpacking problem package");
+        tokio::fs::remove_dir_all(&unpacked).await.ok();
+        tokio::fs::create_dir_all(&unpacked)
+            .await
+            .with_context(|| format!("failed to create {}", unpacked.display()))?;
+        let src = package.to_path_buf();
+        let dest = unpacked.clone();
+        tokio::task::spawn_blocking(move || unpack_archive(&src, &dest, format))
+            .await
+            .unwrap()
+            .with_context(|| format!("failed to unpack {}", package.display()))?;
+        // stamp is written last, so that interrupted unpacking is redone
+        tokio::fs::write(&stamp_path, stamp)
+            .await
+            .with_context(|| format!("failed to write {}", stamp_path.display()))?;
+        Ok(unpacked)
+    }
+}
+
+fn unpack_archive(src: &Path, dest: &Path, format: PackageFormat) -> anyhow::Result<()> {
+    let file = std::fs::File::open(src)?;
+    match format {
+        PackageFormat::TarGz => {
+            let decoder = flate2::read::GzDecoder::new(std::io::BufReader::new(file));
+            tar::Archive::new(decoder).unpack(dest)?;
+        }
+        PackageFormat::Zip => {
+            zip::ZipArchive::new(file)?.extract(dest)?;
+        }
+    }
+    Ok(())
 }
 
 #[async_trait]
@@ -42,44 +169,125 @@ impl Registry for FsRegistry {
         &self,
         problem_name: &str,
         dest_path: &Path,
-    ) -> anyhow::Result<Option<pom::Problem>> {
-        let problem_dir = self.problems_dir.join(problem_name);
-        let manifest_path = problem_dir.join("manifest.json");
-        let manifest_exists = {
-            let mp = manifest_path.clone();
-            tokio::task::spawn_blocking(move || mp.exists())
+    ) -> anyhow::Result<Option<(pom::Problem, ProblemExtensions)>> {
+        let problem = read_problem_dir(&self.problems_dir.join(problem_name), dest_path).await?;
+        if problem.is_some() {
+            return Ok(problem);
         }
-        .await
-        .unwrap();
-        if !manifest_exists {
-            return Ok(None);
+        let (package, format) = match self.find_package(problem_name).await {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+        let unpacked = self.unpack_package(problem_name, &package, format).await?;
+        let problem = read_problem_dir(&unpacked, dest_path).await?;
+        if problem.is_none() {
+            anyhow::bail!(
+                "package {} does not contain manifest.json",
+                package.display()
+            );
         }
-        let manifest = tokio::fs::read(&manifest_path).await.with_context(|| {
-            format!(
-                "failed to read problem manifest from {}",
-                manifest_path.display()
-            )
-        })?;
-        let manifest = serde_json::from_slice(&manifest).context("invalid problem manifest")?;
-        let assets_dir = problem_dir.join("assets");
-        let dest_path = dest_path.to_path_buf();
+        Ok(problem)
+    }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip(manifest))]
+    async fn put_problem(
+        &self,
+        problem_name: &str,
+        manifest: &[u8],
+        assets_path: &Path,
+    ) -> anyhow::Result<()> {
+        let problem_dir = self.problems_dir.join(problem_name);
+        // new version is prepared aside, so that readers never observe
+        // partially written problem
+        let staging_dir = self.problems_dir.join(format!(".{}.new", problem_name));
+        tokio::fs::remove_dir_all(&staging_dir).await.ok();
+        tokio::fs::create_dir_all(&staging_dir)
+            .await
+            .with_context(|| format!("failed to create {}", staging_dir.display()))?;
+        tokio::fs::write(staging_dir.join("manifest.json"), manifest)
+            .await
+            .context("failed to write problem manifest")?;
+        let assets_path = assets_path.to_path_buf();
+        let assets_dest = staging_dir.join("assets");
+        tokio::fs::create_dir(&assets_dest)
+            .await
+            .with_context(|| format!("failed to create {}", assets_dest.display()))?;
         tokio::task::spawn_blocking(move || {
-            fs_extra::dir::copy(&assets_dir, &dest_path, &fs_extra::dir::CopyOptions::new())
-                .with_context(|| {
-                    format!(
-                        "failed to copy {} to {}",
-                        assets_dir.display(),
-                        dest_path.display()
-                    )
-                })?;
+            let options = fs_extra::dir::CopyOptions {
+                content_only: true,
+                ..fs_extra::dir::CopyOptions::new()
+            };
+            fs_extra::dir::copy(&assets_path, &assets_dest, &options).with_context(|| {
+                format!(
+                    "failed to copy {} to {}",
+                    assets_path.display(),
+                    assets_dest.display()
+                )
+            })?;
             Ok::<_, anyhow::Error>(())
         })
         .await
         .unwrap()?;
-        Ok(Some(manifest))
+        if let Err(err) = tokio::fs::remove_dir_all(&problem_dir).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                return Err(err).with_context(|| {
+                    format!("failed to remove old version at {}", problem_dir.display())
+                });
+            }
+        }
+        tokio::fs::rename(&staging_dir, &problem_dir)
+            .await
+            .with_context(|| format!("failed to move problem to {}", problem_dir.display()))?;
+        Ok(())
     }
 }
 
+/// Reads problem directory containing `manifest.json` and `assets`, and
+/// copies assets to `${dest_path}/assets`. Returns None if there is no
+/// manifest.
+pub(crate) async fn read_problem_dir(
+    problem_dir: &Path,
+    dest_path: &Path,
+) -> anyhow::Result<Option<(pom::Problem, ProblemExtensions)>> {
+    let manifest_path = problem_dir.join("manifest.json");
+    let manifest_exists = {
+        let mp = manifest_path.clone();
+        tokio::task::spawn_blocking(move || mp.exists())
+    }
+    .await
+    .unwrap();
+    if !manifest_exists {
+        return Ok(None);
+    }
+    let manifest = tokio::fs::read(&manifest_path).await.with_context(|| {
+        format!(
+            "failed to read problem manifest from {}",
+            manifest_path.display()
+        )
+    })?;
+    let manifest = crate::parse_manifest(&manifest)?;
+    let assets_dir = problem_dir.join("assets");
+    let dest_path = dest_path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        fs_extra::dir::copy(&assets_dir, &dest_path, &fs_extra::dir::CopyOptions::new())
+            .with_context(|| {
+                format!(
+                    "failed to copy {} to {}",
+                    assets_dir.display(),
+                    dest_path.display()
+                )
+            })?;
+        Ok::<_, anyhow::Error>(())
+    })
+    .await
+    .unwrap()?;
+    Ok(Some(manifest))
+}
+
 /// Resolves problems via MongoDB
 pub struct MongoRegistry {
     collection: mongodb::Collection,
@@ -112,7 +320,7 @@ impl Registry for MongoRegistry {
         &self,
         problem_name: &str,
         target_path: &Path,
-    ) -> anyhow::Result<Option<pom::Problem>> {
+    ) -> anyhow::Result<Option<(pom::Problem, ProblemExtensions)>> {
         // at first, let's find document about this problem
         let filter = {
             let mut filter = bson::Document::new();
@@ -133,7 +341,7 @@ impl Registry for MongoRegistry {
         let manifest = doc
             .get_binary_generic("manifest")
             .context("storage schema violation for field `manifest`")?;
-        let manifest = serde_json::from_slice(&manifest).context("invalid problem manifest")?;
+        let manifest = crate::parse_manifest(manifest)?;
 
         let compressed_assets = std::mem::take(
             std::convert::identity(doc)
@@ -157,4 +365,66 @@ impl Registry for MongoRegistry {
 
         Ok(Some(manifest))
     }
+
+    fn is_writable(&self) -> bool {
+        true
+    }
+
+    #[instrument(skip(manifest))]
+    async fn put_problem(
+        &self,
+        problem_name: &str,
+        manifest: &[u8],
+        assets_path: &Path,
+    ) -> anyhow::Result<()> {
+        let assets_path = assets_path.to_path_buf();
+        let compressed_assets = tokio::task::spawn_blocking(move || {
+            let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            let mut archive = tar::Builder::new(encoder);
+            archive.append_dir_all(".", &assets_path)?;
+            archive.into_inner()?.finish()
+        })
+        .await
+        .unwrap()
+        .context("failed to pack assets")?;
+        tracing::info!(
+            compressed_size = compressed_assets.len(),
+            "Uploading problem"
+        );
+
+        let filter = {
+            let mut filter = bson::Document::new();
+            filter.insert("problem-name", problem_name);
+            filter
+        };
+        let doc = {
+            let mut doc = filter.clone();
+            doc.insert(
+                "manifest",
+                bson::Binary {
+                    subtype: bson::spec::BinarySubtype::Generic,
+                    bytes: manifest.to_vec(),
+                },
+            );
+            doc.insert(
+                "assets",
+                bson::Binary {
+                    subtype: bson::spec::BinarySubtype::Generic,
+                    bytes: compressed_assets,
+                },
+            );
+            doc
+        };
+        self.collection
+            .replace_one(
+                filter,
+                doc,
+                mongodb::options::ReplaceOptions::builder()
+                    .upsert(true)
+                    .build(),
+            )
+            .await
+            .context("failed to store problem document")?;
+        Ok(())
+    }
 }
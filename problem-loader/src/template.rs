@@ -0,0 +1,68 @@
+//! Placeholders in checker arguments and environment.
+//!
+//! Judge replaces `$(Name)` placeholders whose names start with `Test.`,
+//! `Group.` or `Problem.` separately for each test. Other placeholders
+//! are left as is, so that invoker substitutions such as
+//! `$(Run.BinaryFilePath)` keep working.
+
+/// Placeholders which are known for every test. In addition, each key
+/// of [`group_params`](crate::ProblemExtensions::group_params) of the
+/// test group is available as `Group.${key}`. `Test.Name` is the test
+/// number if the problem does not name tests.
+pub const TEST_PLACEHOLDERS: &[&str] = &["Test.Id", "Test.Name", "Test.Group", "Problem.Name"];
+
+const PREFIXES: &[&str] = &["Test.", "Group.", "Problem."];
+
+enum Piece<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+fn pieces(template: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("$(") {
+        let after = &rest[start + 2..];
+        let end = match after.find(')') {
+            Some(end) => end,
+            None => break,
+        };
+        let name = &after[..end];
+        if PREFIXES.iter().any(|prefix| name.starts_with(prefix)) {
+            pieces.push(Piece::Text(&rest[..start]));
+            pieces.push(Piece::Placeholder(name));
+        } else {
+            pieces.push(Piece::Text(&rest[..start + 2 + end + 1]));
+        }
+        rest = &after[end + 1..];
+    }
+    pieces.push(Piece::Text(rest));
+    pieces
+}
+
+/// Returns names of placeholders in `template` which are replaced by judge
+pub fn placeholders(template: &str) -> Vec<&str> {
+    pieces(template)
+        .into_iter()
+        .filter_map(|piece| match piece {
+            Piece::Placeholder(name) => Some(name),
+            Piece::Text(_) => None,
+        })
+        .collect()
+}
+
+/// Replaces placeholders with values returned by `lookup`. Fails if
+/// `lookup` does not know some placeholder.
+pub fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(template.len());
+    for piece in pieces(template) {
+        match piece {
+            Piece::Text(text) => out.push_str(text),
+            Piece::Placeholder(name) => match lookup(name) {
+                Some(value) => out.push_str(&value),
+                None => anyhow::bail!("unknown placeholder $({}) in {:?}", name, template),
+            },
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,230 @@
+//! Consistency checks for problem packages.
+//!
+//! This is the only place where problems are checked: uploaded, local and
+//! validated problems, as well as problems loaded from registries for
+//! judging, go through [`validate`].
+
+use crate::{template, Comparator, InvalidProblem, ProblemExtensions};
+use anyhow::Context;
+use std::{collections::BTreeSet, path::Path};
+
+/// Checks that all files referenced by `manifest` are present in `assets`
+/// and that checker, valuer, interactor and pipeline steps are executable.
+/// Files outside of the problem package are not checked.
+pub(crate) async fn validate(
+    manifest: &pom::Problem,
+    extensions: &ProblemExtensions,
+    assets: &Path,
+) -> Result<(), InvalidProblem> {
+    check(manifest, extensions, assets)
+        .await
+        .map_err(|err| InvalidProblem(format!("{:#}", err)))
+}
+
+async fn check(
+    manifest: &pom::Problem,
+    extensions: &ProblemExtensions,
+    assets: &Path,
+) -> anyhow::Result<()> {
+    if manifest.tests.is_empty() {
+        anyhow::bail!("problem has no tests");
+    }
+    let mut refs = Vec::new();
+    let mut executables = Vec::new();
+    if extensions.comparator.is_none() {
+        refs.push(("checker".to_string(), &manifest.checker_exe));
+        executables.push(("checker".to_string(), &manifest.checker_exe));
+    }
+    match &manifest.valuer {
+        pom::Valuer::Child(child) => {
+            refs.push(("valuer".to_string(), &child.exe));
+            executables.push(("valuer".to_string(), &child.exe));
+            if let Some(dir) = &child.current_dir {
+                refs.push(("valuer working directory".to_string(), dir));
+            }
+        }
+    }
+    if let Some(interactor) = &extensions.interactor {
+        refs.push(("interactor".to_string(), &interactor.exe));
+        executables.push(("interactor".to_string(), &interactor.exe));
+    }
+    if !extensions.pipeline.is_empty() && extensions.interactor.is_some() {
+        anyhow::bail!("pipeline steps are not supported for interactive problems");
+    }
+    let mut step_names = std::collections::HashSet::new();
+    for step in &extensions.pipeline {
+        if step.name.is_empty() {
+            anyhow::bail!("pipeline step name must not be empty");
+        }
+        if !step_names.insert(step.name.as_str()) {
+            anyhow::bail!("duplicate pipeline step {}", step.name);
+        }
+        refs.push((format!("pipeline step {}", step.name), &step.exe));
+        executables.push((format!("pipeline step {}", step.name), &step.exe));
+    }
+    for (i, test) in manifest.tests.iter().enumerate() {
+        refs.push((format!("test {} input", i + 1), &test.path));
+        if let Some(correct) = &test.correct {
+            refs.push((format!("test {} answer", i + 1), correct));
+        }
+    }
+    validate_checker_templates(manifest, extensions)?;
+    validate_test_names(manifest, extensions)?;
+    extensions
+        .security
+        .validate()
+        .context("invalid security policy")?;
+    if let Some(comparator) = &extensions.comparator {
+        validate_comparator(manifest, extensions, comparator)?;
+    }
+    if let Some(rerun) = &extensions.rerun {
+        if rerun.window_percent > 100 {
+            anyhow::bail!("rerun window must be at most 100 percent of the time limit");
+        }
+    }
+    for file in &extensions.runtime_files {
+        file.split_sandbox_path()?;
+        refs.push((
+            format!("runtime file {}", file.sandbox_path.display()),
+            &file.source,
+        ));
+    }
+
+    let mut missing = Vec::new();
+    for (what, file_ref) in refs {
+        if let pom::FileRefRoot::Root = file_ref.root {
+            continue;
+        }
+        let path = assets.join(&file_ref.path);
+        if tokio::fs::metadata(&path).await.is_err() {
+            missing.push(format!("{} ({})", what, file_ref.path.display()));
+        }
+    }
+    if !missing.is_empty() {
+        anyhow::bail!("missing files: {}", missing.join(", "));
+    }
+
+    let mut not_executable = Vec::new();
+    for (what, file_ref) in executables {
+        if let pom::FileRefRoot::Root = file_ref.root {
+            continue;
+        }
+        let metadata = tokio::fs::metadata(assets.join(&file_ref.path)).await?;
+        if !is_executable(&metadata) {
+            not_executable.push(format!("{} ({})", what, file_ref.path.display()));
+        }
+    }
+    if !not_executable.is_empty() {
+        anyhow::bail!("files are not executable: {}", not_executable.join(", "));
+    }
+    Ok(())
+}
+
+/// Checks that either all tests or none of them have names, and that
+/// names are unique
+fn validate_test_names(
+    manifest: &pom::Problem,
+    extensions: &ProblemExtensions,
+) -> anyhow::Result<()> {
+    if extensions.test_names.is_empty() {
+        return Ok(());
+    }
+    if extensions.test_names.len() != manifest.tests.len() {
+        anyhow::bail!(
+            "problem has {} tests, but {} test names",
+            manifest.tests.len(),
+            extensions.test_names.len()
+        );
+    }
+    let mut seen = std::collections::HashSet::new();
+    for (i, name) in extensions.test_names.iter().enumerate() {
+        if name.trim().is_empty() {
+            anyhow::bail!("name of test {} is empty", i + 1);
+        }
+        if !seen.insert(name.as_str()) {
+            anyhow::bail!("duplicate test name {:?}", name);
+        }
+    }
+    Ok(())
+}
+
+fn validate_comparator(
+    manifest: &pom::Problem,
+    extensions: &ProblemExtensions,
+    comparator: &Comparator,
+) -> anyhow::Result<()> {
+    if extensions.interactor.is_some() {
+        anyhow::bail!("built-in comparator is not supported for interactive problems");
+    }
+    if let Comparator::Float { epsilon } = comparator {
+        if !epsilon.is_finite() || *epsilon < 0.0 {
+            anyhow::bail!("comparator epsilon must be a non-negative number");
+        }
+    }
+    if let Some(i) = manifest.tests.iter().position(|t| t.correct.is_none()) {
+        anyhow::bail!(
+            "test {} has no answer, which built-in comparator requires",
+            i + 1
+        );
+    }
+    Ok(())
+}
+
+/// Checks that placeholders in checker arguments and environment are
+/// known for every test
+fn validate_checker_templates(
+    manifest: &pom::Problem,
+    extensions: &ProblemExtensions,
+) -> anyhow::Result<()> {
+    for name in extensions.checker_env.keys() {
+        if name.is_empty() || name.contains('=') || name.starts_with("JJS_") {
+            anyhow::bail!(
+                "invalid checker environment variable {:?}: name must not be empty, must not contain '=' and must not start with JJS_",
+                name
+            );
+        }
+    }
+    let mut templates: Vec<&String> = manifest.checker_cmd.iter().collect();
+    if let Some(interactor) = &extensions.interactor {
+        templates.extend(&interactor.argv);
+    }
+    templates.extend(extensions.checker_env.values());
+    let groups: BTreeSet<&str> = manifest.tests.iter().map(|t| t.group.as_str()).collect();
+    for template in templates {
+        for name in template::placeholders(template) {
+            if template::TEST_PLACEHOLDERS.contains(&name) {
+                continue;
+            }
+            let key = match name.strip_prefix("Group.") {
+                Some(key) => key,
+                None => anyhow::bail!("unknown placeholder $({}) in {:?}", name, template),
+            };
+            for group in &groups {
+                let defined = matches!(
+                    extensions.group_params.get(*group),
+                    Some(params) if params.contains_key(key)
+                );
+                if !defined {
+                    anyhow::bail!(
+                        "placeholder $({}) is used, but test group {:?} has no parameter {}",
+                        name,
+                        group,
+                        key
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    metadata.is_file()
+}
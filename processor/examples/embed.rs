@@ -0,0 +1,55 @@
+//! Judges a single run without the HTTP service.
+//!
+//! Usage: `embed <invoker address> <toolchains dir> <problems dir> <problem> <toolchain> <source>`
+
+use anyhow::Context;
+use processor::{problem_loader, toolchain_loader, Event, JudgeOutcome};
+use std::path::PathBuf;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 6 {
+        anyhow::bail!(
+            "usage: embed <invoker address> <toolchains dir> <problems dir> <problem> <toolchain> <source>"
+        );
+    }
+    let mut invokers = processor::invoker_client::Client::builder();
+    invokers.add(processor::invoker_client::Pool::new_from_address(&args[0]));
+    let toolchains = toolchain_loader::ToolchainLoader::new(args[1].as_ref()).await?;
+    let problems = problem_loader::Loader::from_config(
+        &problem_loader::LoaderConfig {
+            fs: Some(PathBuf::from(&args[2])),
+            mongodb: None,
+        },
+        std::env::temp_dir().join("judge-embed-problems"),
+    )
+    .await?;
+    let clients = processor::Clients::builder()
+        .invokers(invokers.build())
+        .toolchains(toolchains)
+        .problems(problems)
+        .build()?;
+
+    let run_source = std::fs::read(&args[5]).context("failed to read run source")?;
+    let request = processor::Request::new(&args[3], &args[4], run_source);
+    let settings = processor::Settings::new("embed");
+
+    let mut progress = processor::judge(request, clients, settings);
+    while let Some(event) = progress.event().await {
+        match event.event {
+            Event::LiveTest(test) => println!("running test {}", test),
+            Event::LogCreated(log) => println!(
+                "{} log: {} ({} points)",
+                log.kind.as_str(),
+                log.status.code,
+                log.score
+            ),
+            _ => {}
+        }
+    }
+    match progress.wait().await {
+        JudgeOutcome::Success => Ok(()),
+        JudgeOutcome::Fault { error } => Err(error.context("judging failed")),
+    }
+}
@@ -0,0 +1,163 @@
+//! Generation of correct answers by running a trusted solution
+
+use crate::{compile, exec_test, extensions::ExtensionBuilder, resources::ResourceTracker};
+use crate::{Clients, FileRefResolver, Settings};
+use anyhow::Context;
+use std::path::PathBuf;
+use valuer_api::{Status, StatusKind};
+
+/// Answers are put to `${assets}/${ANSWERS_DIR}/${test_id}.txt`
+const ANSWERS_DIR: &str = "generated-answers";
+
+/// Trusted solution whose outputs become correct answers
+pub struct AnswersRequest {
+    pub problem_id: String,
+    pub toolchain_name: String,
+    pub run_source: Vec<u8>,
+}
+
+pub struct GeneratedAnswers {
+    pub compile_log: String,
+    /// Statuses of the solution on all tests which were run
+    pub tests: Vec<(pom::TestId, Status)>,
+    /// Problem package with the answers, in the format accepted by
+    /// `problem_loader::Loader::upload`. None if the solution did not
+    /// compile or failed on some test.
+    pub package: Option<Vec<u8>>,
+}
+
+/// Runs solution on all tests of the problem and builds a new package of
+/// the problem, where test outputs are used as correct answers.
+/// Checker is not consulted. Run stops at the first failed test.
+#[tracing::instrument(skip(req, clients, settings), fields(problem_id = %req.problem_id))]
+pub async fn generate_answers(
+    req: AnswersRequest,
+    clients: &Clients,
+    settings: &Settings,
+) -> anyhow::Result<GeneratedAnswers> {
+    let loaded = clients
+        .problems
+        .find(&req.problem_id)
+        .await
+        .context("failed to get problem")?
+        .context("problem not found")?;
+    if loaded.extensions.interactor.is_some() {
+        anyhow::bail!("answers can not be generated for interactive problems");
+    }
+    let toolchain = clients
+        .toolchains
+        .resolve(&req.toolchain_name)
+        .await
+        .context("failed to find toolchain")?;
+    // outputs are needed as is, and nothing is kept after the run
+    let mut settings = settings.clone();
+    settings.output_store = None;
+    settings.checker_logs = None;
+    settings.compile_logs = None;
+    settings.memory_sampling_interval = None;
+
+    let capabilities = crate::query_capabilities(clients, &settings).await?;
+    let extensions = ExtensionBuilder::new(&capabilities);
+    // persisted outputs and reused sandboxes stay in one pool
+    let mut clients = clients.clone();
+    clients.invokers = clients.invokers.with_affinity();
+    let resources = ResourceTracker::new(clients.invokers.clone());
+    let res = run(
+        &req,
+        &loaded,
+        &toolchain,
+        &clients,
+        &settings,
+        &extensions,
+        &resources,
+    )
+    .await;
+    if res.is_err() {
+        resources.release_all(&settings.warnings).await;
+    }
+    res
+}
+
+async fn run(
+    req: &AnswersRequest,
+    loaded: &problem_loader::LoadedProblem,
+    toolchain: &toolchain_loader::Toolchain,
+    clients: &Clients,
+    settings: &Settings,
+    extensions: &ExtensionBuilder<'_>,
+    resources: &ResourceTracker,
+) -> anyhow::Result<GeneratedAnswers> {
+    let mut judge_req =
+        crate::Request::new(&req.problem_id, &req.toolchain_name, req.run_source.clone());
+    judge_req.problem = Some(loaded.clone());
+    tracing::info!("compiling");
+    let mut build = compile::compile(
+        &judge_req,
+        toolchain,
+        clients.invokers.clone(),
+        settings,
+        extensions,
+        resources,
+    )
+    .await?;
+    let built = match &mut build.result {
+        Ok(built) => built.take().expect("compile does not return none"),
+        Err(_) => {
+            return Ok(GeneratedAnswers {
+                compile_log: build.log,
+                tests: Vec::new(),
+                package: None,
+            });
+        }
+    };
+    let file_ref_resolver = FileRefResolver {
+        problem_assets_dir: loaded.assets.clone(),
+    };
+    let exec_ctx = exec_test::ExecContext {
+        toolchain,
+        problem: &loaded.manifest,
+        problem_ext: &loaded.extensions,
+        file_ref_resolver: &file_ref_resolver,
+        settings,
+        built: &built,
+        persistent_outputs_dir: None,
+        extensions,
+        sandbox_reuse_key: None,
+        memory_sampling_interval: None,
+        resources,
+        keep_stdout: true,
+    };
+    let mut manifest = loaded.manifest.clone();
+    let mut tests = Vec::new();
+    let mut answers = Vec::new();
+    for idx in 0..manifest.tests.len() {
+        let test_id = pom::TestId::make(idx as u32 + 1);
+        let outcome = exec_test::exec(&exec_ctx, clients.invokers.clone(), test_id)
+            .await
+            .with_context(|| format!("failed to run solution on test {}", test_id))?;
+        let passed = matches!(outcome.status.kind, StatusKind::Accepted);
+        tests.push((test_id, outcome.status));
+        if !passed {
+            tracing::info!(test_id = %test_id, "solution failed");
+            return Ok(GeneratedAnswers {
+                compile_log: build.log,
+                tests,
+                package: None,
+            });
+        }
+        let path = PathBuf::from(ANSWERS_DIR).join(format!("{}.txt", test_id));
+        manifest.tests.0[idx].correct = Some(pom::FileRef {
+            path: path.clone(),
+            root: pom::FileRefRoot::Problem,
+        });
+        answers.push((path, outcome.raw_stdout.unwrap_or_default()));
+    }
+    let package = problem_loader::pack(&manifest, &loaded.extensions, &loaded.assets, answers)
+        .await
+        .context("failed to build problem package")?;
+    Ok(GeneratedAnswers {
+        compile_log: build.log,
+        tests,
+        package: Some(package),
+    })
+}
@@ -0,0 +1,213 @@
+//! Cache of compiled runs, so that identical sources are not rebuilt.
+//!
+//! Entries are keyed by toolchain (name, image and spec) and source bytes.
+//! Full source is compared on lookup, so hash collisions can not lead to
+//! a wrong binary being used. Only successful builds are cached.
+
+use crate::compile::{Artifact, BuildOutcome, BuiltRun};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    toolchain_name: String,
+    image: String,
+    /// Serialized toolchain spec, so that changed build commands
+    /// invalidate entries even if the image is the same
+    spec: String,
+    source: Vec<u8>,
+}
+
+impl CacheKey {
+    fn new(toolchain: &toolchain_loader::Toolchain, source: &[u8]) -> anyhow::Result<Self> {
+        Ok(CacheKey {
+            toolchain_name: toolchain.spec.name.clone(),
+            image: toolchain.image.clone(),
+            spec: serde_json::to_string(&toolchain.spec)?,
+            source: source.to_vec(),
+        })
+    }
+
+    fn size(&self) -> usize {
+        self.toolchain_name.len() + self.image.len() + self.spec.len() + self.source.len()
+    }
+}
+
+struct CacheEntry {
+    /// Paths, modes and contents of artifacts
+    files: Vec<(PathBuf, u32, Vec<u8>)>,
+    log: String,
+    inserted_at: Instant,
+    last_used: Instant,
+}
+
+impl CacheEntry {
+    fn size(&self) -> usize {
+        let files: usize = self
+            .files
+            .iter()
+            .map(|(path, _, data)| path.as_os_str().len() + data.len())
+            .sum();
+        files + self.log.len()
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<CacheKey, CacheEntry>,
+    /// Total size of keys and entries, in bytes
+    size: usize,
+}
+
+/// In-memory cache of compiled artifacts.
+///
+/// Artifacts must be kept by judge, so when the cache is enabled compiled
+/// artifacts are always transferred from invoker instead of being
+/// persisted there.
+pub struct ArtifactCache {
+    /// Least recently used entries are evicted when total size exceeds it
+    max_size: usize,
+    /// Entries older than this are not used
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl ArtifactCache {
+    pub fn new(max_size: usize, ttl: Duration) -> ArtifactCache {
+        ArtifactCache {
+            max_size,
+            ttl,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Returns cached build of `source`, if there is a fresh one
+    pub(crate) fn get(
+        &self,
+        toolchain: &toolchain_loader::Toolchain,
+        source: &[u8],
+    ) -> Option<BuildOutcome> {
+        let key = match CacheKey::new(toolchain, source) {
+            Ok(k) => k,
+            Err(err) => {
+                tracing::warn!("failed to compute artifact cache key: {:#}", err);
+                return None;
+            }
+        };
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let expired = match entries.map.get_mut(&key) {
+            Some(entry) if now.duration_since(entry.inserted_at) < self.ttl => {
+                entry.last_used = now;
+                return Some(BuildOutcome {
+                    result: Ok(Some(BuiltRun {
+                        persisted: None,
+                        files: entry
+                            .files
+                            .iter()
+                            .map(|(path, mode, data)| {
+                                let artifact = Artifact::Inline {
+                                    data: data.clone(),
+                                    mode: *mode,
+                                };
+                                (path.clone(), artifact)
+                            })
+                            .collect(),
+                    })),
+                    log: entry.log.clone(),
+                    // nothing was invoked
+                    usage: Default::default(),
+                });
+            }
+            Some(_) => true,
+            None => false,
+        };
+        if expired {
+            entries.remove(&key);
+        }
+        None
+    }
+
+    /// Remembers successful build of `source`. Other outcomes and builds
+    /// with persisted artifacts are ignored.
+    pub(crate) fn put(
+        &self,
+        toolchain: &toolchain_loader::Toolchain,
+        source: &[u8],
+        outcome: &BuildOutcome,
+    ) {
+        let built = match &outcome.result {
+            Ok(Some(built)) => built,
+            _ => return,
+        };
+        let mut files = Vec::new();
+        for (path, artifact) in &built.files {
+            match artifact {
+                Artifact::Inline { data, mode } => files.push((path.clone(), *mode, data.clone())),
+                Artifact::Persistent(_) => return,
+            }
+        }
+        let key = match CacheKey::new(toolchain, source) {
+            Ok(k) => k,
+            Err(err) => {
+                tracing::warn!("failed to compute artifact cache key: {:#}", err);
+                return;
+            }
+        };
+        let now = Instant::now();
+        let entry = CacheEntry {
+            files,
+            log: outcome.log.clone(),
+            inserted_at: now,
+            last_used: now,
+        };
+        if key.size() + entry.size() > self.max_size {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&key);
+        entries.size += key.size() + entry.size();
+        entries.map.insert(key, entry);
+        while entries.size > self.max_size {
+            entries.evict(self.ttl, now);
+        }
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.map.remove(key) {
+            self.size -= key.size() + entry.size();
+        }
+    }
+
+    /// Removes expired entries, or the least recently used one if there
+    /// are none
+    fn evict(&mut self, ttl: Duration, now: Instant) {
+        let before = self.map.len();
+        let mut freed = 0;
+        self.map.retain(|key, entry| {
+            let fresh = now.duration_since(entry.inserted_at) < ttl;
+            if !fresh {
+                freed += key.size() + entry.size();
+            }
+            fresh
+        });
+        self.size -= freed;
+        if self.map.len() != before {
+            return;
+        }
+        let lru = self
+            .map
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = lru {
+            self.remove(&key);
+        }
+    }
+}
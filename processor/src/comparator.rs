@@ -0,0 +1,132 @@
+//! Built-in comparison of solution output with the correct answer.
+//!
+//! Problems which only need a standard check select a comparator in the
+//! manifest, so that judge does not run a checker sandbox for each test.
+
+use problem_loader::Comparator;
+
+/// Mismatching tokens and lines are cut to this number of characters in
+/// the comment
+const MAX_EXCERPT_CHARS: usize = 64;
+
+/// Result of the comparison
+pub(crate) struct Comparison {
+    pub(crate) matches: bool,
+    /// Describes the first difference, similarly to checker comments
+    pub(crate) comment: String,
+}
+
+/// Compares solution output with the correct answer
+pub(crate) fn compare(comparator: &Comparator, output: &[u8], answer: &[u8]) -> Comparison {
+    let output = String::from_utf8_lossy(output);
+    let answer = String::from_utf8_lossy(answer);
+    match comparator {
+        Comparator::Tokens => compare_tokens(&output, &answer, |a, b| a == b),
+        Comparator::Lines => compare_lines(&output, &answer),
+        Comparator::Float { epsilon } => compare_tokens(&output, &answer, |a, b| {
+            a == b || tokens_close(a, b, *epsilon)
+        }),
+    }
+}
+
+fn compare_tokens(output: &str, answer: &str, eq: impl Fn(&str, &str) -> bool) -> Comparison {
+    let mut output = output.split_whitespace();
+    let mut answer = answer.split_whitespace();
+    let mut idx = 1;
+    loop {
+        let comment = match (output.next(), answer.next()) {
+            (None, None) => {
+                return Comparison {
+                    matches: true,
+                    comment: format!("ok, {} tokens", idx - 1),
+                }
+            }
+            (Some(out), Some(ans)) if eq(out, ans) => {
+                idx += 1;
+                continue;
+            }
+            (Some(out), Some(ans)) => format!(
+                "token {} differs: expected {:?}, found {:?}",
+                idx,
+                shorten(ans),
+                shorten(out)
+            ),
+            (Some(out), None) => format!("extra token {}: {:?}", idx, shorten(out)),
+            (None, Some(ans)) => {
+                format!("output ended at token {}, expected {:?}", idx, shorten(ans))
+            }
+        };
+        return Comparison {
+            matches: false,
+            comment,
+        };
+    }
+}
+
+/// Splits text into lines without trailing whitespace, ignoring trailing
+/// empty lines
+fn significant_lines(text: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    while lines.last() == Some(&"") {
+        lines.pop();
+    }
+    lines
+}
+
+fn compare_lines(output: &str, answer: &str) -> Comparison {
+    let output = significant_lines(output);
+    let answer = significant_lines(answer);
+    for (i, (out, ans)) in output.iter().zip(&answer).enumerate() {
+        if out != ans {
+            return Comparison {
+                matches: false,
+                comment: format!(
+                    "line {} differs: expected {:?}, found {:?}",
+                    i + 1,
+                    shorten(ans),
+                    shorten(out)
+                ),
+            };
+        }
+    }
+    let comment = if output.len() > answer.len() {
+        format!(
+            "extra line {}: {:?}",
+            answer.len() + 1,
+            shorten(output[answer.len()])
+        )
+    } else if output.len() < answer.len() {
+        format!(
+            "output ended at line {}, expected {:?}",
+            output.len() + 1,
+            shorten(answer[output.len()])
+        )
+    } else {
+        return Comparison {
+            matches: true,
+            comment: format!("ok, {} lines", answer.len()),
+        };
+    };
+    Comparison {
+        matches: false,
+        comment,
+    }
+}
+
+/// Checks if both tokens are finite numbers which differ by at most
+/// `epsilon`, either absolutely or relatively to the expected value
+fn tokens_close(out: &str, ans: &str, epsilon: f64) -> bool {
+    let (out, ans) = match (out.parse::<f64>(), ans.parse::<f64>()) {
+        (Ok(out), Ok(ans)) if out.is_finite() && ans.is_finite() => (out, ans),
+        _ => return false,
+    };
+    let diff = (out - ans).abs();
+    diff <= epsilon || diff <= epsilon * ans.abs()
+}
+
+fn shorten(s: &str) -> String {
+    match s.char_indices().nth(MAX_EXCERPT_CHARS) {
+        Some((end, _)) => format!("{}...", &s[..end]),
+        None => s.to_string(),
+    }
+}
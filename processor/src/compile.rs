@@ -1,68 +1,130 @@
-use crate::CommandStatus;
+use crate::{
+    extensions::ExtensionBuilder,
+    resources::{OwnedResources, ResourceTracker},
+    CommandStatus,
+};
 use anyhow::Context;
 use invoker_api::{
     invoke::{
-        Action, ActionResult, Command, EnvVarValue, EnvironmentVariable, Extensions, FileId,
-        InvokeRequest, Limits, OutputRequest, OutputRequestTarget, PathPrefix, PrefixedPath,
-        SandboxSettings, SharedDir, SharedDirectoryMode, Stdio, Step, VolumeSettings,
+        Action, Command, EnvVarValue, EnvironmentVariable, Extensions, FileId, InvokeRequest,
+        Limits, OutputData, OutputRequest, OutputRequestTarget, PathPrefix, PrefixedPath,
+        SharedDir, SharedDirectoryMode, Stdio, Step, VolumeSettings,
     },
     shim::{ExtraFile, SandboxSettingsExtensions, EXTRA_FILES_DIR_NAME},
 };
-use std::{collections::HashMap, path::PathBuf};
+use invoker_client::{PersistOutputExtension, SandboxExtensions};
+use judge_apis::usage::Usage;
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Component, PathBuf},
+    sync::Arc,
+};
+use toolchain_loader::NetworkPolicy;
 use uuid::Uuid;
 use valuer_api::{status_codes, Status, StatusKind};
 
+#[derive(Clone)]
 pub(crate) struct BuiltRun {
-    pub(crate) binary: Vec<u8>,
+    /// Artifacts declared by the toolchain, with paths relative to the
+    /// output directory
+    pub(crate) files: Vec<(PathBuf, Artifact)>,
+    /// Persisted artifacts, released on invoker when the last clone of the
+    /// run is dropped
+    pub(crate) persisted: Option<Arc<OwnedResources>>,
+}
+
+impl BuiltRun {
+    /// Pool which keeps persisted artifacts of the run
+    pub(crate) fn pool(&self) -> Option<invoker_client::PoolId> {
+        self.persisted.as_ref().and_then(|p| p.pool())
+    }
+}
+
+#[derive(Clone)]
+pub(crate) enum Artifact {
+    /// Artifact contents and mode
+    Inline { data: Vec<u8>, mode: u32 },
+    /// Artifact is persisted by invoker and available to it as this file.
+    /// Invoker does not report modes, so such artifacts are executable.
+    Persistent(PathBuf),
+}
+
+impl Artifact {
+    pub(crate) fn is_executable(&self) -> bool {
+        match self {
+            Artifact::Inline { mode, .. } => mode & 0o111 != 0,
+            Artifact::Persistent(_) => true,
+        }
+    }
+}
+
+/// How artifacts are received from invoker
+enum ArtifactCapture {
+    /// Each artifact is persisted by invoker in `dir` as `${prefix}-${i}`
+    Persisted { dir: PathBuf, prefix: String },
+    /// Each artifact is a plain file returned as output `artifact-${i}`
+    Inline,
+    /// Artifacts are packed into `ARTIFACTS_ARCHIVE` by the command step
+    /// with this index
+    Packed { step: usize },
 }
 
 pub(crate) struct BuildOutcome {
     // Wrapped in option to allow stealing
     pub(crate) result: Result<Option<BuiltRun>, Status>,
     pub(crate) log: String,
+    pub(crate) usage: Usage,
 }
 
 //const FILE_ID_SOURCE: &str = "run-source";
-const FILE_ID_EMPTY: &str = "empty";
+/// Null file opened by `build_request`, usable as stdin
+pub(crate) const FILE_ID_EMPTY: &str = "empty";
 const SANDBOX_NAME: &str = "compile-sandbox";
 const VOLUME_NAME: &str = "work";
+/// Writable mounts of the toolchain use volumes `mount-0`, `mount-1`...
+const MOUNT_VOLUME_PREFIX: &str = "mount-";
+/// Archive of all artifacts in the output volume, unless artifacts are
+/// persisted one by one
+const ARTIFACTS_ARCHIVE: &str = ".artifacts.tar";
+const PACK_STDERR_FILE: &str = "pack-stderr";
+/// Pack command exits with this code if some declared artifact was not
+/// created by the build
+const MISSING_ARTIFACT_EXIT_CODE: i64 = 3;
 
-pub(crate) async fn compile(
-    req: &crate::Request,
+/// Creates request which gets the run source as
+/// `/compile-input/${filename}` (see `sandbox_step`) and has null file
+/// `FILE_ID_EMPTY`. `substitutions` are extended with
+/// `Run.SourceFilePath`.
+pub(crate) async fn build_request(
     toolchain: &toolchain_loader::Toolchain,
-    client: invoker_client::Client,
-) -> anyhow::Result<BuildOutcome> {
-    let req_builder = crate::request_builder::RequestBuilder::new();
-
-    let (substitutions, extra_files) = {
-        let source_file_path = format!("/compile-input/{}", toolchain.spec.filename);
-        let mut s = HashMap::new();
-        let mut ef = HashMap::new();
-        ef.insert(
-            toolchain.spec.filename.clone(),
-            ExtraFile {
-                contents: req_builder.intern(&req.run_source).await?,
-                executable: false,
-            },
-        );
-        s.insert("Run.SourceFilePath".to_string(), source_file_path.clone());
-        s.insert(
-            "Run.BinaryFilePath".to_string(),
-            "/compile-output/bin".to_string(),
-        );
-        (s, ef)
-    };
+    run_source: &[u8],
+    mut substitutions: HashMap<String, String>,
+    req_builder: &crate::request_builder::RequestBuilder,
+    extensions: &ExtensionBuilder<'_>,
+) -> anyhow::Result<InvokeRequest> {
+    let mut extra_files = HashMap::new();
+    extra_files.insert(
+        toolchain.spec.filename.clone(),
+        ExtraFile {
+            contents: req_builder.intern("run source", run_source).await?,
+            executable: false,
+        },
+    );
+    substitutions.insert(
+        "Run.SourceFilePath".to_string(),
+        format!("/compile-input/{}", toolchain.spec.filename),
+    );
     let mut invoke_request = InvokeRequest {
         steps: vec![],
         inputs: vec![],
         outputs: vec![],
         id: Uuid::nil(),
-        ext: Extensions::make(invoker_api::shim::RequestExtensions {
+        ext: extensions.make(invoker_api::shim::RequestExtensions {
             extra_files,
             substitutions,
         })?,
     };
-
     invoke_request.steps.push(Step {
         stage: 0,
         action: Action::OpenNullFile {
@@ -70,6 +132,92 @@ pub(crate) async fn compile(
         },
         ext: Extensions::default(),
     });
+    Ok(invoke_request)
+}
+
+/// Creates sandbox with the toolchain image, which sees the run source
+/// read-only in `/compile-input` and additionally `expose`.
+pub(crate) fn sandbox_step(
+    toolchain: &toolchain_loader::Toolchain,
+    settings: &crate::Settings,
+    extensions: &ExtensionBuilder<'_>,
+    name: &str,
+    limits: Limits,
+    expose: Vec<SharedDir>,
+    network: bool,
+) -> anyhow::Result<Step> {
+    let mut all_expose = vec![SharedDir {
+        host_path: PrefixedPath {
+            prefix: PathPrefix::Extension(extensions.make(
+                invoker_api::shim::SharedDirExtensionSource {
+                    name: EXTRA_FILES_DIR_NAME.to_string(),
+                },
+            )?),
+            path: PathBuf::new(),
+        },
+        sandbox_path: "/compile-input".into(),
+        mode: SharedDirectoryMode::ReadOnly,
+        create: false,
+        ext: Extensions::default(),
+    }];
+    all_expose.extend(expose);
+    Ok(Step {
+        stage: 0,
+        action: Action::CreateSandbox(crate::security::sandbox(
+            &settings.security_policy,
+            extensions,
+            name.to_string(),
+            limits,
+            all_expose,
+            SandboxExtensions {
+                shim: SandboxSettingsExtensions {
+                    image: toolchain.image.clone(),
+                },
+                network,
+                reuse_key: None,
+                writable_paths: None,
+                core_dumps: None,
+            },
+        )?),
+        ext: Extensions::default(),
+    })
+}
+
+pub(crate) async fn compile(
+    req: &crate::Request,
+    toolchain: &toolchain_loader::Toolchain,
+    client: invoker_client::Client,
+    settings: &crate::Settings,
+    extensions: &ExtensionBuilder<'_>,
+    resources: &ResourceTracker,
+) -> anyhow::Result<BuildOutcome> {
+    let req_builder = crate::request_builder::RequestBuilder::new(settings);
+    let instance = client.instance()?;
+    // if invoker can keep artifacts, they will not be transferred back and
+    // forth, unless judge caches artifacts itself. Only files can be
+    // persisted; directories and patterns are packed into an archive.
+    let persistent_artifacts = extensions
+        .persistent_files_dir()
+        .filter(|_| settings.artifact_cache.is_none() && toolchain.spec.has_only_file_artifacts())
+        .map(|dir| (dir.to_path_buf(), format!("artifact-{}", Uuid::new_v4())));
+
+    let mut substitutions = HashMap::new();
+    substitutions.insert(
+        "Run.BinaryFilePath".to_string(),
+        "/compile-output/bin".to_string(),
+    );
+    substitutions.insert(
+        "Run.OutputDirPath".to_string(),
+        "/compile-output".to_string(),
+    );
+    let mut invoke_request = build_request(
+        toolchain,
+        &req.run_source,
+        substitutions,
+        &req_builder,
+        extensions,
+    )
+    .await?;
 
     invoke_request.steps.push(Step {
         stage: 0,
@@ -81,50 +229,66 @@ pub(crate) async fn compile(
         ext: Extensions::default(),
     });
 
+    let writable_mounts = toolchain.spec.build_sandbox.mounts();
+    for (i, mount) in writable_mounts.iter().enumerate() {
+        invoke_request.steps.push(Step {
+            stage: 0,
+            action: Action::CreateVolume(VolumeSettings {
+                name: format!("{}{}", MOUNT_VOLUME_PREFIX, i),
+                limit: mount.quota,
+                ext: Extensions::default(),
+            }),
+            ext: Extensions::default(),
+        });
+    }
+
+    let network = match toolchain.spec.build_network {
+        NetworkPolicy::Allow => {
+            !settings.deny_build_network && !settings.security_policy.deny_network
+        }
+        NetworkPolicy::Deny => false,
+    };
+    if network {
+        tracing::info!("build network access is enabled");
+    }
+
     let limits = Limits {
         memory: toolchain.spec.limits.memory(),
         time: toolchain.spec.limits.time(),
         process_count: toolchain.spec.limits.process_count,
         ext: Extensions::default(),
     };
-    invoke_request.steps.push(Step {
-        stage: 0,
-        action: Action::CreateSandbox(SandboxSettings {
-            limits: limits.clone(),
-            name: SANDBOX_NAME.to_string(),
-            base_image: PathBuf::new(),
-            expose: vec![
-                SharedDir {
-                    host_path: PrefixedPath {
-                        prefix: PathPrefix::Extension(Extensions::make(
-                            invoker_api::shim::SharedDirExtensionSource {
-                                name: EXTRA_FILES_DIR_NAME.to_string(),
-                            },
-                        )?),
-                        path: PathBuf::new(),
-                    },
-                    sandbox_path: "/compile-input".into(),
-                    mode: SharedDirectoryMode::ReadOnly,
-                    create: false,
-                    ext: Extensions::default(),
-                },
-                SharedDir {
-                    host_path: PrefixedPath {
-                        prefix: PathPrefix::Volume(VOLUME_NAME.to_string()),
-                        path: PathBuf::new(),
-                    },
-                    sandbox_path: "/compile-output".into(),
-                    mode: SharedDirectoryMode::ReadWrite,
-                    create: false,
-                    ext: Extensions::default(),
-                },
-            ],
-            ext: Extensions::make(SandboxSettingsExtensions {
-                image: toolchain.image.clone(),
-            })?,
-        }),
+    let mut expose = vec![SharedDir {
+        host_path: PrefixedPath {
+            prefix: PathPrefix::Volume(VOLUME_NAME.to_string()),
+            path: PathBuf::new(),
+        },
+        sandbox_path: "/compile-output".into(),
+        mode: SharedDirectoryMode::ReadWrite,
+        create: false,
         ext: Extensions::default(),
-    });
+    }];
+    for (i, mount) in writable_mounts.iter().enumerate() {
+        expose.push(SharedDir {
+            host_path: PrefixedPath {
+                prefix: PathPrefix::Volume(format!("{}{}", MOUNT_VOLUME_PREFIX, i)),
+                path: PathBuf::new(),
+            },
+            sandbox_path: mount.path.clone(),
+            mode: SharedDirectoryMode::ReadWrite,
+            create: true,
+            ext: Extensions::default(),
+        });
+    }
+    invoke_request.steps.push(sandbox_step(
+        toolchain,
+        settings,
+        extensions,
+        SANDBOX_NAME,
+        limits.clone(),
+        expose,
+        network,
+    )?);
     let mut command_steps = Vec::new();
 
     for (i, command) in toolchain.spec.build_commands.iter().enumerate() {
@@ -190,22 +354,57 @@ pub(crate) async fn compile(
         });
     }
 
-    invoke_request.outputs.push(OutputRequest {
-        name: "artifact".to_string(),
-        target: OutputRequestTarget::Path(PrefixedPath {
-            prefix: PathPrefix::Volume(VOLUME_NAME.to_string()),
-            path: "bin".into(),
-        }),
-        ext: Extensions::default(),
-    });
+    let capture = if toolchain.spec.has_only_file_artifacts() {
+        for (i, path) in toolchain.spec.artifacts.iter().enumerate() {
+            let ext = match &persistent_artifacts {
+                Some((_, prefix)) => {
+                    let name = format!("{}-{}", prefix, i);
+                    resources
+                        .track(invoker_client::Resource::PersistedFile { name: name.clone() })?;
+                    extensions.make(PersistOutputExtension { persist_as: name })?
+                }
+                None => Extensions::default(),
+            };
+            invoke_request.outputs.push(OutputRequest {
+                name: format!("artifact-{}", i),
+                target: OutputRequestTarget::Path(PrefixedPath {
+                    prefix: PathPrefix::Volume(VOLUME_NAME.to_string()),
+                    path: path.clone(),
+                }),
+                ext,
+            });
+        }
+        match persistent_artifacts {
+            Some((dir, prefix)) => ArtifactCapture::Persisted { dir, prefix },
+            None => ArtifactCapture::Inline,
+        }
+    } else {
+        let stage = toolchain.spec.build_commands.len() as u32;
+        ArtifactCapture::Packed {
+            step: push_pack_step(&mut invoke_request, toolchain, stage),
+        }
+    };
 
-    let response = client.instance()?.call(invoke_request).await?;
+    let steps = crate::steps::StepTable::new(&invoke_request);
+    let response = instance.call(invoke_request).await?;
+    // persisted artifacts belong to the run from now on, and are released
+    // right away if it was not built
+    let persisted = match &capture {
+        ArtifactCapture::Persisted { prefix, .. } => Some(Arc::new(resources.detach(
+            |resource| {
+                matches!(resource, invoker_client::Resource::PersistedFile { name }
+                    if name.starts_with(prefix.as_str()))
+            },
+            &settings.warnings,
+        ))),
+        ArtifactCapture::Inline | ArtifactCapture::Packed { .. } => None,
+    };
+    let usage = crate::invoke_usage(&response);
     let mut compile_log = String::new();
     for (step_no, pos) in command_steps.into_iter().enumerate() {
-        let data = match &response.actions[pos] {
-            ActionResult::ExecuteCommand(d) => d,
-            _ => anyhow::bail!("unexpected ActionResult"),
-        };
+        let data = steps
+            .command_result(&response, pos)
+            .with_context(|| format!("build command {} did not run", step_no))?;
 
         let stdout = req_builder
             .read_output(&response, &format!("step-{}-stdout", step_no))
@@ -213,15 +412,18 @@ pub(crate) async fn compile(
         let stderr = req_builder
             .read_output(&response, &format!("step-{}-stderr", step_no))
             .await?;
+        save_raw_log(settings, &format!("step-{}-stdout", step_no), &stdout).await?;
+        save_raw_log(settings, &format!("step-{}-stderr", step_no), &stderr).await?;
         compile_log += &format!("------ step {} ------\n", step_no);
         compile_log += "--- stdout ---\n";
-        compile_log += &String::from_utf8_lossy(&stdout);
+        compile_log += &crate::log_text::decode(&stdout, settings.normalize_logs);
         compile_log += "--- stderr ---\n";
-        compile_log += &String::from_utf8_lossy(&stderr);
+        compile_log += &crate::log_text::decode(&stderr, settings.normalize_logs);
 
-        let status_code = match crate::describe_command_result(&limits, data) {
+        let status_code = match crate::describe_command_result(&limits, data, None) {
             // TODO: use more specific status
             CommandStatus::MemLimit => status_codes::COMPILER_FAILED,
+            CommandStatus::ProcessLimit => status_codes::COMPILER_FAILED,
             CommandStatus::Startup => status_codes::COMPILER_FAILED,
             CommandStatus::Runtime => status_codes::COMPILER_FAILED,
             CommandStatus::TimeLimit => status_codes::COMPILATION_TIMED_OUT,
@@ -233,14 +435,215 @@ pub(crate) async fn compile(
                 code: status_code.to_string(),
             }),
             log: compile_log,
+            usage,
         });
     }
-    let binary = req_builder
-        .read_output(&response, "artifact")
-        .await
-        .context("failed to export compiled binary")?;
+    let files = match capture {
+        ArtifactCapture::Persisted { dir, prefix } => toolchain
+            .spec
+            .artifacts
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let artifact = Artifact::Persistent(dir.join(format!("{}-{}", prefix, i)));
+                (path.clone(), artifact)
+            })
+            .collect(),
+        ArtifactCapture::Inline => {
+            let mut files = Vec::new();
+            for (i, path) in toolchain.spec.artifacts.iter().enumerate() {
+                let name = format!("artifact-{}", i);
+                let missing = response
+                    .outputs
+                    .iter()
+                    .any(|o| o.name == name && matches!(o.data, OutputData::None));
+                if missing {
+                    compile_log += "------ artifacts ------\n";
+                    compile_log += &format!("artifact {} not found\n", path.display());
+                    return Ok(BuildOutcome {
+                        result: Err(Status {
+                            kind: StatusKind::CompilationError,
+                            code: status_codes::COMPILER_FAILED.to_string(),
+                        }),
+                        log: compile_log,
+                        usage,
+                    });
+                }
+                let data = req_builder
+                    .read_output(&response, &name)
+                    .await
+                    .with_context(|| format!("failed to export artifact {}", path.display()))?;
+                // invoker does not report modes, like for persisted artifacts
+                files.push((path.clone(), Artifact::Inline { data, mode: 0o755 }));
+            }
+            files
+        }
+        ArtifactCapture::Packed { step } => {
+            let data = steps
+                .command_result(&response, step)
+                .context("packing of artifacts did not run")?;
+            if let Some(err) = &data.spawn_error {
+                anyhow::bail!("failed to start packing of artifacts: {}", err);
+            }
+            if data.exit_code != 0 {
+                let stderr = req_builder.read_output(&response, PACK_STDERR_FILE).await?;
+                save_raw_log(settings, "artifacts-stderr", &stderr).await?;
+                let stderr = crate::log_text::decode(&stderr, settings.normalize_logs);
+                // anything but a missing artifact is a problem of the
+                // toolchain image, e.g. it has no `tar`
+                if data.exit_code != MISSING_ARTIFACT_EXIT_CODE {
+                    anyhow::bail!(
+                        "packing of artifacts failed with exit code {}: {}",
+                        data.exit_code,
+                        stderr
+                    );
+                }
+                compile_log += "------ artifacts ------\n";
+                compile_log += &stderr;
+                return Ok(BuildOutcome {
+                    result: Err(Status {
+                        kind: StatusKind::CompilationError,
+                        code: status_codes::COMPILER_FAILED.to_string(),
+                    }),
+                    log: compile_log,
+                    usage,
+                });
+            }
+            let archive = req_builder
+                .read_output(&response, ARTIFACTS_ARCHIVE)
+                .await
+                .context("failed to export artifacts")?;
+            tokio::task::spawn_blocking(move || unpack_artifacts(&archive))
+                .await
+                .context("unpacking of artifacts panicked")?
+                .context("failed to unpack artifacts")?
+        }
+    };
     Ok(BuildOutcome {
-        result: Ok(Some(BuiltRun { binary })),
+        result: Ok(Some(BuiltRun { files, persisted })),
         log: compile_log,
+        usage,
     })
 }
+
+/// Saves captured build output as is to `Settings::compile_logs`
+async fn save_raw_log(settings: &crate::Settings, name: &str, data: &[u8]) -> anyhow::Result<()> {
+    let dir = match &settings.compile_logs {
+        Some(d) => d,
+        None => return Ok(()),
+    };
+    tokio::fs::create_dir_all(dir)
+        .await
+        .context("failed to create compile logs directory")?;
+    let path = dir.join(name);
+    tokio::fs::write(&path, data)
+        .await
+        .with_context(|| format!("failed to write compile log {}", path.display()))
+}
+
+/// Adds command which packs all artifacts with their modes into
+/// `ARTIFACTS_ARCHIVE`, and output requests for the archive and the
+/// command stderr. Command exits with `MISSING_ARTIFACT_EXIT_CODE` if
+/// some artifact does not exist. Returns index of the command step.
+fn push_pack_step(
+    invoke_request: &mut InvokeRequest,
+    toolchain: &toolchain_loader::Toolchain,
+    stage: u32,
+) -> usize {
+    // shell expands patterns; files are quoted (see
+    // `ToolchainSpec::artifacts` validation). Pattern which matches
+    // nothing stays as is, so it is reported as missing too.
+    let args: Vec<String> = toolchain
+        .spec
+        .artifacts
+        .iter()
+        .map(|path| {
+            if toolchain_loader::is_file_artifact(path) {
+                format!("'{}'", path.display())
+            } else {
+                path.display().to_string()
+            }
+        })
+        .collect();
+    let mut script = String::new();
+    for arg in &args {
+        script += &format!(
+            "for f in {}; do [ -e \"$f\" ] || {{ echo \"artifact $f not found\" >&2; exit {}; }}; done; ",
+            arg, MISSING_ARTIFACT_EXIT_CODE
+        );
+    }
+    script += &format!("exec tar -cf {} -- {}", ARTIFACTS_ARCHIVE, args.join(" "));
+    invoke_request.steps.push(Step {
+        stage,
+        action: Action::CreateFile {
+            id: FileId(PACK_STDERR_FILE.to_string()),
+            readable: true,
+            writeable: true,
+        },
+        ext: Extensions::default(),
+    });
+    let step = invoke_request.steps.len();
+    invoke_request.steps.push(Step {
+        stage,
+        action: Action::ExecuteCommand(Command {
+            sandbox_name: SANDBOX_NAME.to_string(),
+            argv: vec!["/bin/sh".to_string(), "-c".to_string(), script],
+            env: Vec::new(),
+            cwd: "/compile-output".to_string(),
+            stdio: Stdio {
+                stdin: FileId(FILE_ID_EMPTY.to_string()),
+                stdout: FileId(FILE_ID_EMPTY.to_string()),
+                stderr: FileId(PACK_STDERR_FILE.to_string()),
+                ext: Extensions::default(),
+            },
+            ext: Extensions::default(),
+        }),
+        ext: Extensions::default(),
+    });
+    invoke_request.outputs.push(OutputRequest {
+        name: PACK_STDERR_FILE.to_string(),
+        target: OutputRequestTarget::File(FileId(PACK_STDERR_FILE.to_string())),
+        ext: Extensions::default(),
+    });
+    invoke_request.outputs.push(OutputRequest {
+        name: ARTIFACTS_ARCHIVE.to_string(),
+        target: OutputRequestTarget::Path(PrefixedPath {
+            prefix: PathPrefix::Volume(VOLUME_NAME.to_string()),
+            path: ARTIFACTS_ARCHIVE.into(),
+        }),
+        ext: Extensions::default(),
+    });
+    step
+}
+
+/// Extracts regular files from the archive created by the pack step.
+/// Directories are implied by file paths; links and other special files
+/// are skipped.
+fn unpack_artifacts(archive: &[u8]) -> anyhow::Result<Vec<(PathBuf, Artifact)>> {
+    let mut files = Vec::new();
+    let mut archive = tar::Archive::new(archive);
+    for entry in archive.entries().context("invalid archive")? {
+        let mut entry = entry.context("invalid archive entry")?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().context("invalid path")?.into_owned();
+        let path: PathBuf = path
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .collect();
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            anyhow::bail!("archive contains unexpected path {}", path.display());
+        }
+        let mode = entry
+            .header()
+            .mode()
+            .with_context(|| format!("invalid mode of {}", path.display()))?;
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        files.push((path, Artifact::Inline { data, mode }));
+    }
+    Ok(files)
+}
@@ -0,0 +1,47 @@
+//! Up-front estimation of the work needed to judge a run
+
+use crate::Clients;
+use judge_apis::usage::{CostClass, CostEstimate};
+
+/// Estimates cost of judging a run of `problem_id`, or of `pinned`
+/// revision of the problem if it is given. Build is accounted only if
+/// `toolchain_name` is given. Returns None if the problem is not found.
+pub async fn estimate_cost(
+    clients: &Clients,
+    problem_id: &str,
+    pinned: Option<&problem_loader::LoadedProblem>,
+    toolchain_name: Option<&str>,
+) -> anyhow::Result<Option<CostEstimate>> {
+    let found;
+    let problem = match pinned {
+        Some(p) => p,
+        None => match clients.problems.find(problem_id).await? {
+            Some(p) => {
+                found = p;
+                &found
+            }
+            None => return Ok(None),
+        },
+    };
+    let mut max_cpu_millis: u64 = problem
+        .manifest
+        .tests
+        .iter()
+        .map(|test| test.limits.time())
+        .fold(0, u64::saturating_add);
+    if let Some(toolchain_name) = toolchain_name {
+        let toolchain = clients.toolchains.resolve(toolchain_name).await?;
+        let build_millis = toolchain
+            .spec
+            .limits
+            .time()
+            .saturating_mul(toolchain.spec.build_commands.len() as u64);
+        max_cpu_millis = max_cpu_millis.saturating_add(build_millis);
+    }
+    let max_cpu_seconds = max_cpu_millis as f64 / 1000.0;
+    Ok(Some(CostEstimate {
+        tests: problem.manifest.tests.len() as u32,
+        max_cpu_seconds,
+        class: CostClass::of(max_cpu_seconds),
+    }))
+}
@@ -0,0 +1,115 @@
+//! Troubleshooting dumps of a single job.
+//!
+//! If enabled for the job, raw valuer messages, invoke requests with
+//! their responses and produced judge logs are written to the dump
+//! directory as they appear. Failures to write the dump are reported as
+//! warnings and never fail the job.
+
+use crate::Warnings;
+use anyhow::Context;
+use invoker_api::invoke::{InvokeRequest, InvokeResponse};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::io::AsyncWriteExt;
+use valuer_client::RawMessage;
+
+/// Valuer messages are appended to this file, one JSON object per line
+const VALUER_TRANSCRIPT: &str = "valuer.jsonl";
+
+struct Inner {
+    dir: PathBuf,
+    /// Numbers invoke requests in the order they were answered
+    invoke_counter: AtomicU64,
+    warnings: Warnings,
+}
+
+#[derive(Clone)]
+pub(crate) struct DebugDump(Arc<Inner>);
+
+impl DebugDump {
+    pub(crate) async fn new(dir: PathBuf, warnings: Warnings) -> anyhow::Result<DebugDump> {
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("failed to create debug dump directory {}", dir.display()))?;
+        tracing::info!(dir = %dir.display(), "writing debug dump");
+        Ok(DebugDump(Arc::new(Inner {
+            dir,
+            invoke_counter: AtomicU64::new(0),
+            warnings,
+        })))
+    }
+
+    fn report(&self, res: anyhow::Result<()>) {
+        if let Err(err) = res {
+            self.0.warnings.report(
+                "debug-dump-failed",
+                format!("failed to write debug dump: {:#}", err),
+            );
+        }
+    }
+
+    /// Writes `value` as pretty-printed JSON to `${name}.json`
+    pub(crate) async fn write(&self, name: &str, value: &impl serde::Serialize) {
+        let path = self.0.dir.join(format!("{}.json", name));
+        self.report(write_json(&path, value).await);
+    }
+
+    /// Writes invoke request together with its response or error
+    pub(crate) async fn invoke_call(
+        &self,
+        request: InvokeRequest,
+        outcome: Result<InvokeResponse, String>,
+    ) {
+        let number = self.0.invoke_counter.fetch_add(1, Ordering::SeqCst) + 1;
+        self.write(&format!("invoke-{:04}-request", number), &request)
+            .await;
+        match outcome {
+            Ok(response) => {
+                self.write(&format!("invoke-{:04}-response", number), &response)
+                    .await
+            }
+            Err(error) => {
+                self.write(&format!("invoke-{:04}-error", number), &error)
+                    .await
+            }
+        }
+    }
+
+    /// Appends messages exchanged with valuer to the transcript
+    pub(crate) async fn valuer_messages(&self, messages: Vec<RawMessage>) {
+        if messages.is_empty() {
+            return;
+        }
+        let path = self.0.dir.join(VALUER_TRANSCRIPT);
+        self.report(append_lines(&path, &messages).await);
+    }
+}
+
+async fn write_json(path: &Path, value: &impl serde::Serialize) -> anyhow::Result<()> {
+    let data = serde_json::to_vec_pretty(value).context("failed to serialize")?;
+    tokio::fs::write(path, data)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+async fn append_lines(path: &Path, messages: &[RawMessage]) -> anyhow::Result<()> {
+    let mut data = Vec::new();
+    for message in messages {
+        serde_json::to_writer(&mut data, message).context("failed to serialize")?;
+        data.push(b'\n');
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    file.write_all(&data)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))
+}
@@ -3,20 +3,40 @@ mod checker_proto;
 use anyhow::Context;
 use invoker_api::{
     invoke::{
-        Action, ActionResult, Command, EnvVarValue, EnvironmentVariable, Extensions, FileId, Input,
-        InvokeRequest, Limits, OutputRequest, OutputRequestTarget, PathPrefix, PrefixedPath,
-        SandboxSettings, SharedDir, SharedDirectoryMode, Stdio, Step,
+        Action, Command, CommandResult, EnvVarValue, EnvironmentVariable, Extensions, FileId,
+        Input, InputSource, InvokeRequest, InvokeResponse, Limits, OutputRequest,
+        OutputRequestTarget, PathPrefix, PrefixedPath, SharedDir, SharedDirectoryMode, Stdio, Step,
     },
     shim::{
         ExtraFile, RequestExtensions, SandboxSettingsExtensions, SharedDirExtensionSource,
         EXTRA_FILES_DIR_NAME,
     },
 };
-use std::{collections::HashMap, path::PathBuf};
+use invoker_client::{
+    CommandExtensions, MemorySamplingExtension, OutputExtensions, PersistOutputExtension,
+    SandboxExtensions, SecurityReportExtension, TerminationReport, TerminationReportExtension,
+};
+use judge_apis::{
+    judge_log::{
+        self, downsample_memory, InvokerStepRow, MemorySample, PipelineStepLog, SecurityViolation,
+        TestAttempt, MAX_MEMORY_SAMPLES, MAX_SECURITY_VIOLATIONS,
+    },
+    usage::Usage,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::io::AsyncReadExt;
 use uuid::Uuid;
 use valuer_api::{status_codes, Status, StatusKind};
 
-use crate::compile::BuiltRun;
+use crate::{
+    compile::{Artifact, BuiltRun},
+    extensions::{ExtensionBuilder, Feature},
+    request_builder::OutputTooLarge,
+};
 
 #[derive(Debug, Clone, Copy, Default)]
 pub(crate) struct ResourceUsage {
@@ -24,12 +44,41 @@ pub(crate) struct ResourceUsage {
     pub(crate) time: Option<u64>,
 }
 
+#[derive(Debug, Clone)]
+pub(crate) enum CapturedOutput {
+    /// Output data
+    Inline(String),
+    /// Output was put to the output store with this id
+    Stored(String),
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct ExecOutcome {
     pub(crate) status: Status,
     pub(crate) resource_usage: ResourceUsage,
-    pub(crate) stdout: String,
-    pub(crate) stderr: String,
+    pub(crate) stdout: CapturedOutput,
+    pub(crate) stderr: CapturedOutput,
+    /// True if `stdout` was truncated to `Settings::max_output_size`
+    pub(crate) stdout_truncated: bool,
+    pub(crate) stderr_truncated: bool,
+    pub(crate) usage: Usage,
+    pub(crate) memory_samples: Option<Vec<MemorySample>>,
+    /// None if checker was not consulted
+    pub(crate) checker_comment: Option<String>,
+    /// Solution stdout as is, if `ExecContext::keep_stdout` is set
+    pub(crate) raw_stdout: Option<Vec<u8>>,
+    /// Results of problem-specific pipeline steps
+    pub(crate) pipeline: Vec<PipelineStepLog>,
+    /// Sandbox violations of the solution, as reported by invoker
+    pub(crate) security_violations: Vec<SecurityViolation>,
+    /// Steps of the invoker request, if the test failed with judge fault
+    pub(crate) invoker_steps: Vec<InvokerStepRow>,
+    /// Exit code of the solution, None if it was not started
+    pub(crate) exit_code: Option<i64>,
+    /// Why the solution failed, None if it exited with code 0
+    pub(crate) failure_reason: Option<String>,
+    /// All runs of the test, if it was run several times
+    pub(crate) attempts: Vec<TestAttempt>,
 }
 
 fn map_checker_outcome_to_status(out: checker_proto::Output) -> Status {
@@ -53,38 +102,150 @@ fn map_checker_outcome_to_status(out: checker_proto::Output) -> Status {
     }
 }
 
+/// Stages are executed in order. Each pipeline step gets its own stage
+/// right before (pre-test steps) or after (post-test steps) the stage of
+/// the solution, which is followed by the stage of the checker.
 const PREPARE_STAGE: u32 = 0;
-const EXEC_SOLUTION_STAGE: u32 = 1;
 const TEST_DATA_INPUT_FILE: &str = "test-data";
 const EXEC_SOLUTION_OUTPUT_FILE: &str = "solution-output";
 const EXEC_SOLUTION_ERROR_FILE: &str = "solution-error";
 const CORRECT_ANSWER_FILE: &str = "correct";
 const EMPTY_FILE: &str = "empty";
+const MEMORY_SAMPLES_FILE: &str = "solution-memory-samples";
+const SECURITY_REPORT_FILE: &str = "solution-security-report";
+const TERMINATION_REPORT_FILE: &str = "solution-termination-report";
+
+/// Larger sample files are ignored instead of being parsed
+const MAX_MEMORY_SAMPLES_FILE_SIZE: usize = 1 << 20;
+/// Larger security reports are not parsed, but still mean a violation
+const MAX_SECURITY_REPORT_FILE_SIZE: usize = 1 << 20;
+/// Larger termination reports are ignored
+const MAX_TERMINATION_REPORT_FILE_SIZE: usize = 1 << 16;
 
 const SOLUTION_SANDBOX_NAME: &str = "exec-sandbox";
 const CHECKER_SANDBOX_NAME: &str = "checker-sandbox";
+const INTERACTOR_SANDBOX_NAME: &str = "interactor-sandbox";
+
+/// Image of the checker sandbox and default image of pipeline steps
+const HELPER_IMAGE: &str = "gcr.io/distroless/cc:latest";
 
-const EXEC_CHECKER_STAGE: u32 = 2;
+/// Pipe from interactor stdout to solution stdin
+const TO_SOLUTION_PIPE_READ: &str = "to-solution-read";
+const TO_SOLUTION_PIPE_WRITE: &str = "to-solution-write";
+/// Pipe from solution stdout to interactor stdin
+const FROM_SOLUTION_PIPE_READ: &str = "from-solution-read";
+const FROM_SOLUTION_PIPE_WRITE: &str = "from-solution-write";
 
 const CHECKER_DECISION: &str = "checker-decision";
 const CHECKER_LOG: &str = "checker-logs";
+/// Output of the solution (or of the last post-test step) which is
+/// compared by built-in comparator
+const COMPARED_OUTPUT: &str = "compared-output";
+
+/// Parameters which are the same for all tests of the job
+pub(crate) struct ExecContext<'a> {
+    pub(crate) toolchain: &'a toolchain_loader::Toolchain,
+    pub(crate) problem: &'a pom::Problem,
+    pub(crate) problem_ext: &'a problem_loader::ProblemExtensions,
+    pub(crate) file_ref_resolver: &'a crate::FileRefResolver,
+    pub(crate) settings: &'a crate::Settings,
+    pub(crate) built: &'a BuiltRun,
+    /// If set, solution outputs are persisted by invoker in this directory
+    pub(crate) persistent_outputs_dir: Option<&'a Path>,
+    pub(crate) extensions: &'a ExtensionBuilder<'a>,
+    /// If set, all tests of the job run in the same solution sandbox
+    pub(crate) sandbox_reuse_key: Option<&'a str>,
+    /// If set, solution memory usage is sampled with this interval
+    pub(crate) memory_sampling_interval: Option<Duration>,
+    /// Invoker resources created by the job
+    pub(crate) resources: &'a crate::resources::ResourceTracker,
+    /// If set, solution stdout is returned as is and checker decision is
+    /// ignored. Status only reflects whether the solution succeeded.
+    pub(crate) keep_stdout: bool,
+}
+
+/// How solution output is judged
+enum JudgeStep {
+    /// Step of the checker, or of the interactor for interactive problems
+    Checker(usize),
+    /// Built-in comparator compares output with this name with the correct
+    /// answer
+    Comparator(String),
+}
 
 struct StepIds {
     exec_solution: usize,
-    exec_checker: usize,
+    judge: JudgeStep,
+    /// Pipeline steps in execution order, as indices in
+    /// `ProblemExtensions::pipeline` and step ids
+    pipeline: Vec<(usize, usize)>,
+    /// Paths of solution stdout and stderr, if they were persisted
+    persisted_outputs: Option<(PathBuf, PathBuf)>,
+    /// True if memory samples were requested
+    memory_samples: bool,
+    /// True if security report was requested
+    security_report: bool,
+    /// True if termination report was requested
+    termination_report: bool,
+}
+
+/// Directory with runtime files of the problem
+struct RuntimeDir<'a> {
+    sandbox_path: &'a Path,
+    /// File names and sources
+    files: Vec<(&'a str, &'a pom::FileRef)>,
+}
+
+/// Groups runtime files of the problem by their parent directory in
+/// the sandbox
+fn group_runtime_files(
+    problem_ext: &problem_loader::ProblemExtensions,
+) -> anyhow::Result<Vec<RuntimeDir<'_>>> {
+    let mut dirs: BTreeMap<&Path, Vec<(&str, &pom::FileRef)>> = BTreeMap::new();
+    for file in &problem_ext.runtime_files {
+        let (dir, name) = file.split_sandbox_path()?;
+        dirs.entry(dir).or_default().push((name, &file.source));
+    }
+    Ok(dirs
+        .into_iter()
+        .map(|(sandbox_path, files)| RuntimeDir {
+            sandbox_path,
+            files,
+        })
+        .collect())
 }
 
 async fn create_request(
-    toolchain: &toolchain_loader::Toolchain,
-    problem: &pom::Problem,
-    file_ref_resolver: &crate::FileRefResolver,
+    ctx: &ExecContext<'_>,
+    test_id: pom::TestId,
     test: &pom::Test,
     req_builder: &crate::request_builder::RequestBuilder,
-    built: &BuiltRun,
 ) -> anyhow::Result<(InvokeRequest, StepIds)> {
+    let ExecContext {
+        toolchain,
+        problem,
+        problem_ext,
+        file_ref_resolver,
+        built,
+        ..
+    } = *ctx;
+    let security_policy = ctx.settings.security_policy.combine(&problem_ext.security);
+    let runtime_dirs = group_runtime_files(problem_ext)?;
     let (substitutions, extra_files) = {
         let mut s = HashMap::new();
         let mut ef = HashMap::new();
+        for (i, dir) in runtime_dirs.iter().enumerate() {
+            for (name, source) in &dir.files {
+                let path = file_ref_resolver.resolve_asset(source);
+                ef.insert(
+                    format!("runtime/{}/{}", i, name),
+                    ExtraFile {
+                        contents: req_builder.intern_file(&path).await?,
+                        executable: false,
+                    },
+                );
+            }
+        }
         let test_path = file_ref_resolver.resolve_asset(&test.path);
         ef.insert(
             "exec/test".to_string(),
@@ -93,25 +254,53 @@ async fn create_request(
                 executable: false,
             },
         );
-        ef.insert(
-            "compile-out/bin".to_string(),
-            ExtraFile {
-                contents: req_builder.intern(&built.binary).await?,
-                executable: true,
-            },
-        );
-        let checker = file_ref_resolver.resolve_asset(&problem.checker_exe);
-        ef.insert(
-            "check/checker".to_string(),
-            ExtraFile {
-                contents: req_builder.intern_file(&checker).await?,
-                executable: true,
-            },
-        );
+        for (path, artifact) in &built.files {
+            let contents = match artifact {
+                Artifact::Inline { data, .. } => {
+                    req_builder
+                        .intern(&format!("artifact {}", path.display()), data)
+                        .await?
+                }
+                Artifact::Persistent(path) => InputSource::LocalFile { path: path.clone() },
+            };
+            ef.insert(
+                format!("compile-out/{}", path.display()),
+                ExtraFile {
+                    contents,
+                    executable: artifact.is_executable(),
+                },
+            );
+        }
+        for (i, step) in problem_ext.pipeline.iter().enumerate() {
+            let exe = file_ref_resolver.resolve_asset(&step.exe);
+            ef.insert(
+                format!("pipeline/{}/exe", i),
+                ExtraFile {
+                    contents: req_builder.intern_file(&exe).await?,
+                    executable: true,
+                },
+            );
+        }
+        let judge_exe = match (&problem_ext.interactor, &problem_ext.comparator) {
+            (Some(interactor), _) => Some((&interactor.exe, "check/interactor")),
+            (None, Some(_)) => None,
+            (None, None) => Some((&problem.checker_exe, "check/checker")),
+        };
+        if let Some((judge_exe, judge_exe_name)) = judge_exe {
+            let judge_exe = file_ref_resolver.resolve_asset(judge_exe);
+            ef.insert(
+                judge_exe_name.to_string(),
+                ExtraFile {
+                    contents: req_builder.intern_file(&judge_exe).await?,
+                    executable: true,
+                },
+            );
+        }
         s.insert(
             "Run.BinaryFilePath".to_string(),
             "/compile-out/bin".to_string(),
         );
+        s.insert("Run.OutputDirPath".to_string(), "/compile-out".to_string());
         (s, ef)
     };
     let mut invoke_request = InvokeRequest {
@@ -119,7 +308,7 @@ async fn create_request(
         inputs: vec![],
         outputs: vec![],
         id: Uuid::nil(),
-        ext: Extensions::make(RequestExtensions {
+        ext: ctx.extensions.make(RequestExtensions {
             extra_files,
             substitutions,
         })?,
@@ -145,10 +334,26 @@ async fn create_request(
         ext: Extensions::default(),
     });
 
+    // pre-test steps transform test data one after another
+    let mut pipeline_steps = Vec::new();
+    let mut stage = PREPARE_STAGE;
+    let mut solution_input = TEST_DATA_INPUT_FILE.to_string();
+    for (i, step) in problem_ext.pipeline.iter().enumerate() {
+        if step.phase != problem_loader::PipelinePhase::PreTest {
+            continue;
+        }
+        stage += 1;
+        let step_id =
+            push_pipeline_step(ctx, &mut invoke_request, test, i, stage, &solution_input)?;
+        pipeline_steps.push((i, step_id));
+        solution_input = pipeline_output_file(i);
+    }
+    let exec_solution_stage = stage + 1;
+
     // prepare files for stdout & stderr
 
     invoke_request.steps.push(Step {
-        stage: EXEC_SOLUTION_STAGE,
+        stage: exec_solution_stage,
         action: Action::CreateFile {
             id: FileId(EXEC_SOLUTION_OUTPUT_FILE.to_string()),
             readable: true,
@@ -157,7 +362,7 @@ async fn create_request(
         ext: Extensions::default(),
     });
     invoke_request.steps.push(Step {
-        stage: EXEC_SOLUTION_STAGE,
+        stage: exec_solution_stage,
         action: Action::CreateFile {
             id: FileId(EXEC_SOLUTION_ERROR_FILE.to_string()),
             readable: true,
@@ -166,264 +371,755 @@ async fn create_request(
         ext: Extensions::default(),
     });
 
-    // create solution sandbox
-    invoke_request.steps.push(Step {
-        stage: EXEC_SOLUTION_STAGE,
-        action: Action::CreateSandbox(SandboxSettings {
-            limits: Limits {
-                memory: test.limits.memory(),
-                time: test.limits.time(),
-                process_count: Some(test.limits.process_count()),
+    // interactor and solution are connected with two pipes
+    if problem_ext.interactor.is_some() {
+        for (read, write) in &[
+            (TO_SOLUTION_PIPE_READ, TO_SOLUTION_PIPE_WRITE),
+            (FROM_SOLUTION_PIPE_READ, FROM_SOLUTION_PIPE_WRITE),
+        ] {
+            invoke_request.steps.push(Step {
+                stage: exec_solution_stage,
+                action: Action::CreatePipe {
+                    read: FileId(read.to_string()),
+                    write: FileId(write.to_string()),
+                },
                 ext: Extensions::default(),
-            },
-            name: SOLUTION_SANDBOX_NAME.to_string(),
-            base_image: PathBuf::new(),
-            expose: vec![SharedDir {
-                host_path: PrefixedPath {
-                    prefix: PathPrefix::Extension(Extensions::make(SharedDirExtensionSource {
-                        name: EXTRA_FILES_DIR_NAME.to_string(),
-                    })?),
-                    path: "compile-out".into(),
+            });
+        }
+    }
+
+    let memory_sampling = match ctx.memory_sampling_interval {
+        Some(interval) => {
+            invoke_request.steps.push(Step {
+                stage: exec_solution_stage,
+                action: Action::CreateFile {
+                    id: FileId(MEMORY_SAMPLES_FILE.to_string()),
+                    readable: true,
+                    writeable: true,
                 },
-                sandbox_path: "/compile-out".into(),
-                mode: SharedDirectoryMode::ReadOnly,
-                create: false,
                 ext: Extensions::default(),
-            }],
-            ext: Extensions::make(SandboxSettingsExtensions {
-                image: toolchain.image.clone(),
-            })?,
-        }),
+            });
+            invoke_request.outputs.push(OutputRequest {
+                name: MEMORY_SAMPLES_FILE.to_string(),
+                target: OutputRequestTarget::File(FileId(MEMORY_SAMPLES_FILE.to_string())),
+                ext: Extensions::default(),
+            });
+            Some(MemorySamplingExtension {
+                interval_ms: interval.as_millis() as u64,
+                output: FileId(MEMORY_SAMPLES_FILE.to_string()),
+            })
+        }
+        None => None,
+    };
+
+    // older invokers do not report violations, so they look like
+    // ordinary runtime errors
+    let security_report = if ctx.extensions.supports(Feature::SecurityReport) {
+        invoke_request.steps.push(Step {
+            stage: exec_solution_stage,
+            action: Action::CreateFile {
+                id: FileId(SECURITY_REPORT_FILE.to_string()),
+                readable: true,
+                writeable: true,
+            },
+            ext: Extensions::default(),
+        });
+        invoke_request.outputs.push(OutputRequest {
+            name: SECURITY_REPORT_FILE.to_string(),
+            target: OutputRequestTarget::File(FileId(SECURITY_REPORT_FILE.to_string())),
+            ext: Extensions::default(),
+        });
+        Some(SecurityReportExtension {
+            security_report: FileId(SECURITY_REPORT_FILE.to_string()),
+        })
+    } else {
+        None
+    };
+    let has_security_report = security_report.is_some();
+
+    // without the report, commands killed by sandbox can only be told
+    // apart by resource usage
+    let termination_report = if ctx.extensions.supports(Feature::TerminationReport) {
+        invoke_request.steps.push(Step {
+            stage: exec_solution_stage,
+            action: Action::CreateFile {
+                id: FileId(TERMINATION_REPORT_FILE.to_string()),
+                readable: true,
+                writeable: true,
+            },
+            ext: Extensions::default(),
+        });
+        invoke_request.outputs.push(OutputRequest {
+            name: TERMINATION_REPORT_FILE.to_string(),
+            target: OutputRequestTarget::File(FileId(TERMINATION_REPORT_FILE.to_string())),
+            ext: Extensions::default(),
+        });
+        Some(TerminationReportExtension {
+            termination_report: FileId(TERMINATION_REPORT_FILE.to_string()),
+        })
+    } else {
+        None
+    };
+    let has_termination_report = termination_report.is_some();
+
+    let solution_command_ext =
+        if memory_sampling.is_some() || security_report.is_some() || termination_report.is_some() {
+            ctx.extensions.make(CommandExtensions {
+                memory_sampling,
+                security_report,
+                termination_report,
+            })?
+        } else {
+            Extensions::default()
+        };
+
+    // create solution sandbox
+    let mut solution_expose = vec![SharedDir {
+        host_path: PrefixedPath {
+            prefix: PathPrefix::Extension(ctx.extensions.make(SharedDirExtensionSource {
+                name: EXTRA_FILES_DIR_NAME.to_string(),
+            })?),
+            path: "compile-out".into(),
+        },
+        sandbox_path: "/compile-out".into(),
+        mode: SharedDirectoryMode::ReadOnly,
+        create: false,
+        ext: Extensions::default(),
+    }];
+    for (i, dir) in runtime_dirs.iter().enumerate() {
+        solution_expose.push(SharedDir {
+            host_path: PrefixedPath {
+                prefix: PathPrefix::Extension(ctx.extensions.make(SharedDirExtensionSource {
+                    name: EXTRA_FILES_DIR_NAME.to_string(),
+                })?),
+                path: format!("runtime/{}", i).into(),
+            },
+            sandbox_path: dir.sandbox_path.to_path_buf(),
+            mode: SharedDirectoryMode::ReadOnly,
+            create: true,
+            ext: Extensions::default(),
+        });
+    }
+    invoke_request.steps.push(Step {
+        stage: exec_solution_stage,
+        action: Action::CreateSandbox(crate::security::sandbox(
+            &security_policy,
+            ctx.extensions,
+            SOLUTION_SANDBOX_NAME.to_string(),
+            solution_limits(test),
+            solution_expose,
+            SandboxExtensions {
+                shim: SandboxSettingsExtensions {
+                    image: toolchain.image.clone(),
+                },
+                network: false,
+                reuse_key: ctx.sandbox_reuse_key.map(ToString::to_string),
+                writable_paths: None,
+                core_dumps: None,
+            },
+        )?),
         ext: Extensions::default(),
     });
 
     // produce a step for executing solution
     let exec_solution_step_id = invoke_request.steps.len();
 
+    let mut solution_env: Vec<_> = toolchain
+        .spec
+        .run_command
+        .env
+        .iter()
+        .map(|(k, v)| EnvironmentVariable {
+            name: k.clone(),
+            value: EnvVarValue::Plain(v.clone()),
+            ext: Extensions::default(),
+        })
+        .collect();
+    if problem_ext.expose_test_metadata {
+        solution_env.push(EnvironmentVariable {
+            name: "JJS_TEST_ID".to_string(),
+            value: EnvVarValue::Plain(test_id.get().to_string()),
+            ext: Extensions::default(),
+        });
+        solution_env.push(EnvironmentVariable {
+            name: "JJS_TEST_GROUP".to_string(),
+            value: EnvVarValue::Plain(test.group.clone()),
+            ext: Extensions::default(),
+        });
+        solution_env.push(EnvironmentVariable {
+            name: "JJS_TEST_NAME".to_string(),
+            value: EnvVarValue::Plain(test_display_name(problem_ext, test_id)),
+            ext: Extensions::default(),
+        });
+    }
+
+    // for interactive problems solution output file stays empty
+    let (solution_stdin, solution_stdout) = match problem_ext.interactor {
+        Some(_) => (TO_SOLUTION_PIPE_READ, FROM_SOLUTION_PIPE_WRITE),
+        None => (solution_input.as_str(), EXEC_SOLUTION_OUTPUT_FILE),
+    };
     invoke_request.steps.push(Step {
-        stage: EXEC_SOLUTION_STAGE,
+        stage: exec_solution_stage,
         action: Action::ExecuteCommand(Command {
             sandbox_name: SOLUTION_SANDBOX_NAME.to_string(),
             argv: toolchain.spec.run_command.argv.clone(),
-            env: toolchain
-                .spec
-                .run_command
-                .env
-                .iter()
-                .map(|(k, v)| EnvironmentVariable {
-                    name: k.clone(),
-                    value: EnvVarValue::Plain(v.clone()),
-                    ext: Extensions::default(),
-                })
-                .collect(),
+            env: solution_env,
             cwd: toolchain.spec.run_command.cwd.clone(),
             stdio: Stdio {
-                stdin: FileId(TEST_DATA_INPUT_FILE.to_string()),
-                stdout: FileId(EXEC_SOLUTION_OUTPUT_FILE.to_string()),
+                stdin: FileId(solution_stdin.to_string()),
+                stdout: FileId(solution_stdout.to_string()),
                 stderr: FileId(EXEC_SOLUTION_ERROR_FILE.to_string()),
                 ext: Extensions::default(),
             },
-            ext: Extensions::default(),
+            ext: solution_command_ext,
         }),
         ext: Extensions::default(),
     });
 
-    // provide a correct answer if requested
-    let has_correct_answer;
-    {
-        if let Some(corr_path) = &test.correct {
-            let full_path = file_ref_resolver.resolve_asset(corr_path);
-            let source = req_builder.intern_file(&full_path).await?;
+    // post-test steps transform solution output one after another
+    let mut stage = exec_solution_stage;
+    let mut checked_output = EXEC_SOLUTION_OUTPUT_FILE.to_string();
+    for (i, step) in problem_ext.pipeline.iter().enumerate() {
+        if step.phase != problem_loader::PipelinePhase::PostTest {
+            continue;
+        }
+        stage += 1;
+        let step_id =
+            push_pipeline_step(ctx, &mut invoke_request, test, i, stage, &checked_output)?;
+        pipeline_steps.push((i, step_id));
+        checked_output = pipeline_output_file(i);
+    }
+    let exec_checker_stage = stage + 1;
 
-            has_correct_answer = true;
+    // built-in comparator replaces the checker, and it runs inside judge
+    let judge = if problem_ext.comparator.is_some() {
+        JudgeStep::Comparator(checked_output)
+    } else {
+        // provide a correct answer if requested
+        let has_correct_answer;
+        {
+            if let Some(corr_path) = &test.correct {
+                let full_path = file_ref_resolver.resolve_asset(corr_path);
+                let source = req_builder.intern_file(&full_path).await?;
 
-            invoke_request.inputs.push(Input {
-                file_id: FileId(CORRECT_ANSWER_FILE.to_string()),
-                source,
-                ext: Extensions::default(),
-            })
-        } else {
-            has_correct_answer = false;
+                has_correct_answer = true;
+
+                invoke_request.inputs.push(Input {
+                    file_id: FileId(CORRECT_ANSWER_FILE.to_string()),
+                    source,
+                    ext: Extensions::default(),
+                })
+            } else {
+                has_correct_answer = false;
+            }
         }
-    }
-    // generate checker feedback files
+        // interactor must run in the same stage as the solution
+        let (judge_stage, judge_sandbox_name) = match problem_ext.interactor {
+            Some(_) => (exec_solution_stage, INTERACTOR_SANDBOX_NAME),
+            None => (exec_checker_stage, CHECKER_SANDBOX_NAME),
+        };
 
-    invoke_request.steps.push(Step {
-        stage: EXEC_CHECKER_STAGE,
-        action: Action::CreateFile {
-            id: FileId(CHECKER_DECISION.to_string()),
-            readable: true,
-            writeable: true,
-        },
-        ext: Extensions::default(),
-    });
-    invoke_request.steps.push(Step {
-        stage: EXEC_CHECKER_STAGE,
-        action: Action::CreateFile {
-            id: FileId(CHECKER_LOG.to_string()),
-            readable: true,
-            writeable: true,
-        },
-        ext: Extensions::default(),
-    });
+        // generate checker feedback files
 
-    // create a checker sandbox
-    invoke_request.steps.push(Step {
-        stage: EXEC_CHECKER_STAGE,
-        action: Action::CreateSandbox(SandboxSettings {
-            limits: Limits {
-                memory: test.limits.memory(),
-                time: test.limits.time(),
-                process_count: Some(test.limits.process_count()),
-                ext: Extensions::default(),
+        invoke_request.steps.push(Step {
+            stage: judge_stage,
+            action: Action::CreateFile {
+                id: FileId(CHECKER_DECISION.to_string()),
+                readable: true,
+                writeable: true,
             },
-            name: CHECKER_SANDBOX_NAME.to_string(),
-            base_image: PathBuf::new(),
-            expose: vec![SharedDir {
-                host_path: PrefixedPath {
-                    prefix: PathPrefix::Extension(Extensions::make(SharedDirExtensionSource {
-                        name: EXTRA_FILES_DIR_NAME.to_string(),
-                    })?),
-                    path: "check".into(),
+            ext: Extensions::default(),
+        });
+        invoke_request.steps.push(Step {
+            stage: judge_stage,
+            action: Action::CreateFile {
+                id: FileId(CHECKER_LOG.to_string()),
+                readable: true,
+                writeable: true,
+            },
+            ext: Extensions::default(),
+        });
+
+        // create a checker (or interactor) sandbox
+        invoke_request.steps.push(Step {
+            stage: judge_stage,
+            action: Action::CreateSandbox(crate::security::sandbox(
+                &security_policy,
+                ctx.extensions,
+                judge_sandbox_name.to_string(),
+                checker_limits(test, problem_ext),
+                vec![SharedDir {
+                    host_path: PrefixedPath {
+                        prefix: PathPrefix::Extension(ctx.extensions.make(
+                            SharedDirExtensionSource {
+                                name: EXTRA_FILES_DIR_NAME.to_string(),
+                            },
+                        )?),
+                        path: "check".into(),
+                    },
+                    sandbox_path: "/check".into(),
+                    mode: SharedDirectoryMode::ReadOnly,
+                    create: false,
+                    ext: Extensions::default(),
+                }],
+                SandboxExtensions {
+                    shim: SandboxSettingsExtensions {
+                        // TODO: allow overriding
+                        image: HELPER_IMAGE.to_string(),
+                    },
+                    network: false,
+                    reuse_key: None,
+                    writable_paths: None,
+                    core_dumps: None,
                 },
-                sandbox_path: "/check".into(),
-                mode: SharedDirectoryMode::ReadOnly,
-                create: false,
+            )?),
+            ext: Extensions::default(),
+        });
+
+        // produce a step for executing checker
+        let exec_checker_test_id = invoke_request.steps.len();
+
+        let mut checker_env = vec![
+            EnvironmentVariable {
+                name: "JJS_TEST".to_string(),
+                value: EnvVarValue::File(FileId(TEST_DATA_INPUT_FILE.to_string())),
                 ext: Extensions::default(),
-            }],
-            ext: Extensions::make(SandboxSettingsExtensions {
-                // TODO: allow overriding
-                image: "gcr.io/distroless/cc:latest".to_string(),
-            })?,
-        }),
-        ext: Extensions::default(),
-    });
+            },
+            EnvironmentVariable {
+                name: "JJS_CHECKER_OUT".to_string(),
+                value: EnvVarValue::File(FileId(CHECKER_DECISION.to_string())),
+                ext: Extensions::default(),
+            },
+            EnvironmentVariable {
+                name: "JJS_CHECKER_COMMENT".to_string(),
+                value: EnvVarValue::File(FileId(CHECKER_LOG.to_string())),
+                ext: Extensions::default(),
+            },
+        ];
 
-    // produce a step for executing checker
-    let exec_checker_test_id = invoke_request.steps.len();
+        if has_correct_answer {
+            checker_env.push(EnvironmentVariable {
+                name: "JJS_CORR".to_string(),
+                value: EnvVarValue::File(FileId(CORRECT_ANSWER_FILE.to_string())),
+                ext: Extensions::default(),
+            });
+        }
 
-    let mut checker_cmd = vec!["/check/checker".to_string()];
-    checker_cmd.extend_from_slice(&problem.checker_cmd);
-    let mut checker_env = vec![
-        EnvironmentVariable {
-            name: "JJS_SOL".to_string(),
-            value: EnvVarValue::File(FileId(EXEC_SOLUTION_OUTPUT_FILE.to_string())),
-            ext: Extensions::default(),
-        },
-        EnvironmentVariable {
-            name: "JJS_TEST".to_string(),
-            value: EnvVarValue::File(FileId(TEST_DATA_INPUT_FILE.to_string())),
+        let render = |template: &str| {
+            problem_loader::template::render(template, |name| {
+                checker_placeholder(problem, problem_ext, test_id, test, name)
+            })
+        };
+        for (name, value) in &problem_ext.checker_env {
+            checker_env.push(EnvironmentVariable {
+                name: name.clone(),
+                value: EnvVarValue::Plain(render(value)?),
+                ext: Extensions::default(),
+            });
+        }
+
+        let command = match &problem_ext.interactor {
+            Some(interactor) => {
+                let mut argv = vec!["/check/interactor".to_string()];
+                for arg in &interactor.argv {
+                    argv.push(render(arg)?);
+                }
+                Command {
+                    argv,
+                    env: checker_env,
+                    cwd: "/".to_string(),
+                    stdio: Stdio {
+                        stdin: FileId(FROM_SOLUTION_PIPE_READ.to_string()),
+                        stdout: FileId(TO_SOLUTION_PIPE_WRITE.to_string()),
+                        stderr: FileId(CHECKER_LOG.to_string()),
+                        ext: Extensions::default(),
+                    },
+                    ext: Extensions::default(),
+                    sandbox_name: INTERACTOR_SANDBOX_NAME.to_string(),
+                }
+            }
+            None => {
+                let mut argv = vec!["/check/checker".to_string()];
+                for arg in &problem.checker_cmd {
+                    argv.push(render(arg)?);
+                }
+                checker_env.insert(
+                    0,
+                    EnvironmentVariable {
+                        name: "JJS_SOL".to_string(),
+                        value: EnvVarValue::File(FileId(checked_output)),
+                        ext: Extensions::default(),
+                    },
+                );
+                Command {
+                    argv,
+                    env: checker_env,
+                    cwd: "/".to_string(),
+                    stdio: Stdio {
+                        stdin: FileId(EMPTY_FILE.to_string()),
+                        stdout: FileId(CHECKER_LOG.to_string()),
+                        stderr: FileId(CHECKER_LOG.to_string()),
+                        ext: Extensions::default(),
+                    },
+                    ext: Extensions::default(),
+                    sandbox_name: CHECKER_SANDBOX_NAME.to_string(),
+                }
+            }
+        };
+        invoke_request.steps.push(Step {
+            stage: judge_stage,
+            action: Action::ExecuteCommand(command),
             ext: Extensions::default(),
-        },
-        EnvironmentVariable {
-            name: "JJS_CHECKER_OUT".to_string(),
-            value: EnvVarValue::File(FileId(CHECKER_DECISION.to_string())),
+        });
+
+        // add output requests
+        invoke_request.outputs.push(OutputRequest {
+            name: CHECKER_LOG.to_string(),
+            target: OutputRequestTarget::File(FileId(CHECKER_LOG.to_string())),
             ext: Extensions::default(),
-        },
-        EnvironmentVariable {
-            name: "JJS_CHECKER_COMMENT".to_string(),
-            value: EnvVarValue::File(FileId(CHECKER_LOG.to_string())),
+        });
+        invoke_request.outputs.push(OutputRequest {
+            name: CHECKER_DECISION.to_string(),
+            target: OutputRequestTarget::File(FileId(CHECKER_DECISION.to_string())),
             ext: Extensions::default(),
-        },
+        });
+        JudgeStep::Checker(exec_checker_test_id)
+    };
+    let persisted_outputs = ctx.persistent_outputs_dir.map(|dir| {
+        (
+            dir.join(format!("{}-{}", EXEC_SOLUTION_OUTPUT_FILE, Uuid::new_v4())),
+            dir.join(format!("{}-{}", EXEC_SOLUTION_ERROR_FILE, Uuid::new_v4())),
+        )
+    });
+    let solution_outputs = [
+        (
+            EXEC_SOLUTION_OUTPUT_FILE,
+            persisted_outputs.as_ref().map(|p| &p.0),
+        ),
+        (
+            EXEC_SOLUTION_ERROR_FILE,
+            persisted_outputs.as_ref().map(|p| &p.1),
+        ),
     ];
+    // one byte more than the limit is requested to detect truncation
+    let max_output_size = ctx
+        .settings
+        .max_output_size
+        .filter(|_| ctx.extensions.supports(Feature::OutputTruncation))
+        .map(|size| size + 1);
+    for (name, persist_path) in solution_outputs.iter() {
+        let persist = match persist_path {
+            Some(path) => {
+                let persist_as = path.file_name().unwrap().to_string_lossy().into_owned();
+                ctx.resources
+                    .track(invoker_client::Resource::PersistedFile {
+                        name: persist_as.clone(),
+                    })?;
+                Some(PersistOutputExtension { persist_as })
+            }
+            None => None,
+        };
+        let ext = if persist.is_some() || max_output_size.is_some() {
+            ctx.extensions.make(OutputExtensions {
+                persist,
+                max_size: max_output_size,
+            })?
+        } else {
+            Extensions::default()
+        };
+        invoke_request.outputs.push(OutputRequest {
+            name: name.to_string(),
+            target: OutputRequestTarget::File(FileId(name.to_string())),
+            ext,
+        });
+    }
+    // solution stdout can be truncated or persisted, so unless it is
+    // returned as is, compared output is requested separately
+    let judge = match judge {
+        JudgeStep::Comparator(file)
+            if file != EXEC_SOLUTION_OUTPUT_FILE
+                || persisted_outputs.is_some()
+                || max_output_size.is_some() =>
+        {
+            // larger output is not decoded anyway, so it is not transferred
+            let ext = if ctx.extensions.supports(Feature::OutputTruncation) {
+                ctx.extensions.make(OutputExtensions {
+                    persist: None,
+                    max_size: Some(ctx.settings.max_invoker_output_size + 1),
+                })?
+            } else {
+                Extensions::default()
+            };
+            invoke_request.outputs.push(OutputRequest {
+                name: COMPARED_OUTPUT.to_string(),
+                target: OutputRequestTarget::File(FileId(file)),
+                ext,
+            });
+            JudgeStep::Comparator(COMPARED_OUTPUT.to_string())
+        }
+        judge => judge,
+    };
+
+    Ok((
+        invoke_request,
+        StepIds {
+            judge,
+            exec_solution: exec_solution_step_id,
+            pipeline: pipeline_steps,
+            persisted_outputs,
+            memory_samples: ctx.memory_sampling_interval.is_some(),
+            security_report: has_security_report,
+            termination_report: has_termination_report,
+        },
+    ))
+}
+
+/// Value of a placeholder in checker arguments and environment (see
+/// `problem_loader::template`)
+fn checker_placeholder(
+    problem: &pom::Problem,
+    problem_ext: &problem_loader::ProblemExtensions,
+    test_id: pom::TestId,
+    test: &pom::Test,
+    name: &str,
+) -> Option<String> {
+    match name {
+        "Test.Id" => Some(test_id.get().to_string()),
+        "Test.Group" => Some(test.group.clone()),
+        "Test.Name" => Some(test_display_name(problem_ext, test_id)),
+        "Problem.Name" => Some(problem.name.clone()),
+        _ => problem_ext
+            .group_params
+            .get(&test.group)?
+            .get(name.strip_prefix("Group.")?)
+            .cloned(),
+    }
+}
+
+/// Name of the test, or its number if the problem does not name tests
+fn test_display_name(
+    problem_ext: &problem_loader::ProblemExtensions,
+    test_id: pom::TestId,
+) -> String {
+    match problem_ext.test_name(test_id) {
+        Some(name) => name.to_string(),
+        None => test_id.get().to_string(),
+    }
+}
 
-    if has_correct_answer {
-        checker_env.push(EnvironmentVariable {
-            name: "JJS_CORR".to_string(),
-            value: EnvVarValue::File(FileId(CORRECT_ANSWER_FILE.to_string())),
+fn pipeline_output_file(index: usize) -> String {
+    format!("pipeline-{}-output", index)
+}
+
+fn pipeline_log_file(index: usize) -> String {
+    format!("pipeline-{}-log", index)
+}
+
+/// Adds steps which run pipeline step `index` of the problem in `stage`.
+/// Its stdin is `input`, and stdout is written to `pipeline_output_file`.
+/// Returns id of the step which executes the command.
+fn push_pipeline_step(
+    ctx: &ExecContext<'_>,
+    invoke_request: &mut InvokeRequest,
+    test: &pom::Test,
+    index: usize,
+    stage: u32,
+    input: &str,
+) -> anyhow::Result<usize> {
+    let step = &ctx.problem_ext.pipeline[index];
+    let output = pipeline_output_file(index);
+    let log = pipeline_log_file(index);
+    let sandbox_name = format!("pipeline-sandbox-{}", index);
+    for file in &[&output, &log] {
+        invoke_request.steps.push(Step {
+            stage,
+            action: Action::CreateFile {
+                id: FileId(file.to_string()),
+                readable: true,
+                writeable: true,
+            },
             ext: Extensions::default(),
         });
     }
-
     invoke_request.steps.push(Step {
-        stage: EXEC_CHECKER_STAGE,
+        stage,
+        action: Action::CreateSandbox(crate::security::sandbox(
+            &ctx.settings
+                .security_policy
+                .combine(&ctx.problem_ext.security),
+            ctx.extensions,
+            sandbox_name.clone(),
+            limits_with_overrides(test, step.limits.as_ref()),
+            vec![SharedDir {
+                host_path: PrefixedPath {
+                    prefix: PathPrefix::Extension(ctx.extensions.make(
+                        SharedDirExtensionSource {
+                            name: EXTRA_FILES_DIR_NAME.to_string(),
+                        },
+                    )?),
+                    path: format!("pipeline/{}", index).into(),
+                },
+                sandbox_path: "/pipeline".into(),
+                mode: SharedDirectoryMode::ReadOnly,
+                create: false,
+                ext: Extensions::default(),
+            }],
+            SandboxExtensions {
+                shim: SandboxSettingsExtensions {
+                    image: step
+                        .image
+                        .clone()
+                        .unwrap_or_else(|| HELPER_IMAGE.to_string()),
+                },
+                network: false,
+                reuse_key: None,
+                writable_paths: None,
+                core_dumps: None,
+            },
+        )?),
+        ext: Extensions::default(),
+    });
+    let step_id = invoke_request.steps.len();
+    let mut argv = vec!["/pipeline/exe".to_string()];
+    argv.extend_from_slice(&step.argv);
+    invoke_request.steps.push(Step {
+        stage,
         action: Action::ExecuteCommand(Command {
-            argv: checker_cmd,
-            env: checker_env,
+            sandbox_name,
+            argv,
+            env: vec![EnvironmentVariable {
+                name: "JJS_TEST".to_string(),
+                value: EnvVarValue::File(FileId(TEST_DATA_INPUT_FILE.to_string())),
+                ext: Extensions::default(),
+            }],
             cwd: "/".to_string(),
             stdio: Stdio {
-                stdin: FileId(EMPTY_FILE.to_string()),
-                stdout: FileId(CHECKER_LOG.to_string()),
-                stderr: FileId(CHECKER_LOG.to_string()),
+                stdin: FileId(input.to_string()),
+                stdout: FileId(output),
+                stderr: FileId(log.clone()),
                 ext: Extensions::default(),
             },
             ext: Extensions::default(),
-            sandbox_name: CHECKER_SANDBOX_NAME.to_string(),
         }),
         ext: Extensions::default(),
     });
-
-    // add output requests
-    invoke_request.outputs.push(OutputRequest {
-        name: CHECKER_LOG.to_string(),
-        target: OutputRequestTarget::File(FileId(CHECKER_LOG.to_string())),
-        ext: Extensions::default(),
-    });
     invoke_request.outputs.push(OutputRequest {
-        name: CHECKER_DECISION.to_string(),
-        target: OutputRequestTarget::File(FileId(CHECKER_DECISION.to_string())),
+        name: log.clone(),
+        target: OutputRequestTarget::File(FileId(log)),
         ext: Extensions::default(),
     });
-    invoke_request.outputs.push(OutputRequest {
-        name: EXEC_SOLUTION_OUTPUT_FILE.to_string(),
-        target: OutputRequestTarget::File(FileId(EXEC_SOLUTION_OUTPUT_FILE.to_string())),
-        ext: Extensions::default(),
-    });
-    invoke_request.outputs.push(OutputRequest {
-        name: EXEC_SOLUTION_ERROR_FILE.to_string(),
-        target: OutputRequestTarget::File(FileId(EXEC_SOLUTION_ERROR_FILE.to_string())),
+    Ok(step_id)
+}
+
+pub(crate) fn solution_limits(test: &pom::Test) -> Limits {
+    Limits {
+        memory: test.limits.memory(),
+        time: test.limits.time(),
+        process_count: Some(test.limits.process_count()),
         ext: Extensions::default(),
-    });
+    }
+}
 
-    Ok((
-        invoke_request,
-        StepIds {
-            exec_checker: exec_checker_test_id,
-            exec_solution: exec_solution_step_id,
-        },
-    ))
+/// Limits of the checker or interactor sandbox
+fn checker_limits(test: &pom::Test, problem_ext: &problem_loader::ProblemExtensions) -> Limits {
+    limits_with_overrides(test, problem_ext.checker_limits.as_ref())
+}
+
+/// Limits of the solution on the test, with some of them overridden
+fn limits_with_overrides(test: &pom::Test, overrides: Option<&pom::Limits>) -> Limits {
+    let mut limits = solution_limits(test);
+    if let Some(overrides) = overrides {
+        limits.memory = overrides.memory.unwrap_or(limits.memory);
+        limits.time = overrides.time.unwrap_or(limits.time);
+        limits.process_count = overrides.process_count.or(limits.process_count);
+    }
+    limits
 }
 
 /// Runs Artifact on one test and produces output
 pub(crate) async fn exec(
-    toolchain: &toolchain_loader::Toolchain,
-    problem: &pom::Problem,
+    ctx: &ExecContext<'_>,
     client: invoker_client::Client,
-    file_ref_resolver: &crate::FileRefResolver,
     test_id: pom::TestId,
-    settings: &crate::Settings,
-    built: &BuiltRun,
 ) -> anyhow::Result<ExecOutcome> {
-    let req_builder = crate::request_builder::RequestBuilder::new();
+    let settings = ctx.settings;
+    let req_builder = crate::request_builder::RequestBuilder::new(settings);
 
-    let test = problem
+    let test = ctx
+        .problem
         .tests
         .get(test_id.to_idx())
         .context("unknown test")?;
 
-    let (invoke_request, step_ids) = create_request(
-        toolchain,
-        problem,
-        file_ref_resolver,
-        test,
-        &req_builder,
-        built,
-    )
-    .await
-    .context("failed to prepare invoke request")?;
+    let (invoke_request, step_ids) = create_request(ctx, test_id, test, &req_builder)
+        .await
+        .context("failed to prepare invoke request")?;
 
-    let response = client.instance()?.call(invoke_request).await?;
+    let steps = crate::steps::StepTable::new(&invoke_request);
+    let response = match client.instance()?.call(invoke_request).await {
+        Ok(response) => response,
+        // response size is dominated by solution output
+        Err(err) if invoker_client::ResponseTooLarge::is_cause_of(&err) => {
+            tracing::info!(
+                test_id = test_id.get(),
+                "invoker response is too large: {:#}",
+                err
+            );
+            return Ok(output_limit_exceeded(
+                Default::default(),
+                Default::default(),
+                None,
+                Vec::new(),
+            ));
+        }
+        Err(err) => return Err(err),
+    };
+    let usage = crate::invoke_usage(&response);
 
     tracing::debug!("parsing invoker response");
 
-    if let Some(dir) = &settings.checker_logs {
-        tracing::debug!("saving checker log");
-        tokio::fs::create_dir_all(&dir)
-            .await
-            .context("failed to create checker logs directory")?;
-        let checker_out_file = dir.join(test_id.get().to_string());
+    // built-in comparator does not produce logs
+    let checker_comment = if let JudgeStep::Checker(_) = step_ids.judge {
         let checker_logs = req_builder.read_output(&response, CHECKER_LOG).await?;
-        tokio::fs::write(checker_out_file, checker_logs).await?;
+        if let Some(dir) = &settings.checker_logs {
+            tracing::debug!("saving checker log");
+            tokio::fs::create_dir_all(&dir)
+                .await
+                .context("failed to create checker logs directory")?;
+            let checker_out_file = dir.join(test_id.get().to_string());
+            tokio::fs::write(checker_out_file, &checker_logs).await?;
+        }
+        Some(log_excerpt(&checker_logs, settings.normalize_logs))
+    } else {
+        None
+    };
+
+    let mut pipeline = Vec::new();
+    // first failed step of each phase
+    let mut failed_pre_test = None;
+    let mut failed_post_test = None;
+    for &(index, step_id) in &step_ids.pipeline {
+        let step = &ctx.problem_ext.pipeline[index];
+        let result = steps
+            .command_result(&response, step_id)
+            .with_context(|| format!("pipeline step {} did not run", step.name))?;
+        if result.spawn_error.is_some() || result.exit_code != 0 {
+            let failed = match step.phase {
+                problem_loader::PipelinePhase::PreTest => &mut failed_pre_test,
+                problem_loader::PipelinePhase::PostTest => &mut failed_post_test,
+            };
+            failed.get_or_insert((step.name.as_str(), result.exit_code));
+        }
+        let log = req_builder
+            .read_output(&response, &pipeline_log_file(index))
+            .await?;
+        pipeline.push(PipelineStepLog {
+            name: step.name.clone(),
+            exit_code: result.exit_code,
+            time_usage: result.cpu_time,
+            memory_usage: result.memory,
+            log: log_excerpt(&log, settings.normalize_logs),
+        });
     }
 
     let make_return_value_for_judge_fault = || {
@@ -433,39 +1129,286 @@ pub(crate) async fn exec(
                 code: status_codes::JUDGE_FAULT.to_string(),
             },
             resource_usage: Default::default(),
-            stdout: String::new(),
-            stderr: String::new(),
+            stdout: CapturedOutput::Inline(String::new()),
+            stderr: CapturedOutput::Inline(String::new()),
+            stdout_truncated: false,
+            stderr_truncated: false,
+            usage: usage.clone(),
+            memory_samples: None,
+            // comment of a step which failed would only expose judge
+            // internals; full checker log is kept in `checker_logs`
+            checker_comment: None,
+            raw_stdout: None,
+            pipeline: pipeline.clone(),
+            security_violations: Vec::new(),
+            invoker_steps: steps.rows(&response),
+            exit_code: None,
+            failure_reason: None,
+            attempts: Vec::new(),
         })
     };
 
-    let solution_command_result = {
-        let res = response
-            .actions
-            .get(step_ids.exec_solution)
-            .context("bug: invalid index")?;
-        match res {
-            ActionResult::ExecuteCommand(cmd) => cmd,
-            _ => anyhow::bail!("bug: unexpected action result for exec solution step"),
-        }
+    // solution did not get the test data it expects
+    if let Some((name, exit_code)) = failed_pre_test {
+        tracing::error!(
+            step = name,
+            "pipeline step returned non-zero: {}",
+            exit_code
+        );
+        return make_return_value_for_judge_fault();
+    }
+
+    let solution_command_result = steps
+        .command_result(&response, step_ids.exec_solution)
+        .context("solution did not run")?;
+    let termination_report = if step_ids.termination_report {
+        read_termination_report(&req_builder, &response, test_id).await
+    } else {
+        None
+    };
+    let solution_command_status = crate::describe_command_result(
+        &solution_limits(test),
+        solution_command_result,
+        termination_report.as_ref(),
+    );
+    let exit_code = if solution_command_result.spawn_error.is_none() {
+        Some(solution_command_result.exit_code)
+    } else {
+        None
+    };
+    let failure_reason = describe_failure(
+        &solution_limits(test),
+        solution_command_result,
+        solution_command_status,
+        termination_report.as_ref(),
+    );
+
+    let resource_usage = ResourceUsage {
+        memory: solution_command_result.memory,
+        time: solution_command_result.cpu_time,
+    };
+    let output_limit_outcome = || {
+        tracing::info!(test_id = test_id.get(), "solution output is too large");
+        Ok(output_limit_exceeded(
+            resource_usage,
+            usage.clone(),
+            exit_code,
+            pipeline.clone(),
+        ))
+    };
+
+    let (persisted_stdout, persisted_stderr) = match &step_ids.persisted_outputs {
+        Some((stdout, stderr)) => (Some(stdout.as_path()), Some(stderr.as_path())),
+        None => (None, None),
     };
+    let (solution_stdout, stdout_truncated) = match capture_output(
+        settings,
+        &req_builder,
+        &response,
+        EXEC_SOLUTION_OUTPUT_FILE,
+        persisted_stdout,
+    )
+    .await
+    {
+        Ok(captured) => captured,
+        Err(err) if OutputTooLarge::is_cause_of(&err) => return output_limit_outcome(),
+        Err(err) => return Err(err),
+    };
+    let (solution_stderr, stderr_truncated) = match capture_output(
+        settings,
+        &req_builder,
+        &response,
+        EXEC_SOLUTION_ERROR_FILE,
+        persisted_stderr,
+    )
+    .await
+    {
+        Ok(captured) => captured,
+        Err(err) if OutputTooLarge::is_cause_of(&err) => return output_limit_outcome(),
+        Err(err) => return Err(err),
+    };
+
+    let memory_samples = if step_ids.memory_samples {
+        read_memory_samples(&req_builder, &response, test_id).await
+    } else {
+        None
+    };
+    let security_violations = if step_ids.security_report {
+        read_security_violations(&req_builder, &response, test_id).await
+    } else {
+        Vec::new()
+    };
+
+    if ctx.keep_stdout {
+        let raw_stdout =
+            match read_limited_output(settings, &req_builder, &response, EXEC_SOLUTION_OUTPUT_FILE)
+                .await
+            {
+                // truncated output can not be used as is
+                Ok((_, true)) => return output_limit_outcome(),
+                Ok((data, false)) => data,
+                Err(err) if OutputTooLarge::is_cause_of(&err) => return output_limit_outcome(),
+                Err(err) => return Err(err),
+            };
+        let status = solution_status(solution_command_status);
+        return Ok(ExecOutcome {
+            status,
+            resource_usage,
+            stdout: solution_stdout,
+            stderr: solution_stderr,
+            stdout_truncated,
+            stderr_truncated,
+            usage,
+            memory_samples,
+            checker_comment: None,
+            raw_stdout: Some(raw_stdout),
+            pipeline: pipeline.clone(),
+            security_violations,
+            invoker_steps: Vec::new(),
+            exit_code,
+            failure_reason: failure_reason.clone(),
+            attempts: Vec::new(),
+        });
+    }
+
+    // output of a solution which broke the sandbox can not be trusted
+    if !security_violations.is_empty() {
+        tracing::warn!(
+            test_id = test_id.get(),
+            count = security_violations.len(),
+            "solution violated sandbox restrictions"
+        );
+        return Ok(ExecOutcome {
+            status: Status {
+                kind: StatusKind::Rejected,
+                code: judge_log::SECURITY_VIOLATION_STATUS_CODE.to_string(),
+            },
+            resource_usage,
+            stdout: solution_stdout,
+            stderr: solution_stderr,
+            stdout_truncated,
+            stderr_truncated,
+            usage,
+            memory_samples,
+            checker_comment: None,
+            raw_stdout: None,
+            pipeline: pipeline.clone(),
+            security_violations,
+            invoker_steps: Vec::new(),
+            exit_code,
+            failure_reason: failure_reason.clone(),
+            attempts: Vec::new(),
+        });
+    }
+
+    // output of a killed solution is meaningless, so checker is not consulted
+    if let crate::CommandStatus::ProcessLimit = solution_command_status {
+        tracing::info!(test_id = test_id.get(), "solution exceeded process limit");
+        return Ok(ExecOutcome {
+            status: Status {
+                kind: StatusKind::Rejected,
+                code: judge_apis::judge_log::PROCESS_LIMIT_STATUS_CODE.to_string(),
+            },
+            resource_usage,
+            stdout: solution_stdout,
+            stderr: solution_stderr,
+            stdout_truncated,
+            stderr_truncated,
+            usage,
+            memory_samples,
+            checker_comment: None,
+            raw_stdout: None,
+            pipeline: pipeline.clone(),
+            security_violations,
+            invoker_steps: Vec::new(),
+            exit_code,
+            failure_reason: failure_reason.clone(),
+            attempts: Vec::new(),
+        });
+    }
 
-    let solution_stdout = req_builder
-        .read_output(&response, EXEC_SOLUTION_OUTPUT_FILE)
-        .await?;
-    let solution_stderr = req_builder
-        .read_output(&response, EXEC_SOLUTION_ERROR_FILE)
-        .await?;
-
-    let checker_command_result = {
-        let res = response
-            .actions
-            .get(step_ids.exec_checker)
-            .context("bug: invalid index")?;
-        match res {
-            ActionResult::ExecuteCommand(cmd) => cmd,
-            _ => anyhow::bail!("bug: unexpected action result for exec checker step"),
+    // post-test steps process solution output, so their failure means
+    // that the output is malformed, not that the judge is broken
+    if let Some((name, step_exit_code)) = failed_post_test {
+        tracing::info!(
+            test_id = test_id.get(),
+            step = name,
+            "pipeline step rejected solution output: {}",
+            step_exit_code
+        );
+        let status = match solution_command_status {
+            crate::CommandStatus::Ok => Status {
+                kind: StatusKind::Rejected,
+                code: status_codes::PRESENTATION_ERROR.to_string(),
+            },
+            other => solution_status(other),
+        };
+        return Ok(ExecOutcome {
+            status,
+            resource_usage,
+            stdout: solution_stdout,
+            stderr: solution_stderr,
+            stdout_truncated,
+            stderr_truncated,
+            usage,
+            memory_samples,
+            checker_comment: Some(format!(
+                "pipeline step {} exited with code {}",
+                name, step_exit_code
+            )),
+            raw_stdout: None,
+            pipeline,
+            security_violations,
+            invoker_steps: Vec::new(),
+            exit_code,
+            failure_reason,
+            attempts: Vec::new(),
+        });
+    }
+
+    let exec_checker = match &step_ids.judge {
+        JudgeStep::Checker(step_id) => *step_id,
+        JudgeStep::Comparator(output_name) => {
+            let output = match req_builder.read_output(&response, output_name).await {
+                Ok(output) => output,
+                Err(err) if OutputTooLarge::is_cause_of(&err) => return output_limit_outcome(),
+                Err(err) => return Err(err),
+            };
+            let comparison = compare_with_answer(ctx, test_id, test, output).await?;
+            let status = if comparison.matches {
+                Status {
+                    kind: StatusKind::Accepted,
+                    code: status_codes::TEST_PASSED.to_string(),
+                }
+            } else {
+                Status {
+                    kind: StatusKind::Rejected,
+                    code: status_codes::WRONG_ANSWER.to_string(),
+                }
+            };
+            return Ok(ExecOutcome {
+                status,
+                resource_usage,
+                stdout: solution_stdout,
+                stderr: solution_stderr,
+                stdout_truncated,
+                stderr_truncated,
+                usage,
+                memory_samples,
+                checker_comment: Some(comparison.comment),
+                raw_stdout: None,
+                pipeline,
+                security_violations,
+                invoker_steps: Vec::new(),
+                exit_code,
+                failure_reason,
+                attempts: Vec::new(),
+            });
         }
     };
+    let checker_command_result = steps
+        .command_result(&response, exec_checker)
+        .context("checker did not run")?;
 
     let checker_success = checker_command_result.exit_code == 0;
     if !checker_success {
@@ -495,15 +1438,368 @@ pub(crate) async fn exec(
 
     let status = map_checker_outcome_to_status(parsed_out);
 
-    let resource_usage = ResourceUsage {
-        memory: solution_command_result.memory,
-        time: solution_command_result.cpu_time,
-    };
-
     Ok(ExecOutcome {
         status,
         resource_usage,
-        stdout: String::from_utf8_lossy(&solution_stdout).into_owned(),
-        stderr: String::from_utf8_lossy(&solution_stderr).into_owned(),
+        stdout: solution_stdout,
+        stderr: solution_stderr,
+        stdout_truncated,
+        stderr_truncated,
+        usage,
+        memory_samples,
+        checker_comment,
+        raw_stdout: None,
+        pipeline,
+        security_violations,
+        invoker_steps: Vec::new(),
+        exit_code,
+        failure_reason,
+        attempts: Vec::new(),
     })
 }
+
+/// Compares solution output with the correct answer of the test using
+/// built-in comparator of the problem. Answers are bounded by the same
+/// limit as outputs received from invoker.
+async fn compare_with_answer(
+    ctx: &ExecContext<'_>,
+    test_id: pom::TestId,
+    test: &pom::Test,
+    output: Vec<u8>,
+) -> anyhow::Result<crate::comparator::Comparison> {
+    let comparator = ctx
+        .problem_ext
+        .comparator
+        .clone()
+        .context("problem has no comparator")?;
+    let answer_ref = test.correct.as_ref().with_context(|| {
+        format!(
+            "test {} has no correct answer, which built-in comparator requires",
+            test_id
+        )
+    })?;
+    let answer_path = ctx.file_ref_resolver.resolve_asset(answer_ref);
+    let max_size = ctx.settings.max_invoker_output_size;
+    let mut answer = Vec::new();
+    tokio::fs::File::open(&answer_path)
+        .await
+        .with_context(|| format!("failed to open {}", answer_path.display()))?
+        .take(max_size + 1)
+        .read_to_end(&mut answer)
+        .await
+        .with_context(|| format!("failed to read {}", answer_path.display()))?;
+    if answer.len() as u64 > max_size {
+        anyhow::bail!(
+            "correct answer of test {} is larger than {} bytes",
+            test_id,
+            max_size
+        );
+    }
+    tokio::task::spawn_blocking(move || crate::comparator::compare(&comparator, &output, &answer))
+        .await
+        .context("comparator panicked")
+}
+
+/// Human-readable reason of the solution failure, None if the solution
+/// finished successfully
+fn describe_failure(
+    limits: &Limits,
+    result: &CommandResult,
+    status: crate::CommandStatus,
+    report: Option<&TerminationReport>,
+) -> Option<String> {
+    let reason = match status {
+        crate::CommandStatus::Ok => return None,
+        crate::CommandStatus::Startup => format!(
+            "failed to start: {}",
+            result.spawn_error.as_deref().unwrap_or("unknown error")
+        ),
+        crate::CommandStatus::TimeLimit => format!(
+            "time limit exceeded (used {} ms of {} ms)",
+            result.cpu_time.unwrap_or(0) / 1_000_000,
+            limits.time
+        ),
+        crate::CommandStatus::MemLimit => format!(
+            "memory limit exceeded (used {} bytes of {} bytes)",
+            result.memory.unwrap_or(0),
+            limits.memory
+        ),
+        crate::CommandStatus::ProcessLimit => "killed after exceeding process limit".to_string(),
+        crate::CommandStatus::Runtime => match signal(result.exit_code, report) {
+            Some(signal) => format!("killed by signal {}", signal),
+            None => format!("exited with code {}", result.exit_code),
+        },
+    };
+    Some(reason)
+}
+
+/// Signal which killed the command. Invokers which send termination
+/// report name the signal there. Older invokers report it either as a
+/// negative exit code or as `128 + signal`, like shells do, which can not
+/// be told apart from such exit codes.
+fn signal(exit_code: i64, report: Option<&TerminationReport>) -> Option<i64> {
+    if let Some(report) = report {
+        return report.signal;
+    }
+    match exit_code {
+        -64..=-1 => Some(-exit_code),
+        129..=192 => Some(exit_code - 128),
+        _ => None,
+    }
+}
+
+/// Describes outcome of the solution alone, without checker decision
+fn solution_status(status: crate::CommandStatus) -> Status {
+    let (kind, code) = match status {
+        crate::CommandStatus::Ok => (StatusKind::Accepted, status_codes::TEST_PASSED),
+        crate::CommandStatus::TimeLimit => {
+            (StatusKind::Rejected, status_codes::TIME_LIMIT_EXCEEDED)
+        }
+        crate::CommandStatus::MemLimit => {
+            (StatusKind::Rejected, status_codes::MEMORY_LIMIT_EXCEEDED)
+        }
+        crate::CommandStatus::ProcessLimit => (
+            StatusKind::Rejected,
+            judge_apis::judge_log::PROCESS_LIMIT_STATUS_CODE,
+        ),
+        crate::CommandStatus::Startup => (StatusKind::Rejected, status_codes::LAUNCH_ERROR),
+        crate::CommandStatus::Runtime => (StatusKind::Rejected, status_codes::RUNTIME_ERROR),
+    };
+    Status {
+        kind,
+        code: code.to_string(),
+    }
+}
+
+/// Converts checker (or pipeline step) log to text which fits into the
+/// judge log
+fn log_excerpt(log: &[u8], normalize: bool) -> String {
+    let mut comment = crate::log_text::decode(log, normalize);
+    let mut end = comment.len().min(judge_log::MAX_CHECKER_COMMENT_SIZE);
+    // truncation must not split a character
+    while !comment.is_char_boundary(end) {
+        end -= 1;
+    }
+    comment.truncate(end);
+    comment
+}
+
+/// Parses memory samples produced by invoker. Samples are optional, so
+/// errors are logged instead of failing the test.
+async fn read_memory_samples(
+    req_builder: &crate::request_builder::RequestBuilder,
+    response: &InvokeResponse,
+    test_id: pom::TestId,
+) -> Option<Vec<MemorySample>> {
+    let data = match req_builder.read_output(response, MEMORY_SAMPLES_FILE).await {
+        Ok(data) => data,
+        Err(err) => {
+            tracing::warn!(
+                test_id = test_id.get(),
+                "memory samples are missing: {:#}",
+                err
+            );
+            return None;
+        }
+    };
+    if data.len() > MAX_MEMORY_SAMPLES_FILE_SIZE {
+        tracing::warn!(
+            test_id = test_id.get(),
+            size = data.len(),
+            "memory samples are too large, ignoring"
+        );
+        return None;
+    }
+    let mut samples: Vec<MemorySample> = match serde_json::from_slice(&data) {
+        Ok(s) => s,
+        Err(err) => {
+            tracing::warn!(test_id = test_id.get(), "invalid memory samples: {}", err);
+            return None;
+        }
+    };
+    samples.sort_by_key(|s| s.time);
+    Some(downsample_memory(&samples, MAX_MEMORY_SAMPLES))
+}
+
+/// Parses security report produced by invoker. Report which can not be
+/// parsed still means that solution did something wrong, so it results
+/// in a single violation without details.
+async fn read_security_violations(
+    req_builder: &crate::request_builder::RequestBuilder,
+    response: &InvokeResponse,
+    test_id: pom::TestId,
+) -> Vec<SecurityViolation> {
+    let data = match req_builder
+        .read_output(response, SECURITY_REPORT_FILE)
+        .await
+    {
+        Ok(data) => data,
+        Err(err) => {
+            tracing::warn!(
+                test_id = test_id.get(),
+                "security report is missing: {:#}",
+                err
+            );
+            return Vec::new();
+        }
+    };
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let unparsed = |detail: String| {
+        vec![SecurityViolation {
+            kind: "unknown".to_string(),
+            detail,
+        }]
+    };
+    if data.len() > MAX_SECURITY_REPORT_FILE_SIZE {
+        tracing::warn!(
+            test_id = test_id.get(),
+            size = data.len(),
+            "security report is too large"
+        );
+        return unparsed(format!("report of {} bytes was not parsed", data.len()));
+    }
+    let mut violations: Vec<SecurityViolation> = match serde_json::from_slice(&data) {
+        Ok(v) => v,
+        Err(err) => {
+            tracing::warn!(test_id = test_id.get(), "invalid security report: {}", err);
+            return unparsed(format!("invalid report: {}", err));
+        }
+    };
+    violations.truncate(MAX_SECURITY_VIOLATIONS);
+    violations
+}
+
+/// Outcome of a test on which solution output was too large to receive
+fn output_limit_exceeded(
+    resource_usage: ResourceUsage,
+    usage: Usage,
+    exit_code: Option<i64>,
+    pipeline: Vec<PipelineStepLog>,
+) -> ExecOutcome {
+    ExecOutcome {
+        status: Status {
+            kind: StatusKind::Rejected,
+            code: judge_log::OUTPUT_LIMIT_STATUS_CODE.to_string(),
+        },
+        resource_usage,
+        stdout: CapturedOutput::Inline(String::new()),
+        stderr: CapturedOutput::Inline(String::new()),
+        stdout_truncated: true,
+        stderr_truncated: false,
+        usage,
+        memory_samples: None,
+        checker_comment: None,
+        raw_stdout: None,
+        pipeline,
+        security_violations: Vec::new(),
+        invoker_steps: Vec::new(),
+        exit_code,
+        failure_reason: Some("output limit exceeded".to_string()),
+        attempts: Vec::new(),
+    }
+}
+
+async fn read_termination_report(
+    req_builder: &crate::request_builder::RequestBuilder,
+    response: &InvokeResponse,
+    test_id: pom::TestId,
+) -> Option<TerminationReport> {
+    let data = match req_builder
+        .read_output(response, TERMINATION_REPORT_FILE)
+        .await
+    {
+        Ok(data) => data,
+        Err(err) => {
+            tracing::warn!(
+                test_id = test_id.get(),
+                "termination report is missing: {:#}",
+                err
+            );
+            return None;
+        }
+    };
+    if data.len() > MAX_TERMINATION_REPORT_FILE_SIZE {
+        tracing::warn!(
+            test_id = test_id.get(),
+            size = data.len(),
+            "termination report is too large"
+        );
+        return None;
+    }
+    match serde_json::from_slice(&data) {
+        Ok(report) => Some(report),
+        Err(err) => {
+            tracing::warn!(
+                test_id = test_id.get(),
+                "invalid termination report: {}",
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Reads output returned by invoker, truncating it to the output size
+/// limit. Returns true if output was truncated.
+async fn read_limited_output(
+    settings: &crate::Settings,
+    req_builder: &crate::request_builder::RequestBuilder,
+    response: &InvokeResponse,
+    output_name: &str,
+) -> anyhow::Result<(Vec<u8>, bool)> {
+    // without `output-truncation` invoker returns whole output, so only
+    // the kept part of it is decoded
+    match settings.max_output_size {
+        Some(limit) => {
+            req_builder
+                .read_output_prefix(response, output_name, limit)
+                .await
+        }
+        None => Ok((req_builder.read_output(response, output_name).await?, false)),
+    }
+}
+
+/// Moves solution output to the output store if it is configured.
+/// `persisted` is path to the output if invoker kept it instead of returning.
+/// Also returns true if output was truncated.
+async fn capture_output(
+    settings: &crate::Settings,
+    req_builder: &crate::request_builder::RequestBuilder,
+    response: &InvokeResponse,
+    output_name: &str,
+    persisted: Option<&Path>,
+) -> anyhow::Result<(CapturedOutput, bool)> {
+    let store = match &settings.output_store {
+        Some(store) => store,
+        None => {
+            let (data, truncated) =
+                read_limited_output(settings, req_builder, response, output_name).await?;
+            return Ok((
+                CapturedOutput::Inline(String::from_utf8_lossy(&data).into_owned()),
+                truncated,
+            ));
+        }
+    };
+    let (id, truncated) = match persisted {
+        Some(path) => {
+            let size = tokio::fs::metadata(path)
+                .await
+                .with_context(|| format!("failed to stat persisted output {}", path.display()))?
+                .len();
+            let limit = settings.max_output_size;
+            let id = store.put_file(path, limit).await?;
+            if let Err(err) = tokio::fs::remove_file(path).await {
+                tracing::warn!(path = %path.display(), "failed to remove persisted output: {:#}", err);
+            }
+            (id, matches!(limit, Some(limit) if size > limit))
+        }
+        None => {
+            let (data, truncated) =
+                read_limited_output(settings, req_builder, response, output_name).await?;
+            (store.put_bytes(data).await?, truncated)
+        }
+    };
+    Ok((CapturedOutput::Stored(id), truncated))
+}
@@ -0,0 +1,285 @@
+//! Construction of invoker API extensions.
+//!
+//! Invoker silently ignores extensions it does not understand, so all
+//! extensions are built here: each one is validated and checked against
+//! capabilities reported by the invoker before it is sent.
+use anyhow::Context;
+use invoker_api::{
+    invoke::Extensions,
+    shim::{RequestExtensions, SandboxSettingsExtensions, SharedDirExtensionSource},
+};
+use invoker_client::{
+    Capabilities, CommandExtensions, MemorySamplingExtension, OutputExtensions,
+    PersistOutputExtension, SandboxExtensions, SecurityReportExtension, TerminationReportExtension,
+};
+use serde::Serialize;
+use std::path::{Component, Path};
+
+/// Optional invoker feature
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Feature {
+    /// Outputs can be kept in the persistent files directory
+    PersistentFiles,
+    /// Sandboxes can be given network access
+    SandboxNetwork,
+    /// Sandboxes can be kept and reused by later requests
+    SandboxReuse,
+    /// Memory usage of commands can be sampled
+    MemorySampling,
+    /// Resources which outlive requests can be destroyed on demand
+    ResourceRelease,
+    /// Only first bytes of outputs can be returned
+    OutputTruncation,
+    /// Sandbox violations of commands can be reported
+    SecurityReport,
+    /// Sandbox file system can be made read-only and core dumps can be
+    /// switched
+    SandboxHardening,
+    /// Cause of command termination can be reported
+    TerminationReport,
+}
+
+impl Feature {
+    fn name(self) -> &'static str {
+        match self {
+            Feature::PersistentFiles => "persistent-files",
+            Feature::SandboxNetwork => "sandbox-network",
+            Feature::SandboxReuse => "sandbox-reuse",
+            Feature::MemorySampling => "memory-sampling",
+            Feature::ResourceRelease => "resource-release",
+            Feature::OutputTruncation => "output-truncation",
+            Feature::SecurityReport => "security-report",
+            Feature::SandboxHardening => "sandbox-hardening",
+            Feature::TerminationReport => "termination-report",
+        }
+    }
+
+    fn is_supported(self, caps: &Capabilities) -> bool {
+        match self {
+            Feature::PersistentFiles => caps.persistent_files_dir.is_some(),
+            Feature::SandboxNetwork
+            | Feature::SandboxReuse
+            | Feature::MemorySampling
+            | Feature::ResourceRelease
+            | Feature::OutputTruncation
+            | Feature::SecurityReport
+            | Feature::SandboxHardening
+            | Feature::TerminationReport => caps.extensions.iter().any(|ext| ext == self.name()),
+        }
+    }
+}
+
+/// Extension which can be sent to invoker
+pub(crate) trait InvokerExtension: Serialize {
+    /// Human-readable name used in errors
+    const NAME: &'static str;
+
+    /// Features which invoker must support to understand this value
+    fn required_features(&self) -> Vec<Feature> {
+        Vec::new()
+    }
+
+    /// Checks that value is meaningful
+    fn validate(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+fn validate_relative_path(path: &Path) -> anyhow::Result<()> {
+    let ok = path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !ok {
+        anyhow::bail!(
+            "path {} must be relative and must not contain `..`",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+impl InvokerExtension for RequestExtensions {
+    const NAME: &'static str = "request";
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for name in self.extra_files.keys() {
+            validate_relative_path(Path::new(name))
+                .with_context(|| format!("invalid extra file {}", name))?;
+        }
+        Ok(())
+    }
+}
+
+impl InvokerExtension for SandboxSettingsExtensions {
+    const NAME: &'static str = "sandbox-settings";
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.image.is_empty() {
+            anyhow::bail!("sandbox image is not set");
+        }
+        Ok(())
+    }
+}
+
+impl InvokerExtension for SandboxExtensions {
+    const NAME: &'static str = "sandbox-settings";
+
+    fn required_features(&self) -> Vec<Feature> {
+        let mut features = Vec::new();
+        if self.network {
+            features.push(Feature::SandboxNetwork);
+        }
+        if self.reuse_key.is_some() {
+            features.push(Feature::SandboxReuse);
+        }
+        if self.writable_paths.is_some() || self.core_dumps.is_some() {
+            features.push(Feature::SandboxHardening);
+        }
+        features
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        self.shim.validate()
+    }
+}
+
+impl InvokerExtension for SharedDirExtensionSource {
+    const NAME: &'static str = "shared-dir-source";
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.name.is_empty() {
+            anyhow::bail!("shared directory name is empty");
+        }
+        Ok(())
+    }
+}
+
+impl InvokerExtension for PersistOutputExtension {
+    const NAME: &'static str = "persist-output";
+
+    fn required_features(&self) -> Vec<Feature> {
+        vec![Feature::PersistentFiles]
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut components = Path::new(&self.persist_as).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => Ok(()),
+            _ => anyhow::bail!("{} is not a plain file name", self.persist_as),
+        }
+    }
+}
+
+impl InvokerExtension for OutputExtensions {
+    const NAME: &'static str = "output";
+
+    fn required_features(&self) -> Vec<Feature> {
+        let mut features = Vec::new();
+        if let Some(persist) = &self.persist {
+            features.extend(persist.required_features());
+        }
+        if self.max_size.is_some() {
+            features.push(Feature::OutputTruncation);
+        }
+        features
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        match &self.persist {
+            Some(persist) => persist.validate(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl InvokerExtension for MemorySamplingExtension {
+    const NAME: &'static str = "memory-sampling";
+
+    fn required_features(&self) -> Vec<Feature> {
+        vec![Feature::MemorySampling]
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.interval_ms == 0 {
+            anyhow::bail!("sampling interval must be positive");
+        }
+        Ok(())
+    }
+}
+
+impl InvokerExtension for SecurityReportExtension {
+    const NAME: &'static str = "security-report";
+
+    fn required_features(&self) -> Vec<Feature> {
+        vec![Feature::SecurityReport]
+    }
+}
+
+impl InvokerExtension for TerminationReportExtension {
+    const NAME: &'static str = "termination-report";
+
+    fn required_features(&self) -> Vec<Feature> {
+        vec![Feature::TerminationReport]
+    }
+}
+
+impl InvokerExtension for CommandExtensions {
+    const NAME: &'static str = "command";
+
+    fn required_features(&self) -> Vec<Feature> {
+        let mut features = Vec::new();
+        if let Some(sampling) = &self.memory_sampling {
+            features.extend(sampling.required_features());
+        }
+        if let Some(report) = &self.security_report {
+            features.extend(report.required_features());
+        }
+        if let Some(report) = &self.termination_report {
+            features.extend(report.required_features());
+        }
+        features
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if let Some(sampling) = &self.memory_sampling {
+            sampling.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds extensions supported by the particular invoker
+pub(crate) struct ExtensionBuilder<'a> {
+    caps: &'a Capabilities,
+}
+
+impl<'a> ExtensionBuilder<'a> {
+    pub(crate) fn new(caps: &'a Capabilities) -> Self {
+        ExtensionBuilder { caps }
+    }
+
+    /// Returns true if invoker supports `feature`
+    pub(crate) fn supports(&self, feature: Feature) -> bool {
+        feature.is_supported(self.caps)
+    }
+
+    /// Directory where invoker keeps persisted outputs, if supported
+    pub(crate) fn persistent_files_dir(&self) -> Option<&Path> {
+        self.caps.persistent_files_dir.as_deref()
+    }
+
+    pub(crate) fn make<E: InvokerExtension>(&self, ext: E) -> anyhow::Result<Extensions> {
+        ext.validate()
+            .with_context(|| format!("invalid {} extension", E::NAME))?;
+        for feature in ext.required_features() {
+            if !self.supports(feature) {
+                anyhow::bail!(
+                    "{} extension requires invoker feature `{}`, which invoker does not support",
+                    E::NAME,
+                    feature.name()
+                );
+            }
+        }
+        Extensions::make(ext).with_context(|| format!("failed to serialize {} extension", E::NAME))
+    }
+}
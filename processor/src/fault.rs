@@ -0,0 +1,147 @@
+//! Classification of judge faults.
+//!
+//! Faults are reported as `anyhow` chains, which are hard to triage at
+//! scale. Common chains are recognized here and mapped to a category with
+//! a hint for operators.
+
+use std::{any::Any, fmt};
+
+/// Patterns are checked in order, against each error of the chain
+const PATTERNS: &[(&str, FaultCategory)] = &[
+    ("aborted by watchdog", FaultCategory::JobStalled),
+    ("problem not found", FaultCategory::ProblemNotFound),
+    ("failed to find toolchain", FaultCategory::ToolchainNotFound),
+    (
+        "failed to parse valuer message",
+        FaultCategory::ValuerProtocol,
+    ),
+    (
+        "valuer response timed out",
+        FaultCategory::ValuerUnresponsive,
+    ),
+    ("early eof", FaultCategory::ValuerCrashed),
+    ("failed to initialize valuer", FaultCategory::ValuerCrashed),
+    ("server error", FaultCategory::InvokerServerError),
+    ("failed to send request", FaultCategory::InvokerUnreachable),
+];
+
+/// Kind of a judge fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultCategory {
+    ProblemNotFound,
+    ProblemAssetMissing,
+    ToolchainNotFound,
+    InvokerTimeout,
+    InvokerServerError,
+    InvokerUnreachable,
+    ValuerProtocol,
+    ValuerUnresponsive,
+    ValuerCrashed,
+    JobStalled,
+    TaskPanicked,
+    Unknown,
+}
+
+impl FaultCategory {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FaultCategory::ProblemNotFound => "problem-not-found",
+            FaultCategory::ProblemAssetMissing => "problem-asset-missing",
+            FaultCategory::ToolchainNotFound => "toolchain-not-found",
+            FaultCategory::InvokerTimeout => "invoker-timeout",
+            FaultCategory::InvokerServerError => "invoker-server-error",
+            FaultCategory::InvokerUnreachable => "invoker-unreachable",
+            FaultCategory::ValuerProtocol => "valuer-protocol",
+            FaultCategory::ValuerUnresponsive => "valuer-unresponsive",
+            FaultCategory::ValuerCrashed => "valuer-crashed",
+            FaultCategory::JobStalled => "job-stalled",
+            FaultCategory::TaskPanicked => "task-panicked",
+            FaultCategory::Unknown => "unknown",
+        }
+    }
+
+    /// Suggested remediation
+    pub fn hint(self) -> &'static str {
+        match self {
+            FaultCategory::ProblemNotFound => {
+                "check that the problem is uploaded and problem sources are configured"
+            }
+            FaultCategory::ProblemAssetMissing => {
+                "problem package refers to a file which does not exist; re-upload or fix the package"
+            }
+            FaultCategory::ToolchainNotFound => {
+                "check toolchain name and the toolchains directory"
+            }
+            FaultCategory::InvokerTimeout => {
+                "invoker is overloaded or the run is too slow; consider raising --invoker-request-timeout"
+            }
+            FaultCategory::InvokerServerError => "inspect invoker logs for the failed request",
+            FaultCategory::InvokerUnreachable => "check that invoker is running and --invoker is correct",
+            FaultCategory::ValuerProtocol => {
+                "valuer produced a malformed message; check valuer version compatibility"
+            }
+            FaultCategory::ValuerUnresponsive => "valuer stopped responding; inspect valuer stderr",
+            FaultCategory::ValuerCrashed => "valuer exited unexpectedly; inspect valuer stderr",
+            FaultCategory::JobStalled => "job made no progress; check invoker and valuer health",
+            FaultCategory::TaskPanicked => {
+                "judge hit a bug; report the panic message and the error chain"
+            }
+            FaultCategory::Unknown => "inspect the error chain",
+        }
+    }
+
+    /// Determines category of a judge fault
+    pub fn classify(err: &anyhow::Error) -> FaultCategory {
+        if err.chain().any(|e| e.is::<TaskPanicked>()) {
+            return FaultCategory::TaskPanicked;
+        }
+        if invoker_client::TimeoutError::is_cause_of(err) {
+            return FaultCategory::InvokerTimeout;
+        }
+        let messages: Vec<String> = err.chain().map(|e| e.to_string()).collect();
+        for (pattern, category) in PATTERNS {
+            if messages.iter().any(|msg| msg.contains(pattern)) {
+                return *category;
+            }
+        }
+        let file_missing = err.chain().any(|e| {
+            matches!(
+                e.downcast_ref::<std::io::Error>(),
+                Some(e) if e.kind() == std::io::ErrorKind::NotFound
+            )
+        });
+        if file_missing {
+            return FaultCategory::ProblemAssetMissing;
+        }
+        FaultCategory::Unknown
+    }
+}
+
+/// Error describing a panic of a judge task
+#[derive(Debug)]
+pub struct TaskPanicked {
+    message: String,
+}
+
+impl TaskPanicked {
+    /// Extracts panic message from the payload returned by `catch_unwind`
+    /// or `JoinError::into_panic`
+    pub fn from_payload(payload: Box<dyn Any + Send>) -> TaskPanicked {
+        let message = match payload.downcast::<String>() {
+            Ok(s) => *s,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(s) => s.to_string(),
+                Err(_) => "<non-string payload>".to_string(),
+            },
+        };
+        TaskPanicked { message }
+    }
+}
+
+impl fmt::Display for TaskPanicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task panicked: {}", self.message)
+    }
+}
+
+impl std::error::Error for TaskPanicked {}
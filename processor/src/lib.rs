@@ -1,29 +1,84 @@
 //! Processor is part of judge that deals with a single run (and it doesn't
 //! care where have it come from).
+//!
+//! It can be embedded into other programs: create `Clients` with
+//! `Clients::builder()`, `Settings` with `Settings::new()`, then call
+//! `judge` and consume events from the returned `JobProgress`.
+//! See `examples/embed.rs`.
 
+mod answers;
+mod artifact_cache;
+mod comparator;
 mod compile;
+mod cost;
+mod debug_dump;
 mod exec_test;
+mod extensions;
+mod fault;
+mod log_text;
+mod output_store;
+mod replay;
 mod request_builder;
+mod rerun;
+mod resources;
+mod revalue;
+mod security;
+mod steps;
+mod syntax_check;
+mod trace;
 mod transform_judge_log;
+mod valuer_session;
+mod warnings;
+
+pub use answers::{generate_answers, AnswersRequest, GeneratedAnswers};
+pub use artifact_cache::ArtifactCache;
+pub use cost::estimate_cost;
+pub use fault::{FaultCategory, TaskPanicked};
+pub use output_store::OutputStore;
+pub use replay::{replay, JobRecording};
+pub use request_builder::SharedInputs;
+pub use revalue::revalue;
+pub use syntax_check::{syntax_check, SyntaxCheckOutcome, SYNTAX_CHECK_TIME_LIMIT};
+pub use trace::{FileTraceSink, StdoutTraceSink, Trace, TraceSink};
+pub use warnings::Warnings;
+
+// dependencies which appear in the public API, so that embedders do not
+// have to keep their versions in sync
+pub use invoker_client;
+pub use judge_apis;
+pub use judge_apis::{
+    judge_log::{JudgeLog, JudgeLogKind, Status, StatusKind},
+    rest::JudgingMode,
+    usage::Usage,
+};
+pub use problem_loader;
+pub use toolchain_loader;
 
 use anyhow::Context;
-use invoker_api::invoke::{CommandResult, Limits};
-use judge_apis::judge_log::JudgeLog;
+use debug_dump::DebugDump;
+use extensions::{ExtensionBuilder, Feature};
+use futures::{future::FutureExt, stream::StreamExt};
+use invoker_api::invoke::{ActionResult, CommandResult, InvokeResponse, Limits};
 use pom::Valuer;
+use resources::ResourceTracker;
 use std::{
     borrow::Cow,
+    collections::BTreeMap,
+    panic::AssertUnwindSafe,
     path::{Path, PathBuf},
     sync::Arc,
+    time::{Duration, Instant},
 };
 use tokio::sync::{mpsc, oneshot};
+use trace::{JobTracer, TraceRecord};
 use tracing::Instrument;
-use valuer_api::{
-    status_codes, JudgeLogKind, ProblemInfo, Status, StatusKind, TestDoneNotification,
-    ValuerResponse,
-};
-use valuer_client::{ChildClientConfig, ClientConfig};
+use valuer_api::{status_codes, ValuerResponse};
+use valuer_client::{ChildClientConfig, ClientConfig, HttpClientConfig};
+use valuer_session::{Polled, ValuerSession};
 
-/// Single judging request
+/// Single judging request. New fields can be added in any release, so
+/// requests are created with [`Request::new`] and adjusted afterwards.
+#[non_exhaustive]
 pub struct Request {
     /// Toolchain name (will be passed to toolchain loader)
     pub toolchain_name: String,
@@ -31,17 +86,175 @@ pub struct Request {
     pub problem_id: String,
     /// Run source
     pub run_source: Vec<u8>,
+    /// Judging phase (e.g. pretests). It is passed to the valuer, which
+    /// decides what tests belong to it, and recorded in produced logs.
+    pub phase: Option<String>,
+    /// Result of a previous phase. If set, run is not compiled again,
+    /// unless its persisted artifacts are not available anymore and
+    /// `run_source` is not empty.
+    pub compiled: Option<CompiledRun>,
+    /// Sandbox image used instead of the toolchain image, both for
+    /// compilation and for solution runs. Intended for experiments with
+    /// patched toolchains; recorded in full judge logs.
+    pub image_override: Option<String>,
+    /// Problem revision to judge against. If not set, the latest revision
+    /// is loaded.
+    pub problem: Option<problem_loader::LoadedProblem>,
+    /// Passed to the valuer, which decides what tests to run
+    pub judging_mode: JudgingMode,
+    /// Compilation and tests only run on invoker pools which have all
+    /// these labels
+    pub invoker_labels: invoker_client::Labels,
+    /// If set, valuer messages, invoke requests with their responses and
+    /// judge logs are dumped to this directory for troubleshooting
+    pub debug_dump_dir: Option<PathBuf>,
 }
 
-/// Part of response stream
+impl Request {
+    /// Creates request which compiles `run_source` and judges it on the
+    /// latest revision of the problem, in the default mode, on any invoker
+    pub fn new(
+        problem_id: impl Into<String>,
+        toolchain_name: impl Into<String>,
+        run_source: Vec<u8>,
+    ) -> Request {
+        Request {
+            toolchain_name: toolchain_name.into(),
+            problem_id: problem_id.into(),
+            run_source,
+            phase: None,
+            compiled: None,
+            image_override: None,
+            problem: None,
+            judging_mode: JudgingMode::Default,
+            invoker_labels: Default::default(),
+            debug_dump_dir: None,
+        }
+    }
+}
+
+/// Successfully compiled run, which can be reused by later judging phases
+#[derive(Clone)]
+pub struct CompiledRun {
+    built: compile::BuiltRun,
+    log: String,
+}
+
+impl CompiledRun {
+    /// Checks that artifacts persisted by invoker are still available, and
+    /// pins the job to the pool which keeps them
+    fn check_reusable(
+        &self,
+        caps: &invoker_client::Capabilities,
+        invokers: &invoker_client::Client,
+    ) -> anyhow::Result<()> {
+        for (path, artifact) in &self.built.files {
+            if let compile::Artifact::Persistent(file) = artifact {
+                if file.parent() != caps.persistent_files_dir.as_deref() {
+                    anyhow::bail!(
+                        "artifact {} was persisted in {}, which invoker does not keep anymore",
+                        path.display(),
+                        file.parent().unwrap_or(file).display()
+                    );
+                }
+            }
+        }
+        if let Some(pool) = self.built.pool() {
+            invokers
+                .pin_to(pool)
+                .context("invoker pool which keeps artifacts of the run can not be used")?;
+        }
+        Ok(())
+    }
+}
+
+/// Event together with its position in the response stream.
+///
+/// Events of a job are delivered in the order they were produced.
+/// Sequence numbers start at 1 and increase by one with each event, so
+/// a gap means that consumer lost some events.
+pub struct SequencedEvent {
+    pub seq: u64,
+    pub event: Event,
+}
+
+/// Part of response stream. New variants can be added in future, so
+/// consumers must ignore unknown events.
+///
+/// `LiveTest` for a test is produced before `TestFinished` of the same
+/// test. `LiveScore` and `LogCreated` events follow the order in which
+/// valuer emitted them, so consumer which applies events in sequence order
+/// never shows live status older than the logs it already has.
+#[non_exhaustive]
 pub enum Event {
     /// A judge log has been created.
     /// Sent at most once per each judge log king.
     LogCreated(judge_apis::judge_log::JudgeLog),
     /// Live status update: run is being judged on given test.
-    LiveTest(u32),
+    LiveTest(pom::TestId),
     /// Live status update: run has reached given score.
     LiveScore(u32),
+    /// Live status update: resources consumed by the solution on tests
+    /// finished so far. Sent after `TestFinished`.
+    LiveResources(judge_apis::live::LiveResources),
+    /// Live status update: next chunk of compilation output. Chunks must be
+    /// concatenated. Invoker returns output only when the build is
+    /// finished, so currently the whole log is sent in one chunk before
+    /// testing starts. Not sent if the build was reused.
+    LiveCompileLog(String),
+    /// Test has been judged. These statuses can later be passed to
+    /// `revalue`.
+    TestFinished {
+        test_id: pom::TestId,
+        status: Status,
+    },
+    /// Resources consumed since the previous event of this kind
+    Usage(Usage),
+    /// Judging has progressed past given stage
+    StageCompleted(Stage),
+    /// Run was compiled successfully
+    Compiled(CompiledRun),
+    /// Live status update: all invokers are saturated and requests of the
+    /// job wait for capacity (`true`), or waiting is over (`false`). While
+    /// waiting, sent before each retry.
+    WaitingForInvoker(bool),
+}
+
+/// Parses comma-separated list of judge log kinds
+pub fn parse_log_kinds(s: &str) -> anyhow::Result<Vec<JudgeLogKind>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            JudgeLogKind::list()
+                .find(|kind| kind.as_str() == name)
+                .with_context(|| format!("unknown judge log kind {:?}", name))
+        })
+        .collect()
+}
+
+/// Coarse-grained judging stage, used for diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Stage {
+    ProblemLoaded,
+    ToolchainResolved,
+    Compiled,
+    ValuerStarted,
+    /// Valuer decided that no more tests are needed
+    TestsFinished,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::ProblemLoaded => "problem-loaded",
+            Stage::ToolchainResolved => "toolchain-resolved",
+            Stage::Compiled => "compiled",
+            Stage::ValuerStarted => "valuer-started",
+            Stage::TestsFinished => "tests-finished",
+        }
+    }
 }
 
 /// Overall response state
@@ -64,31 +277,242 @@ pub struct Clients {
     pub invokers: invoker_client::Client,
 }
 
+impl Clients {
+    pub fn builder() -> ClientsBuilder {
+        ClientsBuilder::default()
+    }
+}
+
+/// Builder for `Clients`. All clients are required.
+#[derive(Default)]
+pub struct ClientsBuilder {
+    toolchains: Option<Arc<toolchain_loader::ToolchainLoader>>,
+    problems: Option<Arc<problem_loader::Loader>>,
+    invokers: Option<invoker_client::Client>,
+}
+
+impl ClientsBuilder {
+    pub fn toolchains(&mut self, toolchains: toolchain_loader::ToolchainLoader) -> &mut Self {
+        self.toolchains = Some(Arc::new(toolchains));
+        self
+    }
+
+    pub fn problems(&mut self, problems: problem_loader::Loader) -> &mut Self {
+        self.problems = Some(Arc::new(problems));
+        self
+    }
+
+    pub fn invokers(&mut self, invokers: invoker_client::Client) -> &mut Self {
+        self.invokers = Some(invokers);
+        self
+    }
+
+    pub fn build(&mut self) -> anyhow::Result<Clients> {
+        Ok(Clients {
+            toolchains: self
+                .toolchains
+                .take()
+                .context("toolchain loader is not set")?,
+            problems: self.problems.take().context("problem loader is not set")?,
+            invokers: self.invokers.take().context("invoker client is not set")?,
+        })
+    }
+}
+
 /// Settings are global rather then come from a request.
+/// Create them with `Settings::new` and adjust needed fields.
 #[derive(Clone)]
+#[non_exhaustive]
 pub struct Settings {
     /// ${checker_logs}/${job_id}/${test_id} will contain checker log
     /// for a test test_id.
     pub checker_logs: Option<PathBuf>,
+    /// ${compile_logs}/${job_id}/step-${n}-stdout and step-${n}-stderr
+    /// will contain raw output of build command n, and artifacts-stderr
+    /// will contain errors of artifact packing.
+    pub compile_logs: Option<PathBuf>,
+    /// How many times valuer can be restarted during one job
+    /// if it crashes or stops responding.
+    pub valuer_restart_limit: u32,
+    /// If set, execution records will be written there.
+    pub trace: Option<Trace>,
+    /// How many times test is retried if invoker times out
+    pub test_retry_limit: u32,
+    /// If true, build network access is denied even if toolchain
+    /// allows it (e.g. during contests).
+    pub deny_build_network: bool,
+    /// Recurring warnings are reported there
+    pub warnings: Warnings,
+    /// If set, solution outputs are put there instead of judge logs
+    pub output_store: Option<OutputStore>,
+    /// Identifier of this judge instance, recorded in produced logs
+    pub judge_id: String,
+    /// Only logs of these kinds are produced
+    pub enabled_log_kinds: Vec<JudgeLogKind>,
+    /// If set and invoker supports it, solution memory usage is sampled
+    /// with this interval and put to full judge logs
+    pub memory_sampling_interval: Option<Duration>,
+    /// If set, valuers of all problems are provided by this service
+    /// instead of being spawned by the judge
+    pub valuer_url: Option<String>,
+    /// If set, successful builds are cached and reused for identical
+    /// sources
+    pub artifact_cache: Option<Arc<ArtifactCache>>,
+    /// If set, the job and all its invoker calls are written to this file,
+    /// so that it can later be replayed with `replay`
+    pub invoker_recording: Option<PathBuf>,
+    /// If true, compile and checker logs are converted to UTF-8 with LF
+    /// line endings before they are put to judge logs
+    pub normalize_logs: bool,
+    /// If true, test data, correct answers and inline solution outputs
+    /// are not put to contestant logs. Full logs still contain them.
+    pub strip_contestant_test_data: bool,
+    /// If set, large inputs are passed to invoker by path
+    pub shared_inputs: Option<SharedInputs>,
+    /// Maximal number of tests of one job which run concurrently, when
+    /// valuer requests a batch of tests. If not set, number of healthy
+    /// invoker pools is used.
+    pub test_parallelism: Option<usize>,
+    /// If set, solution stdout and stderr are truncated to this many
+    /// bytes each, and truncation is marked in judge logs. Invoker is
+    /// asked to truncate them if it supports that.
+    pub max_output_size: Option<u64>,
+    /// Inputs which are inlined into invoker requests (rather than passed
+    /// via shared inputs) must not be larger than this many bytes
+    pub max_inlined_input_size: u64,
+    /// Outputs returned by invoker must not be larger than this many
+    /// bytes after decoding, so that a hostile output can not exhaust
+    /// judge memory
+    pub max_invoker_output_size: u64,
+    /// If set, tests whose time usage is close to the time limit are run
+    /// again. Problems may override it.
+    pub rerun_policy: Option<problem_loader::RerunPolicy>,
+    /// Applied to every sandbox judge creates. Problems may make it
+    /// stricter for their sandboxes.
+    pub security_policy: problem_loader::SecurityPolicy,
+}
+
+impl Settings {
+    /// Returns default settings
+    pub fn new(judge_id: impl Into<String>) -> Settings {
+        Settings {
+            checker_logs: None,
+            compile_logs: None,
+            valuer_restart_limit: 2,
+            trace: None,
+            test_retry_limit: 1,
+            deny_build_network: false,
+            warnings: Warnings::new(Duration::from_secs(60)),
+            output_store: None,
+            judge_id: judge_id.into(),
+            enabled_log_kinds: JudgeLogKind::list().collect(),
+            memory_sampling_interval: None,
+            valuer_url: None,
+            artifact_cache: None,
+            invoker_recording: None,
+            normalize_logs: true,
+            strip_contestant_test_data: false,
+            shared_inputs: None,
+            test_parallelism: None,
+            max_output_size: None,
+            max_inlined_input_size: 256 << 20,
+            max_invoker_output_size: 256 << 20,
+            rerun_policy: None,
+            security_policy: Default::default(),
+        }
+    }
 }
 
 /// The main function, which responds to a single request.
 #[tracing::instrument(skip(req, clients, settings))]
-pub fn judge(req: Request, clients: Clients, settings: Settings) -> JobProgress {
+pub fn judge(req: Request, mut clients: Clients, mut settings: Settings) -> JobProgress {
+    // all requests of the job go to the same invoker, if possible
+    clients.invokers = clients
+        .invokers
+        .with_labels(req.invoker_labels.clone())
+        .with_affinity();
+    let recording = settings.invoker_recording.clone().map(|path| {
+        let recorder = invoker_client::Recorder::new();
+        clients.invokers = clients.invokers.recording(recorder.clone());
+        // cached builds would be missing from the recording
+        settings.artifact_cache = None;
+        // replay answers calls in the recorded order, which is only
+        // deterministic if tests are run one by one
+        settings.test_parallelism = Some(1);
+        (path, recorder, replay::JobRecording::new(&req))
+    });
     let (done_tx, done_rx) = oneshot::channel();
     let (events_tx, events_rx) = mpsc::channel(1);
-    tokio::task::spawn(
+    let capacity_tx = events_tx.clone();
+    clients.invokers = clients.invokers.on_capacity_wait(Arc::new(move |waiting| {
+        let tx = capacity_tx.clone();
+        Box::pin(async move {
+            tx.send(Event::WaitingForInvoker(waiting)).await.ok();
+        })
+    }));
+    let task = tokio::task::spawn(
         async move {
+            let debug_dump = match &req.debug_dump_dir {
+                Some(dir) => match DebugDump::new(dir.clone(), settings.warnings.clone()).await {
+                    Ok(dump) => Some(dump),
+                    Err(err) => {
+                        settings
+                            .warnings
+                            .report("debug-dump-failed", format!("{:#}", err));
+                        None
+                    }
+                },
+                None => None,
+            };
+            if let Some(dump) = &debug_dump {
+                let dump = dump.clone();
+                clients.invokers = clients.invokers.on_call(Arc::new(move |request, outcome| {
+                    let dump = dump.clone();
+                    Box::pin(async move { dump.invoke_call(request, outcome).await })
+                }));
+            }
             let mut protocol_sender = ProtocolSender {
                 sent: Vec::new(),
                 tx: events_tx.clone(),
-                // TODO: read from request
-                debug_dump_dir: None,
+                debug_dump,
+                judge_id: settings.judge_id.clone(),
+                enabled_kinds: settings.enabled_log_kinds.clone(),
+                phase: req.phase.clone(),
+                image_override: req.image_override.clone(),
             };
+            let tracer = JobTracer::new(settings.trace.clone(), settings.warnings.clone());
+            tracer
+                .record(TraceRecord::Started {
+                    toolchain: &req.toolchain_name,
+                    problem: &req.problem_id,
+                })
+                .await;
 
-            let res = do_judge(req, events_tx, clients, &mut protocol_sender, settings).await;
+            let warnings = settings.warnings.clone();
+            let resources = ResourceTracker::new(clients.invokers.clone());
+            let res = AssertUnwindSafe(do_judge(
+                req,
+                events_tx,
+                clients,
+                &mut protocol_sender,
+                settings,
+                &tracer,
+                &resources,
+            ))
+            .catch_unwind()
+            .await
+            .unwrap_or_else(|payload| {
+                Err(anyhow::Error::new(TaskPanicked::from_payload(payload))
+                    .context("judging panicked"))
+            });
+            tracer
+                .record(TraceRecord::Finished {
+                    error: res.as_ref().err().map(|err| format!("{:#}", err)),
+                })
+                .await;
             if let Err(err) = &res {
                 tracing::warn!(err = %format_args!("{:#}", err),"judging failed, responding with judge fault");
+                resources.release_all(&warnings).await;
                 protocol_sender
                     .send_fake_logs(
                         Status {
@@ -99,26 +523,54 @@ pub fn judge(req: Request, clients: Clients, settings: Settings) -> JobProgress
                     )
                     .await;
             }
+            if let Some((path, recorder, mut recording)) = recording {
+                recording.invoker = recorder.snapshot();
+                if let Err(err) = recording.save(&path).await {
+                    warnings.report(
+                        "invoker-recording-failed",
+                        format!("failed to save invoker recording: {:#}", err),
+                    );
+                }
+            }
             done_tx.send(res).ok();
         }
         .in_current_span(),
     );
-    JobProgress { events_rx, done_rx }
+    JobProgress {
+        events_rx,
+        done_rx,
+        task: Some(task),
+        last_seq: 0,
+    }
 }
 
-/// Can be used to view judge job progress
+/// Can be used to view judge job progress. Dropping it aborts the job,
+/// so that an abandoned job does not keep using invokers.
 pub struct JobProgress {
     events_rx: mpsc::Receiver<Event>,
     done_rx: oneshot::Receiver<anyhow::Result<()>>,
+    /// Used to find out why the task stopped without sending outcome.
+    /// Taken by `wait`.
+    task: Option<tokio::task::JoinHandle<()>>,
+    /// Sequence number of the last returned event
+    last_seq: u64,
 }
 
 impl JobProgress {
     /// Wait for completion. All pending events will be dropped.
-    pub async fn wait(self) -> JudgeOutcome {
-        let res = self
-            .done_rx
-            .await
-            .unwrap_or_else(|_| Err(anyhow::Error::msg("background task stopped unexpectedly")));
+    pub async fn wait(mut self) -> JudgeOutcome {
+        let res = match (&mut self.done_rx).await {
+            Ok(res) => res,
+            Err(_) => Err(
+                match self.task.take().expect("task is only taken here").await {
+                    Err(err) if err.is_panic() => {
+                        anyhow::Error::new(TaskPanicked::from_payload(err.into_panic()))
+                            .context("background task stopped unexpectedly")
+                    }
+                    _ => anyhow::Error::msg("background task stopped unexpectedly"),
+                },
+            ),
+        };
         match res {
             Ok(()) => JudgeOutcome::Success,
             Err(error) => JudgeOutcome::Fault { error },
@@ -126,8 +578,28 @@ impl JobProgress {
     }
 
     /// Returns next event.
-    pub async fn event(&mut self) -> Option<Event> {
-        self.events_rx.recv().await
+    pub async fn event(&mut self) -> Option<SequencedEvent> {
+        let event = self.events_rx.recv().await?;
+        // all producers share one channel, so receiving order is the
+        // producing order
+        self.last_seq += 1;
+        Some(SequencedEvent {
+            seq: self.last_seq,
+            event,
+        })
+    }
+
+    /// Stops the job. Its outcome is not reported.
+    pub fn abort(&self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+impl Drop for JobProgress {
+    fn drop(&mut self) {
+        self.abort();
     }
 }
 
@@ -137,32 +609,139 @@ async fn do_judge(
     clients: Clients,
     protocol_sender: &mut ProtocolSender,
     settings: Settings,
+    tracer: &JobTracer,
+    resources: &ResourceTracker,
 ) -> anyhow::Result<()> {
-    tracing::info!("loading problem");
-    let (problem, problem_assets) = clients
-        .problems
-        .find(&req.problem_id)
-        .await
-        .context("failed to get problem")?
-        .context("problem not found")?;
+    // assets of the revision are kept until judging finishes
+    let loaded = match &req.problem {
+        Some(pinned) => {
+            tracing::info!(revision = pinned.revision, "using pinned problem revision");
+            pinned.clone()
+        }
+        None => {
+            tracing::info!("loading problem");
+            clients
+                .problems
+                .find(&req.problem_id)
+                .await
+                .context("failed to get problem")?
+                .context("problem not found")?
+        }
+    };
+    let problem_loader::LoadedProblem {
+        manifest: problem,
+        extensions: problem_ext,
+        assets: problem_assets,
+        ..
+    } = loaded.clone();
 
     let file_ref_resolver = FileRefResolver {
-        problem_assets_dir: problem_assets.clone(),
+        problem_assets_dir: problem_assets,
     };
+    tx.send(Event::StageCompleted(Stage::ProblemLoaded))
+        .await
+        .ok();
 
     tracing::info!("loading toolchain");
-    let toolchain = clients
+    let mut toolchain = clients
         .toolchains
         .resolve(&req.toolchain_name)
         .await
         .context("failed to find toolchain")?;
+    if let Some(image) = &req.image_override {
+        tracing::warn!(
+            toolchain = %req.toolchain_name,
+            toolchain_image = %toolchain.image,
+            image_override = %image,
+            "sandbox image is overridden"
+        );
+        toolchain.image = image.clone();
+    }
+    tx.send(Event::StageCompleted(Stage::ToolchainResolved))
+        .await
+        .ok();
 
-    tracing::info!("compiling");
-    let mut compile_res = compile::compile(&req, &toolchain, clients.invokers.clone()).await?;
+    let capabilities = query_capabilities(&clients, &settings).await?;
+    let extensions = ExtensionBuilder::new(&capabilities);
+
+    // persisted artifacts are lost if invoker changed its files directory
+    let reused = match &req.compiled {
+        Some(compiled) => match compiled.check_reusable(&capabilities, &clients.invokers) {
+            Ok(()) => Some(compiled),
+            Err(err) if !req.run_source.is_empty() => {
+                settings.warnings.report(
+                    "compiled-run-invalid",
+                    format!("compiling run again: {:#}", err),
+                );
+                None
+            }
+            Err(err) => return Err(err),
+        },
+        None => None,
+    };
+    let mut compile_res = match reused {
+        Some(compiled) => {
+            tracing::info!("reusing compiled run");
+            compile::BuildOutcome {
+                result: Ok(Some(compiled.built.clone())),
+                log: compiled.log.clone(),
+                usage: Default::default(),
+            }
+        }
+        None => {
+            let cached = settings
+                .artifact_cache
+                .as_ref()
+                .and_then(|cache| cache.get(&toolchain, &req.run_source));
+            let compile_res = match cached {
+                Some(outcome) => {
+                    tracing::info!("reusing cached build");
+                    outcome
+                }
+                None => {
+                    tracing::info!("compiling");
+                    let outcome = compile::compile(
+                        &req,
+                        &toolchain,
+                        clients.invokers.clone(),
+                        &settings,
+                        &extensions,
+                        resources,
+                    )
+                    .await?;
+                    if let Some(cache) = &settings.artifact_cache {
+                        cache.put(&toolchain, &req.run_source, &outcome);
+                    }
+                    outcome
+                }
+            };
+            tx.send(Event::Usage(compile_res.usage.clone())).await.ok();
+            if !compile_res.log.is_empty() {
+                tx.send(Event::LiveCompileLog(compile_res.log.clone()))
+                    .await
+                    .ok();
+            }
+            if let Ok(Some(built)) = &compile_res.result {
+                tx.send(Event::Compiled(CompiledRun {
+                    built: built.clone(),
+                    log: compile_res.log.clone(),
+                }))
+                .await
+                .ok();
+            }
+            compile_res
+        }
+    };
+    tx.send(Event::StageCompleted(Stage::Compiled)).await.ok();
     let built = match &mut compile_res.result {
         Ok(b) => b.take().expect("compile does not return none"),
         Err(status) => {
             tracing::info!("compilation failed");
+            tracer
+                .record(TraceRecord::Compiled {
+                    error: Some(status),
+                })
+                .await;
             protocol_sender
                 .send_fake_logs(status.clone(), &compile_res.log)
                 .await;
@@ -170,94 +749,355 @@ async fn do_judge(
         }
     };
     let compile_res = compile_res;
+    tracer.record(TraceRecord::Compiled { error: None }).await;
     tracing::info!("running tests");
 
-    let valuer_config = match &problem.valuer {
-        Valuer::Child(child) => {
-            let current_dir = match &child.current_dir {
-                Some(p) => file_ref_resolver.resolve_asset(p),
-                None => {
-                    tracing::debug!(
-                        "valuer current_directory unset in problem manifest, defaulting to problem assets directory"
-                    );
-                    problem_assets.clone()
-                }
-            };
-            ClientConfig::Child(ChildClientConfig {
-                exe: file_ref_resolver.resolve_asset(&child.exe),
-                args: child.extra_args.clone(),
-                current_dir,
-            })
+    let mut valuer = start_valuer(
+        &problem,
+        &file_ref_resolver,
+        &settings,
+        req.phase.as_deref(),
+        req.judging_mode,
+        protocol_sender.debug_dump.clone(),
+    )
+    .await?;
+    tx.send(Event::StageCompleted(Stage::ValuerStarted))
+        .await
+        .ok();
+    // if invoker can persist outputs in the shared directory, they are
+    // streamed to the output store from there
+    let persistent_outputs_dir = match &settings.output_store {
+        Some(store) if store.shared_invoker_files() => capabilities.persistent_files_dir.clone(),
+        _ => None,
+    };
+    let sandbox_reuse_key = if !problem_ext.reuse_sandbox {
+        None
+    } else if extensions.supports(Feature::SandboxReuse) {
+        Some(format!("solution-{}", uuid::Uuid::new_v4()))
+    } else {
+        settings.warnings.report(
+            "sandbox-reuse-unsupported",
+            format!(
+                "problem {} requests sandbox reuse, but invoker does not support it",
+                problem.name
+            ),
+        );
+        None
+    };
+    let memory_sampling_interval = match settings.memory_sampling_interval {
+        Some(_) if !extensions.supports(Feature::MemorySampling) => {
+            settings.warnings.report(
+                "memory-sampling-unsupported",
+                "memory sampling is enabled, but invoker does not support it".to_string(),
+            );
+            None
         }
+        interval => interval,
     };
-    let mut valuer = valuer_client::ValuerClient::new(&valuer_config)
-        .await
-        .context("failed to initialize valuer")?;
-    valuer
-        .write_problem_data(ProblemInfo {
-            tests: problem
-                .tests
-                .iter()
-                .map(|test_spec| test_spec.group.clone())
-                .collect(),
-        })
-        .await
-        .context("failed to send problem info to valuer")?;
+    let exec_ctx = exec_test::ExecContext {
+        toolchain: &toolchain,
+        problem: &problem,
+        problem_ext: &problem_ext,
+        file_ref_resolver: &file_ref_resolver,
+        settings: &settings,
+        built: &built,
+        persistent_outputs_dir: persistent_outputs_dir.as_deref(),
+        extensions: &extensions,
+        sandbox_reuse_key: sandbox_reuse_key.as_deref(),
+        memory_sampling_interval,
+        resources,
+        keep_stdout: false,
+    };
+    if let Some(reuse_key) = &sandbox_reuse_key {
+        resources.track(invoker_client::Resource::Sandbox {
+            reuse_key: reuse_key.clone(),
+        })?;
+    }
     let mut test_results = Vec::new();
+    let mut live_resources = judge_apis::live::LiveResources::default();
     loop {
-        match valuer.poll().await? {
-            ValuerResponse::Test { test_id: tid, live } => {
-                if live {
-                    tx.send(Event::LiveTest(tid.get())).await.ok();
-                }
-
-                let test_result = exec_test::exec(
-                    &toolchain,
-                    &problem,
-                    clients.invokers.clone(),
-                    &file_ref_resolver,
-                    tid,
-                    &settings,
-                    &built,
-                )
-                .await
-                .with_context(|| format!("failed to judge solution on test {}", tid))?;
-                test_results.push((tid, test_result.clone()));
-                valuer
-                    .notify_test_done(TestDoneNotification {
-                        test_id: tid,
-                        test_status: test_result.status,
-                    })
+        let (test_ids, live) = match valuer.poll().await? {
+            Polled::Response(ValuerResponse::Test { test_id, live }) => (vec![test_id], live),
+            Polled::Tests(ids) => (ids, false),
+            Polled::Response(ValuerResponse::Finish) => {
+                tx.send(Event::StageCompleted(Stage::TestsFinished))
                     .await
-                    .with_context(|| {
-                        format!("failed to notify valuer that test {} is done", tid)
-                    })?;
-            }
-            ValuerResponse::Finish => {
+                    .ok();
                 break;
             }
-            ValuerResponse::LiveScore { score } => {
+            Polled::Response(ValuerResponse::LiveScore { score }) => {
                 tx.send(Event::LiveScore(score)).await.ok();
+                continue;
             }
-            ValuerResponse::JudgeLog(judge_log) => {
-                let converted_judge_log = transform_judge_log::transform(
+            Polled::Response(ValuerResponse::JudgeLog(judge_log)) => {
+                if protocol_sender.sent.contains(&judge_log.kind) {
+                    // valuer was restarted and emitted this log again
+                    tracing::debug!(
+                        log_kind = judge_log.kind.as_str(),
+                        "ignoring duplicate judge log"
+                    );
+                    continue;
+                }
+                let mut converted_judge_log = transform_judge_log::transform(
                     &judge_log,
                     &compile_res,
                     &test_results,
+                    valuer.skipped_groups(),
                     &problem,
+                    &problem_ext,
                     &file_ref_resolver,
                 )
                 .await
                 .context("failed to convert valuer judge log to invoker judge log")?;
+                if settings.strip_contestant_test_data
+                    && converted_judge_log.kind == JudgeLogKind::Contestant
+                {
+                    transform_judge_log::strip_payloads(&mut converted_judge_log);
+                }
 
                 protocol_sender.send_log(converted_judge_log).await;
+                continue;
             }
+        };
+        let mut to_run = Vec::new();
+        for tid in test_ids {
+            if valuer.is_done(tid) || to_run.contains(&tid) {
+                // valuer was restarted and notification for this test
+                // was already replayed, or test is repeated in the batch
+                tracing::debug!(test_id = %tid, "ignoring request for already finished test");
+                continue;
+            }
+            let group = &problem
+                .tests
+                .get(tid.to_idx())
+                .context("unknown test")?
+                .group;
+            if valuer.is_group_skipped(group) {
+                tracing::debug!(test_id = %tid, "skipping test");
+                let status = Status {
+                    kind: StatusKind::Skipped,
+                    code: judge_apis::judge_log::SKIPPED_STATUS_CODE.to_string(),
+                };
+                tx.send(Event::TestFinished {
+                    test_id: tid,
+                    status: status.clone(),
+                })
+                .await
+                .ok();
+                valuer
+                    .notify_test_done(tid, status)
+                    .await
+                    .with_context(|| {
+                        format!("failed to notify valuer that test {} is skipped", tid)
+                    })?;
+                continue;
+            }
+            if live {
+                tx.send(Event::LiveTest(tid)).await.ok();
+            }
+            to_run.push(tid);
+        }
+        // reused sandbox can not run two tests at once
+        let parallelism = if to_run.len() > 1 && sandbox_reuse_key.is_none() {
+            match settings.test_parallelism {
+                Some(limit) => limit.max(1),
+                None => clients.invokers.available_invokers().max(1),
+            }
+        } else {
+            1
+        };
+        if parallelism > 1 {
+            tracing::debug!(
+                tests = to_run.len(),
+                parallelism,
+                "running tests concurrently"
+            );
+        }
+        let exec_ctx = &exec_ctx;
+        let invokers = &clients.invokers;
+        // results are handled in the requested order, so valuer receives
+        // notifications in the same order as for sequential requests
+        let mut finished = futures::stream::iter(to_run.into_iter().map(|tid| async move {
+            let test_started = Instant::now();
+            let res = rerun::exec(exec_ctx, invokers, tid).await;
+            res.map(|test_result| (tid, test_result, test_started.elapsed()))
+        }))
+        .buffered(parallelism);
+        while let Some(res) = finished.next().await {
+            let (tid, test_result, duration) = res?;
+            tracer
+                .record(TraceRecord::TestFinished {
+                    test_id: tid.get(),
+                    status: &test_result.status,
+                    time_usage: test_result.resource_usage.time,
+                    memory_usage: test_result.resource_usage.memory,
+                    duration_ms: duration.as_millis() as u64,
+                })
+                .await;
+            tx.send(Event::Usage(test_result.usage.clone())).await.ok();
+            test_results.push((tid, test_result.clone()));
+            tx.send(Event::TestFinished {
+                test_id: tid,
+                status: test_result.status.clone(),
+            })
+            .await
+            .ok();
+            live_resources.add_test(
+                test_result.resource_usage.time,
+                test_result.resource_usage.memory,
+            );
+            tx.send(Event::LiveResources(live_resources)).await.ok();
+            valuer
+                .notify_test_done(tid, test_result.status)
+                .await
+                .with_context(|| format!("failed to notify valuer that test {} is done", tid))?;
         }
     }
 
     Ok(())
 }
 
+/// Runs the solution on one test, retrying if invoker times out
+async fn exec_test_with_retries(
+    exec_ctx: &exec_test::ExecContext<'_>,
+    invokers: &invoker_client::Client,
+    tid: pom::TestId,
+) -> anyhow::Result<exec_test::ExecOutcome> {
+    let mut attempt = 0;
+    loop {
+        match exec_test::exec(exec_ctx, invokers.clone(), tid).await {
+            Ok(r) => return Ok(r),
+            Err(err)
+                if attempt < exec_ctx.settings.test_retry_limit
+                    && invoker_client::TimeoutError::is_cause_of(&err) =>
+            {
+                attempt += 1;
+                tracing::warn!(test_id = %tid, attempt, "invoker timed out, retrying test");
+            }
+            Err(err) => {
+                return Err(err.context(format!("failed to judge solution on test {}", tid)));
+            }
+        }
+    }
+}
+
+/// Queries optional invoker features. If invoker can not be queried, it
+/// is assumed to have none.
+async fn query_capabilities(
+    clients: &Clients,
+    settings: &Settings,
+) -> anyhow::Result<invoker_client::Capabilities> {
+    match clients.invokers.instance()?.capabilities().await {
+        Ok(caps) => Ok(caps),
+        Err(err) => {
+            settings.warnings.report(
+                "invoker-capabilities-unavailable",
+                format!("failed to query invoker capabilities: {:#}", err),
+            );
+            Ok(Default::default())
+        }
+    }
+}
+
+async fn start_valuer(
+    problem: &pom::Problem,
+    file_ref_resolver: &FileRefResolver,
+    settings: &Settings,
+    phase: Option<&str>,
+    judging_mode: JudgingMode,
+    debug_dump: Option<DebugDump>,
+) -> anyhow::Result<ValuerSession> {
+    let mut env = vec![(
+        "JJS_VALUER_LOG_KINDS".to_string(),
+        settings
+            .enabled_log_kinds
+            .iter()
+            .map(|kind| kind.as_str())
+            .collect::<Vec<_>>()
+            .join(","),
+    )];
+    if let Some(phase) = phase {
+        env.push(("JJS_VALUER_PHASE".to_string(), phase.to_string()));
+    }
+    env.push((
+        valuer_client::HINTS_ENV.to_string(),
+        "skip_remaining_in_group,tests".to_string(),
+    ));
+    let valuer_config = match (&settings.valuer_url, &problem.valuer) {
+        (Some(url), _) => {
+            let mut params: BTreeMap<String, String> = env.into_iter().collect();
+            params.insert("JJS_PROBLEM_NAME".to_string(), problem.name.clone());
+            ClientConfig::Http(HttpClientConfig {
+                url: url.clone(),
+                params,
+            })
+        }
+        (None, Valuer::Child(child)) => {
+            let current_dir = match &child.current_dir {
+                Some(p) => file_ref_resolver.resolve_asset(p),
+                None => {
+                    tracing::debug!(
+                        "valuer current_directory unset in problem manifest, defaulting to problem assets directory"
+                    );
+                    file_ref_resolver.problem_assets_dir.clone()
+                }
+            };
+            if tokio::fs::metadata(&current_dir).await.is_err() {
+                settings.warnings.report(
+                    "valuer-current-dir-missing",
+                    format!(
+                        "valuer working directory {} does not exist (problem {})",
+                        current_dir.display(),
+                        problem.name
+                    ),
+                );
+            }
+            ClientConfig::Child(ChildClientConfig {
+                exe: file_ref_resolver.resolve_asset(&child.exe),
+                args: child.extra_args.clone(),
+                current_dir,
+                env,
+            })
+        }
+    };
+    let tests = problem
+        .tests
+        .iter()
+        .map(|test_spec| test_spec.group.clone())
+        .collect();
+    let mut extensions = valuer_client::Extensions::new();
+    if judging_mode != JudgingMode::Default {
+        extensions.insert(
+            "judging_mode".to_string(),
+            judging_mode.as_str().to_string(),
+        );
+    }
+    ValuerSession::new(
+        valuer_config,
+        tests,
+        extensions,
+        settings.valuer_restart_limit,
+        settings.warnings.clone(),
+        debug_dump,
+    )
+    .await
+}
+
+/// Resources consumed by a single invoke request
+fn invoke_usage(response: &InvokeResponse) -> Usage {
+    let mut usage = Usage {
+        invoker_calls: 1,
+        ..Default::default()
+    };
+    for action in &response.actions {
+        if let ActionResult::ExecuteCommand(res) = action {
+            usage.add_command(res.cpu_time, res.memory);
+        }
+    }
+    usage
+}
+
+#[derive(Clone, Copy)]
 enum CommandStatus {
     /// Startup error
     Startup,
@@ -265,16 +1105,34 @@ enum CommandStatus {
     TimeLimit,
     /// Memory limit exceeded
     MemLimit,
+    /// Process count limit exceeded
+    ProcessLimit,
     /// Command has failed
     Runtime,
     /// Command has finished successfully
     Ok,
 }
 
-fn describe_command_result(limits: &Limits, data: &CommandResult) -> CommandStatus {
+/// Classifies command result. `report` is the cause of termination
+/// reported by invoker, if it was requested.
+fn describe_command_result(
+    limits: &Limits,
+    data: &CommandResult,
+    report: Option<&invoker_client::TerminationReport>,
+) -> CommandStatus {
     if data.spawn_error.is_some() {
         return CommandStatus::Startup;
     }
+    if let Some(report) = report {
+        match report.cause {
+            invoker_client::TerminationCause::TimeLimit
+            | invoker_client::TerminationCause::WallTimeLimit => return CommandStatus::TimeLimit,
+            invoker_client::TerminationCause::MemoryLimit => return CommandStatus::MemLimit,
+            invoker_client::TerminationCause::ProcessLimit => return CommandStatus::ProcessLimit,
+            invoker_client::TerminationCause::Exited
+            | invoker_client::TerminationCause::Signaled => {}
+        }
+    }
     if let Some(usage) = data.cpu_time {
         if usage > limits.time * 1_000_000 {
             return CommandStatus::TimeLimit;
@@ -309,13 +1167,17 @@ impl FileRefResolver {
 struct ProtocolSender {
     sent: Vec<JudgeLogKind>,
     tx: mpsc::Sender<Event>,
-    debug_dump_dir: Option<PathBuf>,
+    debug_dump: Option<DebugDump>,
+    judge_id: String,
+    enabled_kinds: Vec<JudgeLogKind>,
+    phase: Option<String>,
+    image_override: Option<String>,
 }
 
 impl ProtocolSender {
     async fn send_fake_logs(&mut self, status: Status, compile_log: &str) {
         for kind in JudgeLogKind::list() {
-            if self.sent.contains(&kind) {
+            if self.sent.contains(&kind) || !self.enabled_kinds.contains(&kind) {
                 continue;
             }
             tracing::info!("creating fake protocol of kind {}", kind.as_str());
@@ -327,32 +1189,36 @@ impl ProtocolSender {
                 score: 0,
                 is_full: false,
                 status: status.clone(),
+                manually_adjusted: false,
+                judge_id: None,
+                phase: None,
+                image_override: None,
+                total_tests: None,
             };
             self.send_log(fake).await;
         }
     }
 
     #[tracing::instrument(skip(self, log), fields(log_kind = log.kind.as_str()))]
-    async fn send_log(&mut self, log: JudgeLog) {
+    async fn send_log(&mut self, mut log: JudgeLog) {
         let already_sent = self.sent.contains(&log.kind);
         if already_sent {
             panic!("bug: log of kind {} sent twice", log.kind.as_str());
         }
         self.sent.push(log.kind);
-        if let Some(d) = &self.debug_dump_dir {
-            let dest = d.join(log.kind.as_str());
-            if let Err(e) = Self::try_put_log_to(&log, &dest).await {
-                tracing::warn!("failed to save debug dump of the log: {:#}", e);
-            }
+        if !self.enabled_kinds.contains(&log.kind) {
+            // valuer was asked not to produce it, but did anyway
+            tracing::debug!(log_kind = log.kind.as_str(), "dropping disabled judge log");
+            return;
+        }
+        log.judge_id = Some(self.judge_id.clone());
+        log.phase = self.phase.clone();
+        if log.kind == JudgeLogKind::Full {
+            log.image_override = self.image_override.clone();
+        }
+        if let Some(dump) = &self.debug_dump {
+            dump.write(&log.name(), &log).await;
         }
         self.tx.send(Event::LogCreated(log)).await.ok();
     }
-
-    async fn try_put_log_to(log: &JudgeLog, dest: &Path) -> anyhow::Result<()> {
-        let log = serde_json::to_vec_pretty(log).context("failed to serialize log")?;
-        tokio::fs::write(dest, log)
-            .await
-            .with_context(|| format!("failed to write log to {}", dest.display()))?;
-        Ok(())
-    }
 }
@@ -0,0 +1,60 @@
+//! Conversion of captured command output to text of judge logs.
+//!
+//! Checkers and compilers built for Windows may print CRLF line endings
+//! or even UTF-16, which breaks consumers of judge logs. When
+//! `Settings::normalize_logs` is set, such output is converted to UTF-8
+//! with LF line endings. Raw bytes are still written to
+//! `Settings::checker_logs` and `Settings::compile_logs`.
+
+/// Decodes `data` as UTF-8, replacing invalid sequences. If `normalize`
+/// is set, UTF-16 and byte order marks are recognized and line endings
+/// are converted to LF.
+pub(crate) fn decode(data: &[u8], normalize: bool) -> String {
+    if !normalize {
+        return String::from_utf8_lossy(data).into_owned();
+    }
+    let text = match data {
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        [0xFF, 0xFE, rest @ ..] => decode_utf16(rest, u16::from_le_bytes),
+        [0xFE, 0xFF, rest @ ..] => decode_utf16(rest, u16::from_be_bytes),
+        _ => match guess_utf16(data) {
+            Some(from_bytes) => decode_utf16(data, from_bytes),
+            None => String::from_utf8_lossy(data).into_owned(),
+        },
+    };
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn decode_utf16(data: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units = data
+        .chunks(2)
+        .map(|pair| from_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]));
+    std::char::decode_utf16(units)
+        .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+/// Detects UTF-16 without byte order mark: mostly-ASCII text has zero
+/// in every other byte. Returns function decoding code units.
+fn guess_utf16(data: &[u8]) -> Option<fn([u8; 2]) -> u16> {
+    let pairs = data.len() / 2;
+    if pairs == 0 || pairs * 2 != data.len() {
+        return None;
+    }
+    let zeros_at = |offset: usize| {
+        data.iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    // textual output rarely contains zero bytes, so half of them is a
+    // clear sign
+    if zeros_at(1) * 2 >= pairs && zeros_at(0) == 0 {
+        Some(u16::from_le_bytes)
+    } else if zeros_at(0) * 2 >= pairs && zeros_at(1) == 0 {
+        Some(u16::from_be_bytes)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,107 @@
+//! Storage for solution outputs.
+//!
+//! Outputs are gzip-compressed and written to `${dir}/${job_id}/${id}`,
+//! and judge logs only contain their ids, so that outputs of a job can be
+//! removed together with it. If invoker persists outputs into a directory
+//! which is shared with judge, they are streamed from there and never
+//! loaded into memory.
+use anyhow::Context;
+use flate2::{write::GzEncoder, Compression};
+use std::{
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use uuid::Uuid;
+
+#[derive(Clone)]
+pub struct OutputStore {
+    dir: PathBuf,
+    /// Invoker persistent files directory is available to judge
+    /// at the same path
+    shared_invoker_files: bool,
+}
+
+impl OutputStore {
+    pub async fn new(dir: PathBuf, shared_invoker_files: bool) -> anyhow::Result<OutputStore> {
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("failed to create outputs directory {}", dir.display()))?;
+        Ok(OutputStore {
+            dir,
+            shared_invoker_files,
+        })
+    }
+
+    /// Directory containing stored outputs
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns store which keeps outputs of the given job in a separate
+    /// subdirectory
+    pub fn for_job(&self, job_id: &str) -> OutputStore {
+        OutputStore {
+            dir: self.dir.join(job_id),
+            shared_invoker_files: self.shared_invoker_files,
+        }
+    }
+
+    pub(crate) fn shared_invoker_files(&self) -> bool {
+        self.shared_invoker_files
+    }
+
+    fn new_id() -> String {
+        format!("{}.gz", Uuid::new_v4())
+    }
+
+    /// Compresses file (at most `max_size` first bytes of it, if set)
+    /// into the store and returns id of the output.
+    pub(crate) async fn put_file(
+        &self,
+        src: &Path,
+        max_size: Option<u64>,
+    ) -> anyhow::Result<String> {
+        let id = Self::new_id();
+        let src = src.to_path_buf();
+        let dest = self.dir.join(&id);
+        tokio::task::spawn_blocking(move || {
+            let mut input = std::fs::File::open(&src)
+                .with_context(|| format!("failed to open {}", src.display()))?
+                .take(max_size.unwrap_or(u64::MAX));
+            let mut encoder = Self::create(&dest)?;
+            std::io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?.flush()?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await
+        .unwrap()
+        .context("failed to store output")?;
+        Ok(id)
+    }
+
+    /// Compresses `data` into the store and returns id of the output.
+    pub(crate) async fn put_bytes(&self, data: Vec<u8>) -> anyhow::Result<String> {
+        let id = Self::new_id();
+        let dest = self.dir.join(&id);
+        tokio::task::spawn_blocking(move || {
+            let mut encoder = Self::create(&dest)?;
+            encoder.write_all(&data)?;
+            encoder.finish()?.flush()?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await
+        .unwrap()
+        .context("failed to store output")?;
+        Ok(id)
+    }
+
+    fn create(dest: &Path) -> anyhow::Result<GzEncoder<BufWriter<std::fs::File>>> {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let file = std::fs::File::create(dest)
+            .with_context(|| format!("failed to create {}", dest.display()))?;
+        Ok(GzEncoder::new(BufWriter::new(file), Compression::fast()))
+    }
+}
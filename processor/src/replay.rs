@@ -0,0 +1,82 @@
+//! Recording of jobs and their replay from recorded invoker traffic.
+//!
+//! Replay runs the whole processor (including valuer and transformation
+//! of logs) again, but invoker calls are answered from the recording, so
+//! no sandboxes are needed.
+
+use crate::{Clients, JobProgress, Request, Settings};
+use anyhow::Context;
+use judge_apis::rest::JudgingMode;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Request of a job together with its invoker traffic
+#[derive(Serialize, Deserialize)]
+pub struct JobRecording {
+    pub toolchain_name: String,
+    pub problem_id: String,
+    /// Base64-encoded run source
+    pub run_source: String,
+    pub phase: Option<String>,
+    pub image_override: Option<String>,
+    #[serde(default)]
+    pub judging_mode: JudgingMode,
+    /// Whether compilation of a previous phase was reused. Such jobs can
+    /// not be replayed, because the build is not recorded.
+    pub reused_build: bool,
+    pub invoker: invoker_client::Recording,
+}
+
+impl JobRecording {
+    pub(crate) fn new(req: &Request) -> JobRecording {
+        JobRecording {
+            toolchain_name: req.toolchain_name.clone(),
+            problem_id: req.problem_id.clone(),
+            run_source: base64::encode(&req.run_source),
+            phase: req.phase.clone(),
+            image_override: req.image_override.clone(),
+            judging_mode: req.judging_mode,
+            reused_build: req.compiled.is_some(),
+            invoker: Default::default(),
+        }
+    }
+
+    pub(crate) async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(self).context("failed to serialize recording")?;
+        tokio::fs::write(path, data)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// Judges the run from recording saved at `path` (see
+/// `Settings::invoker_recording`), answering invoker calls with recorded
+/// responses. Latest revision of the problem is used, so the problem must
+/// not have changed since the job was recorded.
+pub async fn replay(
+    path: &Path,
+    mut clients: Clients,
+    mut settings: Settings,
+) -> anyhow::Result<JobProgress> {
+    let data = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let recording: JobRecording =
+        serde_json::from_slice(&data).context("failed to parse recording")?;
+    if recording.reused_build {
+        anyhow::bail!("job reused build of a previous phase and can not be replayed");
+    }
+    let run_source = base64::decode(&recording.run_source).context("invalid run source")?;
+    // recorded responses are not bound to pools, so labels are not set
+    let mut req = Request::new(recording.problem_id, recording.toolchain_name, run_source);
+    req.phase = recording.phase;
+    req.image_override = recording.image_override;
+    req.judging_mode = recording.judging_mode;
+    clients.invokers = invoker_client::Client::replay(recording.invoker);
+    // replayed job must take the same path as the recorded one
+    settings.artifact_cache = None;
+    settings.invoker_recording = None;
+    // recorded responses are matched in order
+    settings.test_parallelism = Some(1);
+    Ok(crate::judge(req, clients, settings))
+}
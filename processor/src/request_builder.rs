@@ -1,32 +1,139 @@
 use anyhow::Context;
 use invoker_api::invoke::{InputSource, InvokeResponse, OutputData};
-use std::path::Path;
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+/// Filesystem shared between judge and invoker. Large inputs are passed
+/// to invoker by path instead of being inlined as base64.
+#[derive(Clone, Debug)]
+pub struct SharedInputs {
+    /// Inputs which are not files yet (e.g. run source) are written there
+    dir: PathBuf,
+    /// Files under these directories (e.g. problems directory) are passed
+    /// by their paths. Invoker must see them at the same paths.
+    roots: Vec<PathBuf>,
+    /// Inputs of at most this size (in bytes) are inlined anyway
+    inline_limit: u64,
+}
+
+impl SharedInputs {
+    pub async fn new(
+        dir: PathBuf,
+        roots: Vec<PathBuf>,
+        inline_limit: u64,
+    ) -> anyhow::Result<SharedInputs> {
+        tokio::fs::create_dir_all(&dir).await.with_context(|| {
+            format!("failed to create shared inputs directory {}", dir.display())
+        })?;
+        Ok(SharedInputs {
+            dir,
+            roots,
+            inline_limit,
+        })
+    }
+
+    fn is_shared(&self, path: &Path) -> bool {
+        path.is_absolute()
+            && std::iter::once(&self.dir)
+                .chain(&self.roots)
+                .any(|root| path.starts_with(root))
+    }
+}
 
 /// Utility for exchanging data with invoker.
-pub(crate) struct RequestBuilder {}
+pub(crate) struct RequestBuilder {
+    shared: Option<SharedInputs>,
+    /// Files written to the shared inputs directory, removed on drop
+    created: Mutex<Vec<PathBuf>>,
+    /// See `Settings::max_inlined_input_size`
+    max_inlined_input_size: u64,
+    /// See `Settings::max_invoker_output_size`
+    max_output_size: u64,
+}
 
 impl RequestBuilder {
-    pub fn new() -> Self {
-        RequestBuilder {}
+    pub fn new(settings: &crate::Settings) -> Self {
+        RequestBuilder {
+            shared: settings.shared_inputs.clone(),
+            created: Mutex::new(Vec::new()),
+            max_inlined_input_size: settings.max_inlined_input_size,
+            max_output_size: settings.max_invoker_output_size,
+        }
     }
 
-    pub async fn intern(&self, data: &[u8]) -> anyhow::Result<InputSource> {
-        // TODO: use LocalFile when possible
-        Ok(InputSource::InlineBase64 {
-            data: base64::encode(data),
-        })
+    fn new_shared_path(&self, shared: &SharedInputs) -> PathBuf {
+        let path = shared.dir.join(Uuid::new_v4().to_string());
+        self.created.lock().unwrap().push(path.clone());
+        path
+    }
+
+    /// Passes `data` to invoker. `what` describes the data in errors,
+    /// e.g. `run source`.
+    pub async fn intern(&self, what: &str, data: &[u8]) -> anyhow::Result<InputSource> {
+        let shared = match &self.shared {
+            Some(s) if data.len() as u64 > s.inline_limit => s,
+            _ => {
+                if data.len() as u64 > self.max_inlined_input_size {
+                    anyhow::bail!(
+                        "{} is larger than {} bytes and can not be inlined",
+                        what,
+                        self.max_inlined_input_size
+                    );
+                }
+                return Ok(InputSource::InlineBase64 {
+                    data: base64::encode(data),
+                });
+            }
+        };
+        let path = self.new_shared_path(shared);
+        tokio::fs::write(&path, data)
+            .await
+            .with_context(|| format!("failed to write shared input {}", path.display()))?;
+        Ok(InputSource::LocalFile { path })
     }
 
     pub async fn intern_file(&self, path: &Path) -> anyhow::Result<InputSource> {
-        // TODO: be smarter here
-        let data = tokio::fs::read(path).await?;
-        self.intern(&data).await
+        let size = tokio::fs::metadata(path)
+            .await
+            .with_context(|| format!("failed to stat {}", path.display()))?
+            .len();
+        if let Some(shared) = &self.shared {
+            if size > shared.inline_limit {
+                if shared.is_shared(path) {
+                    return Ok(InputSource::LocalFile {
+                        path: path.to_path_buf(),
+                    });
+                }
+                let dest = self.new_shared_path(shared);
+                tokio::fs::copy(path, &dest).await.with_context(|| {
+                    format!("failed to copy {} to {}", path.display(), dest.display())
+                })?;
+                return Ok(InputSource::LocalFile { path: dest });
+            }
+        }
+        // the file may grow after it was checked, so encoder checks the
+        // limit as well
+        if size > self.max_inlined_input_size {
+            anyhow::bail!(
+                "{} is larger than {} bytes and can not be inlined",
+                path.display(),
+                self.max_inlined_input_size
+            );
+        }
+        let data = encode_file(path.to_path_buf(), self.max_inlined_input_size).await?;
+        Ok(InputSource::InlineBase64 { data })
     }
 
-    pub async fn read_output_data(&self, out: &OutputData) -> anyhow::Result<Vec<u8>> {
+    /// Decodes output named `name`
+    pub async fn read_output_data(&self, name: &str, out: &OutputData) -> anyhow::Result<Vec<u8>> {
         match out {
-            OutputData::InlineBase64(b) => base64::decode(b).context("invalid base64"),
-            OutputData::None => anyhow::bail!("output is None"),
+            OutputData::InlineBase64(b) => decode_limited(b, self.max_output_size)
+                .with_context(|| format!("failed to decode output {}", name)),
+            OutputData::None => anyhow::bail!("output {} is None", name),
         }
     }
 
@@ -40,7 +147,118 @@ impl RequestBuilder {
             .iter()
             .find(|o| o.name == output_name)
             .with_context(|| format!("output {} not found", output_name))?;
-        let data = self.read_output_data(&output.data).await?;
+        let data = self.read_output_data(output_name, &output.data).await?;
         Ok(data)
     }
+
+    /// Like `read_output`, but decodes at most `limit` bytes. Also returns
+    /// true if the output is longer.
+    pub async fn read_output_prefix(
+        &self,
+        res: &InvokeResponse,
+        output_name: &str,
+        limit: u64,
+    ) -> anyhow::Result<(Vec<u8>, bool)> {
+        let output = res
+            .outputs
+            .iter()
+            .find(|o| o.name == output_name)
+            .with_context(|| format!("output {} not found", output_name))?;
+        let data = match &output.data {
+            OutputData::InlineBase64(b) => b,
+            OutputData::None => anyhow::bail!("output {} is None", output_name),
+        };
+        let max_size = limit.min(self.max_output_size);
+        let mut decoded = decode_prefix(data, max_size)
+            .with_context(|| format!("failed to decode output {}", output_name))?;
+        if decoded.len() as u64 <= max_size {
+            return Ok((decoded, false));
+        }
+        if max_size < limit {
+            return Err(OutputTooLarge {
+                limit: self.max_output_size,
+            })
+            .with_context(|| format!("failed to decode output {}", output_name));
+        }
+        decoded.truncate(limit as usize);
+        Ok((decoded, true))
+    }
+}
+
+/// Encodes file as base64 without reading it into memory at once
+async fn encode_file(path: PathBuf, max_size: u64) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let mut encoder = base64::write::EncoderStringWriter::new(base64::STANDARD);
+        let copied = std::io::copy(&mut file.take(max_size + 1), &mut encoder)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if copied > max_size {
+            anyhow::bail!(
+                "{} is larger than {} bytes and can not be inlined",
+                path.display(),
+                max_size
+            );
+        }
+        Ok(encoder.into_inner())
+    })
+    .await
+    .unwrap()
+}
+
+/// Returned (wrapped in `anyhow::Error`) when invoker output is larger
+/// than `Settings::max_invoker_output_size`.
+#[derive(Debug)]
+pub(crate) struct OutputTooLarge {
+    limit: u64,
+}
+
+impl std::fmt::Display for OutputTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "decoded data is larger than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for OutputTooLarge {}
+
+impl OutputTooLarge {
+    pub(crate) fn is_cause_of(err: &anyhow::Error) -> bool {
+        err.chain().any(|e| e.is::<OutputTooLarge>())
+    }
+}
+
+/// Decodes base64, failing if decoded data is larger than `max_size`.
+/// At most `max_size + 1` bytes are decoded.
+fn decode_limited(data: &str, max_size: u64) -> anyhow::Result<Vec<u8>> {
+    let decoded = decode_prefix(data, max_size)?;
+    if decoded.len() as u64 > max_size {
+        return Err(OutputTooLarge { limit: max_size }.into());
+    }
+    Ok(decoded)
+}
+
+/// Decodes at most `max_size + 1` first bytes of base64 data
+fn decode_prefix(data: &str, max_size: u64) -> anyhow::Result<Vec<u8>> {
+    // every 4 characters encode at most 3 bytes
+    let capacity = (data.len() / 4 * 3).min(max_size as usize);
+    let mut decoded = Vec::with_capacity(capacity);
+    let mut reader = data.as_bytes();
+    let decoder = base64::read::DecoderReader::new(&mut reader, base64::STANDARD);
+    decoder
+        .take(max_size + 1)
+        .read_to_end(&mut decoded)
+        .context("invalid base64")?;
+    Ok(decoded)
+}
+
+impl Drop for RequestBuilder {
+    fn drop(&mut self) {
+        for path in self.created.get_mut().unwrap().drain(..) {
+            if let Err(err) = std::fs::remove_file(&path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!("failed to remove shared input {}: {}", path.display(), err);
+                }
+            }
+        }
+    }
 }
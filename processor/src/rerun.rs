@@ -0,0 +1,87 @@
+//! Re-execution of tests whose time usage is close to the time limit.
+//!
+//! On noisy hardware, CPU time of the same solution varies between runs,
+//! so a verdict of a test which used almost exactly the time limit is
+//! unreliable. Such tests are run again (see `RerunPolicy`), and the
+//! outcome is taken from the best or the median run.
+
+use crate::exec_test::{self, ExecOutcome};
+use judge_apis::judge_log::TestAttempt;
+use problem_loader::{RerunPolicy, RunSelection};
+use valuer_api::StatusKind;
+
+/// Runs the solution on one test, running it again if the policy of the
+/// problem or of the judge requests that
+pub(crate) async fn exec(
+    exec_ctx: &exec_test::ExecContext<'_>,
+    invokers: &invoker_client::Client,
+    tid: pom::TestId,
+) -> anyhow::Result<ExecOutcome> {
+    let first = crate::exec_test_with_retries(exec_ctx, invokers, tid).await?;
+    let policy = match exec_ctx
+        .problem_ext
+        .rerun
+        .or(exec_ctx.settings.rerun_policy)
+    {
+        Some(p) if p.max_reruns > 0 => p,
+        _ => return Ok(first),
+    };
+    let time_limit = exec_test::solution_limits(&exec_ctx.problem.tests[tid]).time;
+    if !near_limit(&policy, &first, time_limit) {
+        return Ok(first);
+    }
+    tracing::info!(test_id = %tid, "time usage is close to the limit, running test again");
+    let mut outcomes = vec![first];
+    for _ in 0..policy.max_reruns {
+        let outcome = crate::exec_test_with_retries(exec_ctx, invokers, tid).await?;
+        // judge fault is reported as is, other runs do not matter
+        if outcome.status.kind == StatusKind::InternalError {
+            return Ok(outcome);
+        }
+        outcomes.push(outcome);
+    }
+    Ok(select(policy.selection, outcomes))
+}
+
+/// Checks if CPU time of the outcome lies within the window around the
+/// time limit (in milliseconds)
+fn near_limit(policy: &RerunPolicy, outcome: &ExecOutcome, time_limit: u64) -> bool {
+    if outcome.status.kind == StatusKind::InternalError {
+        return false;
+    }
+    let time = match outcome.resource_usage.time {
+        Some(t) => t,
+        None => return false,
+    };
+    let limit = time_limit.saturating_mul(1_000_000);
+    time.abs_diff(limit).saturating_mul(100) <= limit.saturating_mul(policy.window_percent.into())
+}
+
+/// Picks outcome of the test from several runs and records all of them
+fn select(selection: RunSelection, outcomes: Vec<ExecOutcome>) -> ExecOutcome {
+    let mut order: Vec<usize> = (0..outcomes.len()).collect();
+    order.sort_by_key(|&i| outcomes[i].resource_usage.time.unwrap_or(u64::MAX));
+    let selected = match selection {
+        RunSelection::Best => order[0],
+        RunSelection::Median => order[(order.len() - 1) / 2],
+    };
+    let attempts = outcomes
+        .iter()
+        .enumerate()
+        .map(|(i, outcome)| TestAttempt {
+            status: outcome.status.clone(),
+            time_usage: outcome.resource_usage.time,
+            memory_usage: outcome.resource_usage.memory,
+            selected: i == selected,
+        })
+        .collect();
+    let mut usage = judge_apis::usage::Usage::default();
+    for outcome in &outcomes {
+        usage.merge(&outcome.usage);
+    }
+    let mut outcome = outcomes.into_iter().nth(selected).unwrap();
+    outcome.attempts = attempts;
+    // all runs consumed invoker resources
+    outcome.usage = usage;
+    outcome
+}
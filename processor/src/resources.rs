@@ -0,0 +1,150 @@
+//! Tracking of invoker resources which outlive requests (reused sandboxes,
+//! persisted outputs). Normally they are consumed or expired by invoker,
+//! but if a job fails mid-way, they are released explicitly.
+//!
+//! Resources exist only in the pool which created them, so the first
+//! tracked resource pins the job to its pool (see
+//! `invoker_client::Client::pin`).
+//!
+//! Resources which must outlive the job (persisted artifacts of a compiled
+//! run) are detached into `OwnedResources`, which releases them when it is
+//! dropped.
+
+use crate::{
+    extensions::{ExtensionBuilder, Feature},
+    Warnings,
+};
+use anyhow::Context;
+use invoker_client::{PoolId, Resource};
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Resources detached from the job. They are released when the value is
+/// dropped.
+pub(crate) struct OwnedResources {
+    tracker: ResourceTracker,
+    warnings: Warnings,
+}
+
+impl OwnedResources {
+    /// Pool which keeps the resources, None if there are no resources
+    pub(crate) fn pool(&self) -> Option<PoolId> {
+        let resources = self.tracker.resources.lock().unwrap();
+        resources.first().map(|(pool, _)| *pool)
+    }
+}
+
+impl Drop for OwnedResources {
+    fn drop(&mut self) {
+        let resources = std::mem::take(self.tracker.resources.get_mut().unwrap());
+        if resources.is_empty() {
+            return;
+        }
+        let tracker = ResourceTracker {
+            invokers: self.tracker.invokers.clone(),
+            resources: Mutex::new(resources),
+        };
+        let warnings = self.warnings.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move { tracker.release_all(&warnings).await });
+            }
+            // shutting down, invoker expires them eventually
+            Err(_) => tracing::debug!("leaving owned invoker resources to invoker"),
+        }
+    }
+}
+
+pub(crate) struct ResourceTracker {
+    /// Client of the job, requests of which create the resources
+    invokers: invoker_client::Client,
+    /// Resources with pools they are created in
+    resources: Mutex<Vec<(PoolId, Resource)>>,
+}
+
+impl ResourceTracker {
+    pub(crate) fn new(invokers: invoker_client::Client) -> Self {
+        ResourceTracker {
+            invokers,
+            resources: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Records resource which will be created by a request, pinning the
+    /// job to the pool the request will be sent to.
+    pub(crate) fn track(&self, resource: Resource) -> anyhow::Result<()> {
+        let pool = self
+            .invokers
+            .pin()
+            .context("failed to pin job to invoker pool")?;
+        let mut resources = self.resources.lock().unwrap();
+        if !resources.iter().any(|(p, r)| *p == pool && *r == resource) {
+            resources.push((pool, resource));
+        }
+        Ok(())
+    }
+
+    /// Moves resources matching `filter` to a value which releases them
+    /// when it is dropped, instead of when the job fails
+    pub(crate) fn detach(
+        &self,
+        filter: impl Fn(&Resource) -> bool,
+        warnings: &Warnings,
+    ) -> OwnedResources {
+        let mut resources = self.resources.lock().unwrap();
+        let (detached, kept) = std::mem::take(&mut *resources)
+            .into_iter()
+            .partition(|(_, resource)| filter(resource));
+        *resources = kept;
+        OwnedResources {
+            tracker: ResourceTracker {
+                // listeners of the job (e.g. one sending its events) must
+                // not outlive it
+                invokers: self.invokers.without_listeners(),
+                resources: Mutex::new(detached),
+            },
+            warnings: warnings.clone(),
+        }
+    }
+
+    /// Asks invoker to destroy all tracked resources. This is best-effort:
+    /// failures are only reported as warnings.
+    pub(crate) async fn release_all(&self, warnings: &Warnings) {
+        let mut by_pool: BTreeMap<PoolId, Vec<Resource>> = BTreeMap::new();
+        for (pool, resource) in std::mem::take(&mut *self.resources.lock().unwrap()) {
+            by_pool.entry(pool).or_default().push(resource);
+        }
+        for (pool, resources) in by_pool {
+            self.release_in(pool, &resources, warnings).await;
+        }
+    }
+
+    async fn release_in(&self, pool: PoolId, resources: &[Resource], warnings: &Warnings) {
+        let res = async {
+            let instance = self.invokers.instance_in(pool);
+            let caps = instance.capabilities().await?;
+            if !ExtensionBuilder::new(&caps).supports(Feature::ResourceRelease) {
+                warnings.report(
+                    "invoker-cleanup-unsupported",
+                    format!(
+                        "{} resources of the failed job are left to invoker, because it does not support releasing them",
+                        resources.len()
+                    ),
+                );
+                return Ok(false);
+            }
+            instance.release(resources).await?;
+            Ok::<_, anyhow::Error>(true)
+        }
+        .await;
+        match res {
+            Ok(true) => {
+                tracing::info!(count = resources.len(), ?pool, "released invoker resources")
+            }
+            Ok(false) => {}
+            Err(err) => warnings.report(
+                "invoker-cleanup-failed",
+                format!("failed to release invoker resources: {:#}", err),
+            ),
+        }
+    }
+}
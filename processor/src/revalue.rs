@@ -0,0 +1,96 @@
+//! Recomputation of judge logs from known test verdicts, without running
+//! the solution again. Used when test verdicts are changed manually.
+use crate::{transform_judge_log, valuer_session::Polled, Clients, FileRefResolver, Settings};
+use anyhow::Context;
+use judge_apis::{judge_log::JudgeLog, rest::JudgingMode};
+use valuer_api::{Status, ValuerResponse};
+
+/// Replays `test_statuses` to a new valuer instance and updates `logs`
+/// according to the judge logs it emits. Only log kinds present in `logs`
+/// are returned. All `logs` must belong to the same judging phase.
+///
+/// Fails if valuer requests a test which is missing from `test_statuses`
+/// (e.g. it was skipped during judging): in that case the run must be
+/// judged again. `judging_mode` must be the one the run was judged in.
+/// `pinned` is the problem revision the run was judged against; if it is
+/// None, the current revision is used.
+#[tracing::instrument(skip(pinned, test_statuses, logs, clients, settings))]
+pub async fn revalue(
+    problem_id: &str,
+    pinned: Option<&problem_loader::LoadedProblem>,
+    judging_mode: JudgingMode,
+    test_statuses: &[(pom::TestId, Status)],
+    logs: &[JudgeLog],
+    clients: &Clients,
+    settings: &Settings,
+) -> anyhow::Result<Vec<JudgeLog>> {
+    // assets of the revision are kept until the function returns
+    let loaded = match pinned {
+        Some(p) => p.clone(),
+        None => clients
+            .problems
+            .find(problem_id)
+            .await
+            .context("failed to get problem")?
+            .context("problem not found")?,
+    };
+    let problem_loader::LoadedProblem {
+        manifest: problem,
+        assets: problem_assets,
+        ..
+    } = loaded.clone();
+    let file_ref_resolver = FileRefResolver {
+        problem_assets_dir: problem_assets,
+    };
+    // all logs belong to the same phase
+    let phase = logs.first().and_then(|log| log.phase.as_deref());
+    let mut valuer = crate::start_valuer(
+        &problem,
+        &file_ref_resolver,
+        settings,
+        phase,
+        judging_mode,
+        None,
+    )
+    .await?;
+    let mut patched: Vec<JudgeLog> = Vec::new();
+    loop {
+        let test_ids = match valuer.poll().await? {
+            Polled::Response(ValuerResponse::Test { test_id, .. }) => vec![test_id],
+            Polled::Tests(ids) => ids,
+            Polled::Response(ValuerResponse::Finish) => break,
+            Polled::Response(ValuerResponse::LiveScore { .. }) => continue,
+            Polled::Response(ValuerResponse::JudgeLog(valuer_log)) => {
+                if patched.iter().any(|log| log.kind == valuer_log.kind) {
+                    continue;
+                }
+                if let Some(old) = logs.iter().find(|log| log.kind == valuer_log.kind) {
+                    patched.push(transform_judge_log::patch(&valuer_log, old));
+                }
+                continue;
+            }
+        };
+        for test_id in test_ids {
+            if valuer.is_done(test_id) {
+                continue;
+            }
+            let status = test_statuses
+                .iter()
+                .find(|(tid, _)| *tid == test_id)
+                .map(|(_, status)| status.clone())
+                .with_context(|| {
+                    format!(
+                        "valuer requested test {} which was not run during judging",
+                        test_id
+                    )
+                })?;
+            valuer
+                .notify_test_done(test_id, status)
+                .await
+                .with_context(|| {
+                    format!("failed to notify valuer that test {} is done", test_id)
+                })?;
+        }
+    }
+    Ok(patched)
+}
@@ -0,0 +1,43 @@
+//! Hardening of sandboxes according to `problem_loader::SecurityPolicy`.
+//!
+//! Every sandbox judge creates is built by [`sandbox`], so the policy is
+//! applied in one place instead of relying on each toolchain and problem
+//! to request a safe configuration.
+
+use crate::extensions::ExtensionBuilder;
+use invoker_api::invoke::{Limits, SandboxSettings, SharedDir};
+use invoker_client::SandboxExtensions;
+use problem_loader::SecurityPolicy;
+use std::path::PathBuf;
+
+/// Builds settings of the sandbox `name` with `policy` applied. If
+/// invoker can not enforce the policy, fails instead of creating a
+/// weaker sandbox.
+pub(crate) fn sandbox(
+    policy: &SecurityPolicy,
+    extensions: &ExtensionBuilder<'_>,
+    name: String,
+    mut limits: Limits,
+    expose: Vec<SharedDir>,
+    mut ext: SandboxExtensions,
+) -> anyhow::Result<SandboxSettings> {
+    if let Some(max) = policy.max_processes {
+        limits.process_count = Some(limits.process_count.map_or(max, |count| count.min(max)));
+    }
+    if policy.deny_network && ext.network {
+        tracing::info!(
+            sandbox = name.as_str(),
+            "network access is denied by security policy"
+        );
+        ext.network = false;
+    }
+    ext.writable_paths = policy.writable_paths.clone();
+    ext.core_dumps = policy.core_dumps;
+    Ok(SandboxSettings {
+        limits,
+        name,
+        base_image: PathBuf::new(),
+        expose,
+        ext: extensions.make(ext)?,
+    })
+}
@@ -0,0 +1,110 @@
+//! Context of invoker request steps for errors and judge logs
+
+use invoker_api::invoke::{Action, ActionResult, CommandResult, InvokeRequest, InvokeResponse};
+use judge_apis::judge_log::InvokerStepRow;
+
+/// Summary of a request step, which is kept after the request is sent
+struct StepInfo {
+    stage: u32,
+    /// Kind of the action, e.g. `execute-command`
+    action: &'static str,
+    /// Sandbox created or used by the step
+    sandbox: Option<String>,
+}
+
+/// Steps of an invoker request
+pub(crate) struct StepTable(Vec<StepInfo>);
+
+impl StepTable {
+    pub(crate) fn new(req: &InvokeRequest) -> StepTable {
+        let steps = req
+            .steps
+            .iter()
+            .map(|step| {
+                let (action, sandbox) = match &step.action {
+                    Action::OpenFile { .. } => ("open-file", None),
+                    Action::CreatePipe { .. } => ("create-pipe", None),
+                    Action::CreateFile { .. } => ("create-file", None),
+                    Action::OpenNullFile { .. } => ("open-null-file", None),
+                    Action::CreateVolume(_) => ("create-volume", None),
+                    Action::CreateSandbox(sandbox) => {
+                        ("create-sandbox", Some(sandbox.name.clone()))
+                    }
+                    Action::ExecuteCommand(command) => {
+                        ("execute-command", Some(command.sandbox_name.clone()))
+                    }
+                };
+                StepInfo {
+                    stage: step.stage,
+                    action,
+                    sandbox,
+                }
+            })
+            .collect();
+        StepTable(steps)
+    }
+
+    /// Describes step for error messages
+    pub(crate) fn describe(&self, index: usize) -> String {
+        match self.0.get(index) {
+            Some(StepInfo {
+                stage,
+                action,
+                sandbox: Some(sandbox),
+            }) => format!(
+                "step {} ({} in sandbox {}, stage {})",
+                index, action, sandbox, stage
+            ),
+            Some(StepInfo {
+                stage,
+                action,
+                sandbox: None,
+            }) => format!("step {} ({}, stage {})", index, action, stage),
+            None => format!("step {} (not in request)", index),
+        }
+    }
+
+    /// Returns result of the command executed by step `index`
+    pub(crate) fn command_result<'a>(
+        &self,
+        response: &'a InvokeResponse,
+        index: usize,
+    ) -> anyhow::Result<&'a CommandResult> {
+        match response.actions.get(index) {
+            Some(ActionResult::ExecuteCommand(result)) => Ok(result),
+            Some(_) => anyhow::bail!(
+                "invoker returned unexpected action result for {}",
+                self.describe(index)
+            ),
+            None => anyhow::bail!(
+                "invoker returned {} action results, result for {} is missing",
+                response.actions.len(),
+                self.describe(index)
+            ),
+        }
+    }
+
+    /// Returns compacted table for judge logs: only steps which create or
+    /// use sandboxes are included
+    pub(crate) fn rows(&self, response: &InvokeResponse) -> Vec<InvokerStepRow> {
+        self.0
+            .iter()
+            .enumerate()
+            .filter_map(|(index, step)| {
+                let sandbox = step.sandbox.clone()?;
+                let command = match response.actions.get(index) {
+                    Some(ActionResult::ExecuteCommand(result)) => Some(result),
+                    _ => None,
+                };
+                Some(InvokerStepRow {
+                    index,
+                    stage: step.stage,
+                    action: step.action.to_string(),
+                    sandbox,
+                    exit_code: command.map(|c| c.exit_code),
+                    spawn_error: command.and_then(|c| c.spawn_error.clone()),
+                })
+            })
+            .collect()
+    }
+}
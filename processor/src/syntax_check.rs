@@ -0,0 +1,148 @@
+//! Fast check of run source, which does not produce a binary. Used by
+//! editors to show diagnostics without judging the run.
+
+use crate::{
+    compile::FILE_ID_EMPTY,
+    extensions::{ExtensionBuilder, Feature},
+    Clients, CommandStatus, Settings,
+};
+use anyhow::Context;
+use invoker_api::invoke::{
+    Action, Command, EnvVarValue, EnvironmentVariable, Extensions, FileId, Limits, OutputRequest,
+    OutputRequestTarget, Stdio, Step,
+};
+use invoker_client::OutputExtensions;
+use std::collections::HashMap;
+
+/// Syntax check is killed after this time (in milliseconds)
+pub const SYNTAX_CHECK_TIME_LIMIT: u64 = 2000;
+
+/// Diagnostics are truncated to this many bytes
+const MAX_DIAGNOSTICS_SIZE: usize = 64 * 1024;
+
+const SANDBOX_NAME: &str = "syntax-check-sandbox";
+const FILE_ID_OUTPUT: &str = "output";
+
+pub struct SyntaxCheckOutcome {
+    /// True if the command exited successfully
+    pub passed: bool,
+    pub timed_out: bool,
+    /// Combined stdout and stderr of the command, at most
+    /// `MAX_DIAGNOSTICS_SIZE` bytes
+    pub diagnostics: String,
+    /// True if diagnostics were truncated
+    pub truncated: bool,
+}
+
+/// Runs `syntax-check` command of the toolchain on the run source.
+/// Returns None if toolchain does not declare it.
+#[tracing::instrument(skip(toolchain, run_source, clients, settings))]
+pub async fn syntax_check(
+    toolchain: &toolchain_loader::Toolchain,
+    run_source: &[u8],
+    clients: &Clients,
+    settings: &Settings,
+) -> anyhow::Result<Option<SyntaxCheckOutcome>> {
+    let command = match &toolchain.spec.syntax_check {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    let req_builder = crate::request_builder::RequestBuilder::new(settings);
+    let capabilities = crate::query_capabilities(clients, settings).await?;
+    let extensions = ExtensionBuilder::new(&capabilities);
+
+    let mut invoke_request = crate::compile::build_request(
+        toolchain,
+        run_source,
+        HashMap::new(),
+        &req_builder,
+        &extensions,
+    )
+    .await?;
+    invoke_request.steps.push(Step {
+        stage: 0,
+        action: Action::CreateFile {
+            id: FileId(FILE_ID_OUTPUT.to_string()),
+            readable: true,
+            writeable: true,
+        },
+        ext: Extensions::default(),
+    });
+    let limits = Limits {
+        memory: toolchain.spec.limits.memory(),
+        time: SYNTAX_CHECK_TIME_LIMIT,
+        process_count: toolchain.spec.limits.process_count,
+        ext: Extensions::default(),
+    };
+    // unlike build sandbox, there is no writable output directory
+    invoke_request.steps.push(crate::compile::sandbox_step(
+        toolchain,
+        settings,
+        &extensions,
+        SANDBOX_NAME,
+        limits.clone(),
+        Vec::new(),
+        false,
+    )?);
+    let exec_step = invoke_request.steps.len();
+    invoke_request.steps.push(Step {
+        stage: 0,
+        action: Action::ExecuteCommand(Command {
+            sandbox_name: SANDBOX_NAME.to_string(),
+            argv: command.argv.clone(),
+            env: command
+                .env
+                .iter()
+                .map(|(k, v)| EnvironmentVariable {
+                    name: k.clone(),
+                    value: EnvVarValue::Plain(v.clone()),
+                    ext: Extensions::default(),
+                })
+                .collect(),
+            cwd: "/".to_string(),
+            stdio: Stdio {
+                stdin: FileId(FILE_ID_EMPTY.to_string()),
+                stdout: FileId(FILE_ID_OUTPUT.to_string()),
+                stderr: FileId(FILE_ID_OUTPUT.to_string()),
+                ext: Extensions::default(),
+            },
+            ext: Extensions::default(),
+        }),
+        ext: Extensions::default(),
+    });
+    let output_ext = if extensions.supports(Feature::OutputTruncation) {
+        extensions.make(OutputExtensions {
+            persist: None,
+            max_size: Some(MAX_DIAGNOSTICS_SIZE as u64 + 1),
+        })?
+    } else {
+        Extensions::default()
+    };
+    invoke_request.outputs.push(OutputRequest {
+        name: FILE_ID_OUTPUT.to_string(),
+        target: OutputRequestTarget::File(FileId(FILE_ID_OUTPUT.to_string())),
+        ext: output_ext,
+    });
+
+    let steps = crate::steps::StepTable::new(&invoke_request);
+    let response = clients.invokers.instance()?.call(invoke_request).await?;
+    let result = steps
+        .command_result(&response, exec_step)
+        .context("syntax check command did not run")?;
+    if let Some(err) = &result.spawn_error {
+        anyhow::bail!("failed to start syntax check command: {}", err);
+    }
+    let mut output = req_builder
+        .read_output(&response, FILE_ID_OUTPUT)
+        .await
+        .context("failed to read syntax check output")?;
+    let truncated = output.len() > MAX_DIAGNOSTICS_SIZE;
+    output.truncate(MAX_DIAGNOSTICS_SIZE);
+    let status = crate::describe_command_result(&limits, result, None);
+    Ok(Some(SyntaxCheckOutcome {
+        passed: matches!(status, CommandStatus::Ok),
+        timed_out: matches!(status, CommandStatus::TimeLimit),
+        diagnostics: crate::log_text::decode(&output, settings.normalize_logs),
+        truncated,
+    }))
+}
@@ -0,0 +1,166 @@
+//! Structured execution records of judge jobs, intended for offline
+//! analysis. Each record is written as a single JSON line.
+use crate::Warnings;
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::Serialize;
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+use valuer_api::Status;
+
+/// Destination for trace records
+#[async_trait]
+pub trait TraceSink: Send + Sync {
+    /// Writes one serialized record (without trailing newline)
+    async fn write(&self, line: &[u8]) -> anyhow::Result<()>;
+}
+
+/// Appends records to a file
+pub struct FileTraceSink {
+    file: Mutex<tokio::fs::File>,
+}
+
+impl FileTraceSink {
+    pub async fn open(path: &Path) -> anyhow::Result<FileTraceSink> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("failed to open trace file {}", path.display()))?;
+        Ok(FileTraceSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl TraceSink for FileTraceSink {
+    async fn write(&self, line: &[u8]) -> anyhow::Result<()> {
+        let mut buf = Vec::with_capacity(line.len() + 1);
+        buf.extend_from_slice(line);
+        buf.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&buf).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Writes records to the standard output
+#[derive(Default)]
+pub struct StdoutTraceSink {
+    _priv: (),
+}
+
+impl StdoutTraceSink {
+    pub fn new() -> StdoutTraceSink {
+        StdoutTraceSink { _priv: () }
+    }
+}
+
+#[async_trait]
+impl TraceSink for StdoutTraceSink {
+    async fn write(&self, line: &[u8]) -> anyhow::Result<()> {
+        use std::io::Write;
+        let mut buf = Vec::with_capacity(line.len() + 1);
+        buf.extend_from_slice(line);
+        buf.push(b'\n');
+        // records are small, so blocking write is fine here
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(&buf)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Trace settings
+#[derive(Clone)]
+pub struct Trace {
+    pub sink: Arc<dyn TraceSink>,
+    /// Will be included in each record
+    pub job_id: String,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "record", rename_all = "kebab-case")]
+pub(crate) enum TraceRecord<'a> {
+    Started {
+        toolchain: &'a str,
+        problem: &'a str,
+    },
+    Compiled {
+        /// None if compilation succeeded
+        error: Option<&'a Status>,
+    },
+    TestFinished {
+        test_id: u32,
+        status: &'a Status,
+        time_usage: Option<u64>,
+        memory_usage: Option<u64>,
+        /// Wall-clock time spent on this test, including checker
+        duration_ms: u64,
+    },
+    Finished {
+        /// None if job succeeded
+        error: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+struct TraceLine<'a> {
+    job_id: &'a str,
+    timestamp_ms: u128,
+    /// Time since job start
+    elapsed_ms: u128,
+    #[serde(flatten)]
+    record: TraceRecord<'a>,
+}
+
+/// Writes records of one job
+pub(crate) struct JobTracer {
+    trace: Option<Trace>,
+    warnings: Warnings,
+    started: Instant,
+}
+
+impl JobTracer {
+    pub(crate) fn new(trace: Option<Trace>, warnings: Warnings) -> Self {
+        JobTracer {
+            trace,
+            warnings,
+            started: Instant::now(),
+        }
+    }
+
+    /// Writes a record. Errors are logged and otherwise ignored.
+    pub(crate) async fn record(&self, record: TraceRecord<'_>) {
+        let trace = match &self.trace {
+            Some(t) => t,
+            None => return,
+        };
+        let line = TraceLine {
+            job_id: &trace.job_id,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0),
+            elapsed_ms: self.started.elapsed().as_millis(),
+            record,
+        };
+        let res = match serde_json::to_vec(&line) {
+            Ok(line) => trace.sink.write(&line).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = res {
+            self.warnings.report(
+                "trace-write-failed",
+                format!("failed to write trace record: {:#}", err),
+            );
+        }
+    }
+}
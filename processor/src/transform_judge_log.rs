@@ -1,15 +1,34 @@
-use crate::exec_test::{ExecOutcome, ResourceUsage};
+use crate::exec_test::{CapturedOutput, ExecOutcome, ResourceUsage};
 use anyhow::Context;
 use judge_apis::judge_log;
-use std::collections::HashMap;
-use valuer_api::{status_codes, Status, StatusKind, TestVisibleComponents};
+use std::collections::{HashMap, HashSet};
+use valuer_api::{status_codes, JudgeLogKind, Status, StatusKind, TestVisibleComponents};
+
+/// Components of a test row which valuer-api does not name yet. Valuer
+/// sends them as extra bits of `TestVisibleComponents`.
+#[derive(Debug, Clone, Copy)]
+#[repr(u32)]
+enum ExtraComponent {
+    /// Checker comment
+    CheckerComment = 1 << 5,
+    /// Exit code and failure reason of the solution
+    FailureDetails = 1 << 6,
+}
+
+impl ExtraComponent {
+    fn is_visible(self, components: TestVisibleComponents) -> bool {
+        components.bits() & self as u32 != 0
+    }
+}
 
 /// Go from valuer judge log to invoker judge log
 pub(crate) async fn transform(
     valuer_log: &valuer_api::JudgeLog,
     compile_result: &crate::compile::BuildOutcome,
     test_results: &[(pom::TestId, crate::exec_test::ExecOutcome)],
+    skipped_groups: &HashSet<String>,
     problem: &pom::Problem,
+    problem_ext: &problem_loader::ProblemExtensions,
     file_ref_resolver: &crate::FileRefResolver,
 ) -> anyhow::Result<judge_log::JudgeLog> {
     let resource_usage_by_test = {
@@ -19,74 +38,171 @@ pub(crate) async fn transform(
         }
         map
     };
-    let mut persistent_judge_log = judge_log::JudgeLog::default();
-    let status = if valuer_log.is_full {
-        Status {
-            kind: StatusKind::Accepted,
-            code: status_codes::ACCEPTED.to_string(),
-        }
-    } else {
-        Status {
-            kind: StatusKind::Rejected,
-            code: status_codes::PARTIAL_SOLUTION.to_string(),
-        }
+    let mut persistent_judge_log = judge_log::JudgeLog {
+        status: overall_status(valuer_log),
+        kind: valuer_log.kind,
+        score: valuer_log.score,
+        compile_log: compile_result.log.clone(),
+        ..Default::default()
     };
-    persistent_judge_log.status = status;
-    persistent_judge_log.kind = valuer_log.kind;
-    persistent_judge_log.score = valuer_log.score;
-    persistent_judge_log.compile_log = compile_result.log.clone();
     // for each test, if valuer allowed, add stdin/stdout/stderr etc to judge_log
     for item in &valuer_log.tests {
         let exec_outcome = test_results
             .iter()
             .find(|(tid, _)| *tid == item.test_id)
             .map(|(_, outcome)| outcome);
-        let new_item = export_test(
+        let mut new_item = export_test(
             item,
             exec_outcome,
             &resource_usage_by_test,
+            valuer_log.kind,
             problem,
             file_ref_resolver,
         )
         .await?;
+        new_item.skipped =
+            exec_outcome.is_none() && skipped_groups.contains(&problem.tests[item.test_id].group);
         persistent_judge_log.tests.push(new_item);
     }
+    // full log lists all skipped tests, even if valuer did not request them
+    if valuer_log.kind == JudgeLogKind::Full {
+        for (idx, test) in problem.tests.iter().enumerate() {
+            let test_id = pom::TestId::make(idx as u32 + 1);
+            let listed = persistent_judge_log
+                .tests
+                .iter()
+                .any(|row| row.test_id == test_id);
+            let was_run = test_results.iter().any(|(tid, _)| *tid == test_id);
+            if listed || was_run || !skipped_groups.contains(&test.group) {
+                continue;
+            }
+            let mut row = empty_test_row(test_id);
+            row.status = Some(Status {
+                kind: StatusKind::Skipped,
+                code: judge_log::SKIPPED_STATUS_CODE.to_string(),
+            });
+            row.skipped = true;
+            persistent_judge_log.tests.push(row);
+        }
+    }
     persistent_judge_log.tests.sort_by_key(|a| a.test_id);
+    for row in &mut persistent_judge_log.tests {
+        row.test_name = problem_ext.test_name(row.test_id).map(str::to_string);
+    }
+
+    persistent_judge_log.subtasks = export_subtasks(valuer_log);
+
+    Ok(persistent_judge_log)
+}
+
+/// Removes test data, correct answers and inline outputs from the log.
+/// Outputs put to the output storage stay available by reference.
+pub(crate) fn strip_payloads(log: &mut judge_log::JudgeLog) {
+    for row in &mut log.tests {
+        row.test_stdin = None;
+        row.test_stdout = None;
+        row.test_stderr = None;
+        row.test_answer = None;
+    }
+}
 
+/// Updates verdicts and scores in already converted judge log according
+/// to the new valuer judge log. Other data (e.g. test outputs) is kept.
+pub(crate) fn patch(
+    valuer_log: &valuer_api::JudgeLog,
+    old: &judge_log::JudgeLog,
+) -> judge_log::JudgeLog {
+    let mut patched = old.clone();
+    patched.status = overall_status(valuer_log);
+    patched.score = valuer_log.score;
+    patched.tests = valuer_log
+        .tests
+        .iter()
+        .map(|item| {
+            let mut row = old
+                .tests
+                .iter()
+                .find(|row| row.test_id == item.test_id)
+                .cloned()
+                .unwrap_or_else(|| empty_test_row(item.test_id));
+            row.status = if item.components.contains(TestVisibleComponents::STATUS) {
+                Some(item.status.clone())
+            } else {
+                None
+            };
+            row
+        })
+        .collect();
+    patched.tests.sort_by_key(|a| a.test_id);
+    patched.subtasks = export_subtasks(valuer_log);
+    patched
+}
+
+fn overall_status(valuer_log: &valuer_api::JudgeLog) -> Status {
+    if valuer_log.is_full {
+        Status {
+            kind: StatusKind::Accepted,
+            code: status_codes::ACCEPTED.to_string(),
+        }
+    } else {
+        Status {
+            kind: StatusKind::Rejected,
+            code: status_codes::PARTIAL_SOLUTION.to_string(),
+        }
+    }
+}
+
+fn export_subtasks(valuer_log: &valuer_api::JudgeLog) -> Vec<judge_log::JudgeLogSubtaskRow> {
     // note that we do not filter subtasks connected staff,
     // because such filtering is done by Valuer.
-    for item in &valuer_log.subtasks {
-        persistent_judge_log
-            .subtasks
-            .push(judge_log::JudgeLogSubtaskRow {
-                subtask_id: item.subtask_id,
-                score: Some(item.score),
-            });
-    }
-    persistent_judge_log
+    let mut subtasks: Vec<_> = valuer_log
         .subtasks
-        .sort_by_key(|a| a.subtask_id.0);
+        .iter()
+        .map(|item| judge_log::JudgeLogSubtaskRow {
+            subtask_id: item.subtask_id,
+            score: Some(item.score),
+        })
+        .collect();
+    subtasks.sort_by_key(|a| a.subtask_id.0);
+    subtasks
+}
 
-    Ok(persistent_judge_log)
+fn empty_test_row(test_id: pom::TestId) -> judge_log::JudgeLogTestRow {
+    judge_log::JudgeLogTestRow {
+        test_id,
+        test_answer: None,
+        test_stdout: None,
+        test_stderr: None,
+        test_stdin: None,
+        status: None,
+        time_usage: None,
+        memory_usage: None,
+        test_stdout_ref: None,
+        test_stderr_ref: None,
+        memory_samples: None,
+        skipped: false,
+        checker_comment: None,
+        pipeline: Vec::new(),
+        security_violations: Vec::new(),
+        invoker_steps: Vec::new(),
+        exit_code: None,
+        failure_reason: None,
+        attempts: Vec::new(),
+        test_name: None,
+        test_stdout_truncated: false,
+        test_stderr_truncated: false,
+    }
 }
 
 async fn export_test(
     item: &valuer_api::JudgeLogTestRow,
     exec_outcome: Option<&ExecOutcome>,
     resource_usage_by_test: &HashMap<pom::TestId, ResourceUsage>,
+    kind: JudgeLogKind,
     problem: &pom::Problem,
     file_ref_resolver: &crate::FileRefResolver,
 ) -> anyhow::Result<judge_log::JudgeLogTestRow> {
-    let mut new_item = judge_log::JudgeLogTestRow {
-        test_id: item.test_id,
-        test_answer: None,
-        test_stdout: None,
-        test_stderr: None,
-        test_stdin: None,
-        status: None,
-        time_usage: None,
-        memory_usage: None,
-    };
+    let mut new_item = empty_test_row(item.test_id);
     if item.components.contains(TestVisibleComponents::STATUS) {
         new_item.status = Some(item.status.clone());
     }
@@ -105,10 +221,16 @@ async fn export_test(
         new_item.test_stdin = Some(test_data);
     }
     if item.components.contains(TestVisibleComponents::OUTPUT) {
-        let sol_stdout = base64::encode(&exec_outcome.stdout);
-        let sol_stderr = base64::encode(&exec_outcome.stderr);
-        new_item.test_stdout = Some(sol_stdout);
-        new_item.test_stderr = Some(sol_stderr);
+        match &exec_outcome.stdout {
+            CapturedOutput::Inline(data) => new_item.test_stdout = Some(base64::encode(data)),
+            CapturedOutput::Stored(id) => new_item.test_stdout_ref = Some(id.clone()),
+        }
+        match &exec_outcome.stderr {
+            CapturedOutput::Inline(data) => new_item.test_stderr = Some(base64::encode(data)),
+            CapturedOutput::Stored(id) => new_item.test_stderr_ref = Some(id.clone()),
+        }
+        new_item.test_stdout_truncated = exec_outcome.stdout_truncated;
+        new_item.test_stderr_truncated = exec_outcome.stderr_truncated;
     }
     if item.components.contains(TestVisibleComponents::ANSWER) {
         let answer_ref = &problem.tests[item.test_id].correct;
@@ -130,5 +252,25 @@ async fn export_test(
             new_item.time_usage = resource_usage.time;
         }
     }
+    // comment of a checker which failed is only meant for judges
+    let judge_fault = item.status.kind == StatusKind::InternalError;
+    if ExtraComponent::CheckerComment.is_visible(item.components)
+        && (kind == JudgeLogKind::Full || !judge_fault)
+    {
+        new_item.checker_comment = exec_outcome.checker_comment.clone();
+    }
+    if kind == JudgeLogKind::Full || ExtraComponent::FailureDetails.is_visible(item.components) {
+        new_item.exit_code = exec_outcome.exit_code;
+        new_item.failure_reason = exec_outcome.failure_reason.clone();
+    }
+    // samples, pipeline logs, violations, invoker steps and attempts are
+    // too detailed for contestants
+    if kind == JudgeLogKind::Full {
+        new_item.memory_samples = exec_outcome.memory_samples.clone();
+        new_item.pipeline = exec_outcome.pipeline.clone();
+        new_item.security_violations = exec_outcome.security_violations.clone();
+        new_item.invoker_steps = exec_outcome.invoker_steps.clone();
+        new_item.attempts = exec_outcome.attempts.clone();
+    }
     Ok(new_item)
 }
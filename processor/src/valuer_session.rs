@@ -0,0 +1,192 @@
+//! Valuer connection which survives valuer crashes.
+//!
+//! All messages sent to valuer are recorded, so that when valuer dies,
+//! a new instance can be started and brought to the same state.
+use crate::{debug_dump::DebugDump, Warnings};
+use anyhow::Context;
+use std::collections::HashSet;
+use valuer_api::{ProblemInfo, Status, TestDoneNotification, ValuerResponse};
+use valuer_client::{ClientConfig, Extensions, ValuerClient, ValuerMessage};
+
+/// Message of valuer which judge must act upon
+pub(crate) enum Polled {
+    Response(ValuerResponse),
+    /// Batch of tests which may be run concurrently
+    Tests(Vec<pom::TestId>),
+}
+
+pub(crate) struct ValuerSession {
+    config: ClientConfig,
+    client: ValuerClient,
+    /// Test groups, as sent in `ProblemInfo`
+    tests: Vec<String>,
+    /// Sent together with `ProblemInfo`
+    extensions: Extensions,
+    /// All notifications sent so far
+    done: Vec<(pom::TestId, Status)>,
+    restarts_left: u32,
+    warnings: Warnings,
+    /// Groups whose remaining tests valuer allowed to skip
+    skipped_groups: HashSet<String>,
+    /// If set, all messages are dumped there
+    debug_dump: Option<DebugDump>,
+}
+
+impl ValuerSession {
+    pub(crate) async fn new(
+        config: ClientConfig,
+        tests: Vec<String>,
+        extensions: Extensions,
+        restart_limit: u32,
+        warnings: Warnings,
+        debug_dump: Option<DebugDump>,
+    ) -> anyhow::Result<Self> {
+        let mut client = ValuerClient::new(&config)
+            .await
+            .context("failed to initialize valuer")?;
+        if debug_dump.is_some() {
+            client.record_transcript();
+        }
+        let res = client
+            .write_problem_data(
+                &ProblemInfo {
+                    tests: tests.clone(),
+                },
+                &extensions,
+            )
+            .await
+            .context("failed to send problem info to valuer");
+        let mut session = ValuerSession {
+            config,
+            client,
+            tests,
+            extensions,
+            done: Vec::new(),
+            restarts_left: restart_limit,
+            warnings,
+            skipped_groups: HashSet::new(),
+            debug_dump,
+        };
+        session.dump_transcript().await;
+        res?;
+        Ok(session)
+    }
+
+    /// Writes messages exchanged so far to the debug dump, if enabled
+    async fn dump_transcript(&mut self) {
+        if let Some(dump) = &self.debug_dump {
+            dump.valuer_messages(self.client.take_transcript()).await;
+        }
+    }
+
+    /// Returns true if valuer was already notified that this test is done.
+    pub(crate) fn is_done(&self, test_id: pom::TestId) -> bool {
+        self.done.iter().any(|(tid, _)| *tid == test_id)
+    }
+
+    /// Returns true if valuer allowed to skip remaining tests of `group`
+    pub(crate) fn is_group_skipped(&self, group: &str) -> bool {
+        self.skipped_groups.contains(group)
+    }
+
+    pub(crate) fn skipped_groups(&self) -> &HashSet<String> {
+        &self.skipped_groups
+    }
+
+    /// Returns next response of the base protocol or a batch of tests.
+    /// Other hints are recorded.
+    pub(crate) async fn poll(&mut self) -> anyhow::Result<Polled> {
+        loop {
+            let res = self.client.poll().await;
+            self.dump_transcript().await;
+            let err = match res {
+                Ok(ValuerMessage::Response(resp)) => return Ok(Polled::Response(resp)),
+                Ok(ValuerMessage::Tests { ids }) => return Ok(Polled::Tests(ids)),
+                Ok(ValuerMessage::SkipRemainingInGroup { group }) => {
+                    tracing::debug!(group = %group, "valuer allowed skipping remaining tests of group");
+                    self.skipped_groups.insert(group);
+                    continue;
+                }
+                Err(err) => err,
+            };
+            self.restart(err).await?;
+        }
+    }
+
+    pub(crate) async fn notify_test_done(
+        &mut self,
+        test_id: pom::TestId,
+        test_status: Status,
+    ) -> anyhow::Result<()> {
+        self.done.push((test_id, test_status.clone()));
+        let res = self
+            .client
+            .notify_test_done(TestDoneNotification {
+                test_id,
+                test_status,
+            })
+            .await;
+        self.dump_transcript().await;
+        match res {
+            Ok(()) => Ok(()),
+            // notification is already recorded, so it will be replayed
+            Err(err) => self.restart(err).await,
+        }
+    }
+
+    /// Starts new valuer instance and replays all messages sent so far.
+    /// `cause` is the error which made previous instance unusable.
+    async fn restart(&mut self, cause: anyhow::Error) -> anyhow::Result<()> {
+        loop {
+            if self.restarts_left == 0 {
+                return Err(cause.context("valuer failed and restart limit is exhausted"));
+            }
+            self.restarts_left -= 1;
+            self.warnings.report(
+                "valuer-restarted",
+                format!("valuer failed, restarting: {:#}", cause),
+            );
+            match self.try_replay().await {
+                Ok(client) => {
+                    self.client = client;
+                    self.dump_transcript().await;
+                    return Ok(());
+                }
+                Err(err) => {
+                    self.warnings.report(
+                        "valuer-restart-failed",
+                        format!("valuer restart failed: {:#}", err),
+                    );
+                }
+            }
+        }
+    }
+
+    async fn try_replay(&self) -> anyhow::Result<ValuerClient> {
+        let mut client = ValuerClient::new(&self.config)
+            .await
+            .context("failed to initialize valuer")?;
+        if self.debug_dump.is_some() {
+            client.record_transcript();
+        }
+        client
+            .write_problem_data(
+                &ProblemInfo {
+                    tests: self.tests.clone(),
+                },
+                &self.extensions,
+            )
+            .await
+            .context("failed to replay problem info")?;
+        for (test_id, test_status) in &self.done {
+            client
+                .notify_test_done(TestDoneNotification {
+                    test_id: *test_id,
+                    test_status: test_status.clone(),
+                })
+                .await
+                .with_context(|| format!("failed to replay notification for test {}", test_id))?;
+        }
+        Ok(client)
+    }
+}
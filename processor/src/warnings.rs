@@ -0,0 +1,93 @@
+//! Aggregation of recurring warnings.
+//!
+//! The same problem (e.g. misconfigured problem or unavailable debug dump
+//! directory) is usually hit by every job. Such warnings are deduplicated by
+//! fingerprint: all occurrences are counted, but each fingerprint is written
+//! to the log at most once per configured interval.
+use judge_apis::admin::WarningSummary;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+struct Entry {
+    summary: WarningSummary,
+    last_emitted: Instant,
+    /// Occurrences which were not written to the log since `last_emitted`
+    suppressed: u64,
+}
+
+/// Shared warnings registry
+#[derive(Clone)]
+pub struct Warnings {
+    emit_interval: Duration,
+    entries: Arc<Mutex<HashMap<&'static str, Entry>>>,
+}
+
+fn unix_time_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl Warnings {
+    pub fn new(emit_interval: Duration) -> Warnings {
+        Warnings {
+            emit_interval,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Records a warning. `fingerprint` identifies kind of the problem,
+    /// `message` describes this particular occurrence.
+    pub fn report(&self, fingerprint: &'static str, message: String) {
+        let now = unix_time_ms();
+        let mut entries = self.entries.lock().unwrap();
+        let entry = match entries.get_mut(fingerprint) {
+            Some(entry) => entry,
+            None => {
+                tracing::warn!(fingerprint, "{}", message);
+                entries.insert(
+                    fingerprint,
+                    Entry {
+                        summary: WarningSummary {
+                            fingerprint: fingerprint.to_string(),
+                            message,
+                            count: 1,
+                            first_seen_ms: now,
+                            last_seen_ms: now,
+                        },
+                        last_emitted: Instant::now(),
+                        suppressed: 0,
+                    },
+                );
+                return;
+            }
+        };
+        entry.summary.count += 1;
+        entry.summary.last_seen_ms = now;
+        entry.summary.message = message;
+        if entry.last_emitted.elapsed() < self.emit_interval {
+            entry.suppressed += 1;
+            return;
+        }
+        tracing::warn!(
+            fingerprint,
+            suppressed = entry.suppressed,
+            "{}",
+            entry.summary.message
+        );
+        entry.last_emitted = Instant::now();
+        entry.suppressed = 0;
+    }
+
+    /// Returns all recorded warnings, most recent first
+    pub fn summaries(&self) -> Vec<WarningSummary> {
+        let entries = self.entries.lock().unwrap();
+        let mut summaries: Vec<_> = entries.values().map(|e| e.summary.clone()).collect();
+        summaries.sort_by_key(|s| std::cmp::Reverse(s.last_seen_ms));
+        summaries
+    }
+}
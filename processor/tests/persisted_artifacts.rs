@@ -0,0 +1,227 @@
+//! Jobs which compile runs into artifacts persisted by invoker.
+//!
+//! Invoker is emulated by a minimal HTTP server which reports persistent
+//! files and answers every command with success. Judging stops at the
+//! valuer, which exits at once, so the job finishes with a judge fault.
+
+use invoker_api::invoke::{
+    Action, ActionResult, CommandResult, InvokeRequest, InvokeResponse, Output, OutputData,
+};
+use processor::{invoker_client, problem_loader, toolchain_loader, Event};
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+const JOB_TIMEOUT: Duration = Duration::from_secs(60);
+
+const TOOLCHAIN_MANIFEST: &str = r#"
+schema-version: 2
+title: Test
+name: test
+filename: source.txt
+build:
+  - argv: ["true"]
+run:
+  argv: ["/compile-output/bin"]
+"#;
+
+fn problem_manifest() -> serde_json::Value {
+    let asset = |path: &str| serde_json::json!({ "path": path, "root": "Problem" });
+    serde_json::json!({
+        "title": "Test",
+        "name": "test",
+        "checker_exe": asset("checker"),
+        "checker_cmd": [],
+        "valuer": {
+            "Child": {
+                "exe": asset("valuer"),
+                "extra_args": [],
+                "current_dir": null,
+            }
+        },
+        "tests": [{
+            "path": asset("1.txt"),
+            "correct": null,
+            "limits": {},
+            "group": "main",
+        }],
+    })
+}
+
+fn write_executable(path: &Path, script: &str) {
+    std::fs::write(path, script).unwrap();
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Creates toolchain `test` and problem `test` in `dir`
+fn create_data(dir: &Path) {
+    let toolchain_dir = dir.join("toolchains/test");
+    std::fs::create_dir_all(&toolchain_dir).unwrap();
+    std::fs::write(toolchain_dir.join("manifest.yaml"), TOOLCHAIN_MANIFEST).unwrap();
+    std::fs::write(toolchain_dir.join("image.txt"), "test-image").unwrap();
+
+    let problem_dir = dir.join("problems/test");
+    let assets = problem_dir.join("assets");
+    std::fs::create_dir_all(&assets).unwrap();
+    std::fs::write(
+        problem_dir.join("manifest.json"),
+        serde_json::to_vec(&problem_manifest()).unwrap(),
+    )
+    .unwrap();
+    std::fs::write(assets.join("1.txt"), "").unwrap();
+    write_executable(&assets.join("checker"), "#!/bin/sh\nexit 0\n");
+    write_executable(&assets.join("valuer"), "#!/bin/sh\nexit 1\n");
+}
+
+fn exec(req: &InvokeRequest) -> InvokeResponse {
+    let actions = req
+        .steps
+        .iter()
+        .map(|step| match step.action {
+            Action::ExecuteCommand(_) => ActionResult::ExecuteCommand(CommandResult {
+                exit_code: 0,
+                spawn_error: None,
+                cpu_time: Some(0),
+                memory: Some(0),
+            }),
+            _ => ActionResult::Other,
+        })
+        .collect();
+    let outputs = req
+        .outputs
+        .iter()
+        .map(|out| Output {
+            name: out.name.clone(),
+            data: OutputData::InlineBase64(String::new()),
+        })
+        .collect();
+    InvokeResponse {
+        id: req.id,
+        actions,
+        outputs,
+    }
+}
+
+async fn handle_connection(stream: TcpStream, files_dir: PathBuf) {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if stream.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+            return;
+        }
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        stream.read_exact(&mut body).await.unwrap();
+        let (status, response) = if request_line.starts_with("GET /capabilities ") {
+            let caps = invoker_client::Capabilities {
+                persistent_files_dir: Some(files_dir.clone()),
+                extensions: Vec::new(),
+            };
+            ("200 OK", serde_json::to_vec(&caps).unwrap())
+        } else if request_line.starts_with("POST /exec ") {
+            let req: InvokeRequest = serde_json::from_slice(&body).unwrap();
+            ("200 OK", serde_json::to_vec(&exec(&req)).unwrap())
+        } else {
+            ("404 Not Found", Vec::new())
+        };
+        let head = format!(
+            "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n",
+            status,
+            response.len()
+        );
+        let stream = stream.get_mut();
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(&response).await.unwrap();
+    }
+}
+
+/// Starts fake invoker and returns its address
+async fn start_invoker(files_dir: PathBuf) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(handle_connection(stream, files_dir.clone()));
+        }
+    });
+    format!("http://{}", addr)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn job_with_persisted_artifacts_completes() {
+    let dir = std::env::temp_dir().join(format!("judge-test-{}", uuid::Uuid::new_v4()));
+    create_data(&dir);
+    let files_dir = dir.join("invoker-files");
+    std::fs::create_dir_all(&files_dir).unwrap();
+
+    let mut invokers = invoker_client::Client::builder();
+    invokers.add(invoker_client::Pool::new_from_address(
+        &start_invoker(files_dir).await,
+    ));
+    let toolchains = toolchain_loader::ToolchainLoader::new(&dir.join("toolchains"))
+        .await
+        .unwrap();
+    let problems = problem_loader::Loader::from_config(
+        &problem_loader::LoaderConfig {
+            fs: Some(dir.join("problems")),
+            mongodb: None,
+        },
+        dir.join("problems-cache"),
+    )
+    .await
+    .unwrap();
+    let clients = processor::Clients::builder()
+        .invokers(invokers.build())
+        .toolchains(toolchains)
+        .problems(problems)
+        .build()
+        .unwrap();
+    let mut settings = processor::Settings::new("test");
+    settings.valuer_restart_limit = 0;
+
+    let request = processor::Request::new("test", "test", b"source".to_vec());
+    let mut progress = processor::judge(request, clients, settings);
+    // compiled run is kept after the job, like REST service does for
+    // later phases
+    let mut compiled = None;
+    loop {
+        let event = tokio::time::timeout(JOB_TIMEOUT, progress.event())
+            .await
+            .expect("job did not finish");
+        match event {
+            Some(event) => {
+                if let Event::Compiled(run) = event.event {
+                    compiled = Some(run);
+                }
+            }
+            None => break,
+        }
+    }
+    tokio::time::timeout(JOB_TIMEOUT, progress.wait())
+        .await
+        .expect("job did not report outcome");
+    assert!(compiled.is_some(), "run was not compiled");
+
+    drop(compiled);
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,203 @@
+//! Export of judging events to external sinks (e.g. Kafka) for analytics.
+//!
+//! Events are queued and sent in background, so slow sinks do not delay
+//! judging. If the queue is full, events are dropped.
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+
+use anyhow::Context;
+use futures::future::{BoxFuture, FutureExt};
+use judge_apis::events::{JudgeEvent, JudgeEventKind};
+use std::{
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{mpsc, Mutex},
+};
+use uuid::Uuid;
+
+const QUEUE_CAPACITY: usize = 4096;
+
+/// Destination of exported events
+pub trait EventSink: Send + Sync + 'static {
+    /// Sends serialized event. `key` is the job id, so that all events
+    /// of a job are kept in order by partitioned sinks.
+    fn send<'a>(&'a self, key: &'a str, payload: Vec<u8>) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// Serialization of exported events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    /// Binary Avro encoding without header, see `AVRO_SCHEMA`
+    Avro,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "json" => Ok(Format::Json),
+            "avro" => Ok(Format::Avro),
+            _ => anyhow::bail!("unknown event format {:?}, expected one of: json, avro", s),
+        }
+    }
+}
+
+/// Schema of Avro-encoded events. Type-specific fields are put to `data`
+/// as a JSON object.
+pub const AVRO_SCHEMA: &str = r#"{
+  "type": "record",
+  "name": "JudgeEvent",
+  "namespace": "io.jjs.judge",
+  "fields": [
+    {"name": "job_id", "type": "string"},
+    {"name": "judge_id", "type": "string"},
+    {"name": "timestamp", "type": {"type": "long", "logicalType": "timestamp-millis"}},
+    {"name": "type", "type": "string"},
+    {"name": "data", "type": "string"}
+  ]
+}"#;
+
+fn avro_long(out: &mut Vec<u8>, value: i64) {
+    let mut n = ((value << 1) ^ (value >> 63)) as u64;
+    while n >= 0x80 {
+        out.push((n as u8 & 0x7f) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn avro_string(out: &mut Vec<u8>, value: &str) {
+    avro_long(out, value.len() as i64);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn encode(event: &JudgeEvent, format: Format) -> anyhow::Result<Vec<u8>> {
+    match format {
+        Format::Json => Ok(serde_json::to_vec(event)?),
+        Format::Avro => {
+            let mut data = serde_json::to_value(&event.kind)?;
+            if let Some(fields) = data.as_object_mut() {
+                fields.remove("type");
+            }
+            let mut out = Vec::new();
+            avro_string(&mut out, &event.job_id.to_hyphenated().to_string());
+            avro_string(&mut out, &event.judge_id);
+            avro_long(&mut out, event.timestamp as i64);
+            avro_string(&mut out, event.kind.name());
+            avro_string(&mut out, &serde_json::to_string(&data)?);
+            Ok(out)
+        }
+    }
+}
+
+/// Appends events to a file, one per line. Only suitable for JSON.
+pub struct FileSink {
+    file: Mutex<tokio::fs::File>,
+}
+
+impl FileSink {
+    pub async fn open(path: &Path) -> anyhow::Result<FileSink> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        Ok(FileSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl EventSink for FileSink {
+    fn send<'a>(
+        &'a self,
+        _key: &'a str,
+        mut payload: Vec<u8>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            payload.push(b'\n');
+            let mut file = self.file.lock().await;
+            file.write_all(&payload).await?;
+            file.flush().await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Creates event of the job which happened now
+pub fn new_event(judge_id: &str, job_id: Uuid, kind: JudgeEventKind) -> JudgeEvent {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    JudgeEvent {
+        job_id,
+        judge_id: judge_id.to_string(),
+        timestamp,
+        kind,
+    }
+}
+
+pub struct EventExporter {
+    tx: mpsc::Sender<JudgeEvent>,
+    warnings: processor::Warnings,
+}
+
+impl EventExporter {
+    /// Creates exporter and starts background sending task
+    pub fn new(
+        sink: Box<dyn EventSink>,
+        format: Format,
+        warnings: processor::Warnings,
+    ) -> EventExporter {
+        if format == Format::Avro {
+            tracing::info!(schema = AVRO_SCHEMA, "exporting events in Avro format");
+        }
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::task::spawn(run(sink, format, rx, warnings.clone()));
+        EventExporter { tx, warnings }
+    }
+
+    /// Queues event for export
+    pub fn emit(&self, event: JudgeEvent) {
+        if self.tx.try_send(event).is_err() {
+            self.warnings.report(
+                "event-export-overflow",
+                "event export queue is full, dropping events".to_string(),
+            );
+        }
+    }
+}
+
+async fn run(
+    sink: Box<dyn EventSink>,
+    format: Format,
+    mut rx: mpsc::Receiver<JudgeEvent>,
+    warnings: processor::Warnings,
+) {
+    while let Some(event) = rx.recv().await {
+        let payload = match encode(&event, format) {
+            Ok(p) => p,
+            Err(err) => {
+                tracing::error!("failed to serialize event: {:#}", err);
+                continue;
+            }
+        };
+        let key = event.job_id.to_hyphenated().to_string();
+        if let Err(err) = sink.send(&key, payload).await {
+            warnings.report(
+                "event-export-failed",
+                format!("failed to export event: {:#}", err),
+            );
+        }
+    }
+}
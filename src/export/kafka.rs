@@ -0,0 +1,42 @@
+//! Kafka event sink
+
+use super::EventSink;
+use anyhow::Context;
+use futures::future::{BoxFuture, FutureExt};
+use rdkafka::{
+    producer::{FutureProducer, FutureRecord},
+    ClientConfig,
+};
+use std::time::Duration;
+
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaSink {
+    /// `brokers` is a comma-separated list of `host:port`
+    pub fn new(brokers: &str, topic: String) -> anyhow::Result<KafkaSink> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", "30000")
+            .create()
+            .context("failed to create kafka producer")?;
+        Ok(KafkaSink { producer, topic })
+    }
+}
+
+impl EventSink for KafkaSink {
+    fn send<'a>(&'a self, key: &'a str, payload: Vec<u8>) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let record = FutureRecord::to(&self.topic).key(key).payload(&payload);
+            self.producer
+                .send(record, Duration::from_secs(5))
+                .await
+                .map_err(|(err, _message)| err)
+                .with_context(|| format!("failed to send event to topic {}", self.topic))?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,156 @@
+//! Storage for run sources and judging events of jobs.
+//!
+//! They are only needed by later phases and job export, so they are kept
+//! on disk if a directory is configured: `${dir}/${job_id}/source`
+//! contains the run source and `${dir}/${job_id}/events.jsonl` contains
+//! events, one per line. Otherwise they are kept in memory.
+
+use anyhow::Context;
+use judge_apis::events::JudgeEvent;
+use std::{collections::HashMap, path::PathBuf};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+#[derive(Default)]
+struct InMemoryFiles {
+    source: Vec<u8>,
+    events: Vec<JudgeEvent>,
+}
+
+pub struct JobFiles {
+    dir: Option<PathBuf>,
+    /// Used if `dir` is not set
+    memory: std::sync::Mutex<HashMap<Uuid, InMemoryFiles>>,
+}
+
+impl JobFiles {
+    pub fn new(dir: Option<PathBuf>) -> JobFiles {
+        JobFiles {
+            dir,
+            memory: Default::default(),
+        }
+    }
+
+    fn job_dir(&self, job_id: Uuid) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(job_id.to_hyphenated().to_string()))
+    }
+
+    async fn create_job_dir(&self, job_id: Uuid) -> anyhow::Result<Option<PathBuf>> {
+        let dir = match self.job_dir(job_id) {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("failed to create directory {}", dir.display()))?;
+        Ok(Some(dir))
+    }
+
+    /// Stores run source of the job, replacing previous one
+    pub async fn put_source(&self, job_id: Uuid, source: &[u8]) -> anyhow::Result<()> {
+        let dir = match self.create_job_dir(job_id).await? {
+            Some(d) => d,
+            None => {
+                let mut memory = self.memory.lock().unwrap();
+                memory.entry(job_id).or_default().source = source.to_vec();
+                return Ok(());
+            }
+        };
+        let path = dir.join("source");
+        tokio::fs::write(&path, source)
+            .await
+            .with_context(|| format!("failed to write run source to {}", path.display()))
+    }
+
+    /// Returns run source of the job, or empty source if it is unknown
+    pub async fn source(&self, job_id: Uuid) -> anyhow::Result<Vec<u8>> {
+        let path = match self.job_dir(job_id) {
+            Some(d) => d.join("source"),
+            None => {
+                let memory = self.memory.lock().unwrap();
+                return Ok(memory
+                    .get(&job_id)
+                    .map(|f| f.source.clone())
+                    .unwrap_or_default());
+            }
+        };
+        match tokio::fs::read(&path).await {
+            Ok(source) => Ok(source),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => {
+                Err(err).with_context(|| format!("failed to read run source {}", path.display()))
+            }
+        }
+    }
+
+    /// Appends events to the events of the job
+    pub async fn append_events(&self, job_id: Uuid, events: &[JudgeEvent]) -> anyhow::Result<()> {
+        let dir = match self.create_job_dir(job_id).await? {
+            Some(d) => d,
+            None => {
+                let mut memory = self.memory.lock().unwrap();
+                let files = memory.entry(job_id).or_default();
+                files.events.extend_from_slice(events);
+                return Ok(());
+            }
+        };
+        let mut data = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut data, event).context("failed to serialize event")?;
+            data.push(b'\n');
+        }
+        let path = dir.join("events.jsonl");
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        file.write_all(&data)
+            .await
+            .with_context(|| format!("failed to write events to {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Returns all events of the job, oldest first
+    pub async fn events(&self, job_id: Uuid) -> anyhow::Result<Vec<JudgeEvent>> {
+        let path = match self.job_dir(job_id) {
+            Some(d) => d.join("events.jsonl"),
+            None => {
+                let memory = self.memory.lock().unwrap();
+                return Ok(memory
+                    .get(&job_id)
+                    .map(|f| f.events.clone())
+                    .unwrap_or_default());
+            }
+        };
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", path.display()));
+            }
+        };
+        data.split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).context("failed to parse stored event"))
+            .collect()
+    }
+
+    /// Removes run source and events of the job
+    pub async fn remove_job(&self, job_id: Uuid) -> anyhow::Result<()> {
+        let dir = match self.job_dir(job_id) {
+            Some(d) => d,
+            None => {
+                self.memory.lock().unwrap().remove(&job_id);
+                return Ok(());
+            }
+        };
+        match tokio::fs::remove_dir_all(&dir).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("failed to remove {}", dir.display())),
+        }
+    }
+}
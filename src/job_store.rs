@@ -0,0 +1,160 @@
+//! Persistence of judge jobs.
+//!
+//! Jobs are written to the store when they are created, when they finish
+//! and when administrators change them. On startup all stored jobs are
+//! restored, so that they can still be queried after a restart. Jobs which
+//! were running when judge stopped are restored as failed.
+
+pub mod mongo;
+
+use anyhow::Context;
+use futures::future::{BoxFuture, FutureExt};
+use judge_apis::{
+    admin::VerdictOverride,
+    judge_log::{JudgeLog, Status},
+    live::{LiveResources, Score},
+    rest::{ByteString, FaultInfo, JobPriority, JudgingMode},
+    usage::{CostEstimate, Usage},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::PathBuf, time::SystemTime};
+use uuid::Uuid;
+
+/// Persisted state of a job
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobRecord {
+    pub id: Uuid,
+    pub problem_id: String,
+    pub toolchain_name: String,
+    pub phase: Option<String>,
+    pub phases: Vec<Option<String>>,
+    pub locale: Option<String>,
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub image_override: Option<String>,
+    #[serde(default)]
+    pub judging_mode: JudgingMode,
+    #[serde(default)]
+    pub priority: JobPriority,
+    #[serde(default)]
+    pub debug: bool,
+    /// Source of the run, if it is known
+    #[serde(default)]
+    pub run_source: Option<ByteString>,
+    /// Whether the job was imported from an archive
+    #[serde(default)]
+    pub imported: bool,
+    /// Whether the problem was loaded bypassing registries
+    #[serde(default)]
+    pub local_problem: bool,
+    pub created_at: SystemTime,
+    pub finished_at: Option<SystemTime>,
+    pub live_test: Option<pom::TestId>,
+    /// Display name of `live_test`
+    #[serde(default)]
+    pub live_test_name: Option<String>,
+    pub live_score: Option<Score>,
+    #[serde(default)]
+    pub live_resources: Option<LiveResources>,
+    pub annotations: HashMap<String, String>,
+    pub completed: bool,
+    /// Error message, if the job has failed
+    pub error: Option<String>,
+    /// Classification of the error, computed before it was stringified
+    pub fault: Option<FaultInfo>,
+    pub test_statuses: Vec<(pom::TestId, Status)>,
+    pub overrides: Vec<VerdictOverride>,
+    pub judge_id: String,
+    pub frozen: bool,
+    pub usage: Usage,
+    #[serde(default)]
+    pub estimated_cost: Option<CostEstimate>,
+    /// All logs of the job, oldest first
+    pub logs: Vec<JudgeLog>,
+}
+
+/// Backend keeping job records
+pub trait JobStore: Send + Sync + 'static {
+    /// Inserts record or replaces previous record of the same job
+    fn put<'a>(&'a self, record: &'a JobRecord) -> BoxFuture<'a, anyhow::Result<()>>;
+
+    /// Returns all stored records
+    fn load_all(&self) -> BoxFuture<'_, anyhow::Result<Vec<JobRecord>>>;
+
+    /// Removes record of the job, if it exists
+    fn delete(&self, id: Uuid) -> BoxFuture<'_, anyhow::Result<()>>;
+}
+
+/// Keeps each job in `${dir}/${job_id}.json`
+pub struct FsJobStore {
+    dir: PathBuf,
+}
+
+impl FsJobStore {
+    pub async fn new(dir: PathBuf) -> anyhow::Result<FsJobStore> {
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("failed to create job store {}", dir.display()))?;
+        Ok(FsJobStore { dir })
+    }
+}
+
+impl JobStore for FsJobStore {
+    fn put<'a>(&'a self, record: &'a JobRecord) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let data = serde_json::to_vec(record).context("failed to serialize job record")?;
+            let path = self.dir.join(format!("{}.json", record.id));
+            // readers must never observe partially written record
+            let tmp_path = self.dir.join(format!(".{}.json.tmp", record.id));
+            tokio::fs::write(&tmp_path, data)
+                .await
+                .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+            tokio::fs::rename(&tmp_path, &path)
+                .await
+                .with_context(|| format!("failed to move job record to {}", path.display()))
+        }
+        .boxed()
+    }
+
+    fn load_all(&self) -> BoxFuture<'_, anyhow::Result<Vec<JobRecord>>> {
+        async move {
+            let mut records = Vec::new();
+            let mut entries = tokio::fs::read_dir(&self.dir)
+                .await
+                .with_context(|| format!("failed to list {}", self.dir.display()))?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                let is_record = path.extension().and_then(|ext| ext.to_str()) == Some("json")
+                    && !entry.file_name().to_string_lossy().starts_with('.');
+                if !is_record {
+                    continue;
+                }
+                let data = tokio::fs::read(&path)
+                    .await
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                match serde_json::from_slice(&data) {
+                    Ok(record) => records.push(record),
+                    Err(err) => {
+                        tracing::warn!(path = %path.display(), "skipping invalid job record: {}", err)
+                    }
+                }
+            }
+            Ok(records)
+        }
+        .boxed()
+    }
+
+    fn delete(&self, id: Uuid) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let path = self.dir.join(format!("{}.json", id));
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => Ok(()),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                Err(err) => {
+                    Err(err).with_context(|| format!("failed to remove {}", path.display()))
+                }
+            }
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,103 @@
+//! MongoDB job store
+
+use super::{JobRecord, JobStore};
+use anyhow::Context;
+use futures::{
+    future::{BoxFuture, FutureExt},
+    stream::TryStreamExt,
+};
+
+/// Keeps each job in a document of the `jobs` collection. Record is
+/// stored as serialized JSON in the `record` field.
+pub struct MongoJobStore {
+    collection: mongodb::Collection,
+}
+
+impl MongoJobStore {
+    pub async fn new(connection_string: &str) -> anyhow::Result<MongoJobStore> {
+        let client = mongodb::Client::with_uri_str(connection_string)
+            .await
+            .context("database is not available")?;
+        let collection = client.database("jjs").collection("jobs");
+        Ok(MongoJobStore { collection })
+    }
+}
+
+impl JobStore for MongoJobStore {
+    fn put<'a>(&'a self, record: &'a JobRecord) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            let data = serde_json::to_vec(record).context("failed to serialize job record")?;
+            let filter = {
+                let mut filter = bson::Document::new();
+                filter.insert("job-id", record.id.to_string());
+                filter
+            };
+            let doc = {
+                let mut doc = filter.clone();
+                doc.insert(
+                    "record",
+                    bson::Binary {
+                        subtype: bson::spec::BinarySubtype::Generic,
+                        bytes: data,
+                    },
+                );
+                doc
+            };
+            self.collection
+                .replace_one(
+                    filter,
+                    doc,
+                    mongodb::options::ReplaceOptions::builder()
+                        .upsert(true)
+                        .build(),
+                )
+                .await
+                .context("failed to store job document")?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn load_all(&self) -> BoxFuture<'_, anyhow::Result<Vec<JobRecord>>> {
+        async move {
+            let mut cursor = self
+                .collection
+                .find(None, None)
+                .await
+                .context("failed to query job documents")?;
+            let mut records = Vec::new();
+            while let Some(doc) = cursor
+                .try_next()
+                .await
+                .context("failed to fetch job document")?
+            {
+                let data = doc
+                    .get_binary_generic("record")
+                    .context("storage schema violation for field `record`")?;
+                match serde_json::from_slice(data) {
+                    Ok(record) => records.push(record),
+                    Err(err) => tracing::warn!(
+                        job_id = ?doc.get_str("job-id").ok(),
+                        "skipping invalid job record: {}",
+                        err
+                    ),
+                }
+            }
+            Ok(records)
+        }
+        .boxed()
+    }
+
+    fn delete(&self, id: uuid::Uuid) -> BoxFuture<'_, anyhow::Result<()>> {
+        async move {
+            let mut filter = bson::Document::new();
+            filter.insert("job-id", id.to_string());
+            self.collection
+                .delete_one(filter, None)
+                .await
+                .context("failed to delete job document")?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,316 @@
+//! Storage for judge logs produced by jobs.
+//!
+//! Logs are kept in memory serialized and gzip-compressed, because test
+//! data encoded in base64 makes them huge. When total size exceeds
+//! configured limit, oldest logs are spilled to disk. Spilled logs are
+//! written without holding the storage lock, and their files are removed
+//! together with the logs.
+
+use anyhow::Context;
+use judge_apis::judge_log::JudgeLog;
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+pub struct LogStorageConfig {
+    /// Maximum total size (in bytes) of logs kept in memory.
+    /// None means logs are never spilled.
+    pub memory_limit: Option<u64>,
+    /// ${spill_dir}/${job_id}/${log_kind}-${n}.json.gz will contain spilled
+    /// log, where `n` is unique for each spill.
+    pub spill_dir: Option<PathBuf>,
+}
+
+enum StoredLog {
+    /// Serialized and compressed log, shared with readers which decode it
+    InMemory(Arc<Vec<u8>>),
+    /// Log was moved to this file
+    Spilled(PathBuf),
+}
+
+type LogKey = (Uuid, String);
+
+struct Inner {
+    logs: HashMap<LogKey, StoredLog>,
+    /// Keys of logs which are still in memory, oldest first
+    in_memory: VecDeque<LogKey>,
+    /// Total (compressed) size of in-memory logs
+    memory_usage: u64,
+    /// Total size of in-memory logs which are being written to disk
+    spilling: u64,
+    /// Used to give spilled files unique names
+    next_spill_id: u64,
+}
+
+impl Inner {
+    fn insert(&mut self, key: LogKey, data: Vec<u8>) {
+        self.memory_usage += data.len() as u64;
+        self.in_memory.push_back(key.clone());
+        self.logs.insert(key, StoredLog::InMemory(Arc::new(data)));
+    }
+}
+
+/// In-memory log chosen to be spilled
+struct Victim {
+    key: LogKey,
+    data: Arc<Vec<u8>>,
+    path: PathBuf,
+}
+
+pub struct LogStorage {
+    config: LogStorageConfig,
+    inner: Mutex<Inner>,
+    warnings: processor::Warnings,
+}
+
+impl LogStorage {
+    pub fn new(config: LogStorageConfig, warnings: processor::Warnings) -> LogStorage {
+        if config.memory_limit.is_some() && config.spill_dir.is_none() {
+            tracing::warn!(
+                "logs memory limit is set, but spill directory is not; limit will not be enforced"
+            );
+        }
+        LogStorage {
+            config,
+            inner: Mutex::new(Inner {
+                logs: HashMap::new(),
+                in_memory: VecDeque::new(),
+                memory_usage: 0,
+                spilling: 0,
+                next_spill_id: 0,
+            }),
+            warnings,
+        }
+    }
+
+    /// Stores a log of the job
+    pub async fn put(&self, job_id: Uuid, log: &JudgeLog) -> anyhow::Result<()> {
+        let data = encode(log)?;
+        let key = (job_id, log.name());
+        let victims = {
+            let mut inner = self.inner.lock().await;
+            assert!(!inner.logs.contains_key(&key), "bug: log stored twice");
+            inner.insert(key, data);
+            self.choose_victims(&mut inner)
+        };
+        self.spill(victims).await;
+        Ok(())
+    }
+
+    /// Replaces previously stored log of the same kind
+    pub async fn replace(&self, job_id: Uuid, log: &JudgeLog) -> anyhow::Result<()> {
+        let data = encode(log)?;
+        let key = (job_id, log.name());
+        let (stale, victims) = {
+            let mut inner = self.inner.lock().await;
+            let stale = match inner.logs.remove(&key) {
+                Some(StoredLog::InMemory(prev)) => {
+                    inner.memory_usage -= prev.len() as u64;
+                    inner.in_memory.retain(|k| *k != key);
+                    None
+                }
+                Some(StoredLog::Spilled(path)) => Some(path),
+                None => None,
+            };
+            inner.insert(key, data);
+            (stale, self.choose_victims(&mut inner))
+        };
+        self.remove_files(stale.into_iter().collect()).await;
+        self.spill(victims).await;
+        Ok(())
+    }
+
+    /// Forgets all logs of the job and removes their spilled files
+    pub async fn remove_job(&self, job_id: Uuid) {
+        let mut spilled = Vec::new();
+        {
+            let mut inner = self.inner.lock().await;
+            let keys: Vec<LogKey> = inner
+                .logs
+                .keys()
+                .filter(|key| key.0 == job_id)
+                .cloned()
+                .collect();
+            for key in keys {
+                match inner.logs.remove(&key) {
+                    Some(StoredLog::InMemory(data)) => inner.memory_usage -= data.len() as u64,
+                    Some(StoredLog::Spilled(path)) => spilled.push(path),
+                    None => {}
+                }
+            }
+            inner.in_memory.retain(|key| key.0 != job_id);
+        }
+        if spilled.is_empty() {
+            return;
+        }
+        self.remove_files(spilled).await;
+        if let Some(dir) = &self.config.spill_dir {
+            // fails if a log of the job is still being spilled; its file
+            // is removed once the spill completes
+            tokio::fs::remove_dir(dir.join(job_id.to_hyphenated().to_string()))
+                .await
+                .ok();
+        }
+    }
+
+    /// Returns previously stored log, or None if it does not exist. Log is
+    /// decoded without holding the lock and off the async runtime.
+    pub async fn get(&self, job_id: Uuid, kind: &str) -> anyhow::Result<Option<JudgeLog>> {
+        let mut retried = false;
+        let data = loop {
+            let stored = {
+                let inner = self.inner.lock().await;
+                match inner.logs.get(&(job_id, kind.to_string())) {
+                    None => return Ok(None),
+                    Some(StoredLog::InMemory(data)) => StoredLog::InMemory(data.clone()),
+                    Some(StoredLog::Spilled(path)) => StoredLog::Spilled(path.clone()),
+                }
+            };
+            let path = match stored {
+                StoredLog::InMemory(data) => break data,
+                StoredLog::Spilled(path) => path,
+            };
+            match tokio::fs::read(&path).await {
+                Ok(data) => break Arc::new(data),
+                // the log was replaced or removed after the lookup
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound && !retried => {
+                    retried = true;
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("failed to read spilled log from {}", path.display())
+                    });
+                }
+            }
+        };
+        tokio::task::spawn_blocking(move || decode(&data))
+            .await
+            .context("log decoding panicked")?
+            .map(Some)
+    }
+
+    /// Chooses oldest in-memory logs which must be spilled to enforce the
+    /// memory limit. They are kept in memory until they are written.
+    fn choose_victims(&self, inner: &mut Inner) -> Vec<Victim> {
+        let (limit, spill_dir) = match (self.config.memory_limit, &self.config.spill_dir) {
+            (Some(l), Some(d)) => (l, d),
+            _ => return Vec::new(),
+        };
+        let mut victims = Vec::new();
+        while inner.memory_usage.saturating_sub(inner.spilling) > limit {
+            let key = match inner.in_memory.pop_front() {
+                Some(k) => k,
+                None => break,
+            };
+            let data = match inner.logs.get(&key) {
+                Some(StoredLog::InMemory(data)) => data.clone(),
+                _ => continue,
+            };
+            inner.spilling += data.len() as u64;
+            let path = spill_dir
+                .join(key.0.to_hyphenated().to_string())
+                .join(format!("{}-{}.json.gz", key.1, inner.next_spill_id));
+            inner.next_spill_id += 1;
+            victims.push(Victim { key, data, path });
+        }
+        victims
+    }
+
+    /// Writes logs to disk without holding the lock, then replaces them
+    /// with their files, unless they were replaced or removed meanwhile
+    async fn spill(&self, victims: Vec<Victim>) {
+        if victims.is_empty() {
+            return;
+        }
+        let mut written = Vec::new();
+        for victim in victims {
+            let res = Self::write_spilled(&victim.data, &victim.path).await;
+            written.push((victim, res));
+        }
+        let mut stale = Vec::new();
+        {
+            let mut inner = self.inner.lock().await;
+            for (victim, res) in written {
+                let size = victim.data.len() as u64;
+                inner.spilling -= size;
+                let current = matches!(
+                    inner.logs.get(&victim.key),
+                    Some(StoredLog::InMemory(data)) if Arc::ptr_eq(data, &victim.data)
+                );
+                match res {
+                    Ok(()) if current => {
+                        tracing::debug!(
+                            job_id = %victim.key.0,
+                            log_kind = %victim.key.1,
+                            size,
+                            "spilled judge log to disk"
+                        );
+                        inner
+                            .logs
+                            .insert(victim.key, StoredLog::Spilled(victim.path));
+                        inner.memory_usage -= size;
+                    }
+                    Ok(()) => stale.push(victim.path),
+                    Err(err) => {
+                        self.warnings.report(
+                            "log-spill-failed",
+                            format!("failed to spill judge log: {:#}", err),
+                        );
+                        if current {
+                            inner.in_memory.push_front(victim.key);
+                        }
+                        stale.push(victim.path);
+                    }
+                }
+            }
+        }
+        self.remove_files(stale).await;
+    }
+
+    /// Removes spilled files which are not referenced anymore
+    async fn remove_files(&self, paths: Vec<PathBuf>) {
+        for path in paths {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => self.warnings.report(
+                    "log-cleanup-failed",
+                    format!("failed to remove spilled log {}: {}", path.display(), err),
+                ),
+            }
+        }
+    }
+
+    async fn write_spilled(data: &[u8], dest: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = dest.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("failed to create directory {}", parent.display()))?;
+        }
+        tokio::fs::write(dest, data)
+            .await
+            .with_context(|| format!("failed to write log to {}", dest.display()))?;
+        Ok(())
+    }
+}
+
+/// Serializes and compresses the log
+fn encode(log: &JudgeLog) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    serde_json::to_writer(&mut encoder, log).context("failed to serialize judge log")?;
+    let data = encoder.finish().context("failed to compress judge log")?;
+    Ok(data)
+}
+
+fn decode(data: &[u8]) -> anyhow::Result<JudgeLog> {
+    let mut json = Vec::new();
+    flate2::read::GzDecoder::new(data)
+        .read_to_end(&mut json)
+        .context("failed to decompress judge log")?;
+    serde_json::from_slice(&json).context("failed to deserialize judge log")
+}
@@ -1,10 +1,18 @@
+mod export;
+mod job_files;
+mod job_store;
+mod log_storage;
+mod queue;
 mod rest;
+mod shadow;
+mod webhooks;
 
 use anyhow::Context;
 use clap::Clap;
 use std::{
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 #[derive(Clap)]
@@ -12,30 +20,346 @@ struct Args {
     /// Port that judge should listen
     #[clap(long, default_value = "1789")]
     port: u16,
-    /// Address which can be used to connect to invoker
+    /// Address which can be used to connect to invoker. Several
+    /// comma-separated addresses can be given, then requests are
+    /// distributed between them. Address can be followed by pool labels:
+    /// `http://arm-invoker:1789;jjs.io/arch=arm64`. Job annotations with
+    /// the same keys select pools which are used for the job. If several
+    /// invokers are behind one address, their number is given after `*`:
+    /// `http://invokers:1789*4`.
     #[clap(long)]
     invoker: String,
+    /// How requests are distributed between invokers: `round-robin` or
+    /// `least-loaded`
+    #[clap(long, default_value = "round-robin")]
+    invoker_policy: invoker_client::Policy,
     /// Directory containing toolchain manifests
     #[clap(long)]
-    toolchains: PathBuf,
+    toolchains: Option<PathBuf>,
+    /// URL identifying MongoDB database containing toolchains. Toolchains
+    /// from `--toolchains` take precedence over ones with the same name.
+    #[clap(long)]
+    toolchains_mongodb: Option<String>,
     /// Directory for caching loaded problems
     #[clap(long, default_value = "/tmp/jjs-judge-problems-cache")]
     problems_cache: PathBuf,
-    /// Directory containing locally available problems
+    /// Directory containing locally available problems, either as
+    /// directories or as `.tar.gz`, `.tgz` or `.zip` packages
     #[clap(long)]
     problems_source_dir: Option<PathBuf>,
     /// URL identifying MongoDB database containing problems
     #[clap(long)]
     problems_source_mongodb: Option<String>,
-    /// Directory containing judging logs. Set to `/dev/null` to disable logging
+    /// Directory containing judging logs. Set to `/dev/null` to disable logging.
+    /// Run sources and events of jobs are kept in `${logs}/jobs`, or in
+    /// memory if logging is disabled
     #[clap(long, default_value = "/var/log/judges")]
     logs: PathBuf,
+    /// Maximum total size (in bytes) of judge logs kept in memory.
+    /// When exceeded, oldest logs are moved to `${logs}/spilled`
+    #[clap(long)]
+    logs_memory_limit: Option<u64>,
+    /// How many times valuer can be restarted during one job
+    #[clap(long, default_value = "2")]
+    valuer_restart_limit: u32,
+    /// YAML file describing webhooks called when jobs are finished
+    #[clap(long)]
+    webhooks_config: Option<PathBuf>,
+    /// File to which per-job execution records are appended
+    /// as newline-delimited JSON (`-` means stdout)
+    #[clap(long)]
+    trace_file: Option<PathBuf>,
+    /// Timeout for connecting to invoker, in seconds
+    #[clap(long, default_value = "10")]
+    invoker_connect_timeout: u64,
+    /// Timeout for a single invoker request, in seconds
+    #[clap(long, default_value = "300")]
+    invoker_request_timeout: u64,
+    /// For how long invoker requests wait while all invokers are
+    /// saturated, in seconds. After that the job fails.
+    #[clap(long, default_value = "600")]
+    invoker_saturation_timeout: u64,
+    /// How many times test is retried if invoker times out
+    #[clap(long, default_value = "1")]
+    test_retry_limit: u32,
+    /// How many times test is run again if its time usage is close to
+    /// the time limit. Problems may override it. Zero disables re-runs.
+    #[clap(long, default_value = "0")]
+    rerun_count: u32,
+    /// Test is run again if its time usage differs from the time limit by
+    /// at most this percentage of the limit
+    #[clap(long, default_value = "10")]
+    rerun_window_percent: u32,
+    /// Which run of a re-run test determines its outcome: `best` or
+    /// `median`
+    #[clap(long, default_value = "best")]
+    rerun_selection: problem_loader::RunSelection,
+    /// Maximal number of tests of one job which run concurrently on
+    /// different invokers. Defaults to the number of invokers in healthy
+    /// pools the job may use. Only valuers which request batches of tests
+    /// benefit.
+    #[clap(long)]
+    test_parallelism: Option<usize>,
+    /// Maximal size of solution stdout and of stderr (each) kept in judge
+    /// logs and output store, in bytes. Larger outputs are truncated.
+    #[clap(long)]
+    max_output_bytes: Option<u64>,
+    /// Maximal size of an input inlined into invoker requests, in bytes.
+    /// Larger inputs fail the job unless `--shared-inputs-dir` is set.
+    #[clap(long, default_value = "268435456")]
+    max_inlined_input_bytes: u64,
+    /// Maximal size of an output returned by invoker (e.g. solution
+    /// stdout or compiled artifact), in bytes. Larger solution outputs
+    /// get output limit verdict, other outputs fail the job.
+    #[clap(long, default_value = "268435456")]
+    max_invoker_output_bytes: u64,
+    /// Maximal size of an invoker response, in bytes. Larger responses
+    /// are not read, and the test gets output limit verdict. Should be
+    /// larger than `--max-invoker-output-bytes`, because outputs are
+    /// base64-encoded.
+    #[clap(long, default_value = "536870912")]
+    max_invoker_response_bytes: u64,
+    /// Directory where judge keeps state which must survive restarts,
+    /// e.g. whether job queue is paused
+    #[clap(long)]
+    state_dir: Option<PathBuf>,
+    /// YAML file with security policy applied to all sandboxes (build,
+    /// solution, checker): `max-processes`, `deny-network`,
+    /// `writable-paths` and `core-dumps`
+    #[clap(long)]
+    security_policy: Option<PathBuf>,
+    /// Deny network access during build even for toolchains which
+    /// request it (contest mode)
+    #[clap(long)]
+    deny_build_network: bool,
+    /// Put compile and checker logs to judge logs as is, without
+    /// converting UTF-16 and CRLF line endings
+    #[clap(long)]
+    raw_logs: bool,
+    /// Do not put test data, correct answers and inline solution outputs
+    /// to contestant logs, even if valuer makes them visible. Full logs
+    /// are not affected.
+    #[clap(long)]
+    strip_contestant_test_data: bool,
+    /// File containing token which must be presented to access admin API.
+    /// If not set, admin API and privileged request fields are disabled,
+    /// unless `--insecure-no-admin-token` is given.
+    #[clap(long)]
+    admin_token_file: Option<PathBuf>,
+    /// Allow everyone to use admin API if admin token is not set. Only
+    /// suitable for judges which are not reachable by untrusted clients.
+    #[clap(long)]
+    insecure_no_admin_token: bool,
+    /// Recurring warnings are written to the log at most once per this
+    /// interval, in seconds
+    #[clap(long, default_value = "60")]
+    warning_log_interval: u64,
+    /// If set, solution outputs are stored gzip-compressed in this directory
+    /// and judge logs only reference them
+    #[clap(long)]
+    outputs_dir: Option<PathBuf>,
+    /// Invoker persistent files directory is mounted into judge at the
+    /// same path, so outputs can be streamed to `--outputs-dir` without
+    /// passing through invoker responses
+    #[clap(long)]
+    shared_invoker_files: bool,
+    /// Directory which invoker sees at the same path. If set, large
+    /// inputs (run sources, tests) are written there or passed by path
+    /// instead of being inlined into invoker requests.
+    #[clap(long)]
+    shared_inputs_dir: Option<PathBuf>,
+    /// Comma-separated directories which invoker sees at the same paths,
+    /// e.g. problems directory. Files under them are passed by path
+    /// without copying. Requires `--shared-inputs-dir`.
+    #[clap(long)]
+    shared_input_roots: Option<String>,
+    /// Inputs of at most this size (in bytes) are always inlined
+    #[clap(long, default_value = "65536")]
+    inline_input_limit: u64,
+    /// Identifier of this judge instance, recorded in judge logs, jobs
+    /// and metrics. Defaults to hostname.
+    #[clap(long)]
+    judge_id: Option<String>,
+    /// If set, jobs which produce no events for this many seconds are
+    /// reported as stale
+    #[clap(long)]
+    stale_job_timeout: Option<u64>,
+    /// Abort stale jobs and mark them as failed
+    #[clap(long)]
+    fail_stale_jobs: bool,
+    /// Finished jobs are removed after this many seconds, together with
+    /// their logs and stored outputs. If not set, jobs are kept forever.
+    #[clap(long)]
+    job_retention: Option<u64>,
+    /// Finished jobs keep their problem revision and compiled run for
+    /// this many seconds, so that later phases can be started. After
+    /// that, problem files and artifacts are released.
+    #[clap(long, default_value = "86400")]
+    pin_retention: u64,
+    /// How live scores are reported: `absolute`, `percent` (of the
+    /// problem max score) or `monotone-max`
+    #[clap(long, default_value = "absolute")]
+    live_score: rest::ScoreAggregation,
+    /// Comma-separated list of judge log kinds which are produced
+    /// (e.g. `contestant`). By default all kinds are enabled.
+    #[clap(long)]
+    log_kinds: Option<String>,
+    /// Enables shadow mode: results are not published (logs are not
+    /// served and webhooks are not called), but written to this directory
+    /// to be compared with the verdicts of the active judge
+    #[clap(long)]
+    shadow_store: Option<PathBuf>,
+    /// If set, solution memory usage is sampled with this interval (in
+    /// milliseconds) and put to full judge logs. Requires invoker support.
+    #[clap(long)]
+    memory_sampling_interval: Option<u64>,
+    /// If set, successful builds are cached in memory, so that identical
+    /// sources are not recompiled. Value is the cache size in megabytes.
+    /// Compiled binaries are then always transferred from invokers.
+    #[clap(long)]
+    artifact_cache_size: Option<usize>,
+    /// For how long cached builds are reused, in seconds
+    #[clap(long, default_value = "3600")]
+    artifact_cache_ttl: u64,
+    /// If set, judge stops accepting and starting jobs while fewer
+    /// invokers are healthy
+    #[clap(long)]
+    min_healthy_invokers: Option<usize>,
+    /// If set, at most this many jobs are judged at the same time. Other
+    /// jobs wait in the queue.
+    #[clap(long)]
+    max_concurrent_jobs: Option<usize>,
+    /// URL of a valuer service. If set, it values runs of all problems
+    /// instead of valuers from problem packages.
+    #[clap(long)]
+    valuer_url: Option<String>,
+    /// How often invoker health is checked, in seconds
+    #[clap(long, default_value = "10")]
+    invoker_health_interval: u64,
+    /// Comma-separated list of Kafka brokers. If set, judging events are
+    /// exported to Kafka (not in shadow mode). Requires `kafka` feature.
+    #[clap(long)]
+    export_kafka_brokers: Option<String>,
+    /// Kafka topic receiving exported events
+    #[clap(long, default_value = "judge-events")]
+    export_kafka_topic: String,
+    /// If set, judging events are appended to this file, one per line
+    #[clap(long)]
+    export_file: Option<PathBuf>,
+    /// Serialization of exported events: `json` or `avro`
+    #[clap(long, default_value = "json")]
+    export_format: export::Format,
+    /// If set, jobs are persisted to this directory and restored on startup
+    #[clap(long)]
+    job_store_dir: Option<PathBuf>,
+    /// URL identifying MongoDB database to which jobs are persisted.
+    /// Jobs are restored on startup.
+    #[clap(long)]
+    job_store_mongodb: Option<String>,
+    /// If set, each job and its invoker traffic are recorded to
+    /// `${dir}/${job_id}.json` (`${dir}/${job_id}-${phase}.json` for
+    /// named phases), so that the job can be replayed later.
+    /// Recorded jobs run their tests one by one.
+    #[clap(long)]
+    record_invoker_calls: Option<PathBuf>,
+    /// Directory for debug dumps of jobs which request them with `debug`
+    /// flag. If not set, such requests are rejected.
+    #[clap(long)]
+    debug_dumps_dir: Option<PathBuf>,
+    /// Send internal errors to clients with all their causes. They may
+    /// contain host paths and other internals, so this is only suitable
+    /// for debugging. Otherwise only the outermost error is sent.
+    #[clap(long)]
+    debug_errors: bool,
+    /// If set, judge logs are returned with at most this many tests.
+    /// Remaining tests can be fetched from `/jobs/{id}/logs/{kind}/tests`.
+    #[clap(long)]
+    max_log_rows: Option<usize>,
+    /// On SIGTERM, for how long running jobs are waited for before exit,
+    /// in seconds. Should be shorter than the grace period of the
+    /// orchestrator.
+    #[clap(long, default_value = "25")]
+    shutdown_timeout: u64,
+    /// If set, access log of the REST API is appended to this file
+    /// (`-` means stdout)
+    #[clap(long)]
+    access_log: Option<PathBuf>,
+    /// Format of the access log: `json` or `clf` (Common Log Format
+    /// followed by request id and latency in milliseconds)
+    #[clap(long, default_value = "json")]
+    access_log_format: rest::AccessLogFormat,
+    /// Comma-separated URL prefixes from which run sources may be
+    /// fetched, e.g. `https://storage.example.com/sources/`. Fetched URL
+    /// must have the same scheme, host and port as a prefix, and its path
+    /// must start with the path segments of the prefix. If not set, run
+    /// sources must be uploaded inline.
+    #[clap(long)]
+    source_fetch_allow: Option<String>,
+    /// Maximal size of a fetched run source, in bytes
+    #[clap(long, default_value = "16777216")]
+    source_fetch_max_size: u64,
+    /// Timeout of run source download, in seconds
+    #[clap(long, default_value = "30")]
+    source_fetch_timeout: u64,
+    /// Instead of serving requests, judge the job recorded in this file
+    /// (see `--record-invoker-calls`) using recorded invoker responses,
+    /// print produced judge logs as JSON lines and exit. Problem and
+    /// toolchain are loaded as usual, invokers are not contacted.
+    #[clap(long)]
+    replay: Option<PathBuf>,
+}
+
+async fn load_security_policy(path: &Path) -> anyhow::Result<problem_loader::SecurityPolicy> {
+    let data = tokio::fs::read(path).await?;
+    let policy: problem_loader::SecurityPolicy = serde_yaml::from_slice(&data)?;
+    policy.validate()?;
+    tracing::info!(?policy, "loaded security policy");
+    Ok(policy)
 }
 
 async fn create_clients(args: &Args) -> anyhow::Result<processor::Clients> {
     let mut invokers = invoker_client::Client::builder();
-    invokers.add(invoker_client::Pool::new_from_address(&args.invoker));
-    let toolchains = toolchain_loader::ToolchainLoader::new(&args.toolchains)
+    for spec in args.invoker.split(',').map(str::trim) {
+        if spec.is_empty() {
+            continue;
+        }
+        let mut parts = spec.split(';');
+        let address = parts.next().unwrap_or_default();
+        let mut pool = match address.rsplit_once('*') {
+            Some((address, count)) => {
+                let count = count.parse().with_context(|| {
+                    format!("invalid number of invokers {:?} of invoker {}", count, spec)
+                })?;
+                let mut pool = invoker_client::Pool::new_from_address(address);
+                pool.invokers(count);
+                pool
+            }
+            None => invoker_client::Pool::new_from_address(address),
+        };
+        for label in parts {
+            let (key, value) = label
+                .split_once('=')
+                .filter(|(key, _)| !key.is_empty())
+                .with_context(|| {
+                    format!(
+                        "invalid label {:?} of invoker {}, expected key=value",
+                        label, spec
+                    )
+                })?;
+            pool.label(key, value);
+        }
+        invokers.add(pool);
+    }
+    invokers.policy(args.invoker_policy);
+    invokers.connect_timeout(Duration::from_secs(args.invoker_connect_timeout));
+    invokers.request_timeout(Duration::from_secs(args.invoker_request_timeout));
+    invokers.saturation_timeout(Duration::from_secs(args.invoker_saturation_timeout));
+    invokers.max_response_size(args.max_invoker_response_bytes);
+    let toolchain_loader_config = toolchain_loader::LoaderConfig {
+        fs: args.toolchains.clone(),
+        mongodb: args.toolchains_mongodb.clone(),
+    };
+    let toolchains = toolchain_loader::ToolchainLoader::from_config(&toolchain_loader_config)
         .await
         .context("failed to initialize toolchain loader")?;
     let problem_loader_config = problem_loader::LoaderConfig {
@@ -47,11 +371,82 @@ async fn create_clients(args: &Args) -> anyhow::Result<processor::Clients> {
             .await
             .context("failed to initialize problem loader")?;
 
-    Ok(processor::Clients {
-        invokers: invokers.build(),
-        toolchains: Arc::new(toolchains),
-        problems: Arc::new(problems),
-    })
+    processor::Clients::builder()
+        .invokers(invokers.build())
+        .toolchains(toolchains)
+        .problems(problems)
+        .build()
+}
+
+#[cfg(feature = "kafka")]
+fn kafka_sink(brokers: &str, topic: &str) -> anyhow::Result<Box<dyn export::EventSink>> {
+    let sink = export::kafka::KafkaSink::new(brokers, topic.to_string())?;
+    Ok(Box::new(sink))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn kafka_sink(_brokers: &str, _topic: &str) -> anyhow::Result<Box<dyn export::EventSink>> {
+    anyhow::bail!("judge was built without Kafka support, enable `kafka` feature")
+}
+
+async fn create_exporter(
+    args: &Args,
+    warnings: &processor::Warnings,
+) -> anyhow::Result<Option<export::EventExporter>> {
+    let sink = match (&args.export_kafka_brokers, &args.export_file) {
+        (None, None) => return Ok(None),
+        _ if args.shadow_store.is_some() => {
+            tracing::warn!("event export is disabled in shadow mode");
+            return Ok(None);
+        }
+        (Some(_), Some(_)) => {
+            anyhow::bail!("--export-kafka-brokers and --export-file are mutually exclusive")
+        }
+        (Some(brokers), None) => kafka_sink(brokers, &args.export_kafka_topic)?,
+        (None, Some(path)) => {
+            if args.export_format != export::Format::Json {
+                anyhow::bail!("--export-file only supports json format");
+            }
+            Box::new(export::FileSink::open(path).await?)
+        }
+    };
+    Ok(Some(export::EventExporter::new(
+        sink,
+        args.export_format,
+        warnings.clone(),
+    )))
+}
+
+async fn create_job_store(args: &Args) -> anyhow::Result<Option<Box<dyn job_store::JobStore>>> {
+    let store: Box<dyn job_store::JobStore> = match (&args.job_store_dir, &args.job_store_mongodb) {
+        (None, None) => return Ok(None),
+        (Some(_), Some(_)) => {
+            anyhow::bail!("--job-store-dir and --job-store-mongodb are mutually exclusive")
+        }
+        (Some(dir), None) => Box::new(job_store::FsJobStore::new(dir.clone()).await?),
+        (None, Some(url)) => Box::new(job_store::mongo::MongoJobStore::new(url).await?),
+    };
+    Ok(Some(store))
+}
+
+/// Replays recorded job and prints its logs to stdout
+async fn replay(
+    path: &Path,
+    clients: processor::Clients,
+    settings: processor::Settings,
+) -> anyhow::Result<()> {
+    let mut progress = processor::replay(path, clients, settings)
+        .await
+        .context("failed to start replay")?;
+    while let Some(event) = progress.event().await {
+        if let processor::Event::LogCreated(log) = event.event {
+            println!("{}", serde_json::to_string(&log)?);
+        }
+    }
+    match progress.wait().await {
+        processor::JudgeOutcome::Success => Ok(()),
+        processor::JudgeOutcome::Fault { error } => Err(error.context("replayed job failed")),
+    }
 }
 
 #[tokio::main]
@@ -64,13 +459,91 @@ async fn main() -> anyhow::Result<()> {
         .await
         .context("failed to initialize dependency clients")?;
     tracing::info!("Running REST API");
-    let cfg = rest::RestConfig { port: args.port };
+    let logs_dir = match &args.logs {
+        p if p == Path::new("/dev/null") => None,
+        p => Some(p.clone()),
+    };
 
+    let spill_dir = logs_dir.as_ref().map(|p| p.join("spilled"));
+    if let Some(p) = &spill_dir {
+        tokio::fs::create_dir_all(&p).await.with_context(|| {
+            format!(
+                "failed to create directory for spilled logs {}",
+                p.display()
+            )
+        })?;
+    }
+    let job_files_dir = logs_dir.as_ref().map(|p| p.join("jobs"));
+    if let Some(p) = &job_files_dir {
+        tokio::fs::create_dir_all(&p)
+            .await
+            .with_context(|| format!("failed to create directory for job files {}", p.display()))?;
+    }
+    if let Some(p) = &args.state_dir {
+        tokio::fs::create_dir_all(&p)
+            .await
+            .with_context(|| format!("failed to create state directory {}", p.display()))?;
+    }
+    let admin_auth = match &args.admin_token_file {
+        Some(path) => {
+            let token = tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("failed to read admin token from {}", path.display()))?;
+            rest::AdminAuth::Token(token.trim().into())
+        }
+        None if args.insecure_no_admin_token => {
+            tracing::warn!("admin token is not set, admin API is not protected");
+            rest::AdminAuth::Insecure
+        }
+        None => {
+            tracing::warn!("admin token is not set, admin API is disabled");
+            rest::AdminAuth::Disabled
+        }
+    };
+    let judge_id = args
+        .judge_id
+        .clone()
+        .unwrap_or_else(|| gethostname::gethostname().to_string_lossy().into_owned());
+    tracing::info!(judge_id = %judge_id, "judge identity");
+    let enabled_log_kinds = match &args.log_kinds {
+        Some(kinds) => processor::parse_log_kinds(kinds).context("invalid --log-kinds")?,
+        None => judge_apis::judge_log::JudgeLogKind::list().collect(),
+    };
+    let warnings = processor::Warnings::new(Duration::from_secs(args.warning_log_interval));
+    if args.max_concurrent_jobs == Some(0) {
+        anyhow::bail!("--max-concurrent-jobs must be positive");
+    }
+    let output_store = match &args.outputs_dir {
+        Some(dir) => Some(
+            processor::OutputStore::new(dir.clone(), args.shared_invoker_files)
+                .await
+                .context("failed to initialize output store")?,
+        ),
+        None => None,
+    };
+    let shared_inputs = match &args.shared_inputs_dir {
+        Some(dir) => {
+            let roots = args
+                .shared_input_roots
+                .iter()
+                .flat_map(|roots| roots.split(','))
+                .map(str::trim)
+                .filter(|root| !root.is_empty())
+                .map(PathBuf::from)
+                .collect();
+            Some(
+                processor::SharedInputs::new(dir.clone(), roots, args.inline_input_limit)
+                    .await
+                    .context("failed to initialize shared inputs")?,
+            )
+        }
+        None if args.shared_input_roots.is_some() => {
+            anyhow::bail!("--shared-input-roots requires --shared-inputs-dir");
+        }
+        None => None,
+    };
     let settings = {
-        let checker_logs = match &args.logs {
-            p if p == Path::new("/dev/null") => (None),
-            p => Some(p.join("checkers")),
-        };
+        let checker_logs = logs_dir.as_ref().map(|p| p.join("checkers"));
         if let Some(p) = &checker_logs {
             tokio::fs::create_dir_all(&p).await.with_context(|| {
                 format!(
@@ -79,8 +552,154 @@ async fn main() -> anyhow::Result<()> {
                 )
             })?;
         }
-        processor::Settings { checker_logs }
+        let trace = match &args.trace_file {
+            Some(path) => {
+                let sink: Arc<dyn processor::TraceSink> = if path == Path::new("-") {
+                    Arc::new(processor::StdoutTraceSink::new())
+                } else {
+                    Arc::new(processor::FileTraceSink::open(path).await?)
+                };
+                Some(processor::Trace {
+                    sink,
+                    job_id: String::new(),
+                })
+            }
+            None => None,
+        };
+        let mut settings = processor::Settings::new(judge_id.clone());
+        settings.checker_logs = checker_logs;
+        settings.compile_logs = logs_dir.as_ref().map(|p| p.join("compilers"));
+        settings.valuer_restart_limit = args.valuer_restart_limit;
+        settings.trace = trace;
+        settings.test_retry_limit = args.test_retry_limit;
+        if args.rerun_window_percent > 100 {
+            anyhow::bail!("--rerun-window-percent must be at most 100");
+        }
+        if args.rerun_count > 0 {
+            settings.rerun_policy = Some(problem_loader::RerunPolicy {
+                max_reruns: args.rerun_count,
+                window_percent: args.rerun_window_percent,
+                selection: args.rerun_selection,
+            });
+        }
+        if args.test_parallelism == Some(0) {
+            anyhow::bail!("--test-parallelism must be positive");
+        }
+        settings.test_parallelism = args.test_parallelism;
+        settings.max_output_size = args.max_output_bytes;
+        settings.max_inlined_input_size = args.max_inlined_input_bytes;
+        settings.max_invoker_output_size = args.max_invoker_output_bytes;
+        settings.deny_build_network = args.deny_build_network;
+        if let Some(path) = &args.security_policy {
+            settings.security_policy = load_security_policy(path)
+                .await
+                .with_context(|| format!("failed to load security policy {}", path.display()))?;
+        }
+        settings.normalize_logs = !args.raw_logs;
+        settings.strip_contestant_test_data = args.strip_contestant_test_data;
+        settings.warnings = warnings.clone();
+        settings.output_store = output_store;
+        settings.shared_inputs = shared_inputs;
+        settings.enabled_log_kinds = enabled_log_kinds;
+        if args.memory_sampling_interval == Some(0) {
+            anyhow::bail!("--memory-sampling-interval must be positive");
+        }
+        settings.memory_sampling_interval =
+            args.memory_sampling_interval.map(Duration::from_millis);
+        settings.valuer_url = args.valuer_url.clone();
+        settings.artifact_cache = args.artifact_cache_size.map(|size| {
+            Arc::new(processor::ArtifactCache::new(
+                size * 1024 * 1024,
+                Duration::from_secs(args.artifact_cache_ttl),
+            ))
+        });
+        if let Some(dir) = &args.record_invoker_calls {
+            tokio::fs::create_dir_all(dir).await.with_context(|| {
+                format!(
+                    "failed to create directory for invoker recordings {}",
+                    dir.display()
+                )
+            })?;
+        }
+        // file name is appended for each job
+        settings.invoker_recording = args.record_invoker_calls.clone();
+        settings
     };
+    if let Some(path) = &args.replay {
+        return replay(path, clients, settings).await;
+    }
+    let cfg = rest::RestConfig {
+        port: args.port,
+        log_storage: log_storage::LogStorageConfig {
+            memory_limit: args.logs_memory_limit,
+            spill_dir,
+        },
+        job_files_dir,
+        webhooks: webhooks::Webhooks::load(args.webhooks_config.as_deref(), warnings.clone())
+            .await
+            .context("failed to load webhooks")?,
+        queue: queue::JobQueue::new(args.state_dir.clone(), args.max_concurrent_jobs)
+            .await
+            .context("failed to initialize job queue")?,
+        admin_auth,
+        job_retention: args.job_retention.map(Duration::from_secs),
+        pin_retention: Duration::from_secs(args.pin_retention),
+        watchdog: args.stale_job_timeout.map(|timeout| rest::WatchdogConfig {
+            stale_timeout: Duration::from_secs(timeout),
+            fail_stale_jobs: args.fail_stale_jobs,
+        }),
+        score_aggregation: args.live_score,
+        shadow: match &args.shadow_store {
+            Some(dir) => {
+                tracing::info!("running in shadow mode");
+                Some(
+                    shadow::ShadowStore::new(dir.clone())
+                        .await
+                        .context("failed to initialize shadow store")?,
+                )
+            }
+            None => None,
+        },
+        health: args.min_healthy_invokers.map(|min| rest::HealthConfig {
+            min_healthy_invokers: min,
+            check_interval: Duration::from_secs(args.invoker_health_interval.max(1)),
+        }),
+        exporter: create_exporter(&args, &warnings)
+            .await
+            .context("failed to initialize event export")?,
+        job_store: create_job_store(&args)
+            .await
+            .context("failed to initialize job store")?,
+        max_log_rows: args.max_log_rows,
+        shutdown_timeout: Duration::from_secs(args.shutdown_timeout),
+        access_log: args
+            .access_log
+            .as_deref()
+            .map(|dest| rest::AccessLog::open(dest, args.access_log_format))
+            .transpose()
+            .context("failed to open access log")?,
+        source_fetcher: args
+            .source_fetch_allow
+            .as_deref()
+            .map(|prefixes| {
+                let prefixes = prefixes
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(ToString::to_string)
+                    .collect();
+                rest::SourceFetcher::new(
+                    prefixes,
+                    args.source_fetch_max_size,
+                    Duration::from_secs(args.source_fetch_timeout),
+                )
+            })
+            .transpose()
+            .context("failed to initialize run source fetcher")?,
+        debug_dumps_dir: args.debug_dumps_dir.clone(),
+        debug_errors: args.debug_errors,
+    };
+
     rest::serve(cfg, clients, settings).await?;
     Ok(())
 }
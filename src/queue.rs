@@ -0,0 +1,260 @@
+//! Job queue: decides when accepted jobs are started.
+//!
+//! Waiting jobs are dispatched in order of their priority, and jobs of
+//! the same priority in order of their virtual finish time: arrival time
+//! plus estimated CPU time. Cheap jobs overtake expensive ones, but
+//! expensive jobs are not starved by jobs of the same priority.
+//! Optionally number of jobs running at the same time is limited.
+
+use anyhow::Context;
+use judge_apis::rest::JobPriority;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+
+/// Persisted part of the queue state
+#[derive(Serialize, Deserialize, Default)]
+struct PersistentState {
+    paused: bool,
+}
+
+type QueueKey = (JobPriority, Instant, u64);
+
+/// Larger cost estimates are treated as this one
+const MAX_ESTIMATED_CPU_SECONDS: f64 = 24.0 * 60.0 * 60.0;
+
+/// Returns virtual finish time of a job arriving at `now`
+fn virtual_finish_time(now: Instant, estimated_cpu_seconds: f64) -> Instant {
+    let estimate = if estimated_cpu_seconds.is_nan() {
+        0.0
+    } else {
+        estimated_cpu_seconds.clamp(0.0, MAX_ESTIMATED_CPU_SECONDS)
+    };
+    now.checked_add(Duration::from_secs_f64(estimate))
+        .unwrap_or(now)
+}
+
+pub struct JobQueue {
+    paused_tx: watch::Sender<bool>,
+    paused_rx: watch::Receiver<bool>,
+    /// Dispatching is also blocked while too few invokers are healthy.
+    /// Unlike `paused`, this is not persisted.
+    degraded_tx: watch::Sender<bool>,
+    degraded_rx: watch::Receiver<bool>,
+    /// Number of jobs waiting for dispatch
+    pending: AtomicUsize,
+    /// Same, by priority, indexed in order of `JobPriority::ALL`
+    pending_by_priority: [AtomicUsize; 3],
+    /// Number of dispatched jobs which are still running
+    running: AtomicUsize,
+    /// Jobs are not dispatched while this many jobs are running
+    max_running: Option<usize>,
+    /// Priorities and virtual finish times of waiting jobs. Ties are
+    /// broken by arrival.
+    waiting: Mutex<BTreeSet<QueueKey>>,
+    next_seq: AtomicU64,
+    /// Incremented when a job leaves `waiting` or stops running
+    dispatched_tx: watch::Sender<u64>,
+    dispatched_rx: watch::Receiver<u64>,
+    /// File containing `PersistentState`
+    state_file: Option<PathBuf>,
+}
+
+impl JobQueue {
+    /// Creates new queue, restoring state from `${state_dir}/queue.json`
+    /// if it exists. If `max_running` is set, at most that many jobs run
+    /// concurrently.
+    pub async fn new(
+        state_dir: Option<PathBuf>,
+        max_running: Option<usize>,
+    ) -> anyhow::Result<JobQueue> {
+        let state_file = state_dir.map(|d| d.join("queue.json"));
+        let mut state = PersistentState::default();
+        if let Some(path) = &state_file {
+            match tokio::fs::read(path).await {
+                Ok(data) => {
+                    state = serde_json::from_slice(&data)
+                        .with_context(|| format!("invalid queue state file {}", path.display()))?;
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("failed to read queue state from {}", path.display())
+                    });
+                }
+            }
+        }
+        if state.paused {
+            tracing::warn!("job queue is paused");
+        }
+        let (paused_tx, paused_rx) = watch::channel(state.paused);
+        let (degraded_tx, degraded_rx) = watch::channel(false);
+        let (dispatched_tx, dispatched_rx) = watch::channel(0);
+        Ok(JobQueue {
+            paused_tx,
+            paused_rx,
+            degraded_tx,
+            degraded_rx,
+            pending: AtomicUsize::new(0),
+            pending_by_priority: Default::default(),
+            running: AtomicUsize::new(0),
+            max_running,
+            waiting: Mutex::new(BTreeSet::new()),
+            next_seq: AtomicU64::new(0),
+            dispatched_tx,
+            dispatched_rx,
+            state_file,
+        })
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused_rx.borrow()
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        *self.degraded_rx.borrow()
+    }
+
+    /// Blocks or unblocks job dispatching because of invoker health
+    pub fn set_degraded(&self, degraded: bool) {
+        if degraded {
+            tracing::warn!("not enough healthy invokers, job queue is blocked");
+        } else {
+            tracing::info!("enough invokers are healthy, job queue is unblocked");
+        }
+        self.degraded_tx.send(degraded).ok();
+    }
+
+    /// Returns number of jobs waiting for dispatch
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Returns number of jobs of the given priority waiting for dispatch
+    pub fn pending_with_priority(&self, priority: JobPriority) -> usize {
+        self.pending_by_priority[priority as usize].load(Ordering::SeqCst)
+    }
+
+    /// Returns number of dispatched jobs which are still running
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    pub fn max_running(&self) -> Option<usize> {
+        self.max_running
+    }
+
+    /// Pauses or resumes job dispatching. New state is persisted.
+    pub async fn set_paused(&self, paused: bool) -> anyhow::Result<()> {
+        if let Some(path) = &self.state_file {
+            let data = serde_json::to_vec(&PersistentState { paused })?;
+            tokio::fs::write(path, data)
+                .await
+                .with_context(|| format!("failed to save queue state to {}", path.display()))?;
+        }
+        tracing::info!(paused, "changing queue state");
+        self.paused_tx.send(paused).ok();
+        Ok(())
+    }
+
+    /// Waits until the job can be started. `priority` and
+    /// `estimated_cpu_seconds` are used to order waiting jobs. Job is
+    /// counted as running until the returned guard is dropped.
+    pub async fn wait_dispatch(
+        &self,
+        priority: JobPriority,
+        estimated_cpu_seconds: f64,
+    ) -> RunningJob<'_> {
+        let key = (
+            priority,
+            virtual_finish_time(Instant::now(), estimated_cpu_seconds),
+            self.next_seq.fetch_add(1, Ordering::SeqCst),
+        );
+        // removes the job from the queue even if the waiting task is aborted
+        let _entry = QueueEntry::new(self, key);
+        let mut paused_rx = self.paused_rx.clone();
+        let mut degraded_rx = self.degraded_rx.clone();
+        let mut dispatched_rx = self.dispatched_rx.clone();
+        loop {
+            let blocked = *paused_rx.borrow() || *degraded_rx.borrow();
+            if !blocked && self.try_start(key) {
+                return RunningJob(self);
+            }
+            let res = tokio::select! {
+                res = paused_rx.changed() => res,
+                res = degraded_rx.changed() => res,
+                res = dispatched_rx.changed() => res,
+            };
+            if res.is_err() {
+                // senders are owned by the queue, so this does not happen
+                // in practice
+                self.running.fetch_add(1, Ordering::SeqCst);
+                return RunningJob(self);
+            }
+        }
+    }
+
+    /// Marks job as running if it is the first in the queue and the
+    /// concurrency limit allows it
+    fn try_start(&self, key: QueueKey) -> bool {
+        // lock is held so that only the first job can take the free slot
+        let waiting = self.waiting.lock().unwrap();
+        if waiting.iter().next() != Some(&key) {
+            return false;
+        }
+        let running = self.running.load(Ordering::SeqCst);
+        if matches!(self.max_running, Some(max) if running >= max) {
+            return false;
+        }
+        self.running.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    /// Wakes up waiting jobs, so that they re-check whether they can start
+    fn notify_waiting(&self) {
+        let next = *self.dispatched_rx.borrow() + 1;
+        self.dispatched_tx.send(next).ok();
+    }
+}
+
+/// Dispatched job. Frees its concurrency slot when dropped.
+pub struct RunningJob<'a>(&'a JobQueue);
+
+impl Drop for RunningJob<'_> {
+    fn drop(&mut self) {
+        self.0.running.fetch_sub(1, Ordering::SeqCst);
+        self.0.notify_waiting();
+    }
+}
+
+/// Job waiting in the queue
+struct QueueEntry<'a> {
+    queue: &'a JobQueue,
+    key: QueueKey,
+}
+
+impl<'a> QueueEntry<'a> {
+    fn new(queue: &'a JobQueue, key: QueueKey) -> Self {
+        queue.pending.fetch_add(1, Ordering::SeqCst);
+        queue.pending_by_priority[key.0 as usize].fetch_add(1, Ordering::SeqCst);
+        queue.waiting.lock().unwrap().insert(key);
+        QueueEntry { queue, key }
+    }
+}
+
+impl Drop for QueueEntry<'_> {
+    fn drop(&mut self) {
+        self.queue.waiting.lock().unwrap().remove(&self.key);
+        self.queue.pending.fetch_sub(1, Ordering::SeqCst);
+        self.queue.pending_by_priority[self.key.0 as usize].fetch_sub(1, Ordering::SeqCst);
+        self.queue.notify_waiting();
+    }
+}
@@ -1,156 +1,1269 @@
 //! Judge REST api
 
+mod access_log;
+mod admin;
+mod answers;
+mod archive;
+mod batch;
+mod errors;
+mod events;
+mod health;
+mod job_map;
+mod json_stream;
+mod log_pages;
+mod metrics;
+mod outputs;
+mod persistence;
+mod problems;
+mod retention;
+mod run_source;
+mod score;
+mod shutdown;
+mod summary;
+mod syntax_check;
+mod timing;
+mod toolchains;
+mod watchdog;
+
+pub use access_log::{AccessLog, AccessLogFormat};
+pub use admin::AdminAuth;
+pub use health::HealthConfig;
+pub use run_source::SourceFetcher;
+pub use score::ScoreAggregation;
+pub use watchdog::WatchdogConfig;
+
+use crate::{
+    export::EventExporter,
+    job_files::JobFiles,
+    job_store::JobStore,
+    log_storage::{LogStorage, LogStorageConfig},
+    queue::JobQueue,
+    shadow::ShadowStore,
+    webhooks::Webhooks,
+};
 use anyhow::Context;
-use api_util::{ApiError, ErrorKind};
-use futures::future::{FutureExt, TryFutureExt};
-use std::{collections::HashMap, convert::Infallible, sync::Arc};
-use tokio::sync::{Mutex, RwLock};
+use errors::RestError;
+use futures::future::{Future, FutureExt, TryFutureExt};
+use job_map::JobMap;
+use judge_apis::{
+    error::codes,
+    events::JudgeEventKind,
+    judge_log::{JudgeLog, JudgeLogKind},
+    live::{LiveEvent, LiveStage, LiveSubtaskScore, Score},
+};
+use processor::toolchain_loader::{Detection, DetectionMethod};
+use std::{
+    collections::HashMap,
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::Mutex;
 use uuid::Uuid;
-use warp::Filter;
+use warp::{http::StatusCode, Filter};
 
 pub struct RestConfig {
     pub port: u16,
+    pub log_storage: LogStorageConfig,
+    pub webhooks: Webhooks,
+    pub queue: JobQueue,
+    /// Authentication of admin API and privileged request fields
+    pub admin_auth: AdminAuth,
+    /// If set, stale jobs are detected
+    pub watchdog: Option<WatchdogConfig>,
+    /// If set, finished jobs are removed after this period, together
+    /// with their logs and outputs
+    pub job_retention: Option<Duration>,
+    /// Finished jobs keep their problem revision and compiled run for
+    /// this period, so that later phases can be started
+    pub pin_retention: Duration,
+    /// Transformation applied to live scores
+    pub score_aggregation: ScoreAggregation,
+    /// If set, judge runs in shadow mode
+    pub shadow: Option<ShadowStore>,
+    /// If set, invoker health is checked
+    pub health: Option<HealthConfig>,
+    /// If set, judging events are exported there
+    pub exporter: Option<EventExporter>,
+    /// If set, jobs are persisted there and restored on startup
+    pub job_store: Option<Box<dyn JobStore>>,
+    /// If set, judge logs are returned with at most this many tests, and
+    /// the rest must be fetched page by page
+    pub max_log_rows: Option<usize>,
+    /// On shutdown, for how long unfinished jobs are waited for
+    pub shutdown_timeout: Duration,
+    /// If set, requests are logged there
+    pub access_log: Option<AccessLog>,
+    /// If set, run sources can be passed by reference
+    pub source_fetcher: Option<SourceFetcher>,
+    /// If set, debug dumps of jobs are written to
+    /// `${debug_dumps_dir}/${job_id}/${phase}`
+    pub debug_dumps_dir: Option<PathBuf>,
+    /// If set, run sources and events of jobs are kept there instead of
+    /// memory
+    pub job_files_dir: Option<PathBuf>,
+    /// If true, internal errors are sent to clients with all their causes
+    pub debug_errors: bool,
 }
 
 /// Contains information about single judge job
 struct JudgeJob {
     id: Uuid,
-    live_test: Option<u32>,
-    live_score: Option<u32>,
-    logs: HashMap<String, judge_apis::judge_log::JudgeLog>,
+    problem_id: String,
+    toolchain_name: String,
+    /// Current judging phase
+    phase: Option<String>,
+    /// All phases started so far, oldest first
+    phases: Vec<Option<String>>,
+    /// Kept to be reused by later phases
+    compiled: Option<processor::CompiledRun>,
+    locale: Option<String>,
+    timezone: Option<String>,
+    /// Set by an administrator, reused by later phases
+    image_override: Option<String>,
+    /// Reused by later phases and revaluation
+    judging_mode: judge_apis::rest::JudgingMode,
+    /// Reused by later phases
+    priority: judge_apis::rest::JobPriority,
+    /// Set by an administrator, later phases are dumped too
+    debug: bool,
+    /// Imported jobs are read-only
+    imported: bool,
+    /// If set, the problem was loaded bypassing registries, and its
+    /// revision is released when the job finishes
+    local_problem: bool,
+    created_at: SystemTime,
+    finished_at: Option<SystemTime>,
+    live_test: Option<pom::TestId>,
+    /// Display name of `live_test`, if the problem names its tests
+    live_test_name: Option<String>,
+    live_score: Option<Score>,
+    /// Subtask scores of the latest contestant log
+    live_subtasks: Vec<LiveSubtaskScore>,
+    /// Set when testing starts
+    live_stage: Option<LiveStage>,
+    /// Set while requests of the job wait for invoker capacity
+    waiting_for_invoker: bool,
+    live_resources: Option<judge_apis::live::LiveResources>,
+    /// Compilation output received so far
+    compile_log: String,
+    /// Kinds of created logs. Logs themselves are kept in `State::logs`.
+    logs: Vec<String>,
     annotations: HashMap<String, String>,
     outcome: Option<processor::JudgeOutcome>,
+    /// Classification of the fault of a restored job, whose error chain
+    /// is not available anymore
+    restored_fault: Option<judge_apis::rest::FaultInfo>,
+    /// Statuses of all judged tests, used to recompute logs on override
+    test_statuses: Vec<(pom::TestId, judge_apis::judge_log::Status)>,
+    overrides: Vec<judge_apis::admin::VerdictOverride>,
+    judge_id: String,
+    /// Contestant log is withheld until the job is thawed
+    frozen: bool,
+    usage: judge_apis::usage::Usage,
+    /// Estimate of the current phase, used to order dispatching
+    estimated_cost: Option<judge_apis::usage::CostEstimate>,
+    /// Time of the latest progress, None if job was not started yet
+    last_progress: Option<Instant>,
+    /// Latest completed step, used for diagnostics
+    last_stage: Option<String>,
+    /// Sequence number of the last applied processor event
+    last_event_seq: u64,
+    /// Problem revision captured when the job started. Later phases are
+    /// judged against it even if the problem is updated. Files of the
+    /// revision are kept while the handle is alive.
+    problem: Option<problem_loader::LoadedProblem>,
+    /// Set when `problem` and `compiled` were released after
+    /// `RestConfig::pin_retention`
+    pins_released: bool,
+    /// Whether watchdog has already reported this job as stale
+    stale: bool,
+    task: Option<tokio::task::JoinHandle<()>>,
+    /// In shadow mode, logs are kept here until the job is finished
+    shadow_logs: Vec<JudgeLog>,
+    /// Live events for streaming subscribers
+    events: tokio::sync::broadcast::Sender<LiveEvent>,
 }
 
 impl JudgeJob {
     fn as_rest(&self) -> judge_apis::rest::JudgeJob {
-        let error = match &self.outcome {
-            Some(processor::JudgeOutcome::Fault { error }) => Some(format!("{:#}", error)),
-            _ => None,
+        let (error, fault) = match &self.outcome {
+            Some(processor::JudgeOutcome::Fault { error }) => {
+                let category = processor::FaultCategory::classify(error);
+                let fault = judge_apis::rest::FaultInfo {
+                    category: category.as_str().to_string(),
+                    hint: category.hint().to_string(),
+                };
+                let fault = self.restored_fault.clone().unwrap_or(fault);
+                (Some(format!("{:#}", error)), Some(fault))
+            }
+            _ => (None, None),
         };
         judge_apis::rest::JudgeJob {
             id: self.id,
-            logs: self.logs.keys().cloned().collect(),
+            logs: self.logs.clone(),
             annotations: self.annotations.clone(),
             completed: self.outcome.is_some(),
+            queued: self.is_queued(),
             live: judge_apis::live::LiveJudgeStatus {
+                version: judge_apis::live::LIVE_STATUS_VERSION,
                 test: self.live_test,
-                score: self.live_score,
+                test_name: self.live_test_name.clone(),
+                score: if self.frozen { None } else { self.live_score },
+                stage: Some(self.live_stage()),
+                finished_tests: if self.frozen {
+                    0
+                } else {
+                    self.test_statuses.len() as u32
+                },
+                total_tests: self.problem.as_ref().map(|p| p.manifest.tests.len() as u32),
+                compile_log: self.compile_log.clone(),
+                resources: self.live_resources,
+                subtasks: if self.frozen {
+                    Vec::new()
+                } else {
+                    self.live_subtasks.clone()
+                },
             },
             error,
+            overrides: self.overrides.clone(),
+            judge_id: self.judge_id.clone(),
+            frozen: self.frozen,
+            usage: self.usage.clone(),
+            cost: judge_apis::usage::JobCost {
+                estimate: self.estimated_cost.clone(),
+                actual_cpu_seconds: self.outcome.as_ref().map(|_| self.usage.cpu_seconds),
+            },
+            phase: self.phase.clone(),
+            fault,
+            locale: self.locale.clone(),
+            timezone: self.timezone.clone(),
+            image_override: self.image_override.clone(),
+            priority: self.priority,
+            debug: self.debug,
+            imported: self.imported,
+            last_event_seq: self.last_event_seq,
         }
     }
+
+    /// Job is accepted, but was not dispatched yet
+    fn is_queued(&self) -> bool {
+        self.outcome.is_none() && self.last_progress.is_none()
+    }
+
+    fn live_stage(&self) -> LiveStage {
+        if self.outcome.is_some() {
+            LiveStage::Finished
+        } else if self.is_queued() {
+            LiveStage::Queued
+        } else if self.waiting_for_invoker {
+            LiveStage::WaitingForInvoker
+        } else {
+            self.live_stage.unwrap_or(LiveStage::Compiling)
+        }
+    }
+
+    /// Fails if the job was imported and must not be changed
+    fn check_writable(&self) -> Result<(), RestError> {
+        if self.imported {
+            return Err(RestError::new(
+                StatusCode::CONFLICT,
+                codes::JOB_READ_ONLY,
+                "job was imported from an archive and cannot be changed",
+            )
+            .with_detail("job_id", self.id));
+        }
+        Ok(())
+    }
 }
 
 struct State {
-    judge: RwLock<HashMap<Uuid, Arc<Mutex<JudgeJob>>>>,
+    judge: JobMap,
+    logs: LogStorage,
+    /// Run sources and events of jobs
+    job_files: JobFiles,
+    webhooks: Webhooks,
+    queue: JobQueue,
+    admin_auth: AdminAuth,
+    /// If true, new jobs are frozen
+    frozen: AtomicBool,
+    /// If true, judge is draining jobs before exit and rejects new ones
+    shutting_down: AtomicBool,
+    /// Number of running jobs which are considered stale by watchdog
+    stale_jobs: AtomicUsize,
+    /// Number of tests on which solutions exceeded process limit
+    process_limit_hits: AtomicU64,
+    /// Number of tests on which solutions violated sandbox restrictions
+    security_violations: AtomicU64,
+    /// Number of failed jobs by fault category
+    faults: std::sync::Mutex<HashMap<&'static str, u64>>,
+    /// Number of job and background tasks which panicked
+    panicked_tasks: AtomicU64,
+    /// Answer generation jobs by id
+    answers_jobs: std::sync::Mutex<HashMap<Uuid, answers::AnswersJobState>>,
+    score_aggregation: ScoreAggregation,
+    /// In shadow mode, results are written there instead of being published
+    shadow: Option<ShadowStore>,
+    /// Result of the latest invoker health check
+    healthy_invokers: AtomicUsize,
+    /// Set if invoker health is checked
+    min_healthy_invokers: Option<usize>,
+    exporter: Option<EventExporter>,
+    job_store: Option<Box<dyn JobStore>>,
+    /// See `RestConfig::max_log_rows`
+    max_log_rows: Option<usize>,
+    source_fetcher: Option<SourceFetcher>,
+    /// See `RestConfig::debug_dumps_dir`
+    debug_dumps_dir: Option<PathBuf>,
+    json_streams: json_stream::JsonStreams,
     clients: processor::Clients,
     settings: processor::Settings,
 }
 
+impl State {
+    /// Fails if new jobs must be rejected
+    fn check_accepting_jobs(&self) -> Result<(), RestError> {
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err(RestError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                codes::SHUTTING_DOWN,
+                "judge is shutting down",
+            )
+            .retryable());
+        }
+        if self.queue.is_degraded() {
+            return Err(RestError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                codes::NOT_ENOUGH_INVOKERS,
+                "too few invokers are healthy",
+            )
+            .retryable());
+        }
+        Ok(())
+    }
+
+    /// Records event of the job, so that it is included in job archives,
+    /// and exports it if exporter is configured
+    async fn export(&self, job_id: Uuid, kind: JudgeEventKind) {
+        let event = crate::export::new_event(&self.settings.judge_id, job_id, kind);
+        if let Err(err) = self
+            .job_files
+            .append_events(job_id, std::slice::from_ref(&event))
+            .await
+        {
+            self.settings.warnings.report(
+                "event-record-failed",
+                format!("failed to record event of job {}: {:#}", job_id, err),
+            );
+        }
+        if let Some(exporter) = &self.exporter {
+            exporter.emit(event);
+        }
+    }
+
+    /// Publishes results of a finished job, or puts them to the comparison
+    /// store in shadow mode
+    async fn job_finished(&self, job: &mut JudgeJob) {
+        job.finished_at = Some(SystemTime::now());
+        job.events.send(LiveEvent::Completed).ok();
+        let rest_job = job.as_rest();
+        self.export(
+            job.id,
+            JudgeEventKind::JobFinished {
+                error: rest_job.error,
+                fault_category: rest_job.fault.map(|f| f.category),
+            },
+        )
+        .await;
+        if let Some(processor::JudgeOutcome::Fault { error }) = &job.outcome {
+            let category = processor::FaultCategory::classify(error);
+            if category == processor::FaultCategory::TaskPanicked {
+                self.panicked_tasks.fetch_add(1, Ordering::SeqCst);
+            }
+            *self
+                .faults
+                .lock()
+                .unwrap()
+                .entry(category.as_str())
+                .or_default() += 1;
+            tracing::error!(
+                judge_id = %self.settings.judge_id,
+                job_id = %job.id,
+                fault_category = category.as_str(),
+                hint = category.hint(),
+                "job failed: {:#}",
+                error
+            );
+        }
+        if job.local_problem {
+            // files of the problem are removed once the job task ends
+            job.problem = None;
+        }
+        self.persist(job).await;
+        let store = match &self.shadow {
+            Some(s) => s,
+            None => {
+                self.webhooks.job_finished(&job.as_rest());
+                return;
+            }
+        };
+        let record = judge_apis::shadow::ShadowRecord {
+            job_id: job.id,
+            problem_id: job.problem_id.clone(),
+            // annotations were validated when the job was created
+            reference: crate::shadow::reference_verdict(&job.annotations)
+                .ok()
+                .flatten(),
+            verdict: crate::shadow::verdict(&job.shadow_logs),
+            error: job.as_rest().error,
+            logs: job.shadow_logs.clone(),
+        };
+        if let Err(err) = store.put(&record).await {
+            tracing::error!(err = %format_args!("{:#}", err), "failed to store shadow record");
+        }
+    }
+}
+
+/// Value of `JudgeRequest::toolchain_name` which requests detection
+const AUTO_TOOLCHAIN: &str = "auto";
+
+/// If annotation with this key is set to `true`, job is frozen
+/// regardless of the global freeze flag
+const FREEZE_ANNOTATION: &str = "judge.freeze";
+
+/// Chooses toolchain for the run, failing if none matches
+async fn detect_toolchain(
+    state: &State,
+    filename: Option<&str>,
+    run_source: &[u8],
+) -> anyhow::Result<(String, DetectionMethod)> {
+    let detected = state
+        .clients
+        .toolchains
+        .detect(filename, run_source)
+        .await
+        .context("failed to detect toolchain")?;
+    match detected {
+        Detection::Found { name, method } => Ok((name, method)),
+        Detection::NotFound => Err(RestError::not_found(
+            codes::TOOLCHAIN_NOT_DETECTED,
+            "no toolchain matches the run",
+        )
+        .into()),
+        Detection::Ambiguous {
+            extension,
+            candidates,
+        } => Err(RestError::new(
+            StatusCode::CONFLICT,
+            codes::TOOLCHAIN_AMBIGUOUS,
+            format!(
+                "several toolchains match extension {:?}: {}; specify toolchain explicitly",
+                extension,
+                candidates.join(", ")
+            ),
+        )
+        .with_detail("extension", &extension)
+        .with_detail("candidates", candidates.join(","))
+        .into()),
+    }
+}
+
+/// Whether toolchain should be detected instead of taken from request
+fn is_auto_toolchain(toolchain_name: &str) -> bool {
+    toolchain_name.is_empty() || toolchain_name == AUTO_TOOLCHAIN
+}
+
+/// Selects invoker pools for the job. Annotations whose keys are used as
+/// labels of some pool (e.g. `jjs.io/arch`) must match pool labels, other
+/// annotations are ignored.
+fn invoker_labels(
+    state: &State,
+    annotations: &HashMap<String, String>,
+) -> anyhow::Result<invoker_client::Labels> {
+    let invokers = &state.clients.invokers;
+    let labels: invoker_client::Labels = annotations
+        .iter()
+        .filter(|(key, _)| invokers.is_label(key))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    if !invokers.with_labels(labels.clone()).has_matching_pool() {
+        return Err(RestError::bad_request(
+            codes::NO_MATCHING_INVOKER_POOL,
+            "no invoker pool has labels selected by annotations",
+        )
+        .with_detail("labels", format_labels(&labels))
+        .into());
+    }
+    Ok(labels)
+}
+
+fn format_labels(labels: &invoker_client::Labels) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Estimates cost of the job. Failure is not fatal: the job will report
+/// it when the problem or toolchain is loaded.
+async fn estimate_cost(
+    state: &State,
+    problem_id: &str,
+    pinned: Option<&problem_loader::LoadedProblem>,
+    toolchain_name: Option<&str>,
+) -> Option<judge_apis::usage::CostEstimate> {
+    match processor::estimate_cost(&state.clients, problem_id, pinned, toolchain_name).await {
+        Ok(estimate) => estimate,
+        Err(err) => {
+            tracing::warn!(problem_id, "failed to estimate job cost: {:#}", err);
+            None
+        }
+    }
+}
+
 async fn start_job(
     state: Arc<State>,
     req: judge_apis::rest::JudgeRequest,
-) -> judge_apis::rest::JudgeJob {
-    let proc_request = processor::Request {
-        toolchain_name: req.toolchain_name,
-        problem_id: req.problem_id,
-        run_source: req.run_source.0,
+    authorization: Option<String>,
+) -> anyhow::Result<judge_apis::rest::JudgeJob> {
+    state.check_accepting_jobs()?;
+    let run_source = run_source::resolve(state.source_fetcher.as_ref(), req.run_source).await?;
+    let mut annotations = req.annotations;
+    let toolchain_name = if is_auto_toolchain(&req.toolchain_name) {
+        let (name, method) = detect_toolchain(&state, req.filename.as_deref(), &run_source).await?;
+        annotations.insert("judge.detected-toolchain".to_string(), name.clone());
+        annotations.insert(
+            "judge.toolchain-detection-method".to_string(),
+            method.as_str().to_string(),
+        );
+        name
+    } else {
+        req.toolchain_name
     };
-    let job_id = Uuid::new_v4();
-    let mut settings = state.settings.clone();
-    {
-        let mut job_id_s = Uuid::encode_buffer();
-        let job_id_s = job_id.to_hyphenated().encode_lower(&mut job_id_s);
-        if let Some(p) = &mut settings.checker_logs {
-            p.push(&*job_id_s);
+    if let Some(phase) = &req.phase {
+        if !judge_apis::judge_log::is_valid_phase(phase) {
+            return Err(RestError::bad_request(
+                codes::INVALID_REQUEST,
+                "phase name must consist of alphanumeric characters, '-' and '_'",
+            )
+            .with_detail("phase", phase)
+            .into());
         }
     }
-    let mut progress = processor::judge(proc_request, state.clients.clone(), settings);
+    if let Some(timezone) = &req.timezone {
+        summary::validate_timezone(timezone)?;
+    }
+    if let Some(image) = &req.image_override {
+        if !admin::has_admin_token(&state.admin_auth, authorization.as_deref()) {
+            return Err(RestError::new(
+                StatusCode::FORBIDDEN,
+                codes::FORBIDDEN,
+                "image_override requires configured admin token",
+            )
+            .into());
+        }
+        if image.trim().is_empty() {
+            return Err(RestError::bad_request(
+                codes::INVALID_REQUEST,
+                "image_override must not be empty",
+            )
+            .into());
+        }
+    }
+    if req.debug {
+        if !admin::is_admin(&state.admin_auth, authorization.as_deref()) {
+            return Err(RestError::new(
+                StatusCode::FORBIDDEN,
+                codes::FORBIDDEN,
+                "debug requires admin token",
+            )
+            .into());
+        }
+        if state.debug_dumps_dir.is_none() {
+            return Err(RestError::bad_request(
+                codes::DEBUG_DUMPS_DISABLED,
+                "debug dumps are not enabled on this judge",
+            )
+            .into());
+        }
+    }
+    let local_problem = match req.local_problem {
+        Some(source) => {
+            if !admin::has_admin_token(&state.admin_auth, authorization.as_deref()) {
+                return Err(RestError::new(
+                    StatusCode::FORBIDDEN,
+                    codes::FORBIDDEN,
+                    "local_problem requires configured admin token",
+                )
+                .into());
+            }
+            Some(load_local_problem(&state, &req.problem_id, source).await?)
+        }
+        None => None,
+    };
+    let job_id = Uuid::new_v4();
+    let mut proc_request = processor::Request::new(&req.problem_id, &toolchain_name, run_source);
+    proc_request.phase = req.phase.clone();
+    proc_request.image_override = req.image_override.clone();
+    proc_request.problem = local_problem.clone();
+    proc_request.judging_mode = req.judging_mode;
+    proc_request.invoker_labels = invoker_labels(&state, &annotations)?;
+    proc_request.debug_dump_dir = debug_dump_dir(&state, req.debug, job_id, req.phase.as_deref());
+    if let Some(image) = &req.image_override {
+        tracing::warn!(
+            job_id = %job_id,
+            image_override = %image,
+            "job uses sandbox image override"
+        );
+    }
+    if state.shadow.is_some() {
+        crate::shadow::reference_verdict(&annotations)
+            .map_err(|err| RestError::bad_request(codes::INVALID_REQUEST, format!("{:#}", err)))?;
+    }
+    let estimated_cost = estimate_cost(
+        &state,
+        &req.problem_id,
+        local_problem.as_ref(),
+        Some(&toolchain_name),
+    )
+    .await;
+    let frozen = state.frozen.load(Ordering::SeqCst)
+        || annotations.get(FREEZE_ANNOTATION).map(String::as_str) == Some("true");
     let job = JudgeJob {
         id: job_id,
+        problem_id: req.problem_id,
+        toolchain_name,
+        phases: vec![req.phase.clone()],
+        phase: req.phase,
+        compiled: None,
+        locale: req.locale,
+        timezone: req.timezone,
+        image_override: req.image_override,
+        judging_mode: req.judging_mode,
+        priority: req.priority,
+        debug: req.debug,
+        imported: false,
+        local_problem: local_problem.is_some(),
+        created_at: SystemTime::now(),
+        finished_at: None,
         live_test: None,
+        live_test_name: None,
         live_score: None,
-        logs: HashMap::new(),
-        annotations: req.annotations,
+        live_subtasks: Vec::new(),
+        live_stage: None,
+        waiting_for_invoker: false,
+        live_resources: None,
+        compile_log: String::new(),
+        logs: Vec::new(),
+        annotations,
         outcome: None,
+        restored_fault: None,
+        test_statuses: Vec::new(),
+        overrides: Vec::new(),
+        judge_id: state.settings.judge_id.clone(),
+        frozen,
+        usage: Default::default(),
+        estimated_cost,
+        last_progress: None,
+        last_stage: None,
+        last_event_seq: 0,
+        problem: local_problem,
+        pins_released: false,
+        stale: false,
+        task: None,
+        shadow_logs: Vec::new(),
+        events: tokio::sync::broadcast::channel(events::CHANNEL_CAPACITY).0,
     };
 
+    state
+        .job_files
+        .put_source(job_id, &proc_request.run_source)
+        .await
+        .context("failed to store run source")?;
     let resp = job.as_rest();
+    state
+        .export(
+            job_id,
+            JudgeEventKind::JobCreated {
+                problem_id: job.problem_id.clone(),
+                toolchain_name: job.toolchain_name.clone(),
+                phase: job.phase.clone(),
+                annotations: job.annotations.clone(),
+            },
+        )
+        .await;
 
+    state.persist(&job).await;
     let job = Arc::new(Mutex::new(job));
-    let prev = state.judge.write().await.insert(job_id, job.clone());
+    let prev = state.judge.insert(job_id, job.clone()).await;
     assert!(prev.is_none());
+    let task = spawn_job(state, job.clone(), proc_request);
+    job.lock().await.task = Some(task);
+
+    Ok(resp)
+}
+
+/// Starts another judging phase of a completed job, reusing its
+/// compiled run
+async fn start_phase(
+    state: Arc<State>,
+    id: Uuid,
+    req: judge_apis::rest::StartPhaseRequest,
+) -> anyhow::Result<judge_apis::rest::JudgeJob> {
+    state.check_accepting_jobs()?;
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => {
+            return Err(RestError::job_not_found(id).into());
+        }
+    };
+    if !judge_apis::judge_log::is_valid_phase(&req.phase) {
+        return Err(RestError::bad_request(
+            codes::INVALID_REQUEST,
+            "phase name must consist of alphanumeric characters, '-' and '_'",
+        )
+        .with_detail("phase", &req.phase)
+        .into());
+    }
+    let mut job_guard = job.lock().await;
+    job_guard.check_writable()?;
+    if !matches!(job_guard.outcome, Some(processor::JudgeOutcome::Success)) {
+        return Err(RestError::new(
+            StatusCode::CONFLICT,
+            codes::JOB_NOT_COMPLETED,
+            "previous phase must be successfully completed",
+        )
+        .into());
+    }
+    let compiled = match &job_guard.compiled {
+        Some(c) => c.clone(),
+        None if job_guard.pins_released => {
+            return Err(RestError::new(
+                StatusCode::CONFLICT,
+                codes::RUN_RELEASED,
+                "compiled run was released because the job finished long ago",
+            )
+            .into());
+        }
+        None => {
+            return Err(RestError::new(
+                StatusCode::CONFLICT,
+                codes::ARTIFACT_NOT_AVAILABLE,
+                "run was not compiled successfully",
+            )
+            .into());
+        }
+    };
+    if job_guard.phases.contains(&Some(req.phase.clone())) {
+        return Err(RestError::new(
+            StatusCode::CONFLICT,
+            codes::PHASE_ALREADY_EXISTS,
+            "phase was already started",
+        )
+        .with_detail("phase", &req.phase)
+        .into());
+    }
+    if job_guard.local_problem {
+        return Err(RestError::new(
+            StatusCode::CONFLICT,
+            codes::LOCAL_PROBLEM_RELEASED,
+            "local problem was removed when the job finished",
+        )
+        .into());
+    }
+    // run is only compiled again if its artifacts were lost
+    let run_source = state
+        .job_files
+        .source(id)
+        .await
+        .context("failed to load run source")?;
+    let mut proc_request =
+        processor::Request::new(&job_guard.problem_id, &job_guard.toolchain_name, run_source);
+    proc_request.phase = Some(req.phase.clone());
+    proc_request.compiled = Some(compiled);
+    proc_request.image_override = job_guard.image_override.clone();
+    proc_request.problem = job_guard.problem.clone();
+    proc_request.judging_mode = job_guard.judging_mode;
+    proc_request.invoker_labels = invoker_labels(&state, &job_guard.annotations)?;
+    proc_request.debug_dump_dir = debug_dump_dir(&state, job_guard.debug, id, Some(&req.phase));
+    job_guard.phases.push(Some(req.phase.clone()));
+    job_guard.phase = Some(req.phase);
+    job_guard.outcome = None;
+    job_guard.finished_at = None;
+    job_guard.live_test = None;
+    job_guard.live_test_name = None;
+    job_guard.live_score = None;
+    job_guard.live_subtasks.clear();
+    job_guard.live_stage = None;
+    job_guard.waiting_for_invoker = false;
+    job_guard.live_resources = None;
+    job_guard.test_statuses.clear();
+    job_guard.last_progress = None;
+    job_guard.last_stage = None;
+    job_guard.last_event_seq = 0;
+    job_guard.stale = false;
+    job_guard.restored_fault = None;
+    // run is not built again
+    let problem_id = job_guard.problem_id.clone();
+    let pinned = job_guard.problem.clone();
+    job_guard.estimated_cost = estimate_cost(&state, &problem_id, pinned.as_ref(), None).await;
+    state.persist(&job_guard).await;
+    let resp = job_guard.as_rest();
+    drop(job_guard);
+    let task = spawn_job(state, job.clone(), proc_request);
+    job.lock().await.task = Some(task);
+    Ok(resp)
+}
+
+/// Returns directory for the debug dump of the job phase, if the dump
+/// was requested
+fn debug_dump_dir(
+    state: &State,
+    debug: bool,
+    job_id: Uuid,
+    phase: Option<&str>,
+) -> Option<PathBuf> {
+    if !debug {
+        return None;
+    }
+    let dir = state.debug_dumps_dir.as_ref()?;
+    // phase names come from clients and must not escape the job directory
+    let phase: String = phase
+        .unwrap_or("default")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Some(dir.join(job_id.to_hyphenated().to_string()).join(phase))
+}
+
+/// Settings of one phase of the job. `phase` only affects invoker
+/// recording, which is made for each phase separately.
+fn job_settings(state: &State, job_id: Uuid, phase: Option<&str>) -> processor::Settings {
+    let mut settings = state.settings.clone();
+    let mut job_id_s = Uuid::encode_buffer();
+    let job_id_s = job_id.to_hyphenated().encode_lower(&mut job_id_s);
+    if let Some(p) = &mut settings.checker_logs {
+        p.push(&*job_id_s);
+    }
+    if let Some(p) = &mut settings.compile_logs {
+        p.push(&*job_id_s);
+    }
+    if let Some(p) = &mut settings.invoker_recording {
+        p.push(match phase {
+            Some(phase) => format!("{}-{}.json", job_id_s, phase),
+            None => format!("{}.json", job_id_s),
+        });
+    }
+    if let Some(store) = &mut settings.output_store {
+        *store = store.for_job(job_id_s);
+    }
+    if let Some(t) = &mut settings.trace {
+        t.job_id = job_id_s.to_string();
+    }
+    settings
+}
+
+/// Live values are applied to the job at most this often, so that
+/// frequent live events do not contend for the job lock
+const LIVE_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Latest live values which were not applied to the job yet
+#[derive(Default)]
+struct PendingLive {
+    test: Option<pom::TestId>,
+    score: Option<Score>,
+    resources: Option<judge_apis::live::LiveResources>,
+    /// Sequence number of the latest coalesced event
+    seq: u64,
+}
+
+impl PendingLive {
+    fn is_empty(&self) -> bool {
+        self.test.is_none() && self.score.is_none() && self.resources.is_none()
+    }
+
+    fn apply(&mut self, job: &mut JudgeJob) {
+        if self.is_empty() {
+            return;
+        }
+        if let Some(test) = self.test.take() {
+            let test_name = job
+                .problem
+                .as_ref()
+                .and_then(|p| p.extensions.test_name(test))
+                .map(str::to_string);
+            job.live_test = Some(test);
+            job.live_test_name = test_name.clone();
+            job.events
+                .send(LiveEvent::LiveTest { test, test_name })
+                .ok();
+        }
+        if let Some(score) = self.score.take() {
+            job.live_score = Some(score);
+            if !job.frozen {
+                job.events.send(LiveEvent::LiveScore { score }).ok();
+            }
+        }
+        if let Some(resources) = self.resources.take() {
+            job.live_resources = Some(resources);
+            job.events.send(LiveEvent::LiveResources { resources }).ok();
+        }
+        job.last_event_seq = self.seq;
+        job.last_progress = Some(Instant::now());
+        job.stale = false;
+    }
+}
+
+/// Loads problem given in the request instead of looking it up in
+/// registries
+async fn load_local_problem(
+    state: &State,
+    problem_id: &str,
+    source: judge_apis::rest::LocalProblemSource,
+) -> anyhow::Result<problem_loader::LoadedProblem> {
+    let source = match source {
+        judge_apis::rest::LocalProblemSource::Path(path) => problem_loader::LocalProblem::Dir(path),
+        judge_apis::rest::LocalProblemSource::Package(package) => {
+            problem_loader::LocalProblem::Package(package.0)
+        }
+    };
+    let problem = state
+        .clients
+        .problems
+        .load_local(problem_id, source)
+        .await?;
+    tracing::info!(
+        problem_id,
+        revision = problem.revision,
+        "loaded local problem"
+    );
+    Ok(problem)
+}
+
+/// Captures the current problem revision, so that all phases of the job
+/// use it. If the problem can not be loaded, nothing is pinned and the
+/// processor reports the error.
+async fn pin_problem(
+    state: &State,
+    job: &Mutex<JudgeJob>,
+    problem_id: &str,
+) -> Option<problem_loader::LoadedProblem> {
+    let problem = state
+        .clients
+        .problems
+        .find(problem_id)
+        .await
+        .ok()
+        .flatten()?;
+    tracing::info!(revision = problem.revision, "pinned problem revision");
+    job.lock().await.problem = Some(problem.clone());
+    Some(problem)
+}
+
+/// Spawns task running the job. If the task panics, the job fails
+/// instead of staying incomplete forever.
+fn spawn_job(
+    state: Arc<State>,
+    job: Arc<Mutex<JudgeJob>>,
+    proc_request: processor::Request,
+) -> tokio::task::JoinHandle<()> {
+    tokio::task::spawn(async move {
+        let res = AssertUnwindSafe(run_job(state.clone(), job.clone(), proc_request))
+            .catch_unwind()
+            .await;
+        let payload = match res {
+            Ok(()) => return,
+            Err(payload) => payload,
+        };
+        let error = anyhow::Error::new(processor::TaskPanicked::from_payload(payload))
+            .context("job task panicked");
+        let mut job = job.lock().await;
+        if job.outcome.is_some() {
+            // outcome is already published, so only the counter is updated
+            tracing::error!(job_id = %job.id, "{:#}", error);
+            state.panicked_tasks.fetch_add(1, Ordering::SeqCst);
+            return;
+        }
+        job.outcome = Some(processor::JudgeOutcome::Fault { error });
+        state.job_finished(&mut job).await;
+    })
+}
+
+/// Spawns long-running task of the judge, e.g. watchdog. Its panic is
+/// logged and counted, since there is no job to fail.
+fn spawn_background(
+    state: &Arc<State>,
+    name: &'static str,
+    task: impl Future<Output = ()> + Send + 'static,
+) {
+    let state = state.clone();
     tokio::task::spawn(async move {
-        while let Some(ev) = progress.event().await {
-            let mut job = job.lock().await;
-            match ev {
-                processor::Event::LiveScore(ls) => {
-                    job.live_score = Some(ls);
+        if let Err(payload) = AssertUnwindSafe(task).catch_unwind().await {
+            state.panicked_tasks.fetch_add(1, Ordering::SeqCst);
+            tracing::error!(
+                task = name,
+                "background task stopped: {}",
+                processor::TaskPanicked::from_payload(payload)
+            );
+        }
+    });
+}
+
+async fn run_job(
+    state: Arc<State>,
+    job: Arc<Mutex<JudgeJob>>,
+    mut proc_request: processor::Request,
+) {
+    let job_id = job.lock().await.id;
+    let settings = job_settings(&state, job_id, proc_request.phase.as_deref());
+    let problem_id = proc_request.problem_id.clone();
+    let (priority, estimated_seconds) = {
+        let job = job.lock().await;
+        let estimated_seconds = job
+            .estimated_cost
+            .as_ref()
+            .map_or(0.0, |e| e.max_cpu_seconds);
+        (job.priority, estimated_seconds)
+    };
+    let _running = state.queue.wait_dispatch(priority, estimated_seconds).await;
+    job.lock().await.last_progress = Some(Instant::now());
+    if proc_request.problem.is_none() {
+        proc_request.problem = pin_problem(&state, &job, &problem_id).await;
+    }
+    let mut score_aggregator = state.score_aggregation.aggregator(
+        proc_request.problem.as_ref(),
+        &problem_id,
+        &state.settings.warnings,
+    );
+    let mut progress = processor::judge(proc_request, state.clients.clone(), settings);
+    let mut pending_live = PendingLive::default();
+    let mut last_live_update = Instant::now();
+    loop {
+        let ev = if pending_live.is_empty() {
+            progress.event().await
+        } else {
+            let deadline = last_live_update + LIVE_UPDATE_INTERVAL;
+            tokio::select! {
+                ev = progress.event() => ev,
+                _ = tokio::time::sleep_until(deadline.into()) => {
+                    pending_live.apply(&mut *job.lock().await);
+                    last_live_update = Instant::now();
+                    continue;
                 }
-                processor::Event::LiveTest(lt) => {
-                    job.live_test = Some(lt);
+            }
+        };
+        let processor::SequencedEvent { seq, event: ev } = match ev {
+            Some(ev) => ev,
+            None => break,
+        };
+        let is_live = match &ev {
+            processor::Event::LiveScore(ls) => {
+                pending_live.score = Some(Score(score_aggregator.aggregate(*ls)));
+                true
+            }
+            processor::Event::LiveTest(lt) => {
+                pending_live.test = Some(*lt);
+                true
+            }
+            processor::Event::LiveResources(resources) => {
+                pending_live.resources = Some(*resources);
+                true
+            }
+            _ => false,
+        };
+        if is_live {
+            pending_live.seq = seq;
+            if last_live_update.elapsed() >= LIVE_UPDATE_INTERVAL {
+                pending_live.apply(&mut *job.lock().await);
+                last_live_update = Instant::now();
+            }
+            continue;
+        }
+        if let processor::Event::LogCreated(log) = &ev {
+            if state.shadow.is_some() {
+                let mut job = job.lock().await;
+                job.shadow_logs.push(log.clone());
+                job.last_event_seq = seq;
+                continue;
+            }
+            if let Err(err) = state.logs.put(job_id, log).await {
+                tracing::error!(err = %format_args!("{:#}", err), "failed to store judge log");
+                continue;
+            }
+        }
+        let mut job = job.lock().await;
+        // live values must not be applied after events which follow them
+        pending_live.apply(&mut job);
+        job.last_event_seq = seq;
+        job.last_progress = Some(Instant::now());
+        job.stale = false;
+        match ev {
+            processor::Event::LogCreated(log) => {
+                state
+                    .export(
+                        job_id,
+                        JudgeEventKind::Verdict {
+                            log: log.name(),
+                            status_code: log.status.code.clone(),
+                            score: log.score,
+                        },
+                    )
+                    .await;
+                if log.kind == JudgeLogKind::Contestant {
+                    job.live_subtasks = LiveSubtaskScore::from_log(&log);
+                }
+                job.logs.push(log.name());
+                job.events
+                    .send(LiveEvent::LogCreated { log: log.name() })
+                    .ok();
+            }
+            processor::Event::TestFinished { test_id, status } => {
+                state
+                    .export(
+                        job_id,
+                        JudgeEventKind::TestFinished {
+                            test_id: test_id.get(),
+                            status_code: status.code.clone(),
+                        },
+                    )
+                    .await;
+                if status.code == judge_apis::judge_log::PROCESS_LIMIT_STATUS_CODE {
+                    state.process_limit_hits.fetch_add(1, Ordering::SeqCst);
                 }
-                processor::Event::LogCreated(log) => {
-                    job.logs.insert(log.kind.as_str().to_string(), log);
+                if status.code == judge_apis::judge_log::SECURITY_VIOLATION_STATUS_CODE {
+                    state.security_violations.fetch_add(1, Ordering::SeqCst);
+                }
+                job.last_stage = Some(format!("test {} finished", test_id));
+                job.test_statuses.push((test_id, status));
+            }
+            processor::Event::Usage(usage) => {
+                job.usage.merge(&usage);
+            }
+            processor::Event::StageCompleted(stage) => {
+                match stage {
+                    processor::Stage::ValuerStarted => job.live_stage = Some(LiveStage::Testing),
+                    processor::Stage::TestsFinished => job.live_stage = Some(LiveStage::Finished),
+                    _ => {}
                 }
+                job.last_stage = Some(stage.as_str().to_string());
             }
+            processor::Event::Compiled(compiled) => {
+                job.compiled = Some(compiled);
+            }
+            processor::Event::LiveCompileLog(chunk) => {
+                job.compile_log.push_str(&chunk);
+                job.events.send(LiveEvent::LiveCompileLog { chunk }).ok();
+            }
+            processor::Event::WaitingForInvoker(waiting) => {
+                job.waiting_for_invoker = waiting;
+            }
+            _ => {}
         }
-        tracing::info!("event stream finished, retrieving outcome");
-        let outcome = progress.wait().await;
-
-        let mut job = job.lock().await;
-        job.outcome = Some(outcome);
-    });
+    }
+    tracing::info!("event stream finished, retrieving outcome");
+    let outcome = progress.wait().await;
 
-    resp
+    let mut job = job.lock().await;
+    pending_live.apply(&mut job);
+    if job.outcome.is_some() {
+        // job was already failed by the watchdog
+        return;
+    }
+    job.outcome = Some(outcome);
+    state.job_finished(&mut job).await;
 }
 
 async fn get_job(state: Arc<State>, id: Uuid) -> anyhow::Result<judge_apis::rest::JudgeJob> {
-    let job = {
-        let jobs = state.judge.read().await;
-        match jobs.get(&id) {
-            Some(job) => job.clone(),
-            None => {
-                return Err(anyhow::Error::new(ApiError::new(
-                    ErrorKind::NotFound,
-                    "JudgeJobNotFound",
-                )));
-            }
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => {
+            return Err(RestError::job_not_found(id).into());
         }
     };
     let job = job.lock().await;
     Ok(job.as_rest())
 }
 
-async fn get_job_judge_log(
-    state: Arc<State>,
-    id: Uuid,
-    kind: String,
-) -> anyhow::Result<judge_apis::judge_log::JudgeLog> {
-    let job = {
-        let jobs = state.judge.read().await;
-        match jobs.get(&id) {
-            Some(job) => job.clone(),
-            None => {
-                return Err(anyhow::Error::new(ApiError::new(
-                    ErrorKind::NotFound,
-                    "JudgeJobNotFound",
-                )));
-            }
+async fn get_job_judge_log(state: Arc<State>, id: Uuid, kind: String) -> anyhow::Result<JudgeLog> {
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => {
+            return Err(RestError::job_not_found(id).into());
         }
     };
-    let job = job.lock().await;
-    let log = match job.logs.get(&kind) {
+    let (frozen, completed) = {
+        let job = job.lock().await;
+        (job.frozen, job.outcome.is_some())
+    };
+    let log = match state.logs.get(id, &kind).await? {
+        Some(l) if frozen && l.kind == JudgeLogKind::Contestant => JudgeLog::pending(l.kind),
         Some(l) => l,
         None => {
-            return Err(anyhow::Error::new(ApiError::new(
-                ErrorKind::NotFound,
-                "JudgeLogNotFound",
-            )));
+            let err = RestError::not_found(codes::JUDGE_LOG_NOT_FOUND, "judge log not found")
+                .with_detail("job_id", id)
+                .with_detail("kind", &kind);
+            // log can still be created by the running job
+            let err = if completed { err } else { err.retryable() };
+            return Err(err.into());
         }
     };
-    Ok(log.clone())
+    Ok(log)
+}
+
+/// Compares logs of two jobs. Only log kinds present in both jobs are
+/// compared.
+async fn diff_jobs(
+    state: Arc<State>,
+    new_id: Uuid,
+    old_id: Uuid,
+) -> anyhow::Result<HashMap<String, judge_apis::diff::JudgeLogDiff>> {
+    let mut log_kinds = Vec::new();
+    {
+        let new_job = state.judge.get(new_id).await;
+        let old_job = state.judge.get(old_id).await;
+        let (new_job, old_job) = match (new_job, old_job) {
+            (Some(n), Some(o)) => (n, o),
+            (None, _) => return Err(RestError::job_not_found(new_id).into()),
+            (_, None) => return Err(RestError::job_not_found(old_id).into()),
+        };
+        let (old_logs, old_frozen) = {
+            let old_job = old_job.lock().await;
+            (old_job.logs.clone(), old_job.frozen)
+        };
+        let new_job = new_job.lock().await;
+        // withheld logs must not leak through diffs
+        let frozen = old_frozen || new_job.frozen;
+        for kind in &new_job.logs {
+            // log name is `${phase}.${kind}` if job has phases
+            let is_contestant = kind.rsplit('.').next() == Some(JudgeLogKind::Contestant.as_str());
+            if frozen && is_contestant {
+                continue;
+            }
+            if old_logs.contains(kind) {
+                log_kinds.push(kind.clone());
+            }
+        }
+    }
+    let mut diffs = HashMap::new();
+    for kind in log_kinds {
+        let new_log = state.logs.get(new_id, &kind).await?;
+        let old_log = state.logs.get(old_id, &kind).await?;
+        if let (Some(new_log), Some(old_log)) = (new_log, old_log) {
+            diffs.insert(kind, judge_apis::diff::diff(&old_log, &new_log));
+        }
+    }
+    Ok(diffs)
 }
 
 /// Serves api
@@ -160,18 +1273,61 @@ pub async fn serve(
     clients: processor::Clients,
     settings: processor::Settings,
 ) -> anyhow::Result<()> {
+    errors::set_debug_errors(cfg.debug_errors);
     let state = Arc::new(State {
-        judge: RwLock::new(HashMap::new()),
+        judge: JobMap::new(),
+        logs: LogStorage::new(cfg.log_storage, settings.warnings.clone()),
+        job_files: JobFiles::new(cfg.job_files_dir),
+        webhooks: cfg.webhooks,
+        queue: cfg.queue,
+        admin_auth: cfg.admin_auth,
+        frozen: AtomicBool::new(false),
+        shutting_down: AtomicBool::new(false),
+        stale_jobs: AtomicUsize::new(0),
+        process_limit_hits: AtomicU64::new(0),
+        security_violations: AtomicU64::new(0),
+        faults: Default::default(),
+        panicked_tasks: AtomicU64::new(0),
+        answers_jobs: Default::default(),
+        score_aggregation: cfg.score_aggregation,
+        shadow: cfg.shadow,
+        healthy_invokers: AtomicUsize::new(0),
+        min_healthy_invokers: cfg.health.as_ref().map(|c| c.min_healthy_invokers),
+        exporter: cfg.exporter,
+        job_store: cfg.job_store,
+        max_log_rows: cfg.max_log_rows,
+        source_fetcher: cfg.source_fetcher,
+        debug_dumps_dir: cfg.debug_dumps_dir,
+        json_streams: json_stream::JsonStreams::new(),
         clients,
         settings,
     });
+    persistence::restore(&state)
+        .await
+        .context("failed to restore jobs")?;
+    if let Some(config) = cfg.watchdog {
+        spawn_background(&state, "watchdog", watchdog::run(state.clone(), config));
+    }
+    if let Some(config) = cfg.health {
+        spawn_background(&state, "health", health::run(state.clone(), config));
+    }
+    spawn_background(
+        &state,
+        "retention",
+        retention::run(state.clone(), cfg.job_retention, cfg.pin_retention),
+    );
     let state2 = state.clone();
     let route_create_job = warp::post()
         .and(warp::path("jobs"))
         .and(warp::path::end())
         .and(warp::filters::body::json())
-        .and_then(move |req| start_job(state2.clone(), req).map(Result::<_, Infallible>::Ok))
+        .and(warp::header::optional::<String>("authorization"))
+        .and_then(move |req, authorization| {
+            start_job(state2.clone(), req, authorization)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
         .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover)
         .boxed();
 
     let state2 = state.clone();
@@ -185,29 +1341,93 @@ pub async fn serve(
                 .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
         })
         .map(|resp| warp::reply::json(&resp))
-        .recover(api_util::recover)
+        .recover(errors::recover)
         .boxed();
 
-    let route_get_log = warp::get()
+    let state2 = state.clone();
+
+    let route_diff_jobs = warp::get()
         .and(warp::path("jobs"))
         .and(warp::path::param::<Uuid>())
-        .and(warp::path("logs"))
-        .and(warp::path::param::<String>())
+        .and(warp::path("diff"))
+        .and(warp::path::param::<Uuid>())
         .and(warp::path::end())
-        .and_then(move |job_id, log_kind| {
-            get_job_judge_log(state.clone(), job_id, log_kind)
+        .and_then(move |new_id, old_id| {
+            diff_jobs(state2.clone(), new_id, old_id)
                 .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
         })
         .map(|resp| warp::reply::json(&resp))
-        .recover(api_util::recover)
+        .recover(errors::recover)
         .boxed();
 
-    let routes = route_create_job.or(route_get_job).or(route_get_log);
+    let state2 = state.clone();
+
+    let route_start_phase = warp::post()
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("phases"))
+        .and(warp::path::end())
+        .and(warp::filters::body::json())
+        .and_then(move |id, req| {
+            start_phase(state2.clone(), id, req)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover)
+        .boxed();
+
+    let route_batch = batch::routes(state.clone());
+    let route_archive = archive::routes(state.clone());
+    let route_syntax_check = syntax_check::routes(state.clone());
+    let route_summary = summary::routes(state.clone());
+    let route_ready = health::routes(state.clone());
+    let route_events = events::routes(state.clone());
+    let route_admin = admin::routes(state.clone());
+    let route_answers = answers::routes(state.clone());
+    let route_problems = problems::routes(state.clone());
+    let route_timing = timing::routes(state.clone());
+    let route_toolchains = toolchains::routes(state.clone());
+    let route_metrics = metrics::routes(state.clone());
+    let route_outputs = outputs::routes(state.clone());
+
+    let route_get_log = log_pages::routes(state.clone());
+    let access_log = access_log::filter(
+        cfg.access_log.map(Arc::new),
+        state.admin_auth.clone(),
+        state.settings.warnings.clone(),
+    );
+
+    let routes = route_create_job
+        .or(route_get_job)
+        .or(route_get_log)
+        .or(route_batch)
+        .or(route_archive)
+        .or(route_syntax_check)
+        .or(route_summary)
+        .or(route_events)
+        .or(route_diff_jobs)
+        .or(route_start_phase)
+        .or(route_answers)
+        .or(route_admin)
+        .or(route_problems)
+        .or(route_timing)
+        .or(route_toolchains)
+        .or(route_metrics)
+        .or(route_ready)
+        .or(route_outputs)
+        .recover(errors::recover_unmatched);
 
-    let server = warp::serve(routes.with(warp::filters::trace::request()));
+    let server = warp::serve(
+        routes
+            .with(warp::filters::trace::request())
+            .with(access_log),
+    );
 
     let srv = server
-        .try_bind_with_graceful_shutdown(([0, 0, 0, 0], cfg.port), futures::future::pending())
+        .try_bind_with_graceful_shutdown(
+            ([0, 0, 0, 0], cfg.port),
+            shutdown::wait(state, cfg.shutdown_timeout),
+        )
         .context("failed to bind")?
         .1;
     srv.await;
@@ -0,0 +1,153 @@
+//! Access log of the REST API.
+//!
+//! One line is written for every request, after the response is
+//! produced. Principal is `admin` if the request presented the admin
+//! token, and is absent otherwise. Request id is taken from the
+//! `X-Request-Id` header.
+
+use super::admin::{self, AdminAuth};
+use anyhow::Context;
+use std::{
+    fs::OpenOptions,
+    io::{LineWriter, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// Format of access log lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// JSON object per line
+    Json,
+    /// Common Log Format, followed by quoted request id and latency in
+    /// milliseconds
+    Clf,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "json" => Ok(AccessLogFormat::Json),
+            "clf" => Ok(AccessLogFormat::Clf),
+            _ => anyhow::bail!(
+                "unknown access log format {:?}, expected one of: json, clf",
+                s
+            ),
+        }
+    }
+}
+
+pub struct AccessLog {
+    format: AccessLogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+struct Entry<'a> {
+    remote_addr: Option<std::net::SocketAddr>,
+    method: &'a str,
+    path: &'a str,
+    version: String,
+    status: u16,
+    latency_ms: f64,
+    principal: Option<&'static str>,
+    request_id: Option<&'a str>,
+}
+
+impl AccessLog {
+    /// Opens the destination: `-` means stdout, other paths are files,
+    /// which are appended to
+    pub fn open(dest: &Path, format: AccessLogFormat) -> anyhow::Result<AccessLog> {
+        let out: Box<dyn Write + Send> = if dest == Path::new("-") {
+            Box::new(std::io::stdout())
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dest)
+                .with_context(|| format!("failed to open {}", dest.display()))?;
+            Box::new(LineWriter::new(file))
+        };
+        Ok(AccessLog {
+            format,
+            out: Mutex::new(out),
+        })
+    }
+
+    fn format(&self, entry: &Entry) -> String {
+        match self.format {
+            AccessLogFormat::Json => serde_json::json!({
+                "time": chrono::Utc::now().to_rfc3339(),
+                "remote_addr": entry.remote_addr.map(|a| a.to_string()),
+                "method": entry.method,
+                "path": entry.path,
+                "status": entry.status,
+                "latency_ms": entry.latency_ms,
+                "principal": entry.principal,
+                "request_id": entry.request_id,
+            })
+            .to_string(),
+            AccessLogFormat::Clf => format!(
+                "{} - {} [{}] \"{} {} {}\" {} - \"{}\" {:.3}",
+                entry
+                    .remote_addr
+                    .map_or_else(|| "-".to_string(), |a| a.ip().to_string()),
+                entry.principal.unwrap_or("-"),
+                chrono::Utc::now().format("%d/%b/%Y:%H:%M:%S %z"),
+                entry.method,
+                entry.path,
+                entry.version,
+                entry.status,
+                entry.request_id.unwrap_or("-"),
+                entry.latency_ms,
+            ),
+        }
+    }
+
+    fn write(&self, entry: &Entry) -> std::io::Result<()> {
+        let mut line = self.format(entry);
+        line.push('\n');
+        let mut out = self.out.lock().unwrap();
+        out.write_all(line.as_bytes())?;
+        out.flush()
+    }
+}
+
+/// Filter writing requests to the access log, if it is configured
+pub(super) fn filter(
+    access_log: Option<Arc<AccessLog>>,
+    admin_auth: AdminAuth,
+    warnings: processor::Warnings,
+) -> warp::log::Log<impl Fn(warp::log::Info<'_>) + Clone + Send> {
+    warp::log::custom(move |info| {
+        let access_log = match &access_log {
+            Some(l) => l,
+            None => return,
+        };
+        let headers = info.request_headers();
+        let authorization = headers.get("authorization").and_then(|h| h.to_str().ok());
+        let principal = if admin::has_admin_token(&admin_auth, authorization) {
+            Some("admin")
+        } else {
+            None
+        };
+        let entry = Entry {
+            remote_addr: info.remote_addr(),
+            method: info.method().as_str(),
+            path: info.path(),
+            version: format!("{:?}", info.version()),
+            status: info.status().as_u16(),
+            latency_ms: info.elapsed().as_secs_f64() * 1000.0,
+            principal,
+            request_id: headers.get("x-request-id").and_then(|h| h.to_str().ok()),
+        };
+        if let Err(err) = access_log.write(&entry) {
+            warnings.report(
+                "access-log-failed",
+                format!("failed to write access log: {}", err),
+            );
+        }
+    })
+}
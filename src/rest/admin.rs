@@ -0,0 +1,538 @@
+//! Administrative endpoints
+
+use super::errors::{self, RestError};
+use super::State;
+use anyhow::Context;
+use futures::future::{FutureExt, TryFutureExt};
+use judge_apis::{
+    admin::{FreezeStatus, JudgeStatus, QueueStatus, VerdictOverride, WarningSummary},
+    error::codes,
+    judge_log::{log_name, JudgeLogKind},
+    output_diff::OutputDiff,
+    rest::JobPriority,
+    shadow::ShadowReport,
+    usage::UsageReport,
+};
+use serde::Deserialize;
+use std::{
+    convert::Infallible,
+    sync::{atomic::Ordering, Arc},
+};
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, http::StatusCode, Filter, Rejection, Reply};
+
+#[derive(Debug)]
+pub(super) struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Admin API was requested, but it is disabled
+#[derive(Debug)]
+pub(super) struct AdminDisabled;
+
+impl warp::reject::Reject for AdminDisabled {}
+
+/// Authentication of admin requests
+#[derive(Clone)]
+pub enum AdminAuth {
+    /// Requests must contain `Authorization: Bearer ${token}`
+    Token(Arc<str>),
+    /// Admin token is not configured, so admin API and privileged request
+    /// fields are not available
+    Disabled,
+    /// Admin token is not configured, and every request is considered to
+    /// be made by an admin. Only used if explicitly requested.
+    Insecure,
+}
+
+/// Checks that `authorization` header contains admin token. In insecure
+/// mode every request is considered to be made by an admin.
+pub(super) fn is_admin(auth: &AdminAuth, header: Option<&str>) -> bool {
+    match auth {
+        AdminAuth::Token(_) => has_admin_token(auth, header),
+        AdminAuth::Disabled => false,
+        AdminAuth::Insecure => true,
+    }
+}
+
+/// Checks that admin token is configured and `authorization` header
+/// contains it. Used for request fields which must never be available
+/// to anonymous clients, even in insecure mode.
+pub(super) fn has_admin_token(auth: &AdminAuth, header: Option<&str>) -> bool {
+    match auth {
+        AdminAuth::Token(token) => match header.and_then(|h| h.strip_prefix("Bearer ")) {
+            Some(provided) => constant_time_eq(provided.as_bytes(), token.as_bytes()),
+            None => false,
+        },
+        AdminAuth::Disabled | AdminAuth::Insecure => false,
+    }
+}
+
+/// Compares secrets in time which only depends on their lengths
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Checks that request is made by an admin
+pub(super) fn authenticate(
+    auth: AdminAuth,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let auth = auth.clone();
+            async move {
+                if is_admin(&auth, header.as_deref()) {
+                    Ok(())
+                } else if let AdminAuth::Disabled = auth {
+                    Err(warp::reject::custom(AdminDisabled))
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn queue_status(state: &State) -> QueueStatus {
+    QueueStatus {
+        paused: state.queue.is_paused(),
+        pending: state.queue.pending(),
+        degraded: state.queue.is_degraded(),
+        running: state.queue.running(),
+        max_running: state.queue.max_running(),
+        pending_by_priority: JobPriority::ALL
+            .iter()
+            .map(|&p| (p, state.queue.pending_with_priority(p)))
+            .collect(),
+    }
+}
+
+async fn get_status(state: Arc<State>) -> JudgeStatus {
+    let jobs = state.judge.snapshot().await;
+    let mut active_jobs = 0;
+    let mut frozen_jobs = 0;
+    for job in &jobs {
+        let job = job.lock().await;
+        if job.outcome.is_none() {
+            active_jobs += 1;
+        }
+        if job.frozen {
+            frozen_jobs += 1;
+        }
+    }
+    JudgeStatus {
+        queue: queue_status(&state),
+        freeze: FreezeStatus {
+            frozen: state.frozen.load(Ordering::SeqCst),
+            frozen_jobs,
+        },
+        active_jobs,
+        total_jobs: jobs.len(),
+        log_kinds: state
+            .settings
+            .enabled_log_kinds
+            .iter()
+            .map(|kind| kind.as_str().to_string())
+            .collect(),
+    }
+}
+
+/// Starts or ends freeze. While freeze is active, contestant logs of new
+/// jobs are withheld; ending it releases logs of all frozen jobs.
+async fn set_frozen(state: Arc<State>, frozen: bool) -> FreezeStatus {
+    state.frozen.store(frozen, Ordering::SeqCst);
+    let jobs = state.judge.snapshot().await;
+    let mut frozen_jobs = 0;
+    for job in &jobs {
+        let mut job = job.lock().await;
+        if !frozen && job.frozen {
+            job.frozen = false;
+            state.persist(&job).await;
+        }
+        if job.frozen {
+            frozen_jobs += 1;
+        }
+    }
+    tracing::info!(frozen, frozen_jobs, "freeze state changed");
+    FreezeStatus {
+        frozen,
+        frozen_jobs,
+    }
+}
+
+/// Releases withheld logs of a single job
+async fn thaw_job(state: Arc<State>, id: Uuid) -> anyhow::Result<judge_apis::rest::JudgeJob> {
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => {
+            return Err(RestError::job_not_found(id).into());
+        }
+    };
+    let mut job = job.lock().await;
+    job.frozen = false;
+    state.persist(&job).await;
+    Ok(job.as_rest())
+}
+
+async fn set_queue_paused(state: Arc<State>, paused: bool) -> anyhow::Result<QueueStatus> {
+    state.queue.set_paused(paused).await?;
+    Ok(queue_status(&state))
+}
+
+/// Changes verdict of a single test and recomputes judge logs of the job
+async fn override_verdict(
+    state: Arc<State>,
+    id: Uuid,
+    mut verdict_override: VerdictOverride,
+) -> anyhow::Result<judge_apis::rest::JudgeJob> {
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => {
+            return Err(RestError::job_not_found(id).into());
+        }
+    };
+    // lock is held until logs are replaced, so that concurrent overrides
+    // of the same job do not lose each other
+    let mut job = job.lock().await;
+    job.check_writable()?;
+    if job.local_problem {
+        return Err(RestError::new(
+            StatusCode::CONFLICT,
+            codes::LOCAL_PROBLEM_RELEASED,
+            "local problem was removed when the job finished",
+        )
+        .into());
+    }
+    if !matches!(job.outcome, Some(processor::JudgeOutcome::Success)) {
+        return Err(RestError::new(
+            StatusCode::CONFLICT,
+            codes::JOB_NOT_COMPLETED,
+            "only successfully completed jobs can be adjusted",
+        )
+        .into());
+    }
+    let mut test_statuses = job.test_statuses.clone();
+    match test_statuses
+        .iter_mut()
+        .find(|(test_id, _)| *test_id == verdict_override.test_id)
+    {
+        Some((_, status)) => *status = verdict_override.status.clone(),
+        None => {
+            return Err(
+                RestError::not_found(codes::TEST_NOT_FOUND, "test not found")
+                    .with_detail("test_id", verdict_override.test_id)
+                    .into(),
+            );
+        }
+    }
+    // only logs of the current phase are recomputed
+    let mut logs = Vec::new();
+    for name in &job.logs {
+        if let Some(log) = state.logs.get(id, name).await? {
+            if log.phase == job.phase {
+                logs.push(log);
+            }
+        }
+    }
+    let new_logs = processor::revalue(
+        &job.problem_id,
+        job.problem.as_ref(),
+        job.judging_mode,
+        &test_statuses,
+        &logs,
+        &state.clients,
+        &state.settings,
+    )
+    .await
+    .context("failed to recompute judge logs")?;
+    for mut log in new_logs {
+        log.manually_adjusted = true;
+        state.logs.replace(id, &log).await?;
+    }
+    verdict_override.judge_id = Some(state.settings.judge_id.clone());
+    tracing::info!(
+        judge_id = %state.settings.judge_id,
+        job_id = %id,
+        test_id = %verdict_override.test_id,
+        status = %verdict_override.status.code,
+        "test verdict overridden"
+    );
+    job.test_statuses = test_statuses;
+    job.overrides.push(verdict_override);
+    state.persist(&job).await;
+    Ok(job.as_rest())
+}
+
+/// Returns decoded output, inlined into the judge log or put to the output
+/// store by the job
+async fn load_output(
+    state: &State,
+    job_id: Uuid,
+    inline: Option<&str>,
+    stored: Option<&str>,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    if let Some(data) = inline {
+        return base64::decode(data)
+            .context("judge log contains invalid base64")
+            .map(Some);
+    }
+    let path = match stored.and_then(|id| super::outputs::output_path(state, job_id, id)) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let data = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let mut data = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(file), &mut data)
+            .with_context(|| format!("failed to decompress {}", path.display()))?;
+        Ok::<_, anyhow::Error>(data)
+    })
+    .await
+    .context("output decompression panicked")??;
+    Ok(Some(data))
+}
+
+#[derive(Deserialize)]
+struct OutputDiffQuery {
+    /// Number of lines shown before and after the mismatch
+    #[serde(default = "OutputDiffQuery::default_context")]
+    context: usize,
+}
+
+impl OutputDiffQuery {
+    fn default_context() -> usize {
+        3
+    }
+}
+
+/// Compares test answer with solution stdout, so that admins do not
+/// have to download both to find the mismatch
+async fn get_output_diff(
+    state: Arc<State>,
+    id: Uuid,
+    test_id: u32,
+    query: OutputDiffQuery,
+) -> anyhow::Result<OutputDiff> {
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => {
+            return Err(RestError::job_not_found(id).into());
+        }
+    };
+    let phase = job.lock().await.phase.clone();
+    let log = state
+        .logs
+        .get(id, &log_name(JudgeLogKind::Full, phase.as_deref()))
+        .await?
+        .ok_or_else(|| {
+            RestError::not_found(codes::JUDGE_LOG_NOT_FOUND, "full judge log not found")
+                .with_detail("job_id", id)
+        })?;
+    let row = log
+        .tests
+        .iter()
+        .find(|row| row.test_id.get() == test_id)
+        .ok_or_else(|| {
+            RestError::not_found(codes::TEST_NOT_FOUND, "test not found")
+                .with_detail("test_id", test_id)
+        })?;
+    let not_available = |what: &str| {
+        RestError::not_found(
+            codes::OUTPUT_NOT_AVAILABLE,
+            format!("{} is not available", what),
+        )
+        .with_detail("test_id", test_id)
+    };
+    let expected = load_output(&state, id, row.test_answer.as_deref(), None)
+        .await?
+        .ok_or_else(|| not_available("test answer"))?;
+    let actual = load_output(
+        &state,
+        id,
+        row.test_stdout.as_deref(),
+        row.test_stdout_ref.as_deref(),
+    )
+    .await?
+    .ok_or_else(|| not_available("solution stdout"))?;
+    let context = query.context;
+    let test_id = row.test_id;
+    // outputs can be large
+    let diff = tokio::task::spawn_blocking(move || {
+        judge_apis::output_diff::diff_outputs(test_id, &expected, &actual, context)
+    })
+    .await
+    .context("output diff panicked")?;
+    Ok(diff)
+}
+
+async fn get_shadow_report(state: Arc<State>) -> anyhow::Result<ShadowReport> {
+    match &state.shadow {
+        Some(store) => store.report().await,
+        None => Err(RestError::not_found(
+            codes::SHADOW_MODE_DISABLED,
+            "judge is not running in shadow mode",
+        )
+        .into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct UsageQuery {
+    /// Annotation key to group jobs by
+    group_by: Option<String>,
+}
+
+/// Sums up resource usage of all known jobs
+async fn get_usage(state: Arc<State>, query: UsageQuery) -> UsageReport {
+    let jobs = state.judge.snapshot().await;
+    let mut report = UsageReport {
+        jobs: jobs.len(),
+        ..Default::default()
+    };
+    for job in &jobs {
+        let job = job.lock().await;
+        report.total.merge(&job.usage);
+        if let Some(key) = &query.group_by {
+            let group = job.annotations.get(key).cloned().unwrap_or_default();
+            report.groups.entry(group).or_default().merge(&job.usage);
+        }
+    }
+    report
+}
+
+fn get_warnings(state: &State) -> Vec<WarningSummary> {
+    state.settings.warnings.summaries()
+}
+
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    let admin = warp::path("admin").and(authenticate(state.admin_auth.clone()));
+
+    let state2 = state.clone();
+    let route_status = warp::get()
+        .and(admin.clone())
+        .and(warp::path("status"))
+        .and(warp::path::end())
+        .and_then(move || get_status(state2.clone()).map(Result::<_, Infallible>::Ok))
+        .map(|resp| warp::reply::json(&resp));
+
+    let state2 = state.clone();
+    let route_warnings = warp::get()
+        .and(admin.clone())
+        .and(warp::path("warnings"))
+        .and(warp::path::end())
+        .map(move || warp::reply::json(&get_warnings(&state2)));
+
+    let state2 = state.clone();
+    let route_usage = warp::get()
+        .and(admin.clone())
+        .and(warp::path("usage"))
+        .and(warp::path::end())
+        .and(warp::query::<UsageQuery>())
+        .and_then(move |query| get_usage(state2.clone(), query).map(Result::<_, Infallible>::Ok))
+        .map(|resp| warp::reply::json(&resp));
+
+    let state2 = state.clone();
+    let route_shadow_report = warp::get()
+        .and(admin.clone())
+        .and(warp::path("shadow"))
+        .and(warp::path("report"))
+        .and(warp::path::end())
+        .and_then(move || {
+            get_shadow_report(state2.clone())
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    let state2 = state.clone();
+    let route_queue = warp::post()
+        .and(admin.clone())
+        .and(warp::path("queue"))
+        .and(
+            warp::path("pause")
+                .map(|| true)
+                .or(warp::path("resume").map(|| false))
+                .unify(),
+        )
+        .and(warp::path::end())
+        .and_then(move |paused| {
+            set_queue_paused(state2.clone(), paused)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    let state2 = state.clone();
+    let route_freeze = warp::post()
+        .and(admin.clone())
+        .and(
+            warp::path("freeze")
+                .map(|| true)
+                .or(warp::path("thaw").map(|| false))
+                .unify(),
+        )
+        .and(warp::path::end())
+        .and_then(move |frozen| set_frozen(state2.clone(), frozen).map(Result::<_, Infallible>::Ok))
+        .map(|resp| warp::reply::json(&resp));
+
+    let state2 = state.clone();
+    let route_thaw_job = warp::post()
+        .and(admin.clone())
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("thaw"))
+        .and(warp::path::end())
+        .and_then(move |id| {
+            thaw_job(state2.clone(), id)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    let state2 = state.clone();
+    let route_output_diff = warp::get()
+        .and(admin.clone())
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("tests"))
+        .and(warp::path::param::<u32>())
+        .and(warp::path("output-diff"))
+        .and(warp::path::end())
+        .and(warp::query::<OutputDiffQuery>())
+        .and_then(move |id, test_id, query| {
+            get_output_diff(state2.clone(), id, test_id, query)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    let route_override = warp::post()
+        .and(admin)
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("overrides"))
+        .and(warp::path::end())
+        .and(warp::filters::body::json())
+        .and_then(move |id, verdict_override| {
+            override_verdict(state.clone(), id, verdict_override)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    route_status
+        .or(route_warnings)
+        .or(route_usage)
+        .or(route_shadow_report)
+        .or(route_queue)
+        .or(route_freeze)
+        .or(route_thaw_job)
+        .or(route_output_diff)
+        .or(route_override)
+        .recover(errors::recover)
+        .boxed()
+}
@@ -0,0 +1,237 @@
+//! Answer generation jobs.
+//!
+//! Trusted solution is run on all tests of a problem, and its outputs
+//! become correct answers in a new problem package. The package can be
+//! downloaded or stored in the writable registry. Jobs are kept in memory
+//! only: finished jobs are removed after job retention period, and only
+//! a limited number of them is kept regardless of retention.
+
+use super::{
+    admin,
+    errors::{self, RestError},
+    spawn_background, State,
+};
+use futures::future::TryFutureExt;
+use judge_apis::{
+    admin::{AnswersJob, AnswersJobRequest},
+    error::codes,
+    rest::JobPriority,
+};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, Filter, Reply};
+
+/// At most this many finished jobs are kept, older ones are evicted first
+const MAX_FINISHED_JOBS: usize = 32;
+
+pub(super) struct AnswersJobState {
+    info: AnswersJob,
+    package: Option<Vec<u8>>,
+    finished_at: Option<SystemTime>,
+}
+
+/// Removes finished jobs older than `retention`
+pub(super) fn evict_expired(state: &State, now: SystemTime, retention: Duration) {
+    state.answers_jobs.lock().unwrap().retain(|_, job| {
+        job.finished_at
+            .is_none_or(|t| now.duration_since(t).map_or(true, |age| age < retention))
+    });
+}
+
+/// Removes the oldest finished jobs above `MAX_FINISHED_JOBS`
+fn evict_excess(jobs: &mut HashMap<Uuid, AnswersJobState>) {
+    let mut finished: Vec<(SystemTime, Uuid)> = jobs
+        .iter()
+        .filter_map(|(id, job)| Some((job.finished_at?, *id)))
+        .collect();
+    if finished.len() <= MAX_FINISHED_JOBS {
+        return;
+    }
+    finished.sort_unstable();
+    let excess = finished.len() - MAX_FINISHED_JOBS;
+    for (_, id) in &finished[..excess] {
+        jobs.remove(id);
+    }
+}
+
+fn not_found(id: Uuid) -> RestError {
+    RestError::not_found(
+        codes::ANSWERS_JOB_NOT_FOUND,
+        "answer generation job not found",
+    )
+    .with_detail("job_id", id)
+}
+
+/// Starts the job in background. It waits in the job queue with low
+/// priority, so that it does not delay judging.
+fn start(state: Arc<State>, req: AnswersJobRequest) -> anyhow::Result<AnswersJob> {
+    state.check_accepting_jobs()?;
+    let id = Uuid::new_v4();
+    let info = AnswersJob {
+        id,
+        problem_id: req.problem_id.clone(),
+        completed: false,
+        error: None,
+        compile_log: None,
+        tests: Vec::new(),
+        package_available: false,
+        stored: false,
+    };
+    state.answers_jobs.lock().unwrap().insert(
+        id,
+        AnswersJobState {
+            info: info.clone(),
+            package: None,
+            finished_at: None,
+        },
+    );
+    tracing::info!(job_id = %id, problem_id = %req.problem_id, "starting answer generation");
+    let state2 = state.clone();
+    spawn_background(&state, "answers", async move {
+        let res = run(&state2, req).await;
+        let mut jobs = state2.answers_jobs.lock().unwrap();
+        let job = match jobs.get_mut(&id) {
+            Some(j) => j,
+            None => return,
+        };
+        job.info.completed = true;
+        job.finished_at = Some(SystemTime::now());
+        match res {
+            Ok((info, package)) => {
+                job.info.compile_log = Some(info.compile_log);
+                job.info.tests = info.tests;
+                job.info.stored = info.stored;
+                job.info.package_available = package.is_some();
+                job.package = package;
+            }
+            Err(err) => {
+                tracing::warn!(job_id = %id, "answer generation failed: {:#}", err);
+                job.info.error = Some(format!("{:#}", err));
+            }
+        }
+        evict_excess(&mut jobs);
+    });
+    Ok(info)
+}
+
+struct Finished {
+    compile_log: String,
+    tests: Vec<(pom::TestId, judge_apis::judge_log::Status)>,
+    stored: bool,
+}
+
+async fn run(state: &State, req: AnswersJobRequest) -> anyhow::Result<(Finished, Option<Vec<u8>>)> {
+    let problem_id = req.problem_id.clone();
+    let store = req.store;
+    let estimated_seconds =
+        super::estimate_cost(state, &problem_id, None, Some(&req.toolchain_name))
+            .await
+            .map_or(0.0, |e| e.max_cpu_seconds);
+    let _running = state
+        .queue
+        .wait_dispatch(JobPriority::Low, estimated_seconds)
+        .await;
+    let generated = processor::generate_answers(
+        processor::AnswersRequest {
+            problem_id: req.problem_id,
+            toolchain_name: req.toolchain_name,
+            run_source: req.run_source.0,
+        },
+        &state.clients,
+        &state.settings,
+    )
+    .await?;
+    let mut stored = false;
+    if let (Some(package), true) = (&generated.package, store) {
+        state
+            .clients
+            .problems
+            .upload(&problem_id, package.clone())
+            .await?;
+        stored = true;
+    }
+    Ok((
+        Finished {
+            compile_log: generated.compile_log,
+            tests: generated.tests,
+            stored,
+        },
+        generated.package,
+    ))
+}
+
+fn get(state: &State, id: Uuid) -> anyhow::Result<AnswersJob> {
+    match state.answers_jobs.lock().unwrap().get(&id) {
+        Some(job) => Ok(job.info.clone()),
+        None => Err(not_found(id).into()),
+    }
+}
+
+fn get_package(state: &State, id: Uuid) -> anyhow::Result<Vec<u8>> {
+    let jobs = state.answers_jobs.lock().unwrap();
+    let job = jobs.get(&id).ok_or_else(|| not_found(id))?;
+    match &job.package {
+        Some(package) => Ok(package.clone()),
+        None => Err(RestError::not_found(
+            codes::PACKAGE_NOT_AVAILABLE,
+            "job has not produced a package",
+        )
+        .with_detail("job_id", id)
+        .into()),
+    }
+}
+
+/// `POST /admin/answers-jobs`, `GET /admin/answers-jobs/{id}` and
+/// `GET /admin/answers-jobs/{id}/package`
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    let base = warp::path("admin")
+        .and(admin::authenticate(state.admin_auth.clone()))
+        .and(warp::path("answers-jobs"));
+    let state2 = state.clone();
+    let route_start = warp::post()
+        .and(base.clone())
+        .and(warp::path::end())
+        .and(warp::filters::body::json())
+        .and_then(move |req| {
+            futures::future::ready(start(state2.clone(), req))
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    let state2 = state.clone();
+    let route_get = warp::get()
+        .and(base.clone())
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path::end())
+        .and_then(move |id| {
+            futures::future::ready(get(&state2, id))
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    let route_package = warp::get()
+        .and(base)
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("package"))
+        .and(warp::path::end())
+        .and_then(move |id| {
+            futures::future::ready(get_package(&state, id))
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|package: Vec<u8>| {
+            warp::reply::with_header(package, "content-type", "application/gzip")
+        })
+        .recover(errors::recover);
+
+    route_start
+        .or(route_get)
+        .or(route_package)
+        .recover(errors::recover)
+        .boxed()
+}
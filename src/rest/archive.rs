@@ -0,0 +1,275 @@
+//! Export of jobs to self-contained archives and their import.
+//!
+//! Archive contains everything needed to review judging later: request
+//! parameters and run source, all logs, judging events, statuses of
+//! judged tests, checker logs and metadata. Imported jobs are read-only.
+//! Archives exported with `?anonymize=true` can be published: they
+//! contain anonymized logs and no checker logs.
+
+use super::{
+    admin,
+    errors::{self, RestError},
+    job_settings, JudgeJob, State,
+};
+use crate::job_store::JobRecord;
+use anyhow::Context;
+use futures::future::TryFutureExt;
+use judge_apis::{error::codes, events::JudgeEvent, judge_log::JudgeLog, rest::ByteString};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path, sync::Arc, time::SystemTime};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, http::StatusCode, Filter, Reply};
+
+/// Version of the archive format, incremented on incompatible changes
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub(super) struct JobArchive {
+    format_version: u32,
+    /// Judge which exported the job
+    exported_by: String,
+    exported_at: SystemTime,
+    /// Request parameters, state, test statuses and logs of the job
+    job: JobRecord,
+    /// Checker logs by test id, if they were saved
+    #[serde(default)]
+    checker_logs: BTreeMap<u32, ByteString>,
+    /// Judging events of the job, oldest first
+    #[serde(default)]
+    events: Vec<JudgeEvent>,
+    /// If true, logs are anonymized and checker logs are omitted
+    #[serde(default)]
+    anonymized: bool,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    /// If set, archive can be published: see `JudgeLog::anonymized`
+    #[serde(default)]
+    anonymize: bool,
+}
+
+async fn export_job(state: Arc<State>, id: Uuid, query: ExportQuery) -> anyhow::Result<JobArchive> {
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => {
+            return Err(RestError::job_not_found(id).into());
+        }
+    };
+    let job = job.lock().await;
+    if job.outcome.is_none() {
+        return Err(RestError::new(
+            StatusCode::CONFLICT,
+            codes::JOB_NOT_COMPLETED,
+            "only completed jobs can be exported",
+        )
+        .into());
+    }
+    let mut logs = state.job_logs(&job).await?;
+    let checker_logs = match &job_settings(&state, id, None).checker_logs {
+        Some(dir) if !query.anonymize => read_checker_logs(dir).await?,
+        _ => BTreeMap::new(),
+    };
+    if query.anonymize {
+        logs = logs.iter().map(JudgeLog::anonymized).collect();
+    }
+    let run_source = state
+        .job_files
+        .source(id)
+        .await
+        .context("failed to load run source")?;
+    let events = state
+        .job_files
+        .events(id)
+        .await
+        .context("failed to load events")?;
+    Ok(JobArchive {
+        format_version: FORMAT_VERSION,
+        exported_by: state.settings.judge_id.clone(),
+        exported_at: SystemTime::now(),
+        job: job.to_record(logs, run_source),
+        checker_logs,
+        events,
+        anonymized: query.anonymize,
+    })
+}
+
+async fn read_checker_logs(dir: &Path) -> anyhow::Result<BTreeMap<u32, ByteString>> {
+    let mut logs = BTreeMap::new();
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        // checker logs are only saved if some test was run
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(logs),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to list {}", dir.display()));
+        }
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        let test_id = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            Some(test_id) => test_id,
+            None => continue,
+        };
+        let path = entry.path();
+        let data = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        logs.insert(test_id, ByteString(data));
+    }
+    Ok(logs)
+}
+
+/// Creates read-only job from the archive. Job keeps its original id.
+async fn import_job(
+    state: Arc<State>,
+    mut archive: JobArchive,
+) -> anyhow::Result<judge_apis::rest::JudgeJob> {
+    if archive.format_version != FORMAT_VERSION {
+        return Err(RestError::bad_request(
+            codes::INVALID_REQUEST,
+            "unsupported archive format version",
+        )
+        .with_detail("format_version", archive.format_version)
+        .into());
+    }
+    if !archive.job.completed {
+        return Err(RestError::bad_request(
+            codes::INVALID_REQUEST,
+            "archive contains a job which was not completed",
+        )
+        .into());
+    }
+    let id = archive.job.id;
+    let logs = archive.job.logs.clone();
+    let run_source = archive.job.run_source.take();
+    let mut job = JudgeJob::from_record(archive.job);
+    job.imported = true;
+    let job = Arc::new(Mutex::new(job));
+    // job is locked until its files are stored, so that it is not seen
+    // half-imported; shard lock is only held to check and insert it
+    let job_guard = job.clone().lock_owned().await;
+    {
+        let mut jobs = state.judge.lock_shard(id).await;
+        if jobs.contains_key(&id) {
+            return Err(RestError::new(
+                StatusCode::CONFLICT,
+                codes::JOB_ALREADY_EXISTS,
+                "job with the same id already exists",
+            )
+            .with_detail("job_id", id)
+            .into());
+        }
+        jobs.insert(id, job);
+    }
+    let stored = store_imported(
+        &state,
+        id,
+        &logs,
+        run_source,
+        &archive.events,
+        &archive.checker_logs,
+    )
+    .await;
+    if let Err(err) = stored {
+        drop(job_guard);
+        state.judge.remove(id).await;
+        state.logs.remove_job(id).await;
+        if let Err(err) = state.job_files.remove_job(id).await {
+            tracing::warn!(job_id = %id, "failed to clean up imported job: {:#}", err);
+        }
+        return Err(err);
+    }
+    tracing::info!(
+        job_id = %id,
+        exported_by = %archive.exported_by,
+        "imported job"
+    );
+    state.persist(&job_guard).await;
+    Ok(job_guard.as_rest())
+}
+
+/// Stores logs, run source, events and checker logs of the imported job
+async fn store_imported(
+    state: &State,
+    id: Uuid,
+    logs: &[JudgeLog],
+    run_source: Option<ByteString>,
+    events: &[JudgeEvent],
+    checker_logs: &BTreeMap<u32, ByteString>,
+) -> anyhow::Result<()> {
+    for log in logs {
+        state
+            .logs
+            .replace(id, log)
+            .await
+            .context("failed to store imported logs")?;
+    }
+    if let Some(source) = run_source {
+        state
+            .job_files
+            .put_source(id, &source.0)
+            .await
+            .context("failed to store imported run source")?;
+    }
+    if !events.is_empty() {
+        state
+            .job_files
+            .append_events(id, events)
+            .await
+            .context("failed to store imported events")?;
+    }
+    if let Some(dir) = &job_settings(state, id, None).checker_logs {
+        if !checker_logs.is_empty() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .context("failed to create checker logs directory")?;
+        }
+        for (test_id, data) in checker_logs {
+            tokio::fs::write(dir.join(test_id.to_string()), &data.0)
+                .await
+                .context("failed to store imported checker log")?;
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    let state2 = state.clone();
+    let state3 = state.clone();
+    let route_export = warp::get()
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(admin::authenticate(state.admin_auth.clone()))
+        .and(warp::query::<ExportQuery>())
+        .and_then(move |id, query| {
+            export_job(state2.clone(), id, query)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        // archives contain all logs of the job and may be large
+        .map(move |resp| state3.json_streams.reply(resp, false))
+        .recover(errors::recover);
+
+    let route_import = warp::post()
+        .and(warp::path("jobs"))
+        .and(warp::path("import"))
+        .and(warp::path::end())
+        .and(admin::authenticate(state.admin_auth.clone()))
+        .and(warp::filters::body::json())
+        .and_then(move |archive| {
+            import_job(state.clone(), archive)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    route_export
+        .or(route_import)
+        .recover(errors::recover)
+        .boxed()
+}
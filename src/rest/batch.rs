@@ -0,0 +1,70 @@
+//! Batch retrieval of judge logs
+
+use super::{
+    errors::{self, RestError},
+    json_stream, State,
+};
+use futures::future::TryFutureExt;
+use judge_apis::error::codes;
+use judge_apis::rest::{BatchGetLogsItem, BatchGetLogsRequest, BatchGetLogsResponse};
+use std::sync::Arc;
+use warp::{filters::BoxedFilter, Filter, Reply};
+
+/// Maximum number of logs in one request
+const MAX_BATCH_SIZE: usize = 10_000;
+
+async fn batch_get_logs(
+    state: Arc<State>,
+    req: BatchGetLogsRequest,
+) -> anyhow::Result<BatchGetLogsResponse> {
+    if req.logs.len() > MAX_BATCH_SIZE {
+        return Err(RestError::bad_request(
+            codes::TOO_MANY_ITEMS,
+            format!("at most {} logs can be requested at once", MAX_BATCH_SIZE),
+        )
+        .with_detail("limit", MAX_BATCH_SIZE)
+        .into());
+    }
+    let mut items = Vec::with_capacity(req.logs.len());
+    for log_ref in req.logs {
+        let (log, error) =
+            match super::get_job_judge_log(state.clone(), log_ref.job_id, log_ref.kind.clone())
+                .await
+            {
+                Ok(mut log) => {
+                    super::log_pages::truncate(&mut log, state.max_log_rows);
+                    (Some(log), None)
+                }
+                Err(err) => (None, Some(format!("{:#}", err))),
+            };
+        items.push(BatchGetLogsItem {
+            job_id: log_ref.job_id,
+            kind: log_ref.kind,
+            log,
+            error,
+        });
+    }
+    Ok(BatchGetLogsResponse { items })
+}
+
+/// `POST /jobs/logs:batchGet`
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    warp::post()
+        .and(warp::path("jobs"))
+        .and(warp::path("logs:batchGet"))
+        .and(warp::path::end())
+        .and(warp::filters::body::json())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(move |req, accept_encoding: Option<String>| {
+            let state = state.clone();
+            async move {
+                let resp = batch_get_logs(state.clone(), req).await?;
+                // response is compressed if client accepts gzip
+                let gzip = json_stream::accepts_gzip(accept_encoding.as_deref());
+                Ok::<_, anyhow::Error>(state.json_streams.reply(resp, gzip))
+            }
+            .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .recover(errors::recover)
+        .boxed()
+}
@@ -0,0 +1,184 @@
+//! Conversion of failures into `ErrorResponse`s
+
+use judge_apis::error::{codes, ErrorResponse};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use warp::{http::StatusCode, Rejection, Reply};
+
+/// If set, internal errors are sent to clients with all their causes.
+/// Otherwise causes, which may contain host paths and other internals,
+/// are only logged.
+static DEBUG_ERRORS: AtomicBool = AtomicBool::new(false);
+
+pub(super) fn set_debug_errors(enabled: bool) {
+    DEBUG_ERRORS.store(enabled, Ordering::Relaxed);
+}
+
+/// Error which is reported to the client as is.
+/// Other errors become `InternalError`.
+#[derive(Debug, Clone)]
+pub(super) struct RestError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: HashMap<String, String>,
+    retryable: bool,
+}
+
+impl RestError {
+    pub(super) fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        RestError {
+            status,
+            code,
+            message: message.into(),
+            details: HashMap::new(),
+            retryable: false,
+        }
+    }
+
+    pub(super) fn not_found(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, code, message)
+    }
+
+    pub(super) fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub(super) fn job_not_found(id: uuid::Uuid) -> Self {
+        Self::not_found(codes::JUDGE_JOB_NOT_FOUND, "judge job not found").with_detail("job_id", id)
+    }
+
+    /// Marks error as transient
+    pub(super) fn retryable(mut self) -> Self {
+        self.retryable = true;
+        self
+    }
+
+    pub(super) fn with_detail(mut self, key: &str, value: impl ToString) -> Self {
+        self.details.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    fn into_reply(self) -> Box<dyn Reply> {
+        let body = ErrorResponse {
+            code: self.code.to_string(),
+            message: self.message,
+            details: self.details,
+            retryable: self.retryable,
+        };
+        Box::new(warp::reply::with_status(
+            warp::reply::json(&body),
+            self.status,
+        ))
+    }
+}
+
+impl std::fmt::Display for RestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for RestError {}
+
+fn from_anyhow(err: &anyhow::Error) -> RestError {
+    if let Some(err) = err.downcast_ref::<RestError>() {
+        return err.clone();
+    }
+    if err
+        .downcast_ref::<problem_loader::InvalidProblem>()
+        .is_some()
+    {
+        return RestError::bad_request(codes::INVALID_PROBLEM_PACKAGE, format!("{:#}", err));
+    }
+    tracing::warn!("request failed: {:#}", err);
+    let message = if DEBUG_ERRORS.load(Ordering::Relaxed) {
+        format!("{:#}", err)
+    } else {
+        err.to_string()
+    };
+    RestError::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        codes::INTERNAL_ERROR,
+        message,
+    )
+}
+
+/// Converts rejections which mean that request was routed, but failed
+fn classify(rejection: &Rejection) -> Option<RestError> {
+    if let Some(err) = rejection.find::<api_util::AnyhowRejection>() {
+        return Some(from_anyhow(&err.0));
+    }
+    if rejection.find::<super::admin::Unauthorized>().is_some() {
+        return Some(RestError::new(
+            StatusCode::UNAUTHORIZED,
+            codes::UNAUTHORIZED,
+            "admin token is missing or invalid",
+        ));
+    }
+    if rejection.find::<super::admin::AdminDisabled>().is_some() {
+        return Some(RestError::new(
+            StatusCode::FORBIDDEN,
+            codes::FORBIDDEN,
+            "admin API is disabled, because admin token is not configured",
+        ));
+    }
+    if let Some(err) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+        return Some(RestError::bad_request(
+            codes::INVALID_REQUEST,
+            err.to_string(),
+        ));
+    }
+    if let Some(err) = rejection.find::<warp::reject::InvalidQuery>() {
+        return Some(RestError::bad_request(
+            codes::INVALID_REQUEST,
+            err.to_string(),
+        ));
+    }
+    if let Some(err) = rejection.find::<warp::reject::UnsupportedMediaType>() {
+        return Some(RestError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            codes::INVALID_REQUEST,
+            err.to_string(),
+        ));
+    }
+    if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        return Some(RestError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            codes::PAYLOAD_TOO_LARGE,
+            "request body is too large",
+        ));
+    }
+    None
+}
+
+/// Per-route recovery. Rejections which do not belong to the route are
+/// passed through, so that other routes can be tried.
+pub(super) async fn recover(rejection: Rejection) -> Result<Box<dyn Reply>, Rejection> {
+    match classify(&rejection) {
+        Some(err) => Ok(err.into_reply()),
+        None => Err(rejection),
+    }
+}
+
+/// Recovery for requests which no route could handle
+pub(super) async fn recover_unmatched(rejection: Rejection) -> Result<Box<dyn Reply>, Infallible> {
+    if let Some(err) = classify(&rejection) {
+        return Ok(err.into_reply());
+    }
+    let err = if rejection.is_not_found() {
+        RestError::not_found(codes::ROUTE_NOT_FOUND, "no endpoint matches request path")
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        RestError::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            codes::METHOD_NOT_ALLOWED,
+            "endpoint does not support request method",
+        )
+    } else {
+        RestError::bad_request(codes::INVALID_REQUEST, format!("{:?}", rejection))
+    };
+    Ok(err.into_reply())
+}
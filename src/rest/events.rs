@@ -0,0 +1,93 @@
+//! Streaming of live job events over Server-Sent Events
+
+use super::{
+    errors::{self, RestError},
+    State,
+};
+use futures::{
+    future::TryFutureExt,
+    stream::{self, Stream, StreamExt},
+};
+use judge_apis::live::LiveEvent;
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, Filter, Reply};
+
+/// Capacity of per-job event channel. Slow subscribers miss older events.
+pub(super) const CHANNEL_CAPACITY: usize = 64;
+
+/// Yields events until the job is completed
+fn follow(rx: broadcast::Receiver<LiveEvent>) -> impl Stream<Item = LiveEvent> {
+    stream::unfold(Some(rx), |rx| async move {
+        let mut rx = rx?;
+        loop {
+            match rx.recv().await {
+                Ok(LiveEvent::Completed) => return Some((LiveEvent::Completed, None)),
+                Ok(ev) => return Some((ev, Some(rx))),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::debug!(skipped, "event subscriber lagged");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+async fn subscribe(
+    state: Arc<State>,
+    id: Uuid,
+) -> anyhow::Result<impl Stream<Item = Result<warp::sse::Event, Infallible>>> {
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => {
+            return Err(RestError::job_not_found(id).into());
+        }
+    };
+    // subscription and snapshot are taken under the same lock, so no
+    // event is missed
+    let (snapshot, rx) = {
+        let job = job.lock().await;
+        let snapshot = job.as_rest();
+        let rx = if snapshot.completed {
+            None
+        } else {
+            Some(job.events.subscribe())
+        };
+        (snapshot, rx)
+    };
+    let updates = match rx {
+        Some(rx) => follow(rx).left_stream(),
+        None => stream::empty().right_stream(),
+    };
+    let events = stream::once(async move {
+        LiveEvent::Snapshot {
+            job: Box::new(snapshot),
+        }
+    })
+    .chain(updates)
+    .map(|ev| {
+        let sse = warp::sse::Event::default().event(ev.name());
+        Ok(sse.json_data(&ev).unwrap_or_else(|err| {
+            tracing::error!("failed to serialize live event: {}", err);
+            warp::sse::Event::default().comment("serialization failed")
+        }))
+    });
+    Ok(events)
+}
+
+/// `GET /jobs/{id}/events`
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("events"))
+        .and(warp::path::end())
+        .and_then(move |id| {
+            subscribe(state.clone(), id)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|events| warp::sse::reply(warp::sse::keep_alive().stream(events)))
+        .recover(errors::recover)
+        .boxed()
+}
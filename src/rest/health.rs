@@ -0,0 +1,77 @@
+//! Invoker health checking and readiness probe.
+//!
+//! If fewer invokers than configured are healthy, job dispatching is
+//! blocked, new jobs are rejected and readiness probe fails, so that
+//! load balancer routes runs to other judges instead of letting them
+//! time out.
+
+use super::State;
+use judge_apis::rest::Readiness;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use warp::{filters::BoxedFilter, http::StatusCode, Filter, Reply};
+
+pub struct HealthConfig {
+    /// Judge is ready only if at least this many invokers are healthy
+    pub min_healthy_invokers: usize,
+    /// How often invokers are checked
+    pub check_interval: Duration,
+}
+
+pub(super) async fn run(state: Arc<State>, config: HealthConfig) {
+    let mut interval = tokio::time::interval(config.check_interval);
+    loop {
+        interval.tick().await;
+        check(&state, &config).await;
+    }
+}
+
+async fn check(state: &State, config: &HealthConfig) {
+    let healthy = state.clients.invokers.healthy_pools().await;
+    state.healthy_invokers.store(healthy, Ordering::SeqCst);
+    let degraded = healthy < config.min_healthy_invokers;
+    if degraded {
+        state.settings.warnings.report(
+            "invokers-unhealthy",
+            format!(
+                "only {} invokers are healthy, at least {} are required",
+                healthy, config.min_healthy_invokers
+            ),
+        );
+    }
+    if degraded != state.queue.is_degraded() {
+        state.queue.set_degraded(degraded);
+    }
+}
+
+fn readiness(state: &State) -> Readiness {
+    let degraded = state.queue.is_degraded();
+    let shutting_down = state.shutting_down.load(Ordering::SeqCst);
+    Readiness {
+        ready: !degraded && !shutting_down,
+        shutting_down,
+        healthy_invokers: state
+            .min_healthy_invokers
+            .map(|_| state.healthy_invokers.load(Ordering::SeqCst)),
+        min_healthy_invokers: state.min_healthy_invokers,
+    }
+}
+
+/// `GET /ready`: 200 if judge can accept jobs, 503 otherwise
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("ready"))
+        .and(warp::path::end())
+        .map(move || {
+            let readiness = readiness(&state);
+            let status = if readiness.ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            warp::reply::with_status(warp::reply::json(&readiness), status)
+        })
+        .boxed()
+}
@@ -0,0 +1,67 @@
+//! Registry of judge jobs.
+//!
+//! Jobs are split between shards by id, so that creation of jobs does
+//! not block lookups of unrelated jobs. To stay deadlock-free, code holds
+//! at most one shard lock at a time, except `snapshot`, which read-locks
+//! all shards in the same order. Shard locks must not be held while a job
+//! is locked.
+
+use super::JudgeJob;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::{Mutex, RwLock, RwLockWriteGuard};
+use uuid::Uuid;
+
+/// Number of shards. Ids are random, so jobs are distributed evenly.
+const SHARDS: usize = 16;
+
+pub(super) type JobRef = Arc<Mutex<JudgeJob>>;
+
+pub(super) struct JobMap {
+    shards: Vec<RwLock<HashMap<Uuid, JobRef>>>,
+}
+
+impl JobMap {
+    pub(super) fn new() -> JobMap {
+        JobMap {
+            shards: (0..SHARDS).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+
+    fn shard(&self, id: Uuid) -> &RwLock<HashMap<Uuid, JobRef>> {
+        &self.shards[(id.as_u128() % SHARDS as u128) as usize]
+    }
+
+    pub(super) async fn get(&self, id: Uuid) -> Option<JobRef> {
+        self.shard(id).read().await.get(&id).cloned()
+    }
+
+    /// Returns previous job with the same id, if any
+    pub(super) async fn insert(&self, id: Uuid, job: JobRef) -> Option<JobRef> {
+        self.shard(id).write().await.insert(id, job)
+    }
+
+    /// Removes job from the map, returning it
+    pub(super) async fn remove(&self, id: Uuid) -> Option<JobRef> {
+        self.shard(id).write().await.remove(&id)
+    }
+
+    /// Locks shard which contains `id`, e.g. to check that a job does not
+    /// exist and insert it atomically. Other shards must not be locked
+    /// while the guard is alive.
+    pub(super) async fn lock_shard(&self, id: Uuid) -> RwLockWriteGuard<'_, HashMap<Uuid, JobRef>> {
+        self.shard(id).write().await
+    }
+
+    /// Returns all jobs. All shards are locked together, so the result
+    /// reflects the map at a single point of time.
+    pub(super) async fn snapshot(&self) -> Vec<JobRef> {
+        let mut guards = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            guards.push(shard.read().await);
+        }
+        guards
+            .iter()
+            .flat_map(|shard| shard.values().cloned())
+            .collect()
+    }
+}
@@ -0,0 +1,156 @@
+//! JSON responses which are serialized while they are being sent.
+//!
+//! `warp::reply::json` serializes the whole value into one buffer, so
+//! several concurrent downloads of large judge logs hold several copies
+//! of them. Here serialization runs on a blocking thread and writes
+//! chunks into a bounded channel, which is drained by the response body.
+//! If the client reads slowly, serialization waits for it, so the number
+//! of responses serialized at once is limited: otherwise slow clients
+//! could occupy the whole blocking thread pool, which `tokio::fs` uses
+//! too.
+
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+use tokio::sync::{mpsc, Semaphore};
+use warp::{
+    http::{HeaderValue, Response},
+    hyper::{body::Bytes, Body},
+};
+
+/// Serialized data is sent to the client in chunks of about this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Number of chunks which can wait for the client
+const CHANNEL_CAPACITY: usize = 4;
+
+/// At most this many responses are serialized at once. Others wait
+/// without holding a blocking thread.
+const MAX_CONCURRENT_STREAMS: usize = 16;
+
+type Chunk = Result<Bytes, io::Error>;
+
+/// Sends written data to the channel in chunks
+struct ChunkWriter {
+    buf: Vec<u8>,
+    tx: mpsc::Sender<Chunk>,
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        self.tx
+            .blocking_send(Ok(Bytes::from(chunk)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+fn write_json<T: serde::Serialize>(value: &T, writer: ChunkWriter, gzip: bool) -> io::Result<()> {
+    let mut writer = if gzip {
+        let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::fast());
+        serde_json::to_writer(&mut encoder, value)?;
+        encoder.finish()?
+    } else {
+        let mut writer = writer;
+        serde_json::to_writer(&mut writer, value)?;
+        writer
+    };
+    writer.flush()
+}
+
+/// Limits number of responses serialized at once
+pub(super) struct JsonStreams {
+    slots: Arc<Semaphore>,
+}
+
+impl JsonStreams {
+    pub(super) fn new() -> JsonStreams {
+        JsonStreams {
+            slots: Arc::new(Semaphore::new(MAX_CONCURRENT_STREAMS)),
+        }
+    }
+
+    /// Replies with `value` serialized as JSON, compressed with gzip if
+    /// `gzip` is set. Failure in the middle of the response aborts it.
+    pub(super) fn reply<T>(&self, value: T, gzip: bool) -> Response<Body>
+    where
+        T: serde::Serialize + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        let slots = self.slots.clone();
+        tokio::task::spawn(async move {
+            let permit = match slots.acquire_owned().await {
+                Ok(p) => p,
+                // semaphore is never closed
+                Err(_) => return,
+            };
+            if tx.is_closed() {
+                return;
+            }
+            tokio::task::spawn_blocking(move || {
+                let _permit = permit;
+                let writer = ChunkWriter {
+                    buf: Vec::with_capacity(CHUNK_SIZE),
+                    tx: tx.clone(),
+                };
+                if let Err(err) = write_json(&value, writer, gzip) {
+                    if err.kind() == io::ErrorKind::BrokenPipe {
+                        return;
+                    }
+                    tracing::error!("failed to serialize response: {}", err);
+                    // client must not take truncated response for a complete one
+                    tx.blocking_send(Err(err)).ok();
+                }
+            });
+        });
+        let body = futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (chunk, rx))
+        });
+        let mut resp = Response::new(Body::wrap_stream(body));
+        let headers = resp.headers_mut();
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        // compression depends on the request, so caches must not reuse
+        // the response for other clients
+        headers.insert("vary", HeaderValue::from_static("accept-encoding"));
+        if gzip {
+            headers.insert("content-encoding", HeaderValue::from_static("gzip"));
+        }
+        resp
+    }
+}
+
+/// Checks if client accepts gzip-compressed responses. Encodings with
+/// zero quality (e.g. `gzip;q=0`) are refused by the client.
+pub(super) fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    let header = match accept_encoding {
+        Some(h) => h,
+        None => return false,
+    };
+    header.split(',').any(|enc| {
+        let mut parts = enc.split(';').map(str::trim);
+        if !parts
+            .next()
+            .unwrap_or_default()
+            .eq_ignore_ascii_case("gzip")
+        {
+            return false;
+        }
+        let quality = parts
+            .filter_map(|param| param.strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        quality > 0.0
+    })
+}
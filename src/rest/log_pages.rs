@@ -0,0 +1,167 @@
+//! Retrieval of large judge logs.
+//!
+//! Logs of problems with thousands of tests do not fit in one response,
+//! so at most `State::max_log_rows` tests are returned with the log, and
+//! all tests are available page by page. Summary view keeps only failed
+//! tests. With `anonymize=true` test data and checker comments are
+//! stripped from both endpoints. Both endpoints compress responses if
+//! client accepts gzip.
+
+use super::{errors, errors::RestError, get_job_judge_log, json_stream, State};
+use futures::future::TryFutureExt;
+use judge_apis::{
+    error::codes,
+    judge_log::JudgeLog,
+    rest::{JudgeLogTestsPage, SummarizedJudgeLog},
+    verdict,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, http::Response, hyper::Body, Filter, Reply};
+
+/// Page size used when neither request nor configuration sets it
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// Maximum number of tests in one page
+const MAX_PAGE_SIZE: usize = 10_000;
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+enum LogView {
+    /// Whole log, possibly with truncated tests
+    #[default]
+    Full,
+    /// Failed tests and counters of all tests
+    Summary,
+}
+
+#[derive(Deserialize)]
+struct LogQuery {
+    #[serde(default)]
+    view: LogView,
+    /// If set, anonymized variant of the log is returned
+    #[serde(default)]
+    anonymize: bool,
+}
+
+#[derive(Deserialize)]
+struct TestsPageQuery {
+    /// Zero-based
+    #[serde(default)]
+    page: usize,
+    page_size: Option<usize>,
+    /// Same as `LogQuery::anonymize`
+    #[serde(default)]
+    anonymize: bool,
+}
+
+/// Keeps at most `max_rows` tests in the log, recording their original
+/// number if some were dropped
+pub(super) fn truncate(log: &mut JudgeLog, max_rows: Option<usize>) {
+    if let Some(max_rows) = max_rows {
+        if log.tests.len() > max_rows {
+            log.total_tests = Some(log.tests.len());
+            log.tests.truncate(max_rows);
+        }
+    }
+}
+
+async fn get_log(
+    state: Arc<State>,
+    id: Uuid,
+    kind: String,
+    query: LogQuery,
+    gzip: bool,
+) -> anyhow::Result<Response<Body>> {
+    let mut log = get_job_judge_log(state.clone(), id, kind).await?;
+    if query.anonymize {
+        log = log.anonymized();
+    }
+    Ok(match query.view {
+        LogView::Full => {
+            truncate(&mut log, state.max_log_rows);
+            state.json_streams.reply(log, gzip)
+        }
+        LogView::Summary => state.json_streams.reply(
+            SummarizedJudgeLog {
+                summary: verdict::summarize(&log),
+                log: verdict::retain_failed_tests(&log),
+            },
+            gzip,
+        ),
+    })
+}
+
+async fn get_tests_page(
+    state: Arc<State>,
+    id: Uuid,
+    kind: String,
+    query: TestsPageQuery,
+) -> anyhow::Result<JudgeLogTestsPage> {
+    let page_size = query
+        .page_size
+        .or(state.max_log_rows)
+        .unwrap_or(DEFAULT_PAGE_SIZE);
+    if page_size == 0 || page_size > MAX_PAGE_SIZE {
+        return Err(RestError::bad_request(
+            codes::TOO_MANY_ITEMS,
+            format!("page size must be between 1 and {}", MAX_PAGE_SIZE),
+        )
+        .with_detail("limit", MAX_PAGE_SIZE)
+        .into());
+    }
+    let mut log = get_job_judge_log(state, id, kind).await?;
+    if query.anonymize {
+        log = log.anonymized();
+    }
+    let total_tests = log.tests.len();
+    let tests = log
+        .tests
+        .into_iter()
+        .skip(query.page.saturating_mul(page_size))
+        .take(page_size)
+        .collect();
+    Ok(JudgeLogTestsPage {
+        page: query.page,
+        page_size,
+        total_tests,
+        tests,
+    })
+}
+
+/// `GET /jobs/{id}/logs/{kind}` and `GET /jobs/{id}/logs/{kind}/tests`
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    let base = warp::get()
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("logs"))
+        .and(warp::path::param::<String>());
+    let state2 = state.clone();
+    let route_log = base
+        .and(warp::path::end())
+        .and(warp::query::<LogQuery>())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(move |id, kind, query, accept_encoding: Option<String>| {
+            let gzip = json_stream::accepts_gzip(accept_encoding.as_deref());
+            get_log(state2.clone(), id, kind, query, gzip)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .recover(errors::recover);
+
+    let route_tests = base
+        .and(warp::path("tests"))
+        .and(warp::path::end())
+        .and(warp::query::<TestsPageQuery>())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(move |id, kind, query, accept_encoding: Option<String>| {
+            let gzip = json_stream::accepts_gzip(accept_encoding.as_deref());
+            let state = state.clone();
+            get_tests_page(state.clone(), id, kind, query)
+                .map_ok(move |resp| state.json_streams.reply(resp, gzip))
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .recover(errors::recover);
+
+    route_log.or(route_tests).recover(errors::recover).boxed()
+}
@@ -0,0 +1,113 @@
+//! Metrics in Prometheus text format
+
+use super::State;
+use judge_apis::rest::JobPriority;
+use std::{
+    fmt::Write,
+    sync::{atomic::Ordering, Arc},
+};
+use warp::{filters::BoxedFilter, Filter, Reply};
+
+fn render(state: &State) -> String {
+    let mut out = String::new();
+    out.push_str("# HELP judge_warnings_total Number of recurring warnings by fingerprint\n");
+    out.push_str("# TYPE judge_warnings_total counter\n");
+    for warning in state.settings.warnings.summaries() {
+        writeln!(
+            out,
+            "judge_warnings_total{{judge_id=\"{}\",fingerprint=\"{}\"}} {}",
+            state.settings.judge_id, warning.fingerprint, warning.count
+        )
+        .unwrap();
+    }
+    out.push_str(
+        "# HELP judge_stale_jobs Number of running jobs which made no progress for too long\n",
+    );
+    out.push_str("# TYPE judge_stale_jobs gauge\n");
+    writeln!(
+        out,
+        "judge_stale_jobs{{judge_id=\"{}\"}} {}",
+        state.settings.judge_id,
+        state.stale_jobs.load(Ordering::SeqCst)
+    )
+    .unwrap();
+    out.push_str("# HELP judge_faults_total Number of failed jobs by fault category\n");
+    out.push_str("# TYPE judge_faults_total counter\n");
+    for (category, count) in state.faults.lock().unwrap().iter() {
+        writeln!(
+            out,
+            "judge_faults_total{{judge_id=\"{}\",category=\"{}\"}} {}",
+            state.settings.judge_id, category, count
+        )
+        .unwrap();
+    }
+    out.push_str(
+        "# HELP judge_process_limit_exceeded_total Number of tests on which solution exceeded process limit\n",
+    );
+    out.push_str("# TYPE judge_process_limit_exceeded_total counter\n");
+    writeln!(
+        out,
+        "judge_process_limit_exceeded_total{{judge_id=\"{}\"}} {}",
+        state.settings.judge_id,
+        state.process_limit_hits.load(Ordering::SeqCst)
+    )
+    .unwrap();
+    out.push_str(
+        "# HELP judge_security_violations_total Number of tests on which solution violated sandbox restrictions\n",
+    );
+    out.push_str("# TYPE judge_security_violations_total counter\n");
+    writeln!(
+        out,
+        "judge_security_violations_total{{judge_id=\"{}\"}} {}",
+        state.settings.judge_id,
+        state.security_violations.load(Ordering::SeqCst)
+    )
+    .unwrap();
+    out.push_str(
+        "# HELP judge_panicked_tasks_total Number of job and background tasks which panicked\n",
+    );
+    out.push_str("# TYPE judge_panicked_tasks_total counter\n");
+    writeln!(
+        out,
+        "judge_panicked_tasks_total{{judge_id=\"{}\"}} {}",
+        state.settings.judge_id,
+        state.panicked_tasks.load(Ordering::SeqCst)
+    )
+    .unwrap();
+    out.push_str("# HELP judge_queue_pending Number of jobs waiting to be started by priority\n");
+    out.push_str("# TYPE judge_queue_pending gauge\n");
+    for priority in JobPriority::ALL {
+        writeln!(
+            out,
+            "judge_queue_pending{{judge_id=\"{}\",priority=\"{}\"}} {}",
+            state.settings.judge_id,
+            priority.as_str(),
+            state.queue.pending_with_priority(priority)
+        )
+        .unwrap();
+    }
+    if state.min_healthy_invokers.is_some() {
+        out.push_str(
+            "# HELP judge_healthy_invokers Number of invokers which passed the latest health check\n",
+        );
+        out.push_str("# TYPE judge_healthy_invokers gauge\n");
+        writeln!(
+            out,
+            "judge_healthy_invokers{{judge_id=\"{}\"}} {}",
+            state.settings.judge_id,
+            state.healthy_invokers.load(Ordering::SeqCst)
+        )
+        .unwrap();
+    }
+    out
+}
+
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .map(move || {
+            warp::reply::with_header(render(&state), "content-type", "text/plain; version=0.0.4")
+        })
+        .boxed()
+}
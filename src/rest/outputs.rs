@@ -0,0 +1,111 @@
+//! Serves solution outputs put to the output store.
+//!
+//! Output is only served if it is referenced by a judge log of the job
+//! which the client is allowed to see, so outputs of frozen jobs stay
+//! hidden together with their contestant logs.
+
+use super::{errors, errors::RestError, get_job_judge_log, State};
+use anyhow::Context;
+use futures::future::TryFutureExt;
+use judge_apis::error::codes;
+use std::{path::PathBuf, sync::Arc};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+use warp::{
+    filters::BoxedFilter,
+    http::{HeaderValue, Response},
+    hyper::{body::Bytes, Body},
+    Filter, Reply,
+};
+
+/// Outputs are sent to the client in chunks of this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Returns path of the stored output of the job
+pub(super) fn output_path(state: &State, job_id: Uuid, output_id: &str) -> Option<PathBuf> {
+    let store = state.settings.output_store.as_ref()?;
+    let job_dir = store.for_job(&job_id.to_hyphenated().to_string());
+    Some(job_dir.dir().join(output_id))
+}
+
+/// Removes all stored outputs of the job
+pub(super) async fn remove_job_outputs(state: &State, job_id: Uuid) -> anyhow::Result<()> {
+    let store = match &state.settings.output_store {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    let dir = store.for_job(&job_id.to_hyphenated().to_string());
+    match tokio::fs::remove_dir_all(dir.dir()).await {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err).with_context(|| format!("failed to remove {}", dir.dir().display())),
+    }
+}
+
+async fn get_output(
+    state: Arc<State>,
+    job_id: Uuid,
+    output_id: String,
+) -> anyhow::Result<Response<Body>> {
+    let log_kinds = match state.judge.get(job_id).await {
+        Some(job) => job.lock().await.logs.clone(),
+        None => return Err(RestError::job_not_found(job_id).into()),
+    };
+    let mut referenced = false;
+    for kind in log_kinds {
+        let log = get_job_judge_log(state.clone(), job_id, kind).await?;
+        referenced = log.tests.iter().any(|row| {
+            row.test_stdout_ref.as_deref() == Some(output_id.as_str())
+                || row.test_stderr_ref.as_deref() == Some(output_id.as_str())
+        });
+        if referenced {
+            break;
+        }
+    }
+    let path = match output_path(&state, job_id, &output_id) {
+        Some(path) if referenced => path,
+        _ => {
+            return Err(
+                RestError::not_found(codes::OUTPUT_NOT_AVAILABLE, "output not found")
+                    .with_detail("job_id", job_id)
+                    .with_detail("output_id", &output_id)
+                    .into(),
+            )
+        }
+    };
+    let file = tokio::fs::File::open(&path)
+        .await
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let body = futures::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buf = vec![0; CHUNK_SIZE];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), Some(file)))
+            }
+            Err(err) => Some((Err(err), None)),
+        }
+    });
+    let mut resp = Response::new(Body::wrap_stream(body));
+    resp.headers_mut()
+        .insert("content-type", HeaderValue::from_static("application/gzip"));
+    Ok(resp)
+}
+
+/// `GET /jobs/{id}/outputs/{output_id}` returns gzip-compressed output
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("outputs"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and_then(move |job_id, output_id| {
+            get_output(state.clone(), job_id, output_id)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .recover(errors::recover)
+        .boxed()
+}
@@ -0,0 +1,192 @@
+//! Saving jobs to the job store and restoring them on startup
+
+use super::{events, JudgeJob, State};
+use crate::job_store::JobRecord;
+use anyhow::Context;
+use judge_apis::{
+    judge_log::{JudgeLog, JudgeLogKind},
+    live::LiveSubtaskScore,
+    rest::ByteString,
+};
+use std::{sync::Arc, time::SystemTime};
+use tokio::sync::Mutex;
+
+impl JudgeJob {
+    pub(super) fn to_record(&self, logs: Vec<JudgeLog>, run_source: Vec<u8>) -> JobRecord {
+        let rest_job = self.as_rest();
+        JobRecord {
+            id: self.id,
+            problem_id: self.problem_id.clone(),
+            toolchain_name: self.toolchain_name.clone(),
+            phase: self.phase.clone(),
+            phases: self.phases.clone(),
+            locale: self.locale.clone(),
+            timezone: self.timezone.clone(),
+            image_override: self.image_override.clone(),
+            judging_mode: self.judging_mode,
+            priority: self.priority,
+            debug: self.debug,
+            run_source: if run_source.is_empty() {
+                None
+            } else {
+                Some(ByteString(run_source))
+            },
+            imported: self.imported,
+            local_problem: self.local_problem,
+            created_at: self.created_at,
+            finished_at: self.finished_at,
+            live_test: self.live_test,
+            live_test_name: self.live_test_name.clone(),
+            live_score: self.live_score,
+            live_resources: self.live_resources,
+            annotations: self.annotations.clone(),
+            completed: rest_job.completed,
+            error: rest_job.error,
+            fault: rest_job.fault,
+            test_statuses: self.test_statuses.clone(),
+            overrides: self.overrides.clone(),
+            judge_id: self.judge_id.clone(),
+            frozen: self.frozen,
+            usage: self.usage.clone(),
+            estimated_cost: self.estimated_cost.clone(),
+            logs,
+        }
+    }
+
+    /// Creates job from the record. Run source of the record is not
+    /// used: it must be put to `State::job_files` separately.
+    pub(super) fn from_record(record: JobRecord) -> JudgeJob {
+        let outcome = match (record.completed, record.error) {
+            (true, None) => Some(processor::JudgeOutcome::Success),
+            (true, Some(error)) => Some(processor::JudgeOutcome::Fault {
+                error: anyhow::Error::msg(error),
+            }),
+            (false, _) => None,
+        };
+        JudgeJob {
+            id: record.id,
+            problem_id: record.problem_id,
+            toolchain_name: record.toolchain_name,
+            phase: record.phase,
+            phases: record.phases,
+            compiled: None,
+            locale: record.locale,
+            timezone: record.timezone,
+            image_override: record.image_override,
+            judging_mode: record.judging_mode,
+            priority: record.priority,
+            debug: record.debug,
+            imported: record.imported,
+            local_problem: record.local_problem,
+            created_at: record.created_at,
+            finished_at: record.finished_at,
+            live_test: record.live_test,
+            live_test_name: record.live_test_name,
+            live_score: record.live_score,
+            live_subtasks: record
+                .logs
+                .iter()
+                .rev()
+                .find(|log| log.kind == JudgeLogKind::Contestant)
+                .map(LiveSubtaskScore::from_log)
+                .unwrap_or_default(),
+            live_stage: None,
+            waiting_for_invoker: false,
+            live_resources: record.live_resources,
+            compile_log: String::new(),
+            logs: record.logs.iter().map(|log| log.name()).collect(),
+            annotations: record.annotations,
+            outcome,
+            restored_fault: record.fault,
+            test_statuses: record.test_statuses,
+            overrides: record.overrides,
+            judge_id: record.judge_id,
+            frozen: record.frozen,
+            usage: record.usage,
+            estimated_cost: record.estimated_cost,
+            last_progress: None,
+            last_stage: None,
+            last_event_seq: 0,
+            problem: None,
+            pins_released: false,
+            stale: false,
+            task: None,
+            shadow_logs: Vec::new(),
+            events: tokio::sync::broadcast::channel(events::CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl State {
+    /// Returns all logs of the job, in order of creation
+    pub(super) async fn job_logs(&self, job: &JudgeJob) -> anyhow::Result<Vec<JudgeLog>> {
+        let mut logs = Vec::new();
+        for name in &job.logs {
+            if let Some(log) = self.logs.get(job.id, name).await? {
+                logs.push(log);
+            }
+        }
+        Ok(logs)
+    }
+
+    /// Writes current state of the job to the job store, if it is
+    /// configured. Failures are reported as warnings.
+    pub(super) async fn persist(&self, job: &JudgeJob) {
+        let store = match &self.job_store {
+            Some(s) => s,
+            None => return,
+        };
+        let res = async {
+            let logs = self.job_logs(job).await?;
+            let run_source = self.job_files.source(job.id).await?;
+            store.put(&job.to_record(logs, run_source)).await
+        }
+        .await;
+        if let Err(err) = res {
+            self.settings.warnings.report(
+                "job-persist-failed",
+                format!("failed to persist job {}: {:#}", job.id, err),
+            );
+        }
+    }
+}
+
+/// Loads all jobs from the job store. Jobs which were not completed are
+/// marked as failed, because their progress is lost.
+pub(super) async fn restore(state: &Arc<State>) -> anyhow::Result<()> {
+    let store = match &state.job_store {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    let records = store.load_all().await.context("failed to load jobs")?;
+    let mut interrupted = 0;
+    let total = records.len();
+    for record in records {
+        for log in &record.logs {
+            state
+                .logs
+                .replace(record.id, log)
+                .await
+                .with_context(|| format!("failed to restore logs of job {}", record.id))?;
+        }
+        if let Some(source) = &record.run_source {
+            state
+                .job_files
+                .put_source(record.id, &source.0)
+                .await
+                .with_context(|| format!("failed to restore run source of job {}", record.id))?;
+        }
+        let mut job = JudgeJob::from_record(record);
+        if job.outcome.is_none() {
+            interrupted += 1;
+            job.outcome = Some(processor::JudgeOutcome::Fault {
+                error: anyhow::anyhow!("judge was restarted while the job was running"),
+            });
+            job.finished_at = Some(SystemTime::now());
+            state.persist(&job).await;
+        }
+        state.judge.insert(job.id, Arc::new(Mutex::new(job))).await;
+    }
+    tracing::info!(jobs = total, interrupted, "restored jobs");
+    Ok(())
+}
@@ -0,0 +1,164 @@
+//! Problem management endpoints
+
+use super::{
+    admin,
+    errors::{self, RestError},
+    State,
+};
+use futures::future::TryFutureExt;
+use judge_apis::{
+    admin::{InUseRevision, InvalidateProblemsRequest, InvalidatedProblems},
+    error::codes,
+};
+use std::sync::Arc;
+use warp::{filters::BoxedFilter, http::StatusCode, hyper::body::Bytes, Filter, Reply};
+
+/// Maximum size of uploaded problem package
+const MAX_PACKAGE_SIZE: u64 = 512 * 1024 * 1024;
+
+async fn upload_problem(
+    state: Arc<State>,
+    problem_id: String,
+    package: Bytes,
+) -> anyhow::Result<()> {
+    state
+        .clients
+        .problems
+        .upload(&problem_id, package.to_vec())
+        .await
+}
+
+/// Checks problem as it is stored in the registries. Defects are reported
+/// in the same way as for uploads.
+async fn validate_problem(state: Arc<State>, problem_id: String) -> anyhow::Result<()> {
+    if state.clients.problems.validate(&problem_id).await? {
+        Ok(())
+    } else {
+        Err(
+            RestError::not_found(codes::PROBLEM_NOT_FOUND, "problem not found")
+                .with_detail("problem_id", problem_id)
+                .into(),
+        )
+    }
+}
+
+/// Drops cached problems, so that re-uploaded packages are used by new
+/// jobs
+async fn invalidate_problems(
+    state: Arc<State>,
+    req: InvalidateProblemsRequest,
+) -> anyhow::Result<InvalidatedProblems> {
+    let loader = &state.clients.problems;
+    let dropped = match req.problem_ids {
+        Some(problem_ids) => {
+            let mut dropped = Vec::new();
+            for problem_id in problem_ids {
+                dropped.extend(loader.invalidate(&problem_id).await);
+            }
+            dropped
+        }
+        None => loader.invalidate_all().await,
+    };
+    Ok(InvalidatedProblems {
+        in_use: dropped
+            .iter()
+            .filter(|d| d.in_use)
+            .map(|d| InUseRevision {
+                problem_id: d.problem_name.clone(),
+                revision: d.revision,
+            })
+            .collect(),
+        invalidated: dropped.into_iter().map(|d| d.problem_name).collect(),
+    })
+}
+
+/// Parses body of the bulk invalidation request. Empty body invalidates
+/// all problems.
+fn parse_invalidate_request(body: &[u8]) -> anyhow::Result<InvalidateProblemsRequest> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(InvalidateProblemsRequest::default());
+    }
+    serde_json::from_slice(body).map_err(|err| {
+        RestError::bad_request(
+            codes::INVALID_REQUEST,
+            format!("invalid request body: {}", err),
+        )
+        .into()
+    })
+}
+
+/// `PUT /problems/{id}`, `POST /problems/{id}/validate`,
+/// `POST /cache/problems/{id}/invalidate` and
+/// `POST /cache/problems/invalidate`
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    let cache = warp::post()
+        .and(warp::path("cache"))
+        .and(warp::path("problems"))
+        .and(admin::authenticate(state.admin_auth.clone()));
+    let route_invalidate = {
+        let state = state.clone();
+        cache
+            .clone()
+            .and(warp::path::param::<String>())
+            .and(warp::path("invalidate"))
+            .and(warp::path::end())
+            .and_then(move |problem_id| {
+                let req = InvalidateProblemsRequest {
+                    problem_ids: Some(vec![problem_id]),
+                };
+                invalidate_problems(state.clone(), req)
+                    .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+            })
+            .map(|resp| warp::reply::json(&resp))
+    };
+    let route_invalidate_bulk = {
+        let state = state.clone();
+        cache
+            .and(warp::path("invalidate"))
+            .and(warp::path::end())
+            .and(warp::body::content_length_limit(1024 * 1024))
+            .and(warp::body::bytes())
+            .and_then(move |body: Bytes| {
+                let state = state.clone();
+                async move {
+                    let req = parse_invalidate_request(&body)?;
+                    invalidate_problems(state, req).await
+                }
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+            })
+            .map(|resp| warp::reply::json(&resp))
+    };
+    let route_validate = {
+        let state = state.clone();
+        warp::post()
+            .and(warp::path("problems"))
+            .and(admin::authenticate(state.admin_auth.clone()))
+            .and(warp::path::param::<String>())
+            .and(warp::path("validate"))
+            .and(warp::path::end())
+            .and_then(move |problem_id| {
+                validate_problem(state.clone(), problem_id)
+                    .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+            })
+            .map(|()| StatusCode::NO_CONTENT)
+    };
+    let route_upload = warp::put()
+        .and(warp::path("problems"))
+        .and(admin::authenticate(state.admin_auth.clone()))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(MAX_PACKAGE_SIZE))
+        .and(warp::body::bytes())
+        .and_then(move |problem_id, package| {
+            upload_problem(state.clone(), problem_id, package)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|()| StatusCode::NO_CONTENT);
+    let route_invalidate = route_invalidate.or(route_invalidate_bulk).unify();
+    route_upload
+        .or(route_validate)
+        .unify()
+        .or(route_invalidate)
+        .recover(errors::recover)
+        .boxed()
+}
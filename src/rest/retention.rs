@@ -0,0 +1,103 @@
+//! Removal of old jobs.
+//!
+//! Finished jobs are kept for the configured period and then removed
+//! together with their logs, run sources, events, stored outputs and job
+//! store records.
+//! Problem revisions and compiled runs pinned by finished jobs are
+//! released earlier, after pin retention period. Finished answer
+//! generation jobs are removed after job retention period too.
+
+use super::State;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+/// Jobs are checked at least this often, even if retention is long
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+pub(super) async fn run(
+    state: Arc<State>,
+    job_retention: Option<Duration>,
+    pin_retention: Duration,
+) {
+    let shortest = job_retention.map_or(pin_retention, |r| r.min(pin_retention));
+    let check_interval = (shortest / 4).clamp(Duration::from_secs(1), MAX_CHECK_INTERVAL);
+    let mut interval = tokio::time::interval(check_interval);
+    loop {
+        interval.tick().await;
+        let now = SystemTime::now();
+        if let Some(retention) = job_retention {
+            super::answers::evict_expired(&state, now, retention);
+        }
+        for job in state.judge.snapshot().await {
+            let mut job = job.lock().await;
+            if let Some(retention) = job_retention {
+                if is_expired(job.finished_at, now, retention) {
+                    let id = job.id;
+                    drop(job);
+                    remove_job(&state, id, retention).await;
+                    continue;
+                }
+            }
+            let pinned = job.problem.is_some() || job.compiled.is_some();
+            if pinned && is_expired(job.finished_at, now, pin_retention) {
+                tracing::info!(job_id = %job.id, "releasing problem revision and compiled run");
+                job.problem = None;
+                job.compiled = None;
+                job.pins_released = true;
+            }
+        }
+    }
+}
+
+fn is_expired(finished_at: Option<SystemTime>, now: SystemTime, retention: Duration) -> bool {
+    match finished_at {
+        Some(t) => now.duration_since(t).is_ok_and(|age| age >= retention),
+        // running jobs are never removed
+        None => false,
+    }
+}
+
+async fn remove_job(state: &State, id: Uuid, retention: Duration) {
+    let job = match state.judge.remove(id).await {
+        Some(job) => job,
+        None => return,
+    };
+    {
+        let mut job_guard = job.lock().await;
+        // another phase could be started after the check
+        if !is_expired(job_guard.finished_at, SystemTime::now(), retention) {
+            drop(job_guard);
+            state.judge.insert(id, job).await;
+            return;
+        }
+        // event subscribers may keep the job alive for a while, but its
+        // pins are not needed anymore
+        job_guard.problem = None;
+        job_guard.compiled = None;
+    }
+    state.logs.remove_job(id).await;
+    if let Err(err) = state.job_files.remove_job(id).await {
+        state.settings.warnings.report(
+            "job-cleanup-failed",
+            format!("failed to remove files of job {}: {:#}", id, err),
+        );
+    }
+    if let Err(err) = super::outputs::remove_job_outputs(state, id).await {
+        state.settings.warnings.report(
+            "job-cleanup-failed",
+            format!("failed to remove outputs of job {}: {:#}", id, err),
+        );
+    }
+    if let Some(store) = &state.job_store {
+        if let Err(err) = store.delete(id).await {
+            state.settings.warnings.report(
+                "job-cleanup-failed",
+                format!("failed to remove job {} from job store: {:#}", id, err),
+            );
+        }
+    }
+    tracing::info!(job_id = %id, "removed expired job");
+}
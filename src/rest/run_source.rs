@@ -0,0 +1,210 @@
+//! Run sources passed by reference.
+//!
+//! Instead of uploading the source, client can pass its URL and SHA-256.
+//! Judge downloads the source before accepting the job, so failures are
+//! reported in response to `POST /jobs`. Only URLs under one of configured
+//! prefixes are fetched, so that judge can not be used to reach internal
+//! services. URL must have the same scheme, host and port as the prefix,
+//! and its path must start with the path segments of the prefix.
+//! Redirects are not followed, because they could lead anywhere.
+
+use super::errors::RestError;
+use anyhow::Context;
+use judge_apis::{
+    error::codes,
+    rest::{RunSource, SourceRef},
+};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use warp::http::StatusCode;
+
+/// URL under which run sources can be fetched
+struct AllowedPrefix {
+    scheme: String,
+    host: String,
+    port: Option<u16>,
+    /// Non-empty path segments
+    path: Vec<String>,
+}
+
+impl AllowedPrefix {
+    fn parse(prefix: &str) -> anyhow::Result<AllowedPrefix> {
+        let url = Url::parse(prefix).with_context(|| format!("invalid URL prefix {:?}", prefix))?;
+        let host = url
+            .host_str()
+            .with_context(|| format!("URL prefix {:?} has no host", prefix))?;
+        if !url.username().is_empty() || url.password().is_some() {
+            anyhow::bail!("URL prefix {:?} must not contain credentials", prefix);
+        }
+        if url.query().is_some() || url.fragment().is_some() {
+            anyhow::bail!("URL prefix {:?} must not contain query or fragment", prefix);
+        }
+        Ok(AllowedPrefix {
+            scheme: url.scheme().to_string(),
+            host: host.to_string(),
+            port: url.port_or_known_default(),
+            path: path_segments(&url),
+        })
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        url.scheme() == self.scheme
+            && url.host_str() == Some(self.host.as_str())
+            && url.port_or_known_default() == self.port
+            && url.username().is_empty()
+            && url.password().is_none()
+            && path_segments(url).starts_with(&self.path)
+    }
+}
+
+fn path_segments(url: &Url) -> Vec<String> {
+    url.path_segments()
+        .map(|segments| {
+            segments
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Storage responded with a redirect, which is not followed
+#[derive(Debug)]
+struct RedirectRefused(reqwest::StatusCode);
+
+impl std::fmt::Display for RedirectRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "storage responded with {}, redirects are not followed",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for RedirectRefused {}
+
+pub struct SourceFetcher {
+    allowed_prefixes: Vec<AllowedPrefix>,
+    /// Maximal size of a source in bytes
+    max_size: u64,
+    transport: reqwest::Client,
+}
+
+impl SourceFetcher {
+    pub fn new(
+        allowed_prefixes: Vec<String>,
+        max_size: u64,
+        timeout: Duration,
+    ) -> anyhow::Result<SourceFetcher> {
+        let allowed_prefixes = allowed_prefixes
+            .iter()
+            .map(|prefix| AllowedPrefix::parse(prefix))
+            .collect::<anyhow::Result<_>>()?;
+        let transport = reqwest::Client::builder()
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(SourceFetcher {
+            allowed_prefixes,
+            max_size,
+            transport,
+        })
+    }
+
+    fn is_allowed(&self, url: &str) -> bool {
+        match Url::parse(url) {
+            Ok(url) => self
+                .allowed_prefixes
+                .iter()
+                .any(|prefix| prefix.matches(&url)),
+            Err(_) => false,
+        }
+    }
+
+    async fn download(&self, source: &SourceRef) -> anyhow::Result<Vec<u8>> {
+        let mut req = self.transport.get(&source.url);
+        if let Some(authorization) = &source.authorization {
+            req = req.header("authorization", authorization);
+        }
+        let resp = req.send().await?;
+        if resp.status().is_redirection() {
+            return Err(RedirectRefused(resp.status()).into());
+        }
+        let mut resp = resp.error_for_status()?;
+        let mut data = Vec::new();
+        while let Some(chunk) = resp.chunk().await? {
+            data.extend_from_slice(&chunk);
+            if data.len() as u64 > self.max_size {
+                anyhow::bail!("source is larger than {} bytes", self.max_size);
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Returns contents of the run source, downloading it if needed
+pub(super) async fn resolve(
+    fetcher: Option<&SourceFetcher>,
+    source: RunSource,
+) -> anyhow::Result<Vec<u8>> {
+    let source = match source {
+        RunSource::Inline(data) => return Ok(data.0),
+        RunSource::Fetch(source) => source,
+    };
+    let fetcher = match fetcher {
+        Some(f) if f.is_allowed(&source.url) => f,
+        _ => {
+            return Err(RestError::new(
+                StatusCode::FORBIDDEN,
+                codes::RUN_SOURCE_URL_NOT_ALLOWED,
+                "fetching run source from this URL is not allowed",
+            )
+            .with_detail("url", &source.url)
+            .into())
+        }
+    };
+    let expected = source.sha256.to_ascii_lowercase();
+    if expected.len() != 64 || !expected.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(RestError::bad_request(
+            codes::INVALID_REQUEST,
+            "sha256 must consist of 64 hexadecimal digits",
+        )
+        .into());
+    }
+    let data = match fetcher.download(&source).await {
+        Ok(data) => data,
+        Err(err) => {
+            tracing::warn!(url = %source.url, "failed to fetch run source: {:#}", err);
+            // client errors of the storage (e.g. 404) and redirects will
+            // not go away
+            let status = err
+                .downcast_ref::<reqwest::Error>()
+                .and_then(reqwest::Error::status);
+            let transient = !matches!(status, Some(s) if s.is_client_error())
+                && err.downcast_ref::<RedirectRefused>().is_none();
+            let err = RestError::new(
+                StatusCode::BAD_GATEWAY,
+                codes::RUN_SOURCE_FETCH_FAILED,
+                format!("failed to fetch run source: {:#}", err),
+            )
+            .with_detail("url", &source.url);
+            return Err(if transient { err.retryable() } else { err }.into());
+        }
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(&data);
+    let actual = hex::encode(hasher.finalize());
+    if actual != expected {
+        return Err(RestError::bad_request(
+            codes::RUN_SOURCE_DIGEST_MISMATCH,
+            "digest of the fetched run source does not match sha256",
+        )
+        .with_detail("url", &source.url)
+        .with_detail("expected", expected)
+        .with_detail("actual", actual)
+        .into());
+    }
+    Ok(data)
+}
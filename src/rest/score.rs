@@ -0,0 +1,105 @@
+//! Transformation of live scores reported by valuer
+
+use anyhow::Context;
+use std::str::FromStr;
+
+/// How live scores are transformed before they are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScoreAggregation {
+    /// Scores are stored as reported
+    Absolute,
+    /// Scores are converted to percents of the maximal score, which is
+    /// taken from the problem manifest
+    PercentOfMax,
+    /// Stored score never decreases
+    MonotoneMax,
+}
+
+impl FromStr for ScoreAggregation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "absolute" => Ok(ScoreAggregation::Absolute),
+            "percent" => Ok(ScoreAggregation::PercentOfMax),
+            "monotone-max" => Ok(ScoreAggregation::MonotoneMax),
+            _ => anyhow::bail!(
+                "unknown score aggregation {:?}, expected one of: absolute, percent, monotone-max",
+                s
+            ),
+        }
+    }
+}
+
+/// Per-job aggregation state
+pub(super) trait ScoreAggregator: Send {
+    fn aggregate(&mut self, score: u32) -> u32;
+}
+
+struct Absolute;
+
+impl ScoreAggregator for Absolute {
+    fn aggregate(&mut self, score: u32) -> u32 {
+        score
+    }
+}
+
+struct PercentOfMax {
+    max_score: u32,
+}
+
+impl ScoreAggregator for PercentOfMax {
+    fn aggregate(&mut self, score: u32) -> u32 {
+        let percent = u64::from(score) * 100 / u64::from(self.max_score);
+        percent.min(100) as u32
+    }
+}
+
+#[derive(Default)]
+struct MonotoneMax {
+    max: u32,
+}
+
+impl ScoreAggregator for MonotoneMax {
+    fn aggregate(&mut self, score: u32) -> u32 {
+        self.max = self.max.max(score);
+        self.max
+    }
+}
+
+fn max_score(problem: Option<&problem_loader::LoadedProblem>) -> anyhow::Result<u32> {
+    let problem = problem.context("problem is not loaded")?;
+    match problem.extensions.max_score {
+        Some(max) if max > 0 => Ok(max),
+        _ => anyhow::bail!("problem manifest does not specify max-score"),
+    }
+}
+
+impl ScoreAggregation {
+    /// Creates aggregator for a job judging `problem` revision of
+    /// `problem_id`
+    pub(super) fn aggregator(
+        self,
+        problem: Option<&problem_loader::LoadedProblem>,
+        problem_id: &str,
+        warnings: &processor::Warnings,
+    ) -> Box<dyn ScoreAggregator> {
+        match self {
+            ScoreAggregation::Absolute => Box::new(Absolute),
+            ScoreAggregation::MonotoneMax => Box::new(MonotoneMax::default()),
+            ScoreAggregation::PercentOfMax => match max_score(problem) {
+                Ok(max_score) => Box::new(PercentOfMax { max_score }),
+                Err(err) => {
+                    warnings.report(
+                        "percent-score-unavailable",
+                        format!(
+                            "reporting absolute live scores for problem {}: {:#}",
+                            problem_id, err
+                        ),
+                    );
+                    Box::new(Absolute)
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Graceful shutdown.
+//!
+//! On SIGTERM or SIGINT judge stops accepting jobs and readiness probe
+//! fails, but API is still served, so that clients can fetch results of
+//! jobs which are finishing. When all jobs are finished or the timeout
+//! expires, unfinished jobs are persisted (on restart they are restored
+//! as interrupted) and the server stops.
+
+use super::{job_map::JobRef, State};
+use anyhow::Context;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// How often the number of unfinished jobs is checked while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Waits for a termination signal
+async fn wait_signal() -> anyhow::Result<&'static str> {
+    let mut sigterm = signal(SignalKind::terminate()).context("failed to handle SIGTERM")?;
+    let mut sigint = signal(SignalKind::interrupt()).context("failed to handle SIGINT")?;
+    Ok(tokio::select! {
+        _ = sigterm.recv() => "SIGTERM",
+        _ = sigint.recv() => "SIGINT",
+    })
+}
+
+async fn unfinished_jobs(state: &State) -> Vec<JobRef> {
+    let jobs = state.judge.snapshot().await;
+    let mut unfinished = Vec::new();
+    for job in jobs {
+        if job.lock().await.outcome.is_none() {
+            unfinished.push(job);
+        }
+    }
+    unfinished
+}
+
+/// Resolves when the server should stop: after a termination signal is
+/// received and running jobs are drained
+pub(super) async fn wait(state: Arc<State>, timeout: Duration) {
+    let signal = match wait_signal().await {
+        Ok(signal) => signal,
+        Err(err) => {
+            tracing::error!("graceful shutdown is disabled: {:#}", err);
+            return futures::future::pending().await;
+        }
+    };
+    state.shutting_down.store(true, Ordering::SeqCst);
+    let deadline = Instant::now() + timeout;
+    let mut unfinished = unfinished_jobs(&state).await;
+    tracing::info!(
+        signal,
+        jobs = unfinished.len(),
+        timeout_secs = timeout.as_secs(),
+        "shutting down, waiting for unfinished jobs"
+    );
+    while !unfinished.is_empty() && Instant::now() < deadline {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        unfinished = unfinished_jobs(&state).await;
+    }
+    if !unfinished.is_empty() {
+        tracing::warn!(
+            jobs = unfinished.len(),
+            "shutdown timeout expired, abandoning unfinished jobs"
+        );
+        for job in unfinished {
+            state.persist(&*job.lock().await).await;
+        }
+    }
+    tracing::info!("shutdown completed");
+}
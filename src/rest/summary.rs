@@ -0,0 +1,164 @@
+//! Human-readable job summary, rendered according to locale and timezone
+//! hints of the job. Canonical job data is not affected by the hints.
+
+use super::{
+    errors::{self, RestError},
+    State,
+};
+use chrono::{DateTime, FixedOffset, Utc};
+use futures::future::TryFutureExt;
+use judge_apis::{
+    error::codes,
+    judge_log::{log_name, JudgeLogKind},
+    rest::JobSummary,
+};
+use std::{sync::Arc, time::SystemTime};
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, Filter, Reply};
+
+/// Locales for which status messages are available
+const LOCALES: &[&str] = &["en", "ru"];
+
+const DEFAULT_TIMEZONE: &str = "UTC";
+
+/// Returns supported locale best matching `hint` (e.g. `ru-RU` -> `ru`)
+fn resolve_locale(hint: Option<&str>) -> &'static str {
+    let language = hint
+        .and_then(|h| h.split(&['-', '_'][..]).next())
+        .map(str::to_ascii_lowercase);
+    LOCALES
+        .iter()
+        .copied()
+        .find(|loc| language.as_deref() == Some(*loc))
+        .unwrap_or(LOCALES[0])
+}
+
+/// Parses timezone hint. Only `UTC` and fixed offsets (`+03:00`) are
+/// supported.
+fn parse_timezone(hint: &str) -> Option<FixedOffset> {
+    if hint == "UTC" || hint == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let sign = match hint.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let mut parts = hint[1..].splitn(2, ':');
+    let hours: i32 = parts.next()?.parse().ok()?;
+    let minutes: i32 = parts.next().unwrap_or("0").parse().ok()?;
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Localized description of a status code
+fn status_message(code: &str, locale: &str) -> Option<&'static str> {
+    let (en, ru) = match code {
+        "ACCEPTED" => ("Accepted", "Полное решение"),
+        "PARTIAL_SOLUTION" => ("Partial solution", "Частичное решение"),
+        "JUDGE_FAULT" => ("Judge fault", "Ошибка проверяющей системы"),
+        "COMPILER_FAILED" => ("Compilation error", "Ошибка компиляции"),
+        "COMPILATION_TIMED_OUT" => ("Compilation timed out", "Превышено время компиляции"),
+        "TEST_PASSED" => ("Test passed", "Тест пройден"),
+        "PRESENTATION_ERROR" => ("Presentation error", "Неправильный формат вывода"),
+        "WRONG_ANSWER" => ("Wrong answer", "Неправильный ответ"),
+        "TIME_LIMIT_EXCEEDED" => ("Time limit exceeded", "Превышено ограничение времени"),
+        "MEMORY_LIMIT_EXCEEDED" => ("Memory limit exceeded", "Превышено ограничение памяти"),
+        "RUNTIME_ERROR" => ("Runtime error", "Ошибка выполнения"),
+        "LAUNCH_ERROR" => ("Launch error", "Ошибка запуска"),
+        judge_apis::judge_log::PENDING_STATUS_CODE => ("Pending", "Ожидает проверки"),
+        judge_apis::judge_log::SKIPPED_STATUS_CODE => ("Skipped", "Пропущен"),
+        judge_apis::judge_log::PROCESS_LIMIT_STATUS_CODE => (
+            "Process limit exceeded",
+            "Превышено ограничение числа процессов",
+        ),
+        judge_apis::judge_log::SECURITY_VIOLATION_STATUS_CODE => {
+            ("Security violation", "Нарушение ограничений песочницы")
+        }
+        _ => return None,
+    };
+    Some(if locale == "ru" { ru } else { en })
+}
+
+fn render_time(time: SystemTime, timezone: FixedOffset) -> String {
+    DateTime::<Utc>::from(time)
+        .with_timezone(&timezone)
+        .to_rfc3339()
+}
+
+async fn get_summary(state: Arc<State>, id: Uuid) -> anyhow::Result<JobSummary> {
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => {
+            return Err(RestError::job_not_found(id).into());
+        }
+    };
+    let (phase, locale, timezone, created_at, finished_at) = {
+        let job = job.lock().await;
+        (
+            job.phase.clone(),
+            job.locale.clone(),
+            job.timezone.clone(),
+            job.created_at,
+            job.finished_at,
+        )
+    };
+    let locale = resolve_locale(locale.as_deref());
+    let timezone = timezone.unwrap_or_else(|| DEFAULT_TIMEZONE.to_string());
+    // validated when the job was created
+    let offset = parse_timezone(&timezone)
+        .or_else(|| FixedOffset::east_opt(0))
+        .expect("zero offset is valid");
+    let log = super::get_job_judge_log(
+        state.clone(),
+        id,
+        log_name(JudgeLogKind::Contestant, phase.as_deref()),
+    )
+    .await
+    .ok();
+    Ok(JobSummary {
+        id,
+        status_code: log.as_ref().map(|log| log.status.code.clone()),
+        status_message: log.as_ref().map(|log| {
+            status_message(&log.status.code, locale)
+                .map(ToString::to_string)
+                .unwrap_or_else(|| log.status.code.clone())
+        }),
+        score: log.as_ref().map(|log| log.score),
+        created_at: render_time(created_at, offset),
+        finished_at: finished_at.map(|t| render_time(t, offset)),
+        locale: locale.to_string(),
+        timezone,
+    })
+}
+
+/// `GET /jobs/{id}/summary`
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("summary"))
+        .and(warp::path::end())
+        .and_then(move |id| {
+            get_summary(state.clone(), id)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover)
+        .boxed()
+}
+
+/// Checks timezone hint of a new job
+pub(super) fn validate_timezone(hint: &str) -> anyhow::Result<()> {
+    match parse_timezone(hint) {
+        Some(_) => Ok(()),
+        None => Err(RestError::bad_request(
+            codes::INVALID_REQUEST,
+            "timezone must be `UTC` or a fixed offset such as `+03:00`",
+        )
+        .with_detail("timezone", hint)
+        .into()),
+    }
+}
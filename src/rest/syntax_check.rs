@@ -0,0 +1,86 @@
+//! Syntax check endpoint
+
+use super::{
+    detect_toolchain,
+    errors::{self, RestError},
+    is_auto_toolchain, State,
+};
+use futures::future::TryFutureExt;
+use judge_apis::{
+    error::codes,
+    rest::{JobPriority, SyntaxCheckRequest, SyntaxCheckResult},
+};
+use std::sync::Arc;
+use warp::{filters::BoxedFilter, Filter, Reply};
+
+/// Runs syntax check of the toolchain. Like jobs, checks wait in the job
+/// queue, but with high priority, because editors wait for the result.
+async fn syntax_check(
+    state: Arc<State>,
+    req: SyntaxCheckRequest,
+) -> anyhow::Result<SyntaxCheckResult> {
+    state.check_accepting_jobs()?;
+    let toolchain_name = if is_auto_toolchain(&req.toolchain_name) {
+        detect_toolchain(&state, req.filename.as_deref(), &req.run_source.0)
+            .await?
+            .0
+    } else {
+        req.toolchain_name
+    };
+    let toolchain = match state.clients.toolchains.resolve(&toolchain_name).await {
+        Ok(t) => t,
+        Err(err) => {
+            tracing::debug!("failed to load toolchain: {:#}", err);
+            return Err(
+                RestError::not_found(codes::TOOLCHAIN_NOT_FOUND, "toolchain not found")
+                    .with_detail("toolchain_name", &toolchain_name)
+                    .into(),
+            );
+        }
+    };
+    let estimated_seconds = processor::SYNTAX_CHECK_TIME_LIMIT as f64 / 1000.0;
+    // the slot is released if client disconnects while waiting
+    let _running = state
+        .queue
+        .wait_dispatch(JobPriority::High, estimated_seconds)
+        .await;
+    let outcome = processor::syntax_check(
+        &toolchain,
+        &req.run_source.0,
+        &state.clients,
+        &state.settings,
+    )
+    .await?;
+    let outcome = match outcome {
+        Some(o) => o,
+        None => {
+            return Err(RestError::not_found(
+                codes::SYNTAX_CHECK_NOT_SUPPORTED,
+                "toolchain does not support syntax check",
+            )
+            .with_detail("toolchain_name", &toolchain_name)
+            .into());
+        }
+    };
+    Ok(SyntaxCheckResult {
+        toolchain_name,
+        passed: outcome.passed,
+        timed_out: outcome.timed_out,
+        diagnostics: outcome.diagnostics,
+        diagnostics_truncated: outcome.truncated,
+    })
+}
+
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    warp::post()
+        .and(warp::path("syntax-check"))
+        .and(warp::path::end())
+        .and(warp::filters::body::json())
+        .and_then(move |req| {
+            syntax_check(state.clone(), req)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover)
+        .boxed()
+}
@@ -0,0 +1,227 @@
+//! Time limit reports for problem setters.
+//!
+//! Report is built from full logs of completed jobs which judged reference
+//! solutions of a problem, and shows how close these solutions are to the
+//! time limit of each test.
+
+use super::{
+    admin,
+    errors::{self, RestError},
+    State,
+};
+use anyhow::Context;
+use futures::future::TryFutureExt;
+use judge_apis::{
+    admin::{TestTiming, TimingReport, TimingReportRequest, MIN_TIME_HEADROOM_PERCENT},
+    error::codes,
+    judge_log::JudgeLogKind,
+};
+use std::{path::Path, sync::Arc};
+use warp::{filters::BoxedFilter, http::StatusCode, Filter, Reply};
+
+/// Archived report is kept in the problem assets under this path
+const TIMING_REPORT_ASSET: &str = "reports/timing.json";
+
+/// Maximum number of jobs in one report
+const MAX_JOBS: usize = 1000;
+
+/// Time usage of one test, in nanoseconds
+#[derive(Default, Clone, Copy)]
+struct Accumulated {
+    max: u64,
+    sum: u64,
+    runs: u32,
+}
+
+fn problem_not_found(problem_id: &str) -> RestError {
+    RestError::not_found(codes::PROBLEM_NOT_FOUND, "problem not found")
+        .with_detail("problem_id", problem_id)
+}
+
+async fn load_problem(
+    state: &State,
+    problem_id: &str,
+) -> anyhow::Result<problem_loader::LoadedProblem> {
+    let loaded = state.clients.problems.find(problem_id).await?;
+    loaded.ok_or_else(|| problem_not_found(problem_id).into())
+}
+
+/// Adds time usage from full logs of the job
+async fn accumulate_job(
+    state: &State,
+    problem_id: &str,
+    id: uuid::Uuid,
+    tests: &mut [Accumulated],
+) -> anyhow::Result<()> {
+    let job = match state.judge.get(id).await {
+        Some(job) => job,
+        None => return Err(RestError::job_not_found(id).into()),
+    };
+    let job = job.lock().await;
+    if job.problem_id != problem_id {
+        return Err(RestError::bad_request(
+            codes::INVALID_REQUEST,
+            "job has judged another problem",
+        )
+        .with_detail("job_id", id)
+        .into());
+    }
+    if !matches!(job.outcome, Some(processor::JudgeOutcome::Success)) {
+        return Err(RestError::new(
+            StatusCode::CONFLICT,
+            codes::JOB_NOT_COMPLETED,
+            "only successfully completed jobs can be used",
+        )
+        .with_detail("job_id", id)
+        .into());
+    }
+    for log in state.job_logs(&job).await? {
+        if log.kind != JudgeLogKind::Full {
+            continue;
+        }
+        for row in &log.tests {
+            let (acc, time) = match (tests.get_mut(row.test_id.to_idx()), row.time_usage) {
+                (Some(acc), Some(time)) => (acc, time),
+                _ => continue,
+            };
+            acc.max = acc.max.max(time);
+            acc.sum += time;
+            acc.runs += 1;
+        }
+    }
+    Ok(())
+}
+
+fn test_timing(test_id: pom::TestId, time_limit_ms: u64, acc: Accumulated) -> TestTiming {
+    let (max_time_ms, avg_time_ms) = if acc.runs > 0 {
+        (
+            Some(acc.max / 1_000_000),
+            Some(acc.sum / u64::from(acc.runs) / 1_000_000),
+        )
+    } else {
+        (None, None)
+    };
+    // max / limit > 1 - headroom, in integers
+    let low_headroom = acc.runs > 0
+        && acc.max * 100 > time_limit_ms * 1_000_000 * (100 - MIN_TIME_HEADROOM_PERCENT);
+    TestTiming {
+        test_id,
+        time_limit_ms,
+        runs: acc.runs,
+        max_time_ms,
+        avg_time_ms,
+        low_headroom,
+    }
+}
+
+async fn build_report(
+    state: Arc<State>,
+    problem_id: String,
+    req: TimingReportRequest,
+) -> anyhow::Result<TimingReport> {
+    if req.job_ids.is_empty() {
+        return Err(
+            RestError::bad_request(codes::INVALID_REQUEST, "no jobs were specified").into(),
+        );
+    }
+    if req.job_ids.len() > MAX_JOBS {
+        return Err(RestError::bad_request(
+            codes::TOO_MANY_ITEMS,
+            format!("at most {} jobs can be used in a report", MAX_JOBS),
+        )
+        .with_detail("limit", MAX_JOBS)
+        .into());
+    }
+    let loaded = load_problem(&state, &problem_id).await?;
+    let mut accumulated = vec![Accumulated::default(); loaded.manifest.tests.len()];
+    for &id in &req.job_ids {
+        accumulate_job(&state, &problem_id, id, &mut accumulated).await?;
+    }
+    let tests = accumulated
+        .into_iter()
+        .enumerate()
+        .map(|(idx, acc)| {
+            let test_id = pom::TestId::make(idx as u32 + 1);
+            test_timing(test_id, loaded.manifest.tests[test_id].limits.time(), acc)
+        })
+        .collect::<Vec<_>>();
+    let flagged = tests.iter().filter(|t| t.low_headroom).count();
+    tracing::info!(problem_id = %problem_id, flagged, "built timing report");
+    let report = TimingReport {
+        problem_id,
+        job_ids: req.job_ids,
+        tests,
+        archived: req.archive,
+    };
+    if req.archive {
+        let data = serde_json::to_vec_pretty(&report).context("failed to serialize report")?;
+        let package = problem_loader::pack(
+            &loaded.manifest,
+            &loaded.extensions,
+            &loaded.assets,
+            vec![(TIMING_REPORT_ASSET.into(), data)],
+        )
+        .await
+        .context("failed to build problem package")?;
+        state
+            .clients
+            .problems
+            .upload(&report.problem_id, package)
+            .await?;
+    }
+    Ok(report)
+}
+
+async fn get_report(state: Arc<State>, problem_id: String) -> anyhow::Result<TimingReport> {
+    let loaded = load_problem(&state, &problem_id).await?;
+    let path = loaded.assets.join(Path::new(TIMING_REPORT_ASSET));
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(RestError::not_found(
+                codes::TIMING_REPORT_NOT_FOUND,
+                "problem has no archived timing report",
+            )
+            .with_detail("problem_id", problem_id)
+            .into());
+        }
+        Err(err) => {
+            return Err(
+                anyhow::Error::new(err).context(format!("failed to read {}", path.display()))
+            );
+        }
+    };
+    serde_json::from_slice(&data).context("archived timing report is invalid")
+}
+
+/// `POST /admin/problems/{id}/timing-report` and
+/// `GET /admin/problems/{id}/timing-report`
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    let base = warp::path("admin")
+        .and(admin::authenticate(state.admin_auth.clone()))
+        .and(warp::path("problems"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("timing-report"))
+        .and(warp::path::end());
+    let state2 = state.clone();
+    let route_build = warp::post()
+        .and(base.clone())
+        .and(warp::filters::body::json())
+        .and_then(move |problem_id, req| {
+            build_report(state2.clone(), problem_id, req)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    let route_get = warp::get()
+        .and(base)
+        .and_then(move |problem_id| {
+            get_report(state.clone(), problem_id)
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover);
+
+    route_build.or(route_get).recover(errors::recover).boxed()
+}
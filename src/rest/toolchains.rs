@@ -0,0 +1,71 @@
+//! Toolchain listing endpoint
+
+use super::{errors, State};
+use futures::future::TryFutureExt;
+use judge_apis::rest::{ToolchainInfo, ToolchainIssue};
+use processor::toolchain_loader::{InvalidToolchain, ManifestIssue};
+use std::sync::Arc;
+use warp::{filters::BoxedFilter, Filter, Reply};
+
+fn to_api_issue(issue: &ManifestIssue) -> ToolchainIssue {
+    ToolchainIssue {
+        path: issue.path.clone(),
+        line: issue.line,
+        message: issue.message.clone(),
+    }
+}
+
+/// Loads all toolchains, reporting problems of their manifests instead
+/// of failing
+async fn list_toolchains(state: Arc<State>) -> anyhow::Result<Vec<ToolchainInfo>> {
+    let loader = &state.clients.toolchains;
+    let mut toolchains = Vec::new();
+    for name in loader.list().await? {
+        let info = match loader.resolve(&name).await {
+            Ok(toolchain) => ToolchainInfo {
+                title: Some(toolchain.spec.title.clone()),
+                schema_version: Some(toolchain.spec.schema_version),
+                extensions: toolchain.spec.source_extensions(),
+                valid: true,
+                errors: Vec::new(),
+                warnings: toolchain.warnings.iter().map(to_api_issue).collect(),
+                name,
+            },
+            Err(err) => {
+                let errors = match err.downcast_ref::<InvalidToolchain>() {
+                    Some(invalid) => invalid.errors.iter().map(to_api_issue).collect(),
+                    None => vec![ToolchainIssue {
+                        path: String::new(),
+                        line: None,
+                        message: format!("{:#}", err),
+                    }],
+                };
+                ToolchainInfo {
+                    name,
+                    title: None,
+                    schema_version: None,
+                    extensions: Vec::new(),
+                    valid: false,
+                    errors,
+                    warnings: Vec::new(),
+                }
+            }
+        };
+        toolchains.push(info);
+    }
+    Ok(toolchains)
+}
+
+/// `GET /toolchains`
+pub(super) fn routes(state: Arc<State>) -> BoxedFilter<(impl Reply,)> {
+    warp::get()
+        .and(warp::path("toolchains"))
+        .and(warp::path::end())
+        .and_then(move || {
+            list_toolchains(state.clone())
+                .map_err(|err| warp::reject::custom(api_util::AnyhowRejection(err)))
+        })
+        .map(|resp| warp::reply::json(&resp))
+        .recover(errors::recover)
+        .boxed()
+}
@@ -0,0 +1,76 @@
+//! Detection of jobs which stopped making progress
+
+use super::State;
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+pub struct WatchdogConfig {
+    /// Job is considered stale if it produced no events for this long
+    pub stale_timeout: Duration,
+    /// If true, stale jobs are aborted and marked as failed
+    pub fail_stale_jobs: bool,
+}
+
+pub(super) async fn run(state: Arc<State>, config: WatchdogConfig) {
+    let check_interval = (config.stale_timeout / 4).max(Duration::from_secs(1));
+    let mut interval = tokio::time::interval(check_interval);
+    loop {
+        interval.tick().await;
+        check(&state, &config).await;
+    }
+}
+
+async fn check(state: &State, config: &WatchdogConfig) {
+    let jobs = state.judge.snapshot().await;
+    let mut stale_jobs = 0;
+    for job in jobs {
+        let mut job = job.lock().await;
+        if job.outcome.is_some() {
+            continue;
+        }
+        let idle = match job.last_progress {
+            Some(t) => t.elapsed(),
+            // job is still queued
+            None => continue,
+        };
+        if idle < config.stale_timeout {
+            continue;
+        }
+        let last_stage = job.last_stage.as_deref().unwrap_or("none").to_string();
+        if !job.stale {
+            job.stale = true;
+            state.settings.warnings.report(
+                "job-stale",
+                format!(
+                    "job {} made no progress for {}s (last completed stage: {})",
+                    job.id,
+                    idle.as_secs(),
+                    last_stage
+                ),
+            );
+        }
+        if !config.fail_stale_jobs {
+            stale_jobs += 1;
+            continue;
+        }
+        // processor task is owned by the job task, so it is aborted too
+        // and stops using invokers
+        if let Some(task) = job.task.take() {
+            task.abort();
+        }
+        let error = anyhow::anyhow!(
+            "job was aborted by watchdog: no progress for {}s (last completed stage: {}, live test: {})",
+            idle.as_secs(),
+            last_stage,
+            job.live_test
+                .map(|t| t.to_string())
+                .unwrap_or_else(|| "none".to_string())
+        );
+        tracing::error!(job_id = %job.id, "{:#}", error);
+        job.outcome = Some(processor::JudgeOutcome::Fault { error });
+        state.job_finished(&mut job).await;
+    }
+    state.stale_jobs.store(stale_jobs, Ordering::SeqCst);
+}
@@ -0,0 +1,84 @@
+//! Comparison store used in shadow judging mode.
+//!
+//! Each completed job is written to `${dir}/${job_id}.json`, so that the
+//! report survives judge restarts.
+use anyhow::Context;
+use judge_apis::{
+    judge_log::{JudgeLog, JudgeLogKind},
+    shadow::{
+        ShadowRecord, ShadowReport, ShadowVerdict, REFERENCE_SCORE_ANNOTATION,
+        REFERENCE_STATUS_ANNOTATION,
+    },
+};
+use std::{collections::HashMap, path::PathBuf};
+
+pub struct ShadowStore {
+    dir: PathBuf,
+}
+
+impl ShadowStore {
+    pub async fn new(dir: PathBuf) -> anyhow::Result<ShadowStore> {
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("failed to create shadow store {}", dir.display()))?;
+        Ok(ShadowStore { dir })
+    }
+
+    pub async fn put(&self, record: &ShadowRecord) -> anyhow::Result<()> {
+        let path = self.dir.join(format!("{}.json", record.job_id));
+        let data = serde_json::to_vec(record)?;
+        tokio::fs::write(&path, data)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Summarizes all stored records
+    pub async fn report(&self) -> anyhow::Result<ShadowReport> {
+        let mut report = ShadowReport::default();
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .with_context(|| format!("failed to list {}", self.dir.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let data = tokio::fs::read(&path)
+                .await
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let record: ShadowRecord = serde_json::from_slice(&data)
+                .with_context(|| format!("invalid shadow record {}", path.display()))?;
+            report.add(&record);
+        }
+        Ok(report)
+    }
+}
+
+/// Extracts verdict of the active judge from job annotations
+pub fn reference_verdict(
+    annotations: &HashMap<String, String>,
+) -> anyhow::Result<Option<ShadowVerdict>> {
+    let status = match annotations.get(REFERENCE_STATUS_ANNOTATION) {
+        Some(s) => s.clone(),
+        None => return Ok(None),
+    };
+    let score = match annotations.get(REFERENCE_SCORE_ANNOTATION) {
+        Some(s) => Some(
+            s.parse()
+                .with_context(|| format!("invalid {} annotation", REFERENCE_SCORE_ANNOTATION))?,
+        ),
+        None => None,
+    };
+    Ok(Some(ShadowVerdict { status, score }))
+}
+
+/// Verdict of this judge, taken from the full log of the last phase
+pub fn verdict(logs: &[JudgeLog]) -> Option<ShadowVerdict> {
+    logs.iter()
+        .rev()
+        .find(|log| log.kind == JudgeLogKind::Full)
+        .map(|log| ShadowVerdict {
+            status: log.status.code.clone(),
+            score: Some(log.score),
+        })
+}
@@ -0,0 +1,154 @@
+//! Webhooks which are called when judge job is finished
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::HashMap, path::Path};
+
+/// Webhooks configuration file
+#[derive(Deserialize)]
+pub struct WebhooksConfig {
+    pub webhooks: Vec<Webhook>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Webhook {
+    /// URL which will receive POST request
+    pub url: String,
+    /// Webhook is only called for jobs that have all these annotations
+    /// with specified values.
+    #[serde(default)]
+    pub match_annotations: HashMap<String, String>,
+    /// Payload template. Strings of form `${path.to.field}` are replaced
+    /// with corresponding fields of the job object. If not set, whole
+    /// job object is sent.
+    #[serde(default)]
+    pub payload: Option<Value>,
+}
+
+impl Webhook {
+    fn matches(&self, job: &judge_apis::rest::JudgeJob) -> bool {
+        self.match_annotations
+            .iter()
+            .all(|(k, v)| job.annotations.get(k) == Some(v))
+    }
+}
+
+pub struct Webhooks {
+    hooks: Vec<Webhook>,
+    transport: reqwest::Client,
+    warnings: processor::Warnings,
+}
+
+impl Webhooks {
+    pub async fn load(
+        path: Option<&Path>,
+        warnings: processor::Warnings,
+    ) -> anyhow::Result<Webhooks> {
+        let hooks = match path {
+            Some(path) => {
+                let data = tokio::fs::read(path).await.with_context(|| {
+                    format!("failed to read webhooks config {}", path.display())
+                })?;
+                let config: WebhooksConfig =
+                    serde_yaml::from_slice(&data).context("invalid webhooks config")?;
+                config.webhooks
+            }
+            None => Vec::new(),
+        };
+        Ok(Webhooks {
+            hooks,
+            transport: reqwest::Client::new(),
+            warnings,
+        })
+    }
+
+    /// Calls all webhooks that match the job in background.
+    pub fn job_finished(&self, job: &judge_apis::rest::JudgeJob) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let job_value = match serde_json::to_value(job) {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!("failed to serialize job for webhooks: {}", err);
+                return;
+            }
+        };
+        for hook in self.hooks.iter().filter(|h| h.matches(job)) {
+            let payload = match &hook.payload {
+                Some(template) => render_template(template, &job_value),
+                None => job_value.clone(),
+            };
+            let req = self.transport.post(&hook.url).json(&payload);
+            let url = hook.url.clone();
+            let warnings = self.warnings.clone();
+            tokio::task::spawn(async move {
+                let res = req.send().await.and_then(|resp| resp.error_for_status());
+                if let Err(err) = res {
+                    warnings.report(
+                        "webhook-failed",
+                        format!("webhook call to {} failed: {}", url, err),
+                    );
+                }
+            });
+        }
+    }
+}
+
+/// Replaces `${path}` placeholders in template with values from `job`.
+/// If a string consists of a single placeholder, it is replaced with the
+/// value as is, otherwise placeholders are interpolated into the string.
+fn render_template(template: &Value, job: &Value) -> Value {
+    match template {
+        Value::String(s) => {
+            if let Some(path) = s.strip_prefix("${").and_then(|s| s.strip_suffix('}')) {
+                if !path.contains("${") {
+                    return lookup(job, path).cloned().unwrap_or(Value::Null);
+                }
+            }
+            Value::String(interpolate(s, job))
+        }
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|v| render_template(v, job)).collect())
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.clone(), render_template(v, job)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn interpolate(s: &str, job: &Value) -> String {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(e) => start + e,
+            None => break,
+        };
+        out.push_str(&rest[..start]);
+        match lookup(job, &rest[start + 2..end]) {
+            Some(Value::String(v)) => out.push_str(v),
+            Some(Value::Null) | None => {}
+            Some(v) => out.push_str(&v.to_string()),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let fields = value.as_object()?;
+    // annotation keys can contain dots themselves
+    if let Some(v) = fields.get(path) {
+        return Some(v);
+    }
+    let dot = path.find('.')?;
+    lookup(fields.get(&path[..dot])?, &path[dot + 1..])
+}
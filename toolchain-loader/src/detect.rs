@@ -0,0 +1,112 @@
+//! Guessing toolchain from run source
+
+/// How the toolchain was chosen
+#[derive(Debug, Clone, Copy)]
+pub enum DetectionMethod {
+    /// Source file name extension
+    Filename,
+    /// Interpreter specified in `#!` line
+    Shebang,
+    /// Characteristic fragments of source code
+    Heuristics,
+}
+
+impl DetectionMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DetectionMethod::Filename => "filename",
+            DetectionMethod::Shebang => "shebang",
+            DetectionMethod::Heuristics => "heuristics",
+        }
+    }
+}
+
+/// Result of toolchain detection
+#[derive(Debug, Clone)]
+pub enum Detection {
+    Found {
+        name: String,
+        method: DetectionMethod,
+    },
+    /// No available toolchain matches
+    NotFound,
+    /// Several toolchains declare the detected extension. Candidates are
+    /// sorted by name.
+    Ambiguous {
+        extension: String,
+        candidates: Vec<String>,
+    },
+}
+
+/// Returns extension of `filename`, without leading dot
+pub(crate) fn extension(filename: &str) -> Option<&str> {
+    let (stem, ext) = filename.rsplit_once('.')?;
+    if stem.is_empty() || ext.is_empty() {
+        return None;
+    }
+    Some(ext)
+}
+
+fn shebang_extension(source: &str) -> Option<&'static str> {
+    let first_line = source.lines().next()?;
+    let command = first_line.strip_prefix("#!")?;
+    let mut words = command.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = words.find(|w| !w.starts_with('-'))?;
+    }
+    let ext = match interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+        "python" => "py",
+        "sh" | "bash" => "sh",
+        "node" | "nodejs" => "js",
+        "ruby" => "rb",
+        "perl" => "pl",
+        "php" => "php",
+        _ => return None,
+    };
+    Some(ext)
+}
+
+fn heuristic_extension(source: &str) -> Option<&'static str> {
+    let has = |fragment: &str| source.contains(fragment);
+    if has("#include") {
+        if has("std::") || has("<iostream>") || has("<bits/stdc++.h>") || has("using namespace") {
+            return Some("cpp");
+        }
+        return Some("c");
+    }
+    if has("fn main()") {
+        return Some("rs");
+    }
+    if has("package main") && has("func main()") {
+        return Some("go");
+    }
+    if has("public static void main") {
+        return Some("java");
+    }
+    if has("def main(") || has("input()") || has("print(") {
+        return Some("py");
+    }
+    None
+}
+
+/// Guesses source file extension. Returns guesses of all methods which
+/// matched, most reliable first, so that the next one can be tried if no
+/// toolchain declares the extension.
+pub(crate) fn detect_extensions(
+    filename: Option<&str>,
+    source: &[u8],
+) -> Vec<(String, DetectionMethod)> {
+    let mut guesses = Vec::new();
+    if let Some(ext) = filename.and_then(extension) {
+        guesses.push((ext.to_ascii_lowercase(), DetectionMethod::Filename));
+    }
+    let source = String::from_utf8_lossy(source);
+    if let Some(ext) = shebang_extension(&source) {
+        guesses.push((ext.to_string(), DetectionMethod::Shebang));
+    }
+    if let Some(ext) = heuristic_extension(&source) {
+        guesses.push((ext.to_string(), DetectionMethod::Heuristics));
+    }
+    guesses
+}
@@ -1,5 +1,13 @@
 //! This module is responsible for toolchain loading
+mod detect;
+mod manifest;
+mod registry;
+
+pub use detect::{Detection, DetectionMethod};
+pub use manifest::{InvalidToolchain, ManifestIssue, SCHEMA_VERSION};
+
 use anyhow::Context as _;
+use registry::{RawToolchain, Registry};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
@@ -12,11 +20,20 @@ pub struct Toolchain {
     pub spec: ToolchainSpec,
     /// Image containing toolchain files
     pub image: String,
+    /// Problems of the manifest which do not prevent using the toolchain
+    pub warnings: Vec<ManifestIssue>,
 }
 
 /// `manifest.yaml` representation
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ToolchainSpec {
+    /// Version of the manifest schema, see [`SCHEMA_VERSION`]
+    #[serde(
+        rename = "schema-version",
+        default = "ToolchainSpec::legacy_schema_version"
+    )]
+    pub schema_version: u32,
+
     /// Human-readable
     pub title: String,
 
@@ -25,17 +42,198 @@ pub struct ToolchainSpec {
 
     pub filename: String,
 
+    /// Source file extensions (without leading dot) this toolchain is
+    /// chosen for when the toolchain is detected. Defaults to extension
+    /// of `filename`.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+
     #[serde(rename = "build")]
     pub build_commands: Vec<Command>,
 
     #[serde(rename = "run")]
     pub run_command: Command,
 
+    /// Fast command which only reports diagnostics, e.g. `gcc
+    /// -fsyntax-only`. It runs without writable directories.
+    #[serde(rename = "syntax-check", default)]
+    pub syntax_check: Option<Command>,
+
     #[serde(rename = "build-limits", default)]
     pub limits: pom::Limits,
 
     #[serde(rename = "env", default)]
     pub env: HashMap<String, String>,
+
+    /// Whether build commands can access network (e.g. to fetch dependencies)
+    #[serde(rename = "build-network", default)]
+    pub build_network: NetworkPolicy,
+
+    /// Writable directories of the build sandbox, in addition to the
+    /// output directory
+    #[serde(rename = "build-sandbox", default)]
+    pub build_sandbox: BuildSandbox,
+
+    /// Files of the build output directory which are passed to the run
+    /// sandbox, relative to that directory. Directories are declared with
+    /// a trailing slash (`classes/`), and glob patterns (`*.py`) select
+    /// several files. If all artifacts are plain files (see
+    /// `is_file_artifact`), they are captured one by one and made
+    /// executable. Otherwise they are packed with `/bin/sh` and `tar` of
+    /// the build image, which preserves modes of the files.
+    #[serde(default = "ToolchainSpec::default_artifacts")]
+    pub artifacts: Vec<PathBuf>,
+}
+
+impl ToolchainSpec {
+    fn legacy_schema_version() -> u32 {
+        1
+    }
+
+    fn default_artifacts() -> Vec<PathBuf> {
+        vec![PathBuf::from("bin")]
+    }
+
+    /// Returns lowercased extensions used for toolchain detection
+    pub fn source_extensions(&self) -> Vec<String> {
+        if self.extensions.is_empty() {
+            detect::extension(&self.filename)
+                .map(|ext| ext.to_ascii_lowercase())
+                .into_iter()
+                .collect()
+        } else {
+            self.extensions
+                .iter()
+                .map(|ext| ext.trim_start_matches('.').to_ascii_lowercase())
+                .collect()
+        }
+    }
+
+    /// Checks if all artifacts are plain files
+    pub fn has_only_file_artifacts(&self) -> bool {
+        self.artifacts.iter().all(|path| is_file_artifact(path))
+    }
+
+    fn validate_artifacts(&self) -> anyhow::Result<()> {
+        if self.artifacts.is_empty() {
+            anyhow::bail!("at least one artifact must be declared");
+        }
+        for (i, path) in self.artifacts.iter().enumerate() {
+            let is_plain = path
+                .components()
+                .all(|c| matches!(c, std::path::Component::Normal(_)));
+            if path.as_os_str().is_empty() || !is_plain {
+                anyhow::bail!(
+                    "artifact {} must be a relative path without `.` and `..`",
+                    path.display()
+                );
+            }
+            if self.artifacts[..i].contains(path) {
+                anyhow::bail!("artifact {} is declared twice", path.display());
+            }
+            // artifacts are passed to shell when they are packed: files in
+            // single quotes, directories and patterns unquoted
+            let path_str = path
+                .to_str()
+                .with_context(|| format!("artifact {} is not UTF-8", path.display()))?;
+            let supported = if is_file_artifact(path) {
+                !path_str.contains(|c: char| c == '\'' || c.is_control())
+            } else {
+                path_str.chars().all(|c| {
+                    c.is_ascii_alphanumeric()
+                        || matches!(c, '.' | '_' | '-' | '+' | '@' | '=' | ',' | '/')
+                        || matches!(c, '*' | '?' | '[' | ']')
+                })
+            };
+            if !supported {
+                anyhow::bail!(
+                    "artifact {} contains unsupported characters",
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks if artifact is a single file, rather than a directory (declared
+/// with a trailing slash) or a glob pattern. Only such artifacts can be
+/// persisted by invoker one by one.
+pub fn is_file_artifact(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    !path.ends_with('/') && !path.contains(['*', '?', '['])
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct BuildSandbox {
+    /// If set, writable `/tmp` of this size (in bytes) is mounted
+    #[serde(default)]
+    pub tmpfs_size: Option<u64>,
+    #[serde(default)]
+    pub writable_mounts: Vec<WritableMount>,
+}
+
+/// Empty writable directory, e.g. home directory of the compiler
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct WritableMount {
+    /// Absolute path in the sandbox
+    pub path: PathBuf,
+    /// Size limit in bytes
+    #[serde(default)]
+    pub quota: Option<u64>,
+}
+
+/// Paths used by the build sandbox itself
+const RESERVED_BUILD_PATHS: &[&str] = &["/compile-input", "/compile-output"];
+
+impl BuildSandbox {
+    /// Returns all writable mounts, including `/tmp` if it is enabled
+    pub fn mounts(&self) -> Vec<WritableMount> {
+        let mut mounts = self.writable_mounts.clone();
+        if let Some(size) = self.tmpfs_size {
+            mounts.push(WritableMount {
+                path: "/tmp".into(),
+                quota: Some(size),
+            });
+        }
+        mounts
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mounts = self.mounts();
+        for (i, mount) in mounts.iter().enumerate() {
+            let path = &mount.path;
+            let has_parent_dir = path
+                .components()
+                .any(|c| c == std::path::Component::ParentDir);
+            if !path.is_absolute() || has_parent_dir || path == Path::new("/") {
+                anyhow::bail!(
+                    "writable mount {} must be absolute, must not contain `..` and must not be /",
+                    path.display()
+                );
+            }
+            if RESERVED_BUILD_PATHS.iter().any(|p| path.starts_with(p)) {
+                anyhow::bail!(
+                    "writable mount {} conflicts with build sandbox directories",
+                    path.display()
+                );
+            }
+            if mounts[..i].iter().any(|m| m.path == *path) {
+                anyhow::bail!("writable mount {} is declared twice", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkPolicy {
+    #[default]
+    Deny,
+    Allow,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Default, Debug, Clone)]
@@ -59,28 +257,131 @@ impl Command {
 
 /// Responsible for fetching toolchains
 pub struct ToolchainLoader {
-    /// Directory containing toolchain definitions
-    toolchains_dir: PathBuf,
+    registries: Vec<Box<dyn Registry>>,
 }
 
 impl ToolchainLoader {
+    /// Creates loader which only uses toolchains from `toolchains_dir`
     pub async fn new(toolchains_dir: &Path) -> anyhow::Result<ToolchainLoader> {
-        Ok(ToolchainLoader {
-            toolchains_dir: toolchains_dir.to_path_buf(),
+        Self::from_config(&LoaderConfig {
+            fs: Some(toolchains_dir.to_path_buf()),
+            mongodb: None,
         })
+        .await
+    }
+
+    pub async fn from_config(conf: &LoaderConfig) -> anyhow::Result<ToolchainLoader> {
+        let mut loader = ToolchainLoader {
+            registries: Vec::new(),
+        };
+        if let Some(fs) = &conf.fs {
+            let fs_reg = registry::FsRegistry::new(fs.clone());
+            loader.registries.push(Box::new(fs_reg));
+        }
+        if let Some(mongodb) = &conf.mongodb {
+            let mongo_reg = registry::MongoRegistry::new(mongodb)
+                .await
+                .context("unable to initialize MongodbRegistry")?;
+            loader.registries.push(Box::new(mongo_reg));
+        }
+        if loader.registries.is_empty() {
+            anyhow::bail!("no toolchain registry is configured");
+        }
+        Ok(loader)
     }
 
+    /// Resolves toolchain in the first registry which knows about it
     #[tracing::instrument(skip(self))]
     pub async fn resolve(&self, toolchain_name: &str) -> anyhow::Result<Toolchain> {
-        let toolchain_dir_path = self.toolchains_dir.join(toolchain_name);
-
-        let toolchain_spec = tokio::fs::read(toolchain_dir_path.join("manifest.yaml"))
-            .await
-            .context("toolchain config file (manifest.yaml in image root) missing")?;
-        let spec: ToolchainSpec =
-            serde_yaml::from_slice(&toolchain_spec).context("invalid toolchain spec")?;
-        let image = tokio::fs::read_to_string(toolchain_dir_path.join("image.txt")).await?;
-        let image = image.trim().to_string();
-        Ok(Toolchain { spec, image })
+        for registry in &self.registries {
+            let raw = registry
+                .get_toolchain(toolchain_name)
+                .await
+                .with_context(|| {
+                    format!(
+                        "failed to search for toolchain {} in registry {}",
+                        toolchain_name,
+                        registry.name()
+                    )
+                })?;
+            if let Some(raw) = raw {
+                tracing::debug!(registry_name = registry.name(), "resolved toolchain");
+                return parse_toolchain(toolchain_name, raw);
+            }
+        }
+        anyhow::bail!("toolchain {} not found", toolchain_name)
     }
+
+    /// Returns names of all available toolchains, sorted
+    pub async fn list(&self) -> anyhow::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for registry in &self.registries {
+            let found = registry.list().await.with_context(|| {
+                format!("failed to list toolchains in registry {}", registry.name())
+            })?;
+            names.extend(found);
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Chooses toolchain for the run source. `filename` is original name
+    /// of the source file, if known. If no toolchain declares the extension
+    /// of the file, source is inspected. Toolchains which fail to load are
+    /// skipped.
+    #[tracing::instrument(skip(self, source))]
+    pub async fn detect(&self, filename: Option<&str>, source: &[u8]) -> anyhow::Result<Detection> {
+        let guesses = detect::detect_extensions(filename, source);
+        if guesses.is_empty() {
+            return Ok(Detection::NotFound);
+        }
+        let mut toolchains = Vec::new();
+        for name in self.list().await? {
+            match self.resolve(&name).await {
+                Ok(toolchain) => toolchains.push((name, toolchain.spec.source_extensions())),
+                Err(err) => {
+                    tracing::warn!(toolchain = %name, "skipping toolchain which failed to load: {:#}", err);
+                }
+            }
+        }
+        for (ext, method) in guesses {
+            let mut candidates: Vec<String> = toolchains
+                .iter()
+                .filter(|(_, extensions)| extensions.contains(&ext))
+                .map(|(name, _)| name.clone())
+                .collect();
+            if candidates.len() > 1 {
+                tracing::info!(extension = %ext, ?candidates, "toolchain detection is ambiguous");
+                return Ok(Detection::Ambiguous {
+                    extension: ext,
+                    candidates,
+                });
+            }
+            if let Some(name) = candidates.pop() {
+                tracing::info!(toolchain = %name, method = method.as_str(), "toolchain detected");
+                return Ok(Detection::Found { name, method });
+            }
+        }
+        Ok(Detection::NotFound)
+    }
+}
+
+fn parse_toolchain(toolchain_name: &str, raw: RawToolchain) -> anyhow::Result<Toolchain> {
+    let (spec, warnings) = manifest::parse(toolchain_name, &raw.manifest)?;
+    Ok(Toolchain {
+        spec,
+        image: raw.image,
+        warnings,
+    })
+}
+
+/// Used in [`from_config`](ToolchainLoader::from_config) constructor
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LoaderConfig {
+    #[serde(default)]
+    pub fs: Option<PathBuf>,
+    #[serde(default)]
+    pub mongodb: Option<String>,
 }
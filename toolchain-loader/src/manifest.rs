@@ -0,0 +1,297 @@
+//! Parsing and validation of toolchain manifests (`manifest.yaml`).
+//!
+//! All problems of the manifest are collected at once, each with the path
+//! of the offending value (e.g. `build[0].argv`) and its line, so that
+//! authors do not have to fix them one by one.
+
+use crate::ToolchainSpec;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use yaml_rust::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
+
+/// Latest supported version of the manifest schema. Manifests without
+/// `schema-version` have version 1, in which unknown fields are only
+/// reported as warnings. Since version 2 they are errors.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Problem found in a toolchain manifest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestIssue {
+    /// Path of the offending value, e.g. `build[0].argv`. Empty if the
+    /// issue concerns the whole manifest.
+    pub path: String,
+    /// Line of the manifest, if known
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ManifestIssue {
+    fn new(path: impl Into<String>, message: impl Into<String>) -> ManifestIssue {
+        ManifestIssue {
+            path: path.into(),
+            line: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ManifestIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Returned by toolchain resolution if the manifest is rejected
+#[derive(Debug)]
+pub struct InvalidToolchain {
+    pub toolchain: String,
+    pub errors: Vec<ManifestIssue>,
+}
+
+impl std::fmt::Display for InvalidToolchain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "toolchain {} has invalid manifest", self.toolchain)?;
+        for (i, error) in self.errors.iter().enumerate() {
+            write!(f, "{} {}", if i == 0 { ":" } else { ";" }, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvalidToolchain {}
+
+/// Formats path of an ignored value the same way as paths in
+/// deserialization errors, e.g. `build[0].argv`
+fn yaml_path(path: &serde_ignored::Path<'_>) -> String {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}[{}]", yaml_path(parent), index),
+        Path::Map { parent, key } => {
+            let parent = yaml_path(parent);
+            if parent.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", parent, key)
+            }
+        }
+        Path::Some { parent }
+        | Path::NewtypeStruct { parent }
+        | Path::NewtypeVariant { parent } => yaml_path(parent),
+    }
+}
+
+fn join_path(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+enum Frame {
+    Map { path: String, key: Option<String> },
+    Seq { path: String, next_index: usize },
+}
+
+/// Records lines of manifest values while the manifest is parsed again.
+/// Values are keyed by their paths formatted like `yaml_path`, and line
+/// of a mapping value is the line of its key.
+#[derive(Default)]
+struct LineLocator {
+    stack: Vec<Frame>,
+    lines: HashMap<String, usize>,
+}
+
+impl LineLocator {
+    /// Records line of the node which starts at `mark`. Returns its path,
+    /// or None if the node is a mapping key.
+    fn node_started(&mut self, mark: Marker) -> Option<String> {
+        let path = match self.stack.last() {
+            None => String::new(),
+            Some(Frame::Map { key: None, .. }) => return None,
+            Some(Frame::Map {
+                path,
+                key: Some(key),
+            }) => join_path(path, key),
+            Some(Frame::Seq { path, next_index }) => format!("{}[{}]", path, next_index),
+        };
+        self.lines.entry(path.clone()).or_insert(mark.line());
+        Some(path)
+    }
+
+    fn node_finished(&mut self) {
+        match self.stack.last_mut() {
+            // keys which are not scalars can not be referenced by paths
+            Some(Frame::Map { key, .. }) if key.is_none() => *key = Some("?".to_string()),
+            Some(Frame::Map { key, .. }) => *key = None,
+            Some(Frame::Seq { next_index, .. }) => *next_index += 1,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for LineLocator {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, ..) => {
+                if let Some(Frame::Map {
+                    path,
+                    key: key @ None,
+                }) = self.stack.last_mut()
+                {
+                    let path = join_path(path, &value);
+                    self.lines.entry(path).or_insert(mark.line());
+                    *key = Some(value);
+                    return;
+                }
+                self.node_started(mark);
+                self.node_finished();
+            }
+            Event::Alias(_) => {
+                self.node_started(mark);
+                self.node_finished();
+            }
+            Event::MappingStart(_) | Event::SequenceStart(_) => {
+                let path = self.node_started(mark).unwrap_or_else(|| "?".to_string());
+                self.stack.push(match ev {
+                    Event::MappingStart(_) => Frame::Map { path, key: None },
+                    _ => Frame::Seq {
+                        path,
+                        next_index: 0,
+                    },
+                });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.node_finished();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sets lines of the issues. If the value itself is missing, the line of
+/// its closest present parent is used.
+fn set_lines(data: &[u8], issues: &mut [ManifestIssue]) {
+    let mut locator = LineLocator::default();
+    if let Ok(src) = std::str::from_utf8(data) {
+        // syntax errors are reported by serde_yaml
+        Parser::new(src.chars()).load(&mut locator, false).ok();
+    }
+    for issue in issues {
+        let mut path = issue.path.as_str();
+        while !path.is_empty() {
+            if let Some(line) = locator.lines.get(path) {
+                issue.line = Some(*line);
+                break;
+            }
+            path = &path[..path.rfind(['.', '[']).unwrap_or(0)];
+        }
+    }
+}
+
+/// Parses and validates manifest of the toolchain. Returns warnings
+/// together with the spec.
+pub(crate) fn parse(
+    toolchain: &str,
+    data: &[u8],
+) -> Result<(ToolchainSpec, Vec<ManifestIssue>), InvalidToolchain> {
+    let invalid = |errors| InvalidToolchain {
+        toolchain: toolchain.to_string(),
+        errors,
+    };
+    let value: serde_yaml::Value = serde_yaml::from_slice(data).map_err(|err| {
+        invalid(vec![ManifestIssue {
+            path: String::new(),
+            line: err.location().map(|loc| loc.line()),
+            message: err.to_string(),
+        }])
+    })?;
+    let mut unknown_fields = Vec::new();
+    let mut record_unknown = |path: serde_ignored::Path<'_>| {
+        unknown_fields.push(yaml_path(&path));
+    };
+    let de = serde_ignored::Deserializer::new(value, &mut record_unknown);
+    let spec: ToolchainSpec = serde_path_to_error::deserialize(de).map_err(|err| {
+        let path = err.path().to_string();
+        let path = if path == "." { String::new() } else { path };
+        let mut errors = vec![ManifestIssue::new(path, err.into_inner().to_string())];
+        set_lines(data, &mut errors);
+        invalid(errors)
+    })?;
+
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    if spec.schema_version == 0 || spec.schema_version > SCHEMA_VERSION {
+        errors.push(ManifestIssue::new(
+            "schema-version",
+            format!(
+                "unsupported schema version {}, expected at most {}",
+                spec.schema_version, SCHEMA_VERSION
+            ),
+        ));
+    }
+    for path in unknown_fields {
+        let issue = ManifestIssue::new(path, "unknown field");
+        if spec.schema_version >= 2 {
+            errors.push(issue);
+        } else {
+            warnings.push(issue);
+        }
+    }
+    if spec.run_command.argv.is_empty() {
+        errors.push(ManifestIssue::new(
+            "run.argv",
+            "run command must not be empty",
+        ));
+    }
+    for (i, command) in spec.build_commands.iter().enumerate() {
+        if command.argv.is_empty() {
+            errors.push(ManifestIssue::new(
+                format!("build[{}].argv", i),
+                "build command must not be empty",
+            ));
+        }
+    }
+    if let Some(command) = &spec.syntax_check {
+        if command.argv.is_empty() {
+            errors.push(ManifestIssue::new(
+                "syntax-check.argv",
+                "syntax check command must not be empty",
+            ));
+        }
+    }
+    let limits = [
+        ("memory", spec.limits.memory),
+        ("time", spec.limits.time),
+        ("process_count", spec.limits.process_count),
+        ("work_dir_size", spec.limits.work_dir_size),
+    ];
+    for (name, value) in limits.iter() {
+        if *value == Some(0) {
+            errors.push(ManifestIssue::new(
+                format!("build-limits.{}", name),
+                "limit must be positive",
+            ));
+        }
+    }
+    if let Err(err) = spec.build_sandbox.validate() {
+        errors.push(ManifestIssue::new("build-sandbox", format!("{:#}", err)));
+    }
+    if let Err(err) = spec.validate_artifacts() {
+        errors.push(ManifestIssue::new("artifacts", format!("{:#}", err)));
+    }
+    if !errors.is_empty() {
+        set_lines(data, &mut errors);
+        return Err(invalid(errors));
+    }
+    set_lines(data, &mut warnings);
+    Ok((spec, warnings))
+}
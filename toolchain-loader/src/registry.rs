@@ -0,0 +1,169 @@
+//! defines Registry trait and several registries
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use futures::stream::TryStreamExt;
+use std::path::PathBuf;
+use tracing::instrument;
+
+/// Toolchain as stored in a registry, not validated yet
+pub struct RawToolchain {
+    /// Contents of `manifest.yaml`
+    pub manifest: Vec<u8>,
+    /// Reference of the image containing toolchain files
+    pub image: String,
+}
+
+/// Single toolchain source.
+/// `ToolchainLoader` itself is just abstraction for group of
+/// registries.
+#[async_trait]
+pub trait Registry: Send + Sync {
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Tries to fetch toolchain manifest and image reference.
+    /// Returns None if toolchain was not found.
+    async fn get_toolchain(&self, toolchain_name: &str) -> anyhow::Result<Option<RawToolchain>>;
+
+    /// Returns names of all toolchains of the registry
+    async fn list(&self) -> anyhow::Result<Vec<String>>;
+}
+
+/// Resolves toolchains from filesystem. Each toolchain is a directory
+/// containing `manifest.yaml` and `image.txt`.
+#[derive(Debug)]
+pub struct FsRegistry {
+    /// Directory containing all toolchains
+    toolchains_dir: PathBuf,
+}
+
+impl FsRegistry {
+    pub fn new(toolchains_dir: PathBuf) -> FsRegistry {
+        FsRegistry { toolchains_dir }
+    }
+}
+
+#[async_trait]
+impl Registry for FsRegistry {
+    #[instrument]
+    async fn get_toolchain(&self, toolchain_name: &str) -> anyhow::Result<Option<RawToolchain>> {
+        let toolchain_dir_path = self.toolchains_dir.join(toolchain_name);
+        let manifest = match tokio::fs::read(toolchain_dir_path.join("manifest.yaml")).await {
+            Ok(m) => m,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("failed to read manifest.yaml"),
+        };
+        let image = tokio::fs::read_to_string(toolchain_dir_path.join("image.txt"))
+            .await
+            .context("failed to read image.txt")?;
+        Ok(Some(RawToolchain {
+            manifest,
+            image: image.trim().to_string(),
+        }))
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<String>> {
+        let mut entries = tokio::fs::read_dir(&self.toolchains_dir)
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to list toolchains in {}",
+                    self.toolchains_dir.display()
+                )
+            })?;
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if tokio::fs::metadata(entry.path().join("manifest.yaml"))
+                .await
+                .is_err()
+            {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+}
+
+/// Resolves toolchains via MongoDB. Each toolchain is a document of the
+/// `toolchains` collection with `toolchain-name`, `manifest` (contents of
+/// `manifest.yaml`) and `image` fields. Documents are read on each
+/// lookup, so updates apply without restarting judge.
+pub struct MongoRegistry {
+    collection: mongodb::Collection,
+}
+
+impl std::fmt::Debug for MongoRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MongoRegistry")
+            .field("collection", &"..")
+            .finish()
+    }
+}
+
+impl MongoRegistry {
+    #[instrument]
+    pub async fn new(connection_string: &str) -> anyhow::Result<MongoRegistry> {
+        let client = mongodb::Client::with_uri_str(connection_string)
+            .await
+            .context("database is not available")?;
+        let database = client.database("jjs");
+        let collection = database.collection("toolchains");
+        Ok(MongoRegistry { collection })
+    }
+}
+
+#[async_trait]
+impl Registry for MongoRegistry {
+    #[instrument]
+    async fn get_toolchain(&self, toolchain_name: &str) -> anyhow::Result<Option<RawToolchain>> {
+        let filter = {
+            let mut filter = bson::Document::new();
+            filter.insert("toolchain-name", toolchain_name);
+            filter
+        };
+        let doc = self
+            .collection
+            .find_one(filter, None)
+            .await
+            .context("toolchain document lookup failure")?;
+        let doc = match doc {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+        let manifest = doc
+            .get_binary_generic("manifest")
+            .context("storage schema violation for field `manifest`")?;
+        let image = doc
+            .get_str("image")
+            .context("storage schema violation for field `image`")?;
+        Ok(Some(RawToolchain {
+            manifest: manifest.clone(),
+            image: image.trim().to_string(),
+        }))
+    }
+
+    async fn list(&self) -> anyhow::Result<Vec<String>> {
+        let mut cursor = self
+            .collection
+            .find(None, None)
+            .await
+            .context("failed to query toolchain documents")?;
+        let mut names = Vec::new();
+        while let Some(doc) = cursor
+            .try_next()
+            .await
+            .context("failed to fetch toolchain document")?
+        {
+            let name = doc
+                .get_str("toolchain-name")
+                .context("storage schema violation for field `toolchain-name`")?;
+            names.push(name.to_string());
+        }
+        Ok(names)
+    }
+}
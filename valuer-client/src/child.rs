@@ -1,7 +1,31 @@
-use crate::ChildClientConfig;
+use crate::{ChildClientConfig, ProblemData, ValuerMessage};
 use anyhow::Context;
+use serde::Deserialize;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
 
+/// Messages which are not part of `valuer_api`
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Hint {
+    SkipRemainingInGroup { group: String },
+    Tests { ids: Vec<valuer_api::TestId> },
+}
+
+/// Parses message of the base protocol or a hint
+pub(crate) fn parse_message(data: &str) -> anyhow::Result<ValuerMessage> {
+    let err = match serde_json::from_str(data) {
+        Ok(response) => return Ok(ValuerMessage::Response(response)),
+        Err(err) => err,
+    };
+    match serde_json::from_str(data) {
+        Ok(Hint::SkipRemainingInGroup { group }) => {
+            Ok(ValuerMessage::SkipRemainingInGroup { group })
+        }
+        Ok(Hint::Tests { ids }) => Ok(ValuerMessage::Tests { ids }),
+        Err(_) => Err(anyhow::Error::new(err).context("failed to parse valuer message")),
+    }
+}
+
 pub(crate) struct ChildClient {
     stdin: BufWriter<tokio::process::ChildStdin>,
     stdout: BufReader<tokio::process::ChildStdout>,
@@ -18,13 +42,14 @@ impl ChildClient {
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::inherit());
         cmd.env("JJS_VALUER", "1");
+        cmd.envs(cfg.env.iter().map(|(k, v)| (k, v)));
         // TODO: this is hack
         cmd.env("RUST_LOG", "info,svaluer=debug");
         let work_dir_exists = tokio::fs::metadata(&cfg.current_dir).await.is_ok();
         if work_dir_exists {
             cmd.current_dir(&cfg.current_dir);
         } else {
-            tracing::warn!(
+            tracing::debug!(
                 "Not setting current dir for valuer because path specified ({}) does not exists",
                 cfg.current_dir.display()
             );
@@ -64,14 +89,12 @@ impl ChildClient {
         Ok(())
     }
 
-    pub(crate) async fn write_problem_data(
-        &mut self,
-        info: valuer_api::ProblemInfo,
-    ) -> anyhow::Result<()> {
-        self.write_val(info).await
+    pub(crate) async fn write_problem_data(&mut self, data: ProblemData<'_>) -> anyhow::Result<()> {
+        self.write_val(data).await
     }
 
-    pub(crate) async fn poll(&mut self) -> anyhow::Result<valuer_api::ValuerResponse> {
+    /// Returns next message without parsing it
+    pub(crate) async fn poll(&mut self) -> anyhow::Result<String> {
         let mut line = String::new();
         let read_line_fut = self.stdout.read_line(&mut line);
         match tokio::time::timeout(std::time::Duration::from_secs(15), read_line_fut).await {
@@ -82,9 +105,7 @@ impl ChildClient {
                 anyhow::bail!("valuer response timed out");
             }
         }
-        let response = serde_json::from_str(&line).context("failed to parse valuer message")?;
-
-        Ok(response)
+        Ok(line)
     }
 
     pub(crate) async fn notify_test_done(
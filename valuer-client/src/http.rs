@@ -0,0 +1,137 @@
+//! Client of a valuer service, shared between judges.
+//!
+//! Protocol messages are the same as for child valuers, but each one is
+//! sent in a separate request:
+//! - `POST /sessions` with `{"params": {..}, "problem": ProblemInfo}`
+//!   (`ProblemInfo` may contain judge `extensions`)
+//!   starts valuing a run and returns `{"session": "<id>"}`;
+//! - `POST /sessions/<id>/poll` returns the next message (as a child valuer
+//!   would print it), waiting for it if needed;
+//! - `POST /sessions/<id>/test-done` with `TestDoneNotification`;
+//! - `DELETE /sessions/<id>` is sent when the client is dropped.
+use crate::{HttpClientConfig, ProblemData};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+/// How long valuer service may take to respond to a poll
+const POLL_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Serialize)]
+struct CreateSession<'a> {
+    params: &'a BTreeMap<String, String>,
+    problem: ProblemData<'a>,
+}
+
+#[derive(Deserialize)]
+struct SessionCreated {
+    session: String,
+}
+
+pub(crate) struct HttpClient {
+    transport: reqwest::Client,
+    url: String,
+    params: BTreeMap<String, String>,
+    /// Set once problem data is sent
+    session: Option<String>,
+}
+
+impl HttpClient {
+    pub(crate) fn new(cfg: &HttpClientConfig) -> anyhow::Result<Self> {
+        let transport = reqwest::Client::builder()
+            .timeout(POLL_TIMEOUT)
+            .build()
+            .context("failed to initialize HTTP client")?;
+        Ok(HttpClient {
+            transport,
+            url: cfg.url.trim_end_matches('/').to_string(),
+            params: cfg.params.clone(),
+            session: None,
+        })
+    }
+
+    fn session_url(&self, action: &str) -> anyhow::Result<String> {
+        let session = self
+            .session
+            .as_ref()
+            .context("bug: problem data was not sent to valuer")?;
+        Ok(format!("{}/sessions/{}/{}", self.url, session, action))
+    }
+
+    pub(crate) async fn write_problem_data(&mut self, data: ProblemData<'_>) -> anyhow::Result<()> {
+        let created: SessionCreated = self
+            .transport
+            .post(format!("{}/sessions", self.url))
+            .json(&CreateSession {
+                params: &self.params,
+                problem: data,
+            })
+            .send()
+            .await
+            .context("failed to send request")?
+            .error_for_status()
+            .context("valuer service rejected session")?
+            .json()
+            .await
+            .context("failed to receive session id")?;
+        tracing::debug!(session = %created.session, "created valuer session");
+        self.session = Some(created.session);
+        Ok(())
+    }
+
+    /// Returns next message without parsing it
+    pub(crate) async fn poll(&mut self) -> anyhow::Result<String> {
+        self.transport
+            .post(self.session_url("poll")?)
+            .send()
+            .await
+            .map_err(|err| {
+                if err.is_timeout() {
+                    anyhow::anyhow!("valuer response timed out")
+                } else {
+                    anyhow::Error::new(err).context("failed to send request")
+                }
+            })?
+            .error_for_status()
+            .context("response is not successful")?
+            .text()
+            .await
+            .context("failed to receive valuer message")
+    }
+
+    pub(crate) async fn notify_test_done(
+        &mut self,
+        notification: valuer_api::TestDoneNotification,
+    ) -> anyhow::Result<()> {
+        self.transport
+            .post(self.session_url("test-done")?)
+            .json(&notification)
+            .send()
+            .await
+            .context("failed to send request")?
+            .error_for_status()
+            .context("response is not successful")?;
+        Ok(())
+    }
+}
+
+impl Drop for HttpClient {
+    fn drop(&mut self) {
+        let session = match self.session.take() {
+            Some(s) => s,
+            None => return,
+        };
+        // session is closed in background, service expires it anyway
+        let req = self
+            .transport
+            .delete(format!("{}/sessions/{}", self.url, session))
+            .send();
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                if let Err(err) = req.await {
+                    tracing::debug!("failed to close valuer session: {}", err);
+                }
+            });
+        }
+    }
+}
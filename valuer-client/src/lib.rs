@@ -1,13 +1,48 @@
-use child::ChildClient;
-use std::path::PathBuf;
+use child::{parse_message, ChildClient};
+use http::HttpClient;
+use std::{collections::BTreeMap, path::PathBuf};
 
 mod child;
+mod http;
 
 /// Data, required to create a valuer client.
 /// This is a bit lowered version of `pom::Valuer`.
 #[derive(Debug)]
 pub enum ClientConfig {
     Child(ChildClientConfig),
+    Http(HttpClientConfig),
+}
+
+/// Environment variable listing hints judge understands, comma-separated.
+/// Valuer must not send other hints.
+pub const HINTS_ENV: &str = "JJS_VALUER_HINTS";
+
+/// Judge-specific fields sent together with `ProblemInfo`, e.g.
+/// `judging_mode`. Field is omitted if there are no extensions.
+pub type Extensions = BTreeMap<String, String>;
+
+/// `ProblemInfo` as it is sent to valuer
+#[derive(serde::Serialize)]
+struct ProblemData<'a> {
+    #[serde(flatten)]
+    info: &'a valuer_api::ProblemInfo,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    extensions: &'a Extensions,
+}
+
+/// Message received from valuer
+#[derive(Debug)]
+pub enum ValuerMessage {
+    /// Message of the base protocol
+    Response(valuer_api::ValuerResponse),
+    /// Remaining tests of the group can not change the score, so judge may
+    /// skip them. Valuer can still request such tests.
+    /// Hint name is `skip_remaining_in_group`.
+    SkipRemainingInGroup { group: String },
+    /// Tests which may be judged concurrently. Each of them is answered
+    /// with a separate notification, as if it was requested with `Test`.
+    /// Hint name is `tests`.
+    Tests { ids: Vec<valuer_api::TestId> },
 }
 
 #[derive(Debug)]
@@ -15,45 +50,116 @@ pub struct ChildClientConfig {
     pub exe: PathBuf,
     pub args: Vec<String>,
     pub current_dir: PathBuf,
+    /// Additional environment variables
+    pub env: Vec<(String, String)>,
+}
+
+/// Valuer service, possibly shared between several judges
+#[derive(Debug)]
+pub struct HttpClientConfig {
+    /// Base URL of the service
+    pub url: String,
+    /// Sent when a session is created. These are the same values child
+    /// valuers receive in the environment (e.g. hints and log kinds),
+    /// plus name of the problem.
+    pub params: BTreeMap<String, String>,
+}
+
+/// Message exchanged with valuer, as it was sent or received
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RawMessage {
+    pub direction: Direction,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    ToValuer,
+    FromValuer,
 }
 
 enum Inner {
-    Child(ChildClient),
+    Child(Box<ChildClient>),
+    Http(HttpClient),
 }
 
 /// ValuerClient can be used to communicate with valuer.
-pub struct ValuerClient(Inner);
+pub struct ValuerClient {
+    inner: Inner,
+    /// Messages which were not taken yet, if recording is enabled
+    transcript: Option<Vec<RawMessage>>,
+}
 
 impl ValuerClient {
     pub async fn new(config: &ClientConfig) -> anyhow::Result<Self> {
         tracing::info!(config = ?config, "connecting to valuer");
         let inner = match config {
-            ClientConfig::Child(cfg) => Inner::Child(ChildClient::new(cfg).await?),
+            ClientConfig::Child(cfg) => Inner::Child(Box::new(ChildClient::new(cfg).await?)),
+            ClientConfig::Http(cfg) => Inner::Http(HttpClient::new(cfg)?),
         };
-        Ok(ValuerClient(inner))
+        Ok(ValuerClient {
+            inner,
+            transcript: None,
+        })
+    }
+
+    /// Starts recording all messages, see [`take_transcript`](Self::take_transcript)
+    pub fn record_transcript(&mut self) {
+        self.transcript.get_or_insert_with(Vec::new);
+    }
+
+    /// Returns messages recorded since the previous call
+    pub fn take_transcript(&mut self) -> Vec<RawMessage> {
+        self.transcript
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn record(&mut self, direction: Direction, data: impl FnOnce() -> String) {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.push(RawMessage {
+                direction,
+                data: data(),
+            });
+        }
     }
 
     pub async fn write_problem_data(
         &mut self,
-        info: valuer_api::ProblemInfo,
+        info: &valuer_api::ProblemInfo,
+        extensions: &Extensions,
     ) -> anyhow::Result<()> {
-        match &mut self.0 {
-            Inner::Child(inner) => inner.write_problem_data(info).await,
+        let data = ProblemData { info, extensions };
+        self.record(Direction::ToValuer, || {
+            serde_json::to_string(&data).unwrap_or_default()
+        });
+        match &mut self.inner {
+            Inner::Child(inner) => inner.write_problem_data(data).await,
+            Inner::Http(inner) => inner.write_problem_data(data).await,
         }
     }
 
-    pub async fn poll(&mut self) -> anyhow::Result<valuer_api::ValuerResponse> {
-        match &mut self.0 {
-            Inner::Child(inner) => inner.poll().await,
-        }
+    pub async fn poll(&mut self) -> anyhow::Result<ValuerMessage> {
+        let data = match &mut self.inner {
+            Inner::Child(inner) => inner.poll().await?,
+            Inner::Http(inner) => inner.poll().await?,
+        };
+        self.record(Direction::FromValuer, || data.trim_end().to_string());
+        parse_message(&data)
     }
 
     pub async fn notify_test_done(
         &mut self,
         notification: valuer_api::TestDoneNotification,
     ) -> anyhow::Result<()> {
-        match &mut self.0 {
+        self.record(Direction::ToValuer, || {
+            serde_json::to_string(&notification).unwrap_or_default()
+        });
+        match &mut self.inner {
             Inner::Child(inner) => inner.notify_test_done(notification).await,
+            Inner::Http(inner) => inner.notify_test_done(notification).await,
         }
     }
 }